@@ -0,0 +1,86 @@
+use crate::terminal_emulator::{MockIo, Recording, RecordingItem, TerminalEmulator};
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const CHUNK_SIZE: usize = 4096;
+
+#[derive(Default)]
+struct BenchmarkResults {
+    total_bytes: usize,
+    total_time: Duration,
+    num_chunks: usize,
+    max_chunk_time: Duration,
+}
+
+impl BenchmarkResults {
+    fn print(&self) {
+        let secs = self.total_time.as_secs_f64();
+        let throughput = if secs > 0.0 {
+            self.total_bytes as f64 / secs / 1_000_000.0
+        } else {
+            f64::INFINITY
+        };
+        let avg_chunk_time = if self.num_chunks > 0 {
+            self.total_time / self.num_chunks as u32
+        } else {
+            Duration::ZERO
+        };
+
+        println!("Total bytes processed: {}", self.total_bytes);
+        println!("Total time: {:?}", self.total_time);
+        println!("Throughput: {throughput:.2} MB/s");
+        println!("Average time per {CHUNK_SIZE} byte chunk: {avg_chunk_time:?}");
+        println!(
+            "Max time per {CHUNK_SIZE} byte chunk: {:?}",
+            self.max_chunk_time
+        );
+    }
+}
+
+fn run_iteration(
+    recording: &Recording,
+    results: &mut BenchmarkResults,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut terminal_emulator =
+        TerminalEmulator::from_snapshot(recording.initial_state(), MockIo::new())?;
+
+    for item in recording.items() {
+        match item {
+            RecordingItem::Write { data, .. } => {
+                for chunk in data.chunks(CHUNK_SIZE) {
+                    let start = Instant::now();
+                    terminal_emulator.write_sequence(chunk);
+                    let elapsed = start.elapsed();
+
+                    results.total_bytes += chunk.len();
+                    results.total_time += elapsed;
+                    results.num_chunks += 1;
+                    results.max_chunk_time = results.max_chunk_time.max(elapsed);
+                }
+            }
+            RecordingItem::SetWinSize { width, height, .. } => {
+                terminal_emulator.set_win_size(*width, *height)?;
+            }
+            RecordingItem::ResetParser { .. } => terminal_emulator.reset_parser(),
+        }
+    }
+
+    Ok(())
+}
+
+/// Push a recording through the parser and terminal buffer as fast as possible, `iters` times,
+/// and print how long it took
+pub fn run(recording_path: &Path, iters: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let recording = Recording::load(recording_path)?;
+
+    let mut results = BenchmarkResults::default();
+    for _ in 0..iters {
+        run_iteration(&recording, &mut results)?;
+    }
+
+    results.print();
+    Ok(())
+}
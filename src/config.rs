@@ -0,0 +1,209 @@
+use thiserror::Error;
+use toml::{Table, Value};
+
+use std::path::{Path, PathBuf};
+
+use crate::gui::ColorTheme;
+
+#[derive(Debug, Error)]
+enum ConfigErrorKind {
+    #[error("failed to read {0}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("failed to parse toml")]
+    Parse(#[source] toml::de::Error),
+    #[error("{0} is not a {1}")]
+    WrongType(String, &'static str),
+    #[error("{0} is out of range")]
+    OutOfRange(String),
+    #[error("unknown theme: {0}")]
+    UnknownTheme(String),
+    #[error("invalid geometry: {0}")]
+    InvalidGeometry(String),
+    #[error("unknown setting: {0}")]
+    UnknownSetting(String),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct ConfigError(#[from] ConfigErrorKind);
+
+mod config_keys {
+    pub const SHELL: &str = "shell";
+    pub const RECORDING_PATH: &str = "recording_path";
+    pub const FONT_SIZE: &str = "font_size";
+    pub const THEME: &str = "theme";
+    pub const SCROLLBACK_LINES: &str = "scrollback_lines";
+    pub const GEOMETRY: &str = "geometry";
+}
+
+/// Directory termie's config files live in, $XDG_CONFIG_HOME/termie or ~/.config/termie
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join("termie"))
+}
+
+/// Parse a terminal size in the form WIDTHxHEIGHT, e.g. 80x24
+pub fn parse_geometry(s: &str) -> Option<(usize, usize)> {
+    let (width, height) = s.split_once('x')?;
+    let width = width.parse().ok()?;
+    let height = height.parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+fn as_str(key: &str, value: Value) -> Result<String, ConfigErrorKind> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(ConfigErrorKind::WrongType(key.to_string(), "string")),
+    }
+}
+
+fn as_usize(key: &str, value: Value) -> Result<usize, ConfigErrorKind> {
+    match value {
+        Value::Integer(i) => i
+            .try_into()
+            .map_err(|_| ConfigErrorKind::OutOfRange(key.to_string())),
+        _ => Err(ConfigErrorKind::WrongType(key.to_string(), "integer")),
+    }
+}
+
+fn as_f32(key: &str, value: Value) -> Result<f32, ConfigErrorKind> {
+    match value {
+        Value::Float(f) => Ok(f as f32),
+        Value::Integer(i) => Ok(i as f32),
+        _ => Err(ConfigErrorKind::WrongType(key.to_string(), "number")),
+    }
+}
+
+/// Settings from config.toml. Anything left unset falls back to the default, and command line
+/// arguments take precedence over all of it
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub shell: Option<PathBuf>,
+    pub recording_path: Option<PathBuf>,
+    pub font_size: Option<f32>,
+    pub theme: Option<ColorTheme>,
+    pub scrollback_lines: Option<usize>,
+    pub geometry: Option<(usize, usize)>,
+}
+
+impl Config {
+    fn from_toml(s: &str) -> Result<Config, ConfigError> {
+        use ConfigErrorKind::*;
+
+        let table: Table = s.parse().map_err(Parse)?;
+        let mut config = Config::default();
+
+        for (key, value) in table {
+            match key.as_str() {
+                config_keys::SHELL => config.shell = Some(as_str(&key, value)?.into()),
+                config_keys::RECORDING_PATH => {
+                    config.recording_path = Some(as_str(&key, value)?.into())
+                }
+                config_keys::FONT_SIZE => config.font_size = Some(as_f32(&key, value)?),
+                config_keys::THEME => {
+                    let name = as_str(&key, value)?;
+                    let theme = ColorTheme::by_name(&name).ok_or(UnknownTheme(name))?;
+                    config.theme = Some(theme.clone());
+                }
+                config_keys::SCROLLBACK_LINES => {
+                    config.scrollback_lines = Some(as_usize(&key, value)?)
+                }
+                config_keys::GEOMETRY => {
+                    let geometry = as_str(&key, value)?;
+                    config.geometry =
+                        Some(parse_geometry(&geometry).ok_or(InvalidGeometry(geometry))?);
+                }
+                _ => Err(UnknownSetting(key))?,
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Load settings from `path`. A missing file is not an error, it leaves everything unset
+    fn load_from(path: &Path) -> Result<Config, ConfigError> {
+        let toml = match std::fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => Err(ConfigErrorKind::Read(path.to_path_buf(), e))?,
+        };
+
+        Self::from_toml(&toml)
+    }
+
+    /// Load settings from ~/.config/termie/config.toml
+    pub fn load() -> Result<Config, ConfigError> {
+        let Some(config_dir) = config_dir() else {
+            return Ok(Config::default());
+        };
+
+        Self::load_from(&config_dir.join("config.toml"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_geometry() {
+        assert_eq!(parse_geometry("80x24"), Some((80, 24)));
+        assert_eq!(parse_geometry("132x50"), Some((132, 50)));
+        assert_eq!(parse_geometry("80"), None);
+        assert_eq!(parse_geometry("80x"), None);
+        assert_eq!(parse_geometry("x24"), None);
+        assert_eq!(parse_geometry("80X24"), None);
+        assert_eq!(parse_geometry("0x24"), None);
+        assert_eq!(parse_geometry("-80x24"), None);
+        assert_eq!(parse_geometry("80x24x10"), None);
+    }
+
+    #[test]
+    fn test_load_config() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let path = temp_dir.path().join("config.toml");
+
+        assert_eq!(
+            Config::load_from(&path).expect("missing config should load"),
+            Config::default()
+        );
+
+        std::fs::write(
+            &path,
+            r#"
+                shell = "/bin/zsh"
+                recording_path = "/tmp/recordings"
+                font_size = 14
+                theme = "nord"
+                scrollback_lines = 5000
+                geometry = "80x24"
+            "#,
+        )
+        .expect("failed to write config");
+
+        let config = Config::load_from(&path).expect("failed to load config");
+        assert_eq!(
+            config,
+            Config {
+                shell: Some("/bin/zsh".into()),
+                recording_path: Some("/tmp/recordings".into()),
+                font_size: Some(14.0),
+                theme: ColorTheme::by_name("nord").cloned(),
+                scrollback_lines: Some(5000),
+                geometry: Some((80, 24)),
+            }
+        );
+
+        assert!(Config::from_toml("theme = \"nope\"").is_err());
+        assert!(Config::from_toml("scrollback_lines = -1").is_err());
+        assert!(Config::from_toml("geometry = 80").is_err());
+        assert!(Config::from_toml("fontsize = 12").is_err());
+    }
+}
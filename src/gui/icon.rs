@@ -0,0 +1,52 @@
+use eframe::egui::{self, Color32, Pos2};
+
+const ICON_SIZE: u32 = 64;
+const BACKGROUND: Color32 = Color32::from_rgb(0x1b, 0x1b, 0x1b);
+const FOREGROUND: Color32 = Color32::from_rgb(0xe0, 0xe0, 0xe0);
+const STROKE_WIDTH: f32 = 5.0;
+
+fn distance_to_segment(p: Pos2, a: Pos2, b: Pos2) -> f32 {
+    let ab = b - a;
+    let t = ((p - a).dot(ab) / ab.length_sq()).clamp(0.0, 1.0);
+    p.distance(a + ab * t)
+}
+
+fn is_foreground(p: Pos2) -> bool {
+    // >
+    let top = Pos2::new(14.0, 16.0);
+    let tip = Pos2::new(30.0, 32.0);
+    let bottom = Pos2::new(14.0, 48.0);
+    let half_stroke = STROKE_WIDTH / 2.0;
+    if distance_to_segment(p, top, tip) <= half_stroke
+        || distance_to_segment(p, tip, bottom) <= half_stroke
+    {
+        return true;
+    }
+
+    // _
+    (34.0..52.0).contains(&p.x) && (44.0..44.0 + STROKE_WIDTH).contains(&p.y)
+}
+
+/// A ">_" prompt on a dark background, generated so that we do not need to ship and decode an
+/// image
+pub fn termie_icon() -> egui::IconData {
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            // Sample at the pixel center
+            let p = Pos2::new(x as f32 + 0.5, y as f32 + 0.5);
+            let color = if is_foreground(p) {
+                FOREGROUND
+            } else {
+                BACKGROUND
+            };
+            rgba.extend_from_slice(&color.to_array());
+        }
+    }
+
+    egui::IconData {
+        rgba,
+        width: ICON_SIZE,
+        height: ICON_SIZE,
+    }
+}
@@ -0,0 +1,263 @@
+use eframe::egui::{Key, Modifiers};
+use thiserror::Error;
+use tinyjson::JsonValue;
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// Environment variable pointing at an alternate config file
+const CONFIG_ENV_VAR: &str = "TERMIE_CONFIG";
+
+#[derive(Debug, Error)]
+enum LoadKeyBindingsErrorKind {
+    #[error("failed to read {0}")]
+    Read(PathBuf, #[source] std::io::Error),
+    #[error("failed to parse json")]
+    Parse(#[source] tinyjson::JsonParseError),
+    #[error("root element is not an object")]
+    RootNotObject,
+    #[error("bindings for {0} are not an array")]
+    ActionNotArray(String),
+    #[error("binding for {0} is not an object")]
+    ComboNotObject(String),
+    #[error("binding for {0} has no key")]
+    KeyMissing(String),
+    #[error("unknown key for {0}: {1}")]
+    UnknownKey(String, String),
+    #[error("modifier {1} for {0} is not a bool")]
+    ModifierNotBool(String, String),
+    #[error("unknown action: {0}")]
+    UnknownAction(String),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct LoadKeyBindingsError(#[from] LoadKeyBindingsErrorKind);
+
+mod key_bindings_keys {
+    pub const FONT_INCREASE: &str = "font_increase";
+    pub const FONT_DECREASE: &str = "font_decrease";
+    pub const FIND: &str = "find";
+    pub const KEY: &str = "key";
+    pub const CTRL: &str = "ctrl";
+    pub const SHIFT: &str = "shift";
+    pub const ALT: &str = "alt";
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyCombo {
+    pub key: Key,
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl KeyCombo {
+    const fn ctrl(key: Key) -> KeyCombo {
+        KeyCombo {
+            key,
+            ctrl: true,
+            shift: false,
+            alt: false,
+        }
+    }
+
+    pub fn matches(&self, key: Key, modifiers: &Modifiers) -> bool {
+        self.key == key
+            && self.ctrl == modifiers.ctrl
+            && self.shift == modifiers.shift
+            && self.alt == modifiers.alt
+    }
+
+    fn from_json(action: &str, json: JsonValue) -> Result<KeyCombo, LoadKeyBindingsErrorKind> {
+        use LoadKeyBindingsErrorKind::*;
+
+        let JsonValue::Object(mut map) = json else {
+            return Err(ComboNotObject(action.to_string()));
+        };
+
+        let key = match map.remove(key_bindings_keys::KEY) {
+            Some(JsonValue::String(name)) => {
+                Key::from_name(&name).ok_or_else(|| UnknownKey(action.to_string(), name))?
+            }
+            _ => return Err(KeyMissing(action.to_string())),
+        };
+
+        let mut modifier = |name: &str| match map.remove(name) {
+            None => Ok(false),
+            Some(JsonValue::Boolean(b)) => Ok(b),
+            Some(_) => Err(ModifierNotBool(action.to_string(), name.to_string())),
+        };
+
+        Ok(KeyCombo {
+            key,
+            ctrl: modifier(key_bindings_keys::CTRL)?,
+            shift: modifier(key_bindings_keys::SHIFT)?,
+            alt: modifier(key_bindings_keys::ALT)?,
+        })
+    }
+}
+
+/// User configurable shortcuts. Each action can be bound to several combos, e.g. so that font
+/// size can be increased with ctrl+= and ctrl++ on layouts where + needs shift
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct KeyBindings {
+    pub font_increase: Vec<KeyCombo>,
+    pub font_decrease: Vec<KeyCombo>,
+    pub find: Vec<KeyCombo>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings {
+            font_increase: vec![
+                KeyCombo::ctrl(Key::Equals),
+                KeyCombo::ctrl(Key::Plus),
+                KeyCombo {
+                    shift: true,
+                    ..KeyCombo::ctrl(Key::Plus)
+                },
+            ],
+            font_decrease: vec![KeyCombo::ctrl(Key::Minus)],
+            find: vec![KeyCombo {
+                shift: true,
+                ..KeyCombo::ctrl(Key::F)
+            }],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Parse bindings from json, e.g. `{"font_increase": [{"key": "Equals", "ctrl": true}]}`.
+    /// Actions that are not present keep their default bindings
+    pub fn from_json(json: &str) -> Result<KeyBindings, LoadKeyBindingsError> {
+        use LoadKeyBindingsErrorKind::*;
+
+        let root: JsonValue = json.parse().map_err(Parse)?;
+        let JsonValue::Object(root) = root else {
+            Err(RootNotObject)?
+        };
+
+        let mut bindings = KeyBindings::default();
+        for (action, combos) in root {
+            let JsonValue::Array(combos) = combos else {
+                Err(ActionNotArray(action))?
+            };
+
+            let combos = combos
+                .into_iter()
+                .map(|combo| KeyCombo::from_json(&action, combo))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            match action.as_str() {
+                key_bindings_keys::FONT_INCREASE => bindings.font_increase = combos,
+                key_bindings_keys::FONT_DECREASE => bindings.font_decrease = combos,
+                key_bindings_keys::FIND => bindings.find = combos,
+                _ => Err(UnknownAction(action))?,
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    fn default_path() -> Option<PathBuf> {
+        Some(crate::config::config_dir()?.join("keybindings.json"))
+    }
+
+    /// Pick the config file in order of precedence: --config, $TERMIE_CONFIG, the default path.
+    /// Returns whether the file was explicitly requested alongside the path
+    fn config_path(cli_path: Option<&Path>, env_path: Option<OsString>) -> Option<(PathBuf, bool)> {
+        if let Some(path) = cli_path {
+            return Some((path.to_path_buf(), true));
+        }
+
+        if let Some(path) = env_path {
+            return Some((path.into(), true));
+        }
+
+        Some((Self::default_path()?, false))
+    }
+
+    /// Load bindings from `cli_path`, $TERMIE_CONFIG or ~/.config/termie/keybindings.json, in that
+    /// order. A missing file is only an error if it was explicitly requested
+    pub fn load(cli_path: Option<&Path>) -> Result<KeyBindings, LoadKeyBindingsError> {
+        let Some((path, explicit)) = Self::config_path(cli_path, std::env::var_os(CONFIG_ENV_VAR))
+        else {
+            return Ok(KeyBindings::default());
+        };
+
+        let json = match std::fs::read_to_string(&path) {
+            Ok(v) => v,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {
+                return Ok(KeyBindings::default())
+            }
+            Err(e) => Err(LoadKeyBindingsErrorKind::Read(path, e))?,
+        };
+
+        Self::from_json(&json)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_bindings_from_json() {
+        let bindings = KeyBindings::from_json(
+            r#"{"font_decrease": [{"key": "J", "ctrl": true, "shift": true}, {"key": "F11"}]}"#,
+        )
+        .expect("failed to parse bindings");
+
+        assert_eq!(bindings.font_increase, KeyBindings::default().font_increase);
+        assert_eq!(
+            bindings.font_decrease,
+            &[
+                KeyCombo {
+                    key: Key::J,
+                    ctrl: true,
+                    shift: true,
+                    alt: false,
+                },
+                KeyCombo {
+                    key: Key::F11,
+                    ctrl: false,
+                    shift: false,
+                    alt: false,
+                },
+            ]
+        );
+
+        let modifiers = Modifiers {
+            ctrl: true,
+            shift: true,
+            ..Default::default()
+        };
+        assert!(bindings.font_decrease[0].matches(Key::J, &modifiers));
+        assert!(!bindings.font_decrease[0].matches(Key::J, &Modifiers::CTRL));
+
+        assert!(KeyBindings::from_json(r#"{"font_decrease": [{"key": "NotAKey"}]}"#).is_err());
+        assert!(KeyBindings::from_json(r#"{"new_window": []}"#).is_err());
+    }
+
+    #[test]
+    fn test_config_path_precedence() {
+        let cli = Path::new("/cli/config.json");
+        let env = OsString::from("/env/config.json");
+
+        assert_eq!(
+            KeyBindings::config_path(Some(cli), Some(env.clone())),
+            Some((cli.to_path_buf(), true))
+        );
+        assert_eq!(
+            KeyBindings::config_path(None, Some(env)),
+            Some((PathBuf::from("/env/config.json"), true))
+        );
+        assert!(matches!(
+            KeyBindings::config_path(None, None),
+            None | Some((_, false))
+        ));
+    }
+}
@@ -1,17 +1,75 @@
 use crate::{
     error::backtraced_err,
     terminal_emulator::{
-        ControlAction, LoadRecordingError, LoadSnapshotError, PtyIo, Recording, RecordingHandle,
-        ReplayControl, ReplayIo, TerminalEmulator,
+        LoadRecordingError, LoadSnapshotError, PtyIo, Recording, RecordingHandle, ReplayControl,
+        ReplayIo, TerminalEmulator, Thumbnail,
     },
 };
 use eframe::egui::{self, CentralPanel};
 use terminal::TerminalWidget;
+pub use theme::ColorTheme;
 use thiserror::Error;
 
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
+mod icon;
+mod keybindings;
 mod terminal;
+mod theme;
+
+/// How many screens are captured for previewing replay seek positions
+const NUM_THUMBNAILS: usize = 50;
+const MIN_PLAYBACK_SPEED: f64 = 0.1;
+const MAX_PLAYBACK_SPEED: f64 = 16.0;
+
+/// How much scrollback "Clear old scrollback" leaves behind
+const KEPT_SCROLLBACK_BYTES: usize = 64 * 1024;
+/// How often an active recording is written to disk, so that a crash does not lose all of it
+const RECORDING_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+/// How long to wait for the window manager to apply a requested window size
+const WINDOW_RESIZE_TIMEOUT: Duration = Duration::from_millis(500);
+const APP_NAME: &str = "Termie";
+
+fn native_options() -> eframe::NativeOptions {
+    eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_icon(icon::termie_icon()),
+        ..Default::default()
+    }
+}
+
+/// Forward title changes from the terminal to the window
+fn sync_window_title(ctx: &egui::Context, shown_title: &mut String, title: &str) {
+    if shown_title == title {
+        return;
+    }
+
+    *shown_title = title.to_string();
+    let title = if title.is_empty() { APP_NAME } else { title };
+    ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.to_string()));
+}
+
+/// Overlay shown over the terminal once the shell is gone
+fn show_shell_exited(ctx: &egui::Context, exit_code: Option<i32>) {
+    let message = match exit_code {
+        Some(code) if code != 0 => {
+            format!("Shell exited with code {code} \u{2014} press Enter to close")
+        }
+        _ => "Shell exited \u{2014} press Enter to close".to_string(),
+    };
+
+    egui::Area::new(egui::Id::new("shell_exited"))
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.label(message);
+            });
+        });
+}
 
 fn set_egui_options(ctx: &egui::Context) {
     ctx.options_mut(|options| {
@@ -48,85 +106,274 @@ fn load_replay(path: &Path) -> Result<LoadReplayResponse, LoadReplayError> {
 struct ReplayTermieGui {
     terminal_emulator: TerminalEmulator<ReplayIo>,
     terminal_widget: TerminalWidget,
-    replay_path: PathBuf,
     replay_control: ReplayControl,
     slider_pos: usize,
+    /// Show and seek progress in recording items instead of bytes
+    progress_by_item: bool,
+    /// Title currently set on the window, empty for the default
+    window_title: String,
+    thumbnails: Vec<Thumbnail>,
+    thumbnail_thread: Option<JoinHandle<Vec<Thumbnail>>>,
+    playback_speed: f64,
 }
 
 impl ReplayTermieGui {
     fn new(
         cc: &eframe::CreationContext<'_>,
-        replay_path: PathBuf,
         terminal_emulator: TerminalEmulator<ReplayIo>,
         replay_control: ReplayControl,
+        config_path: Option<&Path>,
+        theme: ColorTheme,
+        font_size: Option<f32>,
     ) -> Self {
         set_egui_options(&cc.egui_ctx);
 
+        let mut terminal_widget = TerminalWidget::new(&cc.egui_ctx, config_path);
+        terminal_widget.set_theme(theme);
+        if let Some(font_size) = font_size {
+            terminal_widget.set_font_size(font_size);
+        }
+
+        let thumbnail_thread = {
+            let recording = replay_control.recording().clone();
+            let ctx = cc.egui_ctx.clone();
+            std::thread::spawn(move || {
+                let thumbnails = ReplayControl::new(recording)
+                    .generate_thumbnails(NUM_THUMBNAILS)
+                    .unwrap_or_else(|e| {
+                        error!("failed to generate thumbnails: {}", backtraced_err(&*e));
+                        Vec::new()
+                    });
+                ctx.request_repaint();
+                thumbnails
+            })
+        };
+
         ReplayTermieGui {
             terminal_emulator,
-            terminal_widget: TerminalWidget::new(&cc.egui_ctx),
-            replay_path,
+            terminal_widget,
             replay_control,
             slider_pos: 0,
+            progress_by_item: false,
+            window_title: String::new(),
+            thumbnails: Vec::new(),
+            thumbnail_thread: Some(thumbnail_thread),
+            playback_speed: 1.0,
         }
     }
 
-    fn step_replay(&mut self) {
-        let action = self.replay_control.next();
-        match action {
-            ControlAction::Resize { width, height } => {
-                if let Err(e) = self.terminal_emulator.set_win_size(width, height) {
-                    error!("failed to set window size: {}", backtraced_err(&*e));
-                }
+    fn poll_thumbnails(&mut self) {
+        if !self
+            .thumbnail_thread
+            .as_ref()
+            .is_some_and(JoinHandle::is_finished)
+        {
+            return;
+        }
+
+        if let Some(thread) = self.thumbnail_thread.take() {
+            match thread.join() {
+                Ok(thumbnails) => self.thumbnails = thumbnails,
+                Err(_) => error!("thumbnail generation panicked"),
             }
-            ControlAction::None => (),
         }
     }
+
+    /// Thumbnail closest to the seek position at `fraction` of the slider
+    fn nearest_thumbnail(&self, fraction: f32) -> Option<&Thumbnail> {
+        let pos = if self.progress_by_item {
+            let item_idx = (fraction * self.replay_control.item_count() as f32).round() as usize;
+            self.replay_control.item_start_pos(item_idx)
+        } else {
+            (fraction * self.replay_control.len() as f32).round() as usize
+        };
+
+        self.thumbnails
+            .iter()
+            .min_by_key(|thumbnail| thumbnail.pos.abs_diff(pos))
+    }
+
+    /// Step the replay up to the current playback time. Returns how long until it should be
+    /// stepped again, None if not playing
+    fn advance_playback(&mut self) -> Option<Duration> {
+        if !self.replay_control.is_playing() {
+            return None;
+        }
+
+        if let Err(e) = self
+            .replay_control
+            .poll_playback(&mut self.terminal_emulator)
+        {
+            error!("failed to set window size: {}", backtraced_err(&*e));
+        }
+        self.slider_pos = self.replay_control.current_pos();
+        self.replay_control.time_to_next_item()
+    }
+
+    /// Right arrow or n steps forward, left arrow or p steps back. Any key press pauses playback
+    fn handle_replay_keys(&mut self, ctx: &egui::Context) {
+        // Typing in the speed box should not move the replay
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+
+        let (any_pressed, forward, back) = ctx.input(|i| {
+            let any_pressed = i
+                .events
+                .iter()
+                .any(|e| matches!(e, egui::Event::Key { pressed: true, .. }));
+            let forward = i.key_pressed(egui::Key::ArrowRight) || i.key_pressed(egui::Key::N);
+            let back = i.key_pressed(egui::Key::ArrowLeft) || i.key_pressed(egui::Key::P);
+            (any_pressed, forward, back)
+        });
+
+        if any_pressed {
+            self.replay_control.pause();
+        }
+        if forward {
+            self.step_replay();
+        }
+        if back {
+            self.step_replay_back();
+        }
+    }
+
+    fn seek_replay(&mut self, pos: usize) {
+        if let Err(e) = self
+            .replay_control
+            .seek_to(pos, &mut self.terminal_emulator)
+        {
+            error!("failed to seek replay: {}", backtraced_err(&*e));
+        }
+        self.slider_pos = self.replay_control.current_pos();
+    }
+
+    fn step_replay(&mut self) {
+        self.seek_replay(self.replay_control.current_pos() + 1);
+    }
+
+    fn step_replay_back(&mut self) {
+        if let Err(e) = self.replay_control.step_back(&mut self.terminal_emulator) {
+            error!("failed to seek replay: {}", backtraced_err(&*e));
+        }
+        self.slider_pos = self.replay_control.current_pos();
+    }
 }
 
 impl eframe::App for ReplayTermieGui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        let current_pos = self.replay_control.current_pos();
-        if current_pos > self.slider_pos {
-            match load_replay(&self.replay_path) {
-                Ok(response) => {
-                    self.terminal_emulator = response.terminal_emulator;
-                    self.replay_control = response.replay_control;
-                }
-                Err(e) => {
-                    error!("failed to reload replay: {}", backtraced_err(&e));
-                }
-            }
+        self.handle_replay_keys(ctx);
+
+        if let Some(wait) = self.advance_playback() {
+            ctx.request_repaint_after(wait);
         }
 
-        let current_pos = self.replay_control.current_pos();
-        if current_pos < self.slider_pos {
-            for _ in 0..self.slider_pos - current_pos {
-                self.step_replay();
-            }
+        if self.replay_control.current_pos() != self.slider_pos {
+            self.seek_replay(self.slider_pos);
         }
 
+        self.poll_thumbnails();
+
         egui::TopBottomPanel::top("header").show(ctx, |ui| {
-            if ui.button("next").clicked() {
-                self.step_replay();
-                self.slider_pos += 1;
-            }
+            ui.horizontal(|ui| {
+                if ui.button("Prev").clicked() {
+                    self.step_replay_back();
+                }
+                if ui.button("Next").clicked() {
+                    self.step_replay();
+                }
+
+                let playing = self.replay_control.is_playing();
+                if ui.button(if playing { "Pause" } else { "Play" }).clicked() {
+                    if playing {
+                        self.replay_control.pause();
+                    } else {
+                        self.replay_control.play_at_speed(self.playback_speed);
+                        ctx.request_repaint();
+                    }
+                }
+
+                ui.label("Speed");
+                let speed = egui::DragValue::new(&mut self.playback_speed)
+                    .clamp_range(MIN_PLAYBACK_SPEED..=MAX_PLAYBACK_SPEED)
+                    .speed(0.1)
+                    .suffix("x");
+                if ui.add(speed).changed() {
+                    self.replay_control.set_playback_speed(self.playback_speed);
+                }
+            });
         });
 
         egui::TopBottomPanel::bottom("seek").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.progress_by_item, false, "By byte");
+                ui.selectable_value(&mut self.progress_by_item, true, "By item");
+                if self.progress_by_item {
+                    ui.label(format!(
+                        "item {} / {}",
+                        self.replay_control.current_item_index(),
+                        self.replay_control.item_count()
+                    ));
+                } else {
+                    ui.label(format!(
+                        "byte {} / {}",
+                        self.replay_control.current_pos(),
+                        self.replay_control.len()
+                    ));
+                }
+            });
+
             ui.style_mut().spacing.slider_width = ui.available_width();
-            let slider = egui::Slider::new(&mut self.slider_pos, 0..=self.replay_control.len() - 1)
-                .show_value(false)
-                .clamp_to_range(true);
-            ui.add(slider);
+            let response = if self.progress_by_item {
+                let mut item_idx = self.replay_control.current_item_index();
+                let slider = egui::Slider::new(&mut item_idx, 0..=self.replay_control.item_count())
+                    .show_value(false)
+                    .clamp_to_range(true);
+                let response = ui.add(slider);
+                if response.changed() {
+                    self.slider_pos = self.replay_control.item_start_pos(item_idx);
+                }
+                response
+            } else {
+                let slider = egui::Slider::new(&mut self.slider_pos, 0..=self.replay_control.len())
+                    .show_value(false)
+                    .clamp_to_range(true);
+                ui.add(slider)
+            };
+
+            // Seeking by hand stops playback
+            if response.changed() {
+                self.replay_control.pause();
+            }
+
+            let hovered_thumbnail = response.hover_pos().and_then(|pos| {
+                let rect = response.rect;
+                let fraction = ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0);
+                self.nearest_thumbnail(fraction)
+            });
+            if let Some(thumbnail) = hovered_thumbnail {
+                response.on_hover_ui_at_pointer(|ui| {
+                    ui.label(
+                        egui::RichText::new(thumbnail.screen.join("\n"))
+                            .monospace()
+                            .small(),
+                    );
+                });
+            }
         });
 
         let panel_response = CentralPanel::default().show(ctx, |ui| {
             self.terminal_widget.show(ui, &mut self.terminal_emulator);
         });
+        sync_window_title(
+            ctx,
+            &mut self.window_title,
+            self.terminal_emulator.window_title(),
+        );
 
         panel_response.response.context_menu(|ui| {
-            self.terminal_widget.show_options(ui);
+            self.terminal_widget
+                .show_options(ui, &mut self.terminal_emulator);
         });
     }
 }
@@ -135,37 +382,120 @@ struct TermieGui {
     terminal_emulator: TerminalEmulator<PtyIo>,
     terminal_widget: TerminalWidget,
     recording_handle: Option<RecordingHandle>,
+    /// Exit code of the shell, set once it exits
+    exit_code: Arc<Mutex<Option<i32>>>,
+    /// Size last sent to the terminal emulator, in characters
+    last_win_size: (usize, usize),
+    last_recording_flush: Instant,
+    /// Title currently set on the window, empty for the default
+    window_title: String,
+    /// Terminal size to fit the window to on the next frame, in characters
+    pending_window_size: Option<(usize, usize)>,
+    /// Terminal size the window was asked to fit and when to stop waiting for it
+    requested_window_size: Option<((usize, usize), Instant)>,
 }
 
 impl TermieGui {
-    fn new(cc: &eframe::CreationContext<'_>, terminal_emulator: TerminalEmulator<PtyIo>) -> Self {
+    fn new(
+        cc: &eframe::CreationContext<'_>,
+        mut terminal_emulator: TerminalEmulator<PtyIo>,
+        config_path: Option<&Path>,
+        theme: ColorTheme,
+        geometry: Option<(usize, usize)>,
+        font_size: Option<f32>,
+    ) -> Self {
         set_egui_options(&cc.egui_ctx);
 
+        let mut terminal_widget = TerminalWidget::new(&cc.egui_ctx, config_path);
+        terminal_widget.set_theme(theme);
+        if let Some(font_size) = font_size {
+            terminal_widget.set_font_size(font_size);
+        }
+
+        let exit_code = Arc::new(Mutex::new(None));
+        {
+            let exit_code = Arc::clone(&exit_code);
+            let ctx = cc.egui_ctx.clone();
+            terminal_emulator.set_exit_callback(move |code| {
+                *exit_code.lock().expect("exit code lock poisoned") = Some(code);
+                ctx.request_repaint();
+            });
+        }
+
         TermieGui {
+            last_win_size: terminal_emulator.get_win_size(),
+            last_recording_flush: Instant::now(),
             terminal_emulator,
-            terminal_widget: TerminalWidget::new(&cc.egui_ctx),
+            terminal_widget,
             recording_handle: None,
+            exit_code,
+            window_title: String::new(),
+            pending_window_size: geometry,
+            requested_window_size: None,
         }
     }
 }
 
 impl eframe::App for TermieGui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Fonts are not loaded before the first frame, so the size of a character is not known
+        // when the window is created
+        if let Some(win_size) = self.pending_window_size.take() {
+            let window_size = self.terminal_widget.window_size_for(ctx, win_size);
+            ctx.send_viewport_cmd(egui::ViewportCommand::InnerSize(window_size));
+            self.requested_window_size = Some((win_size, Instant::now() + WINDOW_RESIZE_TIMEOUT));
+        }
+
         let panel_response = CentralPanel::default().show(ctx, |ui| {
-            let (width_chars, height_chars) = self.terminal_widget.calculate_available_size(ui);
+            let win_size = self.terminal_widget.calculate_available_size(ui);
+
+            // Resizing the window is asynchronous, the panel can have the old window size for a
+            // few frames. Keep the requested terminal size until the resize lands or the window
+            // manager ignores it
+            let resizing_window = match self.requested_window_size {
+                Some((requested, deadline)) if win_size != requested => {
+                    let now = Instant::now();
+                    if now < deadline {
+                        ui.ctx().request_repaint_after(deadline - now);
+                        true
+                    } else {
+                        false
+                    }
+                }
+                _ => false,
+            };
+            if !resizing_window {
+                self.requested_window_size = None;
+            }
 
-            if let Err(e) = self
-                .terminal_emulator
-                .set_win_size(width_chars, height_chars)
-            {
-                error!("failed to set window size {}", backtraced_err(&*e));
+            if win_size != self.last_win_size && !resizing_window {
+                let (width_chars, height_chars) = win_size;
+                match self
+                    .terminal_emulator
+                    .set_win_size(width_chars, height_chars)
+                {
+                    Ok(()) => self.last_win_size = win_size,
+                    Err(e) => error!("failed to set window size {}", backtraced_err(&*e)),
+                }
             }
 
             self.terminal_widget.show(ui, &mut self.terminal_emulator);
         });
+        sync_window_title(
+            ctx,
+            &mut self.window_title,
+            self.terminal_emulator.window_title(),
+        );
 
         panel_response.response.context_menu(|ui| {
-            self.terminal_widget.show_options(ui);
+            self.terminal_widget
+                .show_options(ui, &mut self.terminal_emulator);
+
+            if ui.button("Clear old scrollback").clicked() {
+                self.terminal_emulator
+                    .trim_scrollback(KEPT_SCROLLBACK_BYTES);
+                ui.close_menu();
+            }
 
             if self.recording_handle.is_some() {
                 if ui.button("Stop recording").clicked() {
@@ -175,6 +505,7 @@ impl eframe::App for TermieGui {
                 match self.terminal_emulator.start_recording() {
                     Ok(v) => {
                         self.recording_handle = Some(v);
+                        self.last_recording_flush = Instant::now();
                     }
                     Err(e) => {
                         error!("failed to start recording: {}", backtraced_err(&e));
@@ -182,11 +513,35 @@ impl eframe::App for TermieGui {
                 }
             }
         });
+
+        if self.recording_handle.is_some() {
+            if self.last_recording_flush.elapsed() >= RECORDING_FLUSH_INTERVAL {
+                if let Err(e) = self.terminal_emulator.flush_recording() {
+                    error!("failed to flush recording: {}", backtraced_err(&e));
+                }
+                self.last_recording_flush = Instant::now();
+            }
+            // Nothing else may trigger a repaint while the terminal is idle
+            ctx.request_repaint_after(RECORDING_FLUSH_INTERVAL);
+        }
+
+        if self.terminal_emulator.is_done() {
+            let exit_code = *self.exit_code.lock().expect("exit code lock poisoned");
+            show_shell_exited(ctx, exit_code);
+            if ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
     }
 }
 
-pub fn run_replay(replay_path: PathBuf) -> Result<(), Box<dyn std::error::Error>> {
-    let native_options = eframe::NativeOptions::default();
+pub fn run_replay(
+    replay_path: PathBuf,
+    config_path: Option<PathBuf>,
+    theme: ColorTheme,
+    font_size: Option<f32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let native_options = native_options();
 
     let LoadReplayResponse {
         terminal_emulator,
@@ -194,14 +549,16 @@ pub fn run_replay(replay_path: PathBuf) -> Result<(), Box<dyn std::error::Error>
     } = load_replay(&replay_path)?;
 
     eframe::run_native(
-        "Termie",
+        APP_NAME,
         native_options,
         Box::new(move |cc| {
             Box::new(ReplayTermieGui::new(
                 cc,
-                replay_path,
                 terminal_emulator,
                 replay_control,
+                config_path.as_deref(),
+                theme,
+                font_size,
             ))
         }),
     )?;
@@ -209,12 +566,158 @@ pub fn run_replay(replay_path: PathBuf) -> Result<(), Box<dyn std::error::Error>
     Ok(())
 }
 
-pub fn run(terminal_emulator: TerminalEmulator<PtyIo>) -> Result<(), Box<dyn std::error::Error>> {
-    let native_options = eframe::NativeOptions::default();
+/// Play a recording to the end without a window and write the final terminal state as html
+pub fn export_replay_html(
+    replay_path: &Path,
+    output_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let LoadReplayResponse {
+        mut terminal_emulator,
+        mut replay_control,
+    } = load_replay(replay_path)?;
+
+    replay_control.seek_to(replay_control.len(), &mut terminal_emulator)?;
+    terminal_emulator.read();
+
+    std::fs::write(output_path, terminal_emulator.export_to_html())?;
+    Ok(())
+}
+
+pub fn run(
+    terminal_emulator: TerminalEmulator<PtyIo>,
+    config_path: Option<PathBuf>,
+    theme: ColorTheme,
+    geometry: Option<(usize, usize)>,
+    font_size: Option<f32>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let native_options = native_options();
     eframe::run_native(
-        "Termie",
+        APP_NAME,
         native_options,
-        Box::new(move |cc| Box::new(TermieGui::new(cc, terminal_emulator))),
+        Box::new(move |cc| {
+            Box::new(TermieGui::new(
+                cc,
+                terminal_emulator,
+                config_path.as_deref(),
+                theme,
+                geometry,
+                font_size,
+            ))
+        }),
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn load_test_gui(ctx: &egui::Context) -> ReplayTermieGui {
+        let replay_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("res/test/hello.cast");
+        let LoadReplayResponse {
+            terminal_emulator,
+            replay_control,
+        } = load_replay(&replay_path).expect("failed to load replay");
+        ReplayTermieGui {
+            terminal_emulator,
+            terminal_widget: TerminalWidget::new(ctx, None),
+            replay_control,
+            slider_pos: 0,
+            progress_by_item: false,
+            window_title: String::new(),
+            thumbnails: Vec::new(),
+            thumbnail_thread: None,
+            playback_speed: MAX_PLAYBACK_SPEED,
+        }
+    }
+
+    #[test]
+    fn test_replay_step_back() {
+        let ctx = egui::Context::default();
+        let mut gui = load_test_gui(&ctx);
+
+        // Initial resize, then "$ "
+        for _ in 0..3 {
+            gui.step_replay();
+        }
+        assert_eq!(gui.replay_control.current_pos(), 3);
+        gui.terminal_emulator.read();
+        assert_eq!(gui.terminal_emulator.dump_screen()[0], "$ ");
+
+        gui.step_replay_back();
+        assert_eq!(gui.replay_control.current_pos(), 2);
+        assert_eq!(gui.slider_pos, 2);
+        gui.terminal_emulator.read();
+        assert_eq!(gui.terminal_emulator.dump_screen()[0], "$");
+
+        // Seeking forward again continues from the rewound emulator
+        gui.step_replay();
+        gui.terminal_emulator.read();
+        assert_eq!(gui.terminal_emulator.dump_screen()[0], "$ ");
+    }
+
+    fn press_key(ctx: &egui::Context, gui: &mut ReplayTermieGui, key: egui::Key) {
+        let input = egui::RawInput {
+            events: vec![egui::Event::Key {
+                key,
+                physical_key: None,
+                pressed: true,
+                repeat: false,
+                modifiers: Default::default(),
+            }],
+            ..Default::default()
+        };
+        let _ = ctx.run(input, |ctx| gui.handle_replay_keys(ctx));
+    }
+
+    #[test]
+    fn test_replay_keys() {
+        let ctx = egui::Context::default();
+        let mut gui = load_test_gui(&ctx);
+
+        press_key(&ctx, &mut gui, egui::Key::ArrowRight);
+        press_key(&ctx, &mut gui, egui::Key::N);
+        press_key(&ctx, &mut gui, egui::Key::N);
+        assert_eq!(gui.replay_control.current_pos(), 3);
+        assert_eq!(gui.slider_pos, 3);
+
+        press_key(&ctx, &mut gui, egui::Key::ArrowLeft);
+        assert_eq!(gui.replay_control.current_pos(), 2);
+        press_key(&ctx, &mut gui, egui::Key::P);
+        assert_eq!(gui.replay_control.current_pos(), 1);
+        assert_eq!(gui.slider_pos, 1);
+
+        // Other keys do not move the replay, but stop playback
+        gui.replay_control.play_at_speed(gui.playback_speed);
+        press_key(&ctx, &mut gui, egui::Key::Space);
+        assert!(!gui.replay_control.is_playing());
+        assert_eq!(gui.replay_control.current_pos(), 1);
+    }
+
+    #[test]
+    fn test_replay_playback() {
+        let ctx = egui::Context::default();
+        let mut gui = load_test_gui(&ctx);
+
+        assert!(gui.advance_playback().is_none());
+        assert_eq!(gui.replay_control.current_pos(), 0);
+
+        gui.replay_control.play_at_speed(gui.playback_speed);
+        let mut last_pos = 0;
+        let wait = gui.advance_playback().expect("playback should be running");
+        assert!(gui.replay_control.current_pos() > 0);
+        std::thread::sleep(wait);
+        while let Some(wait) = gui.advance_playback() {
+            let pos = gui.replay_control.current_pos();
+            assert!(pos >= last_pos);
+            assert_eq!(gui.slider_pos, pos);
+            last_pos = pos;
+            std::thread::sleep(wait);
+        }
+
+        assert!(!gui.replay_control.is_playing());
+        assert_eq!(gui.replay_control.current_pos(), gui.replay_control.len());
+        assert_eq!(gui.slider_pos, gui.replay_control.len());
+        assert_eq!(gui.terminal_emulator.get_win_size(), (30, 6));
+    }
+}
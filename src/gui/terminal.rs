@@ -1,23 +1,111 @@
+use super::keybindings::{KeyBindings, KeyCombo};
+use super::theme::{self, ColorTheme};
 use crate::error::backtraced_err;
 use crate::terminal_emulator::{
-    CursorPos, FormatTag, TermIo, TerminalColor, TerminalEmulator, TerminalInput,
+    CursorPos, CursorStyle, FormatTag, TermIo, TerminalColor, TerminalData, TerminalEmulator,
+    TerminalInput,
 };
 use eframe::egui::{
     self, text::LayoutJob, Color32, Context, DragValue, Event, FontData, FontDefinitions,
-    FontFamily, FontId, InputState, Key, Modifiers, Rect, TextFormat, TextStyle, Ui,
+    FontFamily, FontId, Galley, InputState, Key, Modifiers, PointerButton, Pos2, Rect, TextFormat,
+    TextStyle, Ui,
 };
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    ops::Range,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 const REGULAR_FONT_NAME: &str = "hack";
 const BOLD_FONT_NAME: &str = "hack-bold";
+const ITALIC_FONT_NAME: &str = "hack-italic";
+const BOLD_ITALIC_FONT_NAME: &str = "hack-bold-italic";
+const MIN_FONT_SIZE: f32 = 4.0;
+const MAX_FONT_SIZE: f32 = 72.0;
+/// How much ctrl+scroll changes the font size by default
+const DEFAULT_FONT_ZOOM_STEP: f32 = 1.0;
+/// How much SGR 2 (dim) text is darkened
+const DIM_TEXT_MULTIPLIER: f32 = 0.6;
+const BELL_FLASH_DURATION: Duration = Duration::from_millis(150);
+/// Shown in place of spaces when invisible characters are displayed
+const INVISIBLE_SPACE: &str = "\u{b7}";
+/// How much the color of a displayed space is dimmed
+const INVISIBLE_SPACE_DIM: f32 = 0.4;
+/// Thickness of underline and bar cursors, as a fraction of the character size
+const THIN_CURSOR_FRACTION: f32 = 0.15;
+/// Opacity of the highlight drawn over selected text
+const SELECTION_OPACITY: f32 = 0.35;
+/// Width of the scroll position indicator on the right edge of the terminal
+const SCROLL_INDICATOR_WIDTH: f32 = 3.0;
+/// Background of text matching the find bar query
+const SEARCH_MATCH_COLOR: Color32 = Color32::from_rgb(110, 90, 0);
+/// Background of the match the find bar navigated to
+const CURRENT_SEARCH_MATCH_COLOR: Color32 = Color32::from_rgb(200, 110, 0);
+
+fn function_key_number(key: Key) -> Option<u8> {
+    let n = match key {
+        Key::F1 => 1,
+        Key::F2 => 2,
+        Key::F3 => 3,
+        Key::F4 => 4,
+        Key::F5 => 5,
+        Key::F6 => 6,
+        Key::F7 => 7,
+        Key::F8 => 8,
+        Key::F9 => 9,
+        Key::F10 => 10,
+        Key::F11 => 11,
+        Key::F12 => 12,
+        _ => return None,
+    };
+    Some(n)
+}
 
 fn write_input_to_terminal<Io: TermIo>(
     input: &InputState,
     terminal_emulator: &mut TerminalEmulator<Io>,
+    font_size: &mut f32,
+    key_bindings: &KeyBindings,
+    find_requested: &mut bool,
+    input_written: &mut bool,
 ) {
     for event in &input.raw.events {
+        if let Event::Key {
+            key,
+            pressed: true,
+            modifiers,
+            ..
+        } = event
+        {
+            let bound = |combos: &[KeyCombo]| combos.iter().any(|c| c.matches(*key, modifiers));
+
+            if bound(&key_bindings.font_increase) {
+                *font_size = (*font_size + 1.0).min(MAX_FONT_SIZE);
+                continue;
+            }
+
+            if bound(&key_bindings.font_decrease) {
+                *font_size = (*font_size - 1.0).max(MIN_FONT_SIZE);
+                continue;
+            }
+
+            if bound(&key_bindings.find) {
+                *find_requested = true;
+                continue;
+            }
+        }
+
         let inputs: Cow<'static, [TerminalInput]> = match event {
+            // Text events do not carry modifiers, but alt+key arrives in the same frame as the key
+            // press
+            Event::Text(text) if input.modifiers.alt => text
+                .as_bytes()
+                .iter()
+                .map(|c| TerminalInput::AltAscii(*c))
+                .collect::<Vec<_>>()
+                .into(),
             Event::Text(text) => text
                 .as_bytes()
                 .iter()
@@ -29,8 +117,11 @@ fn write_input_to_terminal<Io: TermIo>(
                 pressed: true,
                 ..
             } => [TerminalInput::Enter].as_ref().into(),
+            Event::Paste(text) => vec![TerminalInput::PasteBuffer(text.as_bytes().to_vec())].into(),
             // https://github.com/emilk/egui/issues/3653
-            Event::Copy => {
+            // egui-winit only turns ctrl+c, ctrl+x and ctrl+v into clipboard events, other ctrl
+            // combinations such as ctrl+d (EOF) arrive as key events below
+            Event::Copy if !copies_selection(&input.modifiers) => {
                 // NOTE: Technically not correct if we were on a mac, but also we are using linux
                 // syscalls so we'd have to solve that before this is a problem
                 [TerminalInput::Ctrl(b'c')].as_ref().into()
@@ -42,6 +133,7 @@ fn write_input_to_terminal<Io: TermIo>(
                 ..
             } => {
                 if *key >= Key::A && *key <= Key::Z {
+                    // Key names are upper case, e.g. ctrl+z -> Ctrl(b'Z') -> 0x1a (SIGTSTP)
                     let name = key.name();
                     assert!(name.len() == 1);
                     let name_c = name.as_bytes()[0];
@@ -57,11 +149,33 @@ fn write_input_to_terminal<Io: TermIo>(
                     continue;
                 }
             }
+            Event::Key {
+                key: Key::Backspace,
+                pressed: true,
+                modifiers: Modifiers { alt: true, .. },
+                ..
+            } => [TerminalInput::AltAscii(0x7f)].as_ref().into(),
             Event::Key {
                 key: Key::Backspace,
                 pressed: true,
                 ..
             } => [TerminalInput::Backspace].as_ref().into(),
+            Event::Key {
+                key: Key::Tab,
+                pressed: true,
+                modifiers: Modifiers { shift: true, .. },
+                ..
+            } => [TerminalInput::ShiftTab].as_ref().into(),
+            Event::Key {
+                key: Key::Tab,
+                pressed: true,
+                ..
+            } => [TerminalInput::Tab].as_ref().into(),
+            Event::Key {
+                key: Key::Escape,
+                pressed: true,
+                ..
+            } => [TerminalInput::Escape].as_ref().into(),
             Event::Key {
                 key: Key::ArrowUp,
                 pressed: true,
@@ -112,12 +226,19 @@ fn write_input_to_terminal<Io: TermIo>(
                 pressed: true,
                 ..
             } => [TerminalInput::PageDown].as_ref().into(),
+            Event::Key {
+                key, pressed: true, ..
+            } => match function_key_number(*key) {
+                Some(n) => vec![TerminalInput::F(n)].into(),
+                None => continue,
+            },
             _ => {
                 continue;
             }
         };
 
         for input in inputs.as_ref() {
+            *input_written = true;
             if let Err(e) = terminal_emulator.write(input.clone()) {
                 error!(
                     "Failed to write input to terminal emulator: {}",
@@ -128,13 +249,43 @@ fn write_input_to_terminal<Io: TermIo>(
     }
 }
 
+fn is_zoom_wheel(modifiers: &Modifiers) -> bool {
+    // Same check egui-winit uses to turn the wheel into a zoom
+    modifiers.ctrl || modifiers.command
+}
+
+/// Change the font size on ctrl+wheel by step per event, and by the zoom factor of pinch
+/// gestures. egui-winit reports ctrl+wheel as a zoom as well, that zoom is ignored in favor of the
+/// step
+fn zoom_font(input: &InputState, font_size: &mut f32, step: f32) {
+    let wheel_zoomed = input.raw.events.iter().any(
+        |event| matches!(event, Event::MouseWheel { modifiers, .. } if is_zoom_wheel(modifiers)),
+    );
+
+    for event in &input.raw.events {
+        match event {
+            Event::MouseWheel {
+                delta, modifiers, ..
+            } if is_zoom_wheel(modifiers) && delta.y != 0.0 => {
+                *font_size += step.copysign(delta.y);
+            }
+            Event::Zoom(factor) if !wheel_zoomed => {
+                *font_size *= factor;
+            }
+            _ => continue,
+        }
+        *font_size = font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+    }
+}
+
 fn get_char_size(ctx: &egui::Context, font_size: f32) -> (f32, f32) {
     let font_id = FontId {
         size: font_size,
         family: FontFamily::Name(REGULAR_FONT_NAME.into()),
     };
 
-    // NOTE: Using glyph width and row height do not give accurate results. Even using the mesh
+    // NOTE: Using glyph width and row height do not give accurate results, laid out glyphs are
+    // snapped to pixels and glyph_width is not (see test_char_size_metrics). Even using the mesh
     // bounds of a single character is not reasonable. Instead we layout 16 rows and 16 cols and
     // divide by 16. This seems to work better at all font scales
     ctx.fonts(move |fonts| {
@@ -170,10 +321,120 @@ fn get_char_size(ctx: &egui::Context, font_size: f32) -> (f32, f32) {
     })
 }
 
+/// Ctrl+c goes to the terminal, ctrl+shift+c or a dedicated copy key copy the selection
+fn copies_selection(modifiers: &Modifiers) -> bool {
+    modifiers.shift || !modifiers.ctrl
+}
+
+/// Terminal cell under pos, clamped to the grid. grid_rect is the screen area of the top left
+/// cell of the visible area
+fn pointer_to_cursor_pos(
+    pos: Pos2,
+    grid_rect: Rect,
+    character_size: &(f32, f32),
+    win_size: (usize, usize),
+) -> CursorPos {
+    let offset = pos - grid_rect.min;
+    let x = (offset.x / character_size.0).floor().max(0.0) as usize;
+    let y = (offset.y / character_size.1).floor().max(0.0) as usize;
+    CursorPos {
+        x: x.min(win_size.0.saturating_sub(1)),
+        y: y.min(win_size.1.saturating_sub(1)),
+    }
+}
+
+/// Start a selection when the primary button is pressed on the terminal, and move its end while
+/// the button is held
+fn update_selection(
+    selection: &mut Option<(CursorPos, CursorPos)>,
+    selecting: &mut bool,
+    input: &InputState,
+    grid_rect: Rect,
+    character_size: &(f32, f32),
+    win_size: (usize, usize),
+) {
+    let to_cursor_pos = |pos| pointer_to_cursor_pos(pos, grid_rect, character_size, win_size);
+    for event in &input.raw.events {
+        match event {
+            Event::PointerButton {
+                pos,
+                button: PointerButton::Primary,
+                pressed: true,
+                ..
+            } => {
+                *selecting = grid_rect.contains(*pos);
+                *selection = selecting.then(|| {
+                    let pos = to_cursor_pos(*pos);
+                    (pos.clone(), pos)
+                });
+            }
+            Event::PointerButton {
+                button: PointerButton::Primary,
+                pressed: false,
+                ..
+            } => {
+                *selecting = false;
+                // A click without dragging selects nothing
+                if matches!(selection, Some((start, end)) if start == end) {
+                    *selection = None;
+                }
+            }
+            Event::PointerMoved(pos) if *selecting => {
+                if let Some((_, end)) = selection {
+                    *end = to_cursor_pos(*pos);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+fn paint_selection(
+    grid_rect: Rect,
+    character_size: &(f32, f32),
+    selection: &(CursorPos, CursorPos),
+    width: usize,
+    ui: &mut Ui,
+) {
+    let (start, end) = &selection;
+    let (start, end) = if (start.y, start.x) <= (end.y, end.x) {
+        (start, end)
+    } else {
+        (end, start)
+    };
+
+    let color = ui
+        .visuals()
+        .selection
+        .bg_fill
+        .gamma_multiply(SELECTION_OPACITY);
+    for y in start.y..=end.y {
+        let first = if y == start.y { start.x } else { 0 };
+        let last = if y == end.y {
+            end.x
+        } else {
+            width.saturating_sub(1)
+        };
+        let rect = Rect::from_min_max(
+            egui::pos2(
+                grid_rect.left() + first as f32 * character_size.0,
+                grid_rect.top() + y as f32 * character_size.1,
+            ),
+            egui::pos2(
+                grid_rect.left() + (last + 1) as f32 * character_size.0,
+                grid_rect.top() + (y + 1) as f32 * character_size.1,
+            ),
+        );
+        ui.painter().rect_filled(rect, 0.0, color);
+    }
+}
+
 fn paint_cursor(
     label_rect: Rect,
     character_size: &(f32, f32),
     cursor_pos: &CursorPos,
+    cursor_width: usize,
+    cursor_style: CursorStyle,
     ui: &mut Ui,
 ) {
     let painter = ui.painter();
@@ -182,14 +443,25 @@ fn paint_cursor(
     let left = label_rect.left();
     let y_offset = cursor_pos.y as f32 * character_size.1;
     let x_offset = cursor_pos.x as f32 * character_size.0;
-    painter.rect_filled(
-        Rect::from_min_size(
-            egui::pos2(left + x_offset, top + y_offset),
-            egui::vec2(character_size.0, character_size.1),
-        ),
-        0.0,
-        Color32::GRAY,
+    let cell = Rect::from_min_size(
+        egui::pos2(left + x_offset, top + y_offset),
+        egui::vec2(character_size.0 * cursor_width as f32, character_size.1),
     );
+
+    // The cursor does not blink, blinking styles are drawn like their steady counterparts
+    let rect = match cursor_style {
+        CursorStyle::BlinkingBlock | CursorStyle::SteadyBlock => cell,
+        CursorStyle::BlinkingUnderline | CursorStyle::SteadyUnderline => {
+            let thickness = (cell.height() * THIN_CURSOR_FRACTION).max(1.0);
+            Rect::from_min_max(egui::pos2(cell.left(), cell.bottom() - thickness), cell.max)
+        }
+        CursorStyle::BlinkingBar | CursorStyle::SteadyBar => {
+            let thickness = (character_size.0 * THIN_CURSOR_FRACTION).max(1.0);
+            Rect::from_min_max(cell.min, egui::pos2(cell.left() + thickness, cell.bottom()))
+        }
+    };
+
+    painter.rect_filled(rect, 0.0, Color32::GRAY);
 }
 
 fn setup_fonts(ctx: &egui::Context) {
@@ -205,6 +477,16 @@ fn setup_fonts(ctx: &egui::Context) {
         FontData::from_static(include_bytes!("../../res/Hack-Bold.ttf")),
     );
 
+    fonts.font_data.insert(
+        ITALIC_FONT_NAME.to_owned(),
+        FontData::from_static(include_bytes!("../../res/Hack-Italic.ttf")),
+    );
+
+    fonts.font_data.insert(
+        BOLD_ITALIC_FONT_NAME.to_owned(),
+        FontData::from_static(include_bytes!("../../res/Hack-BoldItalic.ttf")),
+    );
+
     fonts
         .families
         .get_mut(&FontFamily::Monospace)
@@ -219,6 +501,14 @@ fn setup_fonts(ctx: &egui::Context) {
         FontFamily::Name(BOLD_FONT_NAME.to_string().into()),
         vec![BOLD_FONT_NAME.to_string()],
     );
+    fonts.families.insert(
+        FontFamily::Name(ITALIC_FONT_NAME.to_string().into()),
+        vec![ITALIC_FONT_NAME.to_string()],
+    );
+    fonts.families.insert(
+        FontFamily::Name(BOLD_ITALIC_FONT_NAME.to_string().into()),
+        vec![BOLD_ITALIC_FONT_NAME.to_string()],
+    );
 
     ctx.set_fonts(fonts);
 }
@@ -226,70 +516,264 @@ fn setup_fonts(ctx: &egui::Context) {
 struct TerminalFonts {
     regular: FontFamily,
     bold: FontFamily,
+    italic: FontFamily,
+    bold_italic: FontFamily,
 }
 
 impl TerminalFonts {
     fn new() -> TerminalFonts {
         let bold = FontFamily::Name(BOLD_FONT_NAME.to_string().into());
         let regular = FontFamily::Name(REGULAR_FONT_NAME.to_string().into());
+        let italic = FontFamily::Name(ITALIC_FONT_NAME.to_string().into());
+        let bold_italic = FontFamily::Name(BOLD_ITALIC_FONT_NAME.to_string().into());
 
-        TerminalFonts { regular, bold }
+        TerminalFonts {
+            regular,
+            bold,
+            italic,
+            bold_italic,
+        }
     }
 
-    fn get_family(&self, is_bold: bool) -> FontFamily {
-        if is_bold {
-            self.bold.clone()
-        } else {
-            self.regular.clone()
+    fn get_family(&self, is_bold: bool, is_italic: bool) -> FontFamily {
+        match (is_bold, is_italic) {
+            (false, false) => self.regular.clone(),
+            (true, false) => self.bold.clone(),
+            (false, true) => self.italic.clone(),
+            (true, true) => self.bold_italic.clone(),
         }
     }
 }
 
-fn terminal_color_to_egui(default_color: &Color32, color: &TerminalColor) -> Color32 {
+fn terminal_color_to_egui(
+    theme: &ColorTheme,
+    default_color: &Color32,
+    color: &TerminalColor,
+) -> Color32 {
     match color {
         TerminalColor::Default => *default_color,
-        TerminalColor::Black => Color32::BLACK,
-        TerminalColor::Red => Color32::RED,
-        TerminalColor::Green => Color32::GREEN,
-        TerminalColor::Yellow => Color32::YELLOW,
-        TerminalColor::Blue => Color32::BLUE,
-        TerminalColor::Magenta => Color32::from_rgb(255, 0, 255),
-        TerminalColor::Cyan => Color32::from_rgb(0, 255, 255),
-        TerminalColor::White => Color32::WHITE,
+        TerminalColor::Black => theme.palette[0],
+        TerminalColor::Red => theme.palette[1],
+        TerminalColor::Green => theme.palette[2],
+        TerminalColor::Yellow => theme.palette[3],
+        TerminalColor::Blue => theme.palette[4],
+        TerminalColor::Magenta => theme.palette[5],
+        TerminalColor::Cyan => theme.palette[6],
+        TerminalColor::White => theme.palette[7],
+        TerminalColor::Palette(idx) if usize::from(*idx) < theme.palette.len() => {
+            theme.palette[usize::from(*idx)]
+        }
+        TerminalColor::Palette(idx) => {
+            let (r, g, b) = TerminalColor::palette_rgb(*idx);
+            Color32::from_rgb(r, g, b)
+        }
+        TerminalColor::Rgb(r, g, b) => Color32::from_rgb(*r, *g, *b),
     }
 }
 
 fn create_terminal_output_layout_job(
     style: &egui::Style,
     width: f32,
-    data: &[u8],
-) -> Result<(LayoutJob, TextFormat), std::str::Utf8Error> {
+    text: String,
+) -> (LayoutJob, TextFormat) {
     let text_style = &style.text_styles[&TextStyle::Monospace];
-    let data_utf8 = std::str::from_utf8(data)?;
-    let mut job = egui::text::LayoutJob::simple(
-        data_utf8.to_string(),
-        text_style.clone(),
-        style.visuals.text_color(),
-        width,
-    );
+    let mut job =
+        egui::text::LayoutJob::simple(text, text_style.clone(), style.visuals.text_color(), width);
 
     job.wrap.break_anywhere = true;
     let textformat = job.sections[0].format.clone();
     job.sections.clear();
-    Ok((job, textformat))
+    (job, textformat)
+}
+
+/// Split range into runs of spaces and other text. Returns (range, is_space) pairs
+fn split_spaces(data: &[u8], range: Range<usize>) -> Vec<(Range<usize>, bool)> {
+    let mut ret: Vec<(Range<usize>, bool)> = Vec::new();
+    for pos in range {
+        let is_space = data[pos] == b' ';
+        match ret.last_mut() {
+            Some((last_range, last_is_space)) if *last_is_space == is_space => {
+                last_range.end = pos + 1;
+            }
+            _ => ret.push((pos..pos + 1, is_space)),
+        }
+    }
+    ret
+}
+
+/// Index of the character under pos, where pos is relative to the galley
+fn char_idx_at_pos(galley: &Galley, pos: egui::Vec2) -> Option<usize> {
+    let mut char_idx = 0;
+    for row in &galley.rows {
+        if pos.y >= row.min_y() && pos.y < row.max_y() {
+            return row
+                .glyphs
+                .iter()
+                .position(|g| pos.x >= g.pos.x && pos.x < g.pos.x + g.size.x)
+                .map(|i| char_idx + i);
+        }
+        char_idx += row.char_count_including_newline();
+    }
+    None
+}
+
+/// Split range so that the part overlapping underline_range can be formatted separately.
+/// Returns (range, underlined) pairs
+fn split_for_underline(
+    range: Range<usize>,
+    underline_range: Option<&Range<usize>>,
+) -> Vec<(Range<usize>, bool)> {
+    let Some(underline_range) = underline_range else {
+        return vec![(range, false)];
+    };
+
+    let underline_start = underline_range.start.clamp(range.start, range.end);
+    let underline_end = underline_range.end.clamp(underline_start, range.end);
+
+    [
+        (range.start..underline_start, false),
+        (underline_start..underline_end, true),
+        (underline_end..range.end, false),
+    ]
+    .into_iter()
+    .filter(|(r, _)| !r.is_empty())
+    .collect()
+}
+
+/// Non overlapping ranges of data that equal query, ignoring ASCII case
+fn find_matches(data: &[u8], query: &[u8]) -> Vec<Range<usize>> {
+    let mut ret = Vec::new();
+    if query.is_empty() {
+        return ret;
+    }
+
+    let mut pos = 0;
+    while pos + query.len() <= data.len() {
+        if data[pos..pos + query.len()].eq_ignore_ascii_case(query) {
+            ret.push(pos..pos + query.len());
+            pos += query.len();
+        } else {
+            pos += 1;
+        }
+    }
+    ret
+}
+
+/// Split range at the edges of matches, which have to be sorted and not overlap. Returns (range,
+/// index of the match covering it) pairs
+fn split_for_matches(
+    range: Range<usize>,
+    matches: &[Range<usize>],
+) -> Vec<(Range<usize>, Option<usize>)> {
+    let mut ret = Vec::new();
+    let mut pos = range.start;
+    let first_match = matches.partition_point(|m| m.end <= range.start);
+    for (i, m) in matches.iter().enumerate().skip(first_match) {
+        if m.start >= range.end {
+            break;
+        }
+
+        let start = m.start.max(pos);
+        if start > pos {
+            ret.push((pos..start, None));
+        }
+        let end = m.end.min(range.end);
+        ret.push((start..end, Some(i)));
+        pos = end;
+    }
+
+    if pos < range.end {
+        ret.push((pos..range.end, None));
+    }
+    ret
+}
+
+type DetectedUrl = (usize, usize, String);
+
+struct TerminalDataUiResponse {
+    rect: Rect,
+    /// Absolute buffer range and url of the hovered link
+    hovered_url: Option<(Range<usize>, String)>,
+    clicked: bool,
+}
+
+#[derive(Clone, Copy, Default)]
+struct SearchHighlight<'a> {
+    /// Sorted terminal buffer ranges to highlight
+    matches: &'a [Range<usize>],
+    /// Index into matches that is highlighted differently
+    current: Option<usize>,
+    /// Scroll so that the current match is visible
+    scroll_to_current: bool,
 }
 
+#[derive(Clone, Copy)]
+struct RenderOptions<'a> {
+    font_size: f32,
+    /// Draw spaces as dimmed dots
+    show_invisible: bool,
+    search: SearchHighlight<'a>,
+    theme: &'a ColorTheme,
+    /// Keep showing the end of the output as it grows, as long as it is already shown
+    stick_to_bottom: bool,
+    /// Jump to the end of the output, even if scrolled up
+    scroll_to_bottom: bool,
+}
+
+/// `data_offset` is the position of data in the terminal buffer, `urls` and `hovered_url` are
+/// in terminal buffer positions
 fn add_terminal_data_to_ui(
     ui: &mut Ui,
     data: &[u8],
     format_data: &[FormatTag],
-    font_size: f32,
-) -> Result<egui::Response, std::str::Utf8Error> {
+    options: RenderOptions,
+    data_offset: usize,
+    urls: &[DetectedUrl],
+    hovered_url: Option<&Range<usize>>,
+) -> Result<TerminalDataUiResponse, std::str::Utf8Error> {
+    let data_utf8 = std::str::from_utf8(data)?;
+    let text = if options.show_invisible {
+        data_utf8.replace(' ', INVISIBLE_SPACE)
+    } else {
+        data_utf8.to_string()
+    };
     let (mut job, mut textformat) =
-        create_terminal_output_layout_job(ui.style(), ui.available_width(), data)?;
+        create_terminal_output_layout_job(ui.style(), ui.available_width(), text);
 
-    let default_color = textformat.color;
+    // Replacing spaces makes the text longer, section ranges have to be shifted to match.
+    // space_counts[i] is the number of spaces in data[..i]
+    let space_counts = options.show_invisible.then(|| {
+        std::iter::once(0)
+            .chain(data.iter().scan(0, |count, b| {
+                *count += usize::from(*b == b' ');
+                Some(*count)
+            }))
+            .collect::<Vec<_>>()
+    });
+    let to_text_pos = |pos: usize| match &space_counts {
+        Some(space_counts) => pos + space_counts[pos] * (INVISIBLE_SPACE.len() - 1),
+        None => pos,
+    };
+
+    let theme = options.theme;
     let terminal_fonts = TerminalFonts::new();
+    let hovered_url =
+        hovered_url.map(|r| r.start.saturating_sub(data_offset)..r.end.saturating_sub(data_offset));
+
+    // Search matches that start in data, relative to data
+    let search = options.search;
+    let first_match = search.matches.partition_point(|m| m.start < data_offset);
+    let end_match = search
+        .matches
+        .partition_point(|m| m.start < data_offset + data.len());
+    let matches = search.matches[first_match..end_match]
+        .iter()
+        .map(|m| m.start - data_offset..(m.end - data_offset).min(data.len()))
+        .collect::<Vec<_>>();
+    let current_match = search
+        .current
+        .filter(|i| (first_match..end_match).contains(i))
+        .map(|i| i - first_match);
 
     for tag in format_data {
         let mut range = tag.start..tag.end;
@@ -315,79 +799,266 @@ fn add_terminal_data_to_ui(
             range.end = data.len();
         }
 
-        textformat.font_id.family = terminal_fonts.get_family(tag.bold);
-        textformat.font_id.size = font_size;
-        textformat.color = terminal_color_to_egui(&default_color, &color);
+        textformat.font_id.family = terminal_fonts.get_family(tag.bold, tag.italic);
+        textformat.font_id.size = options.font_size;
+        textformat.color = terminal_color_to_egui(theme, &theme.foreground, &color);
+        if tag.dim {
+            textformat.color = textformat.color.gamma_multiply(DIM_TEXT_MULTIPLIER);
+        }
+        textformat.background =
+            terminal_color_to_egui(theme, &Color32::TRANSPARENT, &tag.background_color);
+        if tag.reverse {
+            // A transparent background would make the text invisible once swapped
+            let background =
+                terminal_color_to_egui(theme, &theme.background, &tag.background_color);
+            textformat.background = textformat.color;
+            textformat.color = background;
+        }
+        textformat.strikethrough = if tag.strikethrough {
+            egui::Stroke::new(1.0, textformat.color)
+        } else {
+            egui::Stroke::NONE
+        };
+
+        for (range, hovered) in split_for_underline(range, hovered_url.as_ref()) {
+            let mut format = textformat.clone();
+            if hovered || tag.underline {
+                format.underline = egui::Stroke::new(1.0, format.color);
+            }
+
+            for (range, match_idx) in split_for_matches(range, &matches) {
+                let mut format = format.clone();
+                if let Some(match_idx) = match_idx {
+                    format.background = if Some(match_idx) == current_match {
+                        CURRENT_SEARCH_MATCH_COLOR
+                    } else {
+                        SEARCH_MATCH_COLOR
+                    };
+                }
 
-        job.sections.push(egui::text::LayoutSection {
-            leading_space: 0.0f32,
-            byte_range: range,
-            format: textformat.clone(),
-        });
+                let runs = if options.show_invisible {
+                    split_spaces(data, range)
+                } else {
+                    vec![(range, false)]
+                };
+
+                for (range, is_space) in runs {
+                    let mut format = format.clone();
+                    if is_space {
+                        format.color = format.color.gamma_multiply(INVISIBLE_SPACE_DIM);
+                    }
+
+                    job.sections.push(egui::text::LayoutSection {
+                        leading_space: 0.0f32,
+                        byte_range: to_text_pos(range.start)..to_text_pos(range.end),
+                        format,
+                    });
+                }
+            }
+        }
+    }
+
+    let galley = ui.fonts(|fonts| fonts.layout_job(job));
+    let response = ui.add(egui::Label::new(galley.clone()).sense(egui::Sense::click()));
+
+    if let (true, Some(current_match)) = (search.scroll_to_current, current_match) {
+        let text_pos = to_text_pos(matches[current_match].start);
+        let char_idx = galley.text()[..text_pos].chars().count();
+        let cursor = galley.from_ccursor(egui::text::CCursor::new(char_idx));
+        let rect = galley
+            .pos_from_cursor(&cursor)
+            .translate(response.rect.min.to_vec2());
+        ui.scroll_to_rect(rect, Some(egui::Align::Center));
     }
 
-    Ok(ui.label(job))
+    let hovered_pos = response
+        .hover_pos()
+        .and_then(|pos| char_idx_at_pos(&galley, pos - response.rect.min))
+        // The displayed text may differ from data, but it has the same characters at the same
+        // indices
+        .and_then(|char_idx| data_utf8.char_indices().nth(char_idx))
+        .map(|(byte_idx, _)| byte_idx);
+
+    // OSC 8 links take priority over urls found in the text
+    let hovered_hyperlink = hovered_pos.and_then(|pos| {
+        format_data.iter().find_map(|tag| {
+            let hyperlink = tag.hyperlink.as_ref()?;
+            let end = tag.end.min(data.len());
+            (tag.start..end).contains(&pos).then(|| {
+                (
+                    tag.start + data_offset..end + data_offset,
+                    hyperlink.clone(),
+                )
+            })
+        })
+    });
+
+    let response = match &hovered_hyperlink {
+        // The link text does not necessarily show the uri, so show it on hover
+        Some((_, hyperlink)) => response.on_hover_text_at_pointer(hyperlink),
+        None => response,
+    };
+
+    let hovered_url = hovered_hyperlink.or_else(|| {
+        let buf_pos = hovered_pos? + data_offset;
+        urls.iter()
+            .find(|(start, end, _)| (*start..*end).contains(&buf_pos))
+            .map(|(start, end, url)| (*start..*end, url.clone()))
+    });
+
+    Ok(TerminalDataUiResponse {
+        rect: response.rect,
+        clicked: hovered_url.is_some() && response.clicked(),
+        hovered_url,
+    })
+}
+
+/// Owned copy of the emulator output, only rebuilt when the emulator reports changes so that
+/// idle frames do not walk the whole buffer
+struct TerminalOutputCache {
+    data: TerminalData<Vec<u8>>,
+    format_data: TerminalData<Vec<FormatTag>>,
+    /// Offset of the visible data in the buffer, before the trailing newline of the scrollback
+    /// is stripped
+    canvas_offset: usize,
+    urls: Vec<(usize, usize, String)>,
+}
+
+impl TerminalOutputCache {
+    fn new<Io: TermIo>(terminal_emulator: &mut TerminalEmulator<Io>) -> TerminalOutputCache {
+        let urls = terminal_emulator.detect_urls();
+        let data = terminal_emulator.data();
+        let mut scrollback_data = data.scrollback;
+        let mut canvas_data = data.visible;
+        let mut format_data = terminal_emulator.format_data();
+        let canvas_offset = scrollback_data.len();
+
+        // Arguably incorrect. Scrollback does end with a newline, and that newline causes a blank
+        // space between widgets. Should we strip it here, or in the terminal emulator output?
+        if scrollback_data.ends_with(b"\n") {
+            scrollback_data = &scrollback_data[0..scrollback_data.len() - 1];
+            if let Some(last_tag) = format_data.scrollback.last_mut() {
+                last_tag.end = last_tag.end.min(scrollback_data.len());
+            }
+        }
+
+        if canvas_data.ends_with(b"\n") {
+            canvas_data = &canvas_data[0..canvas_data.len() - 1];
+        }
+
+        TerminalOutputCache {
+            data: TerminalData {
+                scrollback: scrollback_data.to_vec(),
+                visible: canvas_data.to_vec(),
+            },
+            format_data,
+            canvas_offset,
+            urls,
+        }
+    }
 }
 
 struct TerminalOutputRenderResponse {
     scrollback_area: Rect,
     canvas_area: Rect,
+    hovered_url: Option<(Range<usize>, String)>,
+    clicked_url: Option<String>,
+    /// Screen area the output is scrolled in
+    viewport: Rect,
+    content_height: f32,
+    scroll_offset: f32,
 }
 
-fn render_terminal_output<Io: TermIo>(
+fn render_terminal_output(
     ui: &mut egui::Ui,
-    terminal_emulator: &TerminalEmulator<Io>,
-    font_size: f32,
+    output: &TerminalOutputCache,
+    options: RenderOptions,
+    hovered_url: Option<&Range<usize>>,
 ) -> TerminalOutputRenderResponse {
-    let terminal_data = terminal_emulator.data();
-    let mut scrollback_data = terminal_data.scrollback;
-    let mut canvas_data = terminal_data.visible;
-    let mut format_data = terminal_emulator.format_data();
+    let scrollback_data = &output.data.scrollback;
+    let canvas_data = &output.data.visible;
+    let format_data = &output.format_data;
+    let canvas_offset = output.canvas_offset;
+    let urls = &output.urls;
 
-    // Arguably incorrect. Scrollback does end with a newline, and that newline causes a blank
-    // space between widgets. Should we strip it here, or in the terminal emulator output?
-    if scrollback_data.ends_with(b"\n") {
-        scrollback_data = &scrollback_data[0..scrollback_data.len() - 1];
-        if let Some(last_tag) = format_data.scrollback.last_mut() {
-            last_tag.end = last_tag.end.min(scrollback_data.len());
-        }
+    let mut scroll_area = egui::ScrollArea::new([false, true])
+        .auto_shrink([false, false])
+        .stick_to_bottom(options.stick_to_bottom)
+        // Dragging selects text
+        .drag_to_scroll(false);
+    if options.scroll_to_bottom {
+        // Clamped to the end of the content
+        scroll_area = scroll_area.vertical_scroll_offset(f32::MAX);
     }
 
-    if canvas_data.ends_with(b"\n") {
-        canvas_data = &canvas_data[0..canvas_data.len() - 1];
+    let response = scroll_area.show(ui, |ui| {
+        let error_logged =
+            |response: Result<TerminalDataUiResponse, std::str::Utf8Error>| match response {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("failed to add terminal data to ui: {}", backtraced_err(&e));
+                    TerminalDataUiResponse {
+                        rect: Rect::NOTHING,
+                        hovered_url: None,
+                        clicked: false,
+                    }
+                }
+            };
+        let scrollback_response = error_logged(add_terminal_data_to_ui(
+            ui,
+            scrollback_data,
+            &format_data.scrollback,
+            options,
+            0,
+            urls,
+            hovered_url,
+        ));
+        let canvas_response = error_logged(add_terminal_data_to_ui(
+            ui,
+            canvas_data,
+            &format_data.visible,
+            options,
+            canvas_offset,
+            urls,
+            hovered_url,
+        ));
+        (scrollback_response, canvas_response)
+    });
+
+    let (scrollback_response, canvas_response) = response.inner;
+    let (hovered_url, clicked) = if scrollback_response.hovered_url.is_some() {
+        (scrollback_response.hovered_url, scrollback_response.clicked)
+    } else {
+        (canvas_response.hovered_url, canvas_response.clicked)
+    };
+
+    TerminalOutputRenderResponse {
+        scrollback_area: scrollback_response.rect,
+        canvas_area: canvas_response.rect,
+        clicked_url: hovered_url
+            .as_ref()
+            .filter(|_| clicked)
+            .map(|(_, url)| url.clone()),
+        hovered_url,
+        viewport: response.inner_rect,
+        content_height: response.content_size.y,
+        scroll_offset: response.state.offset.y,
     }
+}
 
-    let response = egui::ScrollArea::new([false, true])
-        .auto_shrink([false, false])
-        .stick_to_bottom(true)
-        .show(ui, |ui| {
-            let error_logged_rect =
-                |response: Result<egui::Response, std::str::Utf8Error>| match response {
-                    Ok(v) => v.rect,
-                    Err(e) => {
-                        error!("failed to add terminal data to ui: {}", backtraced_err(&e));
-                        Rect::NOTHING
-                    }
-                };
-            let scrollback_area = error_logged_rect(add_terminal_data_to_ui(
-                ui,
-                scrollback_data,
-                &format_data.scrollback,
-                font_size,
-            ));
-            let canvas_area = error_logged_rect(add_terminal_data_to_ui(
-                ui,
-                canvas_data,
-                &format_data.visible,
-                font_size,
-            ));
-            TerminalOutputRenderResponse {
-                scrollback_area,
-                canvas_area,
-            }
-        });
+/// Thin bar on the right edge of viewport showing which part of the content is visible. None if
+/// all of it fits
+fn scroll_indicator_rect(viewport: Rect, content_height: f32, scroll_offset: f32) -> Option<Rect> {
+    if content_height <= viewport.height() {
+        return None;
+    }
 
-    response.inner
+    let top = viewport.top() + viewport.height() * scroll_offset / content_height;
+    let height = viewport.height() * viewport.height() / content_height;
+    Some(Rect::from_min_size(
+        egui::pos2(viewport.right() - SCROLL_INDICATOR_WIDTH, top),
+        egui::vec2(SCROLL_INDICATOR_WIDTH, height),
+    ))
 }
 
 struct DebugRenderer {
@@ -409,69 +1080,831 @@ impl DebugRenderer {
     }
 }
 
+fn show_stats_window<Io: TermIo>(ctx: &Context, terminal_emulator: &mut TerminalEmulator<Io>) {
+    let stats = terminal_emulator.stats();
+    egui::Window::new("Emulator stats").show(ctx, |ui| {
+        ui.label(format!("Bytes processed: {}", stats.bytes_processed));
+        ui.label(format!("Frames rendered: {}", stats.frames_rendered));
+        ui.label(format!("Scrollback bytes: {}", stats.scrollback_bytes));
+        ui.label(format!("Format tags: {}", stats.format_tag_count));
+        ui.label(format!(
+            "Cursor: ({}, {})",
+            stats.cursor_pos.x, stats.cursor_pos.y
+        ));
+        if ui.button("Reset").clicked() {
+            terminal_emulator.reset_stats();
+        }
+
+        ui.collapsing("Resize history", |ui| {
+            for (width, height, time) in terminal_emulator.resize_history().iter().rev() {
+                let timestamp = time
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs_f64();
+                ui.label(format!("{timestamp:.3}: {width}x{height}"));
+            }
+        });
+    });
+}
+
+/// Text search over the scrollback and visible area
+struct FindBar {
+    query: String,
+    /// Index into matches of the match that was navigated to
+    current_match: usize,
+    /// Terminal buffer ranges matching query, None when they have to be searched for again
+    matches: Option<Vec<Range<usize>>>,
+    /// Scroll the current match into view in the next frame
+    scroll_to_current: bool,
+}
+
+impl FindBar {
+    fn new() -> FindBar {
+        FindBar {
+            query: String::new(),
+            current_match: 0,
+            matches: None,
+            scroll_to_current: false,
+        }
+    }
+
+    fn update_matches(&mut self, output: &TerminalOutputCache) {
+        if self.matches.is_some() {
+            return;
+        }
+
+        let query = self.query.as_bytes();
+        let mut matches = find_matches(&output.data.scrollback, query);
+        matches.extend(
+            find_matches(&output.data.visible, query)
+                .into_iter()
+                .map(|m| m.start + output.canvas_offset..m.end + output.canvas_offset),
+        );
+        self.current_match = self.current_match.min(matches.len().saturating_sub(1));
+        self.matches = Some(matches);
+    }
+
+    fn step(&mut self, ctx: &Context, forwards: bool) {
+        let num_matches = self.matches.as_ref().map_or(0, Vec::len);
+        if num_matches == 0 {
+            return;
+        }
+
+        self.current_match = if forwards {
+            (self.current_match + 1) % num_matches
+        } else {
+            (self.current_match + num_matches - 1) % num_matches
+        };
+        self.scroll_to_current = true;
+        ctx.request_repaint();
+    }
+
+    fn highlight(&self) -> SearchHighlight<'_> {
+        let matches = self.matches.as_deref().unwrap_or_default();
+        SearchHighlight {
+            matches,
+            current: (!matches.is_empty()).then_some(self.current_match),
+            scroll_to_current: self.scroll_to_current,
+        }
+    }
+
+    /// Returns false once the bar should be closed
+    fn show(&mut self, ui: &mut Ui, id: egui::Id) -> bool {
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.query)
+                    .id(id)
+                    .hint_text("Find"),
+            );
+            if response.changed() {
+                // Start from the match closest to the bottom of the terminal
+                self.matches = None;
+                self.current_match = usize::MAX;
+                self.scroll_to_current = true;
+                ui.ctx().request_repaint();
+            }
+
+            // Single line text edits lose focus when enter or escape is pressed
+            let (enter, escape) =
+                ui.input(|i| (i.key_pressed(Key::Enter), i.key_pressed(Key::Escape)));
+            if response.lost_focus() && enter {
+                self.step(ui.ctx(), true);
+                response.request_focus();
+            }
+
+            match self.matches.as_ref().map_or(0, Vec::len) {
+                0 => ui.label("No matches"),
+                n => ui.label(format!("{} / {}", self.current_match + 1, n)),
+            };
+            if ui.button("Previous match").clicked() {
+                self.step(ui.ctx(), false);
+            }
+            if ui.button("Next match").clicked() {
+                self.step(ui.ctx(), true);
+            }
+
+            let close = ui.button("Close").clicked() || (response.lost_focus() && escape);
+            !close
+        })
+        .inner
+    }
+}
+
 pub struct TerminalWidget {
     font_size: f32,
+    /// Font size change per ctrl+scroll event
+    font_zoom_step: f32,
+    show_invisible: bool,
+    /// Character size for the font size it was measured at. Laying out the sample text every
+    /// frame is not free
+    char_size_cache: Option<(f32, (f32, f32))>,
     debug_renderer: DebugRenderer,
+    /// Buffer range of the url under the mouse in the last frame
+    hovered_url: Option<Range<usize>>,
+    output_cache: Option<TerminalOutputCache>,
+    key_bindings: KeyBindings,
+    /// When the bell rang, while the flash for it is still visible
+    bell_flash_remaining: Option<Instant>,
+    bell_enabled: bool,
+    /// Start and end cell of the selected text in the visible area, in the order they were
+    /// selected
+    selection: Option<(CursorPos, CursorPos)>,
+    /// If the primary button was pressed on the terminal and has not been released yet
+    selecting: bool,
+    find_bar: Option<FindBar>,
+    /// Follow new output while scrolled to the bottom
+    stick_to_bottom: bool,
+    theme: ColorTheme,
+    scroll_to_bottom_requested: bool,
+    /// Show the cursor position and terminal size below the terminal
+    show_status_bar: bool,
 }
 
 impl TerminalWidget {
-    pub fn new(ctx: &Context) -> TerminalWidget {
+    pub fn new(ctx: &Context, config_path: Option<&Path>) -> TerminalWidget {
         setup_fonts(ctx);
 
+        let key_bindings = KeyBindings::load(config_path).unwrap_or_else(|e| {
+            error!("Failed to load key bindings: {}", backtraced_err(&e));
+            KeyBindings::default()
+        });
+
+        // NOTE: The char size cache cannot be pre-warmed here, fonts are not available until the
+        // first frame has started
         TerminalWidget {
             font_size: 12.0,
+            font_zoom_step: DEFAULT_FONT_ZOOM_STEP,
+            show_invisible: false,
+            char_size_cache: None,
             debug_renderer: DebugRenderer::new(),
+            hovered_url: None,
+            output_cache: None,
+            key_bindings,
+            bell_flash_remaining: None,
+            bell_enabled: true,
+            selection: None,
+            selecting: false,
+            find_bar: None,
+            stick_to_bottom: true,
+            scroll_to_bottom_requested: false,
+            theme: ColorTheme::default(),
+            show_status_bar: true,
+        }
+    }
+
+    fn char_size(&mut self, ctx: &Context) -> (f32, f32) {
+        let font_size = self.font_size;
+        match self.char_size_cache {
+            // Exact comparison is intended, any change in font size changes the layout
+            Some((cached_font_size, char_size)) if cached_font_size == font_size => char_size,
+            _ => {
+                let char_size = get_char_size(ctx, font_size);
+                self.char_size_cache = Some((font_size, char_size));
+                char_size
+            }
         }
     }
 
-    pub fn calculate_available_size(&self, ui: &mut Ui) -> (usize, usize) {
-        let character_size = get_char_size(ui.ctx(), self.font_size);
+    pub fn set_theme(&mut self, theme: ColorTheme) {
+        self.theme = theme;
+    }
+
+    pub fn set_font_size(&mut self, font_size: f32) {
+        self.font_size = font_size.clamp(MIN_FONT_SIZE, MAX_FONT_SIZE);
+    }
+
+    /// Show the end of the output in the next frame
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll_to_bottom_requested = true;
+    }
+
+    pub fn calculate_available_size(&mut self, ui: &mut Ui) -> (usize, usize) {
+        let character_size = self.char_size(ui.ctx());
+        // The find bar and status bar are shown below the terminal, one row each
+        let footer_rows = usize::from(self.find_bar.is_some()) + usize::from(self.show_status_bar);
+        let footer_row_height = ui.spacing().interact_size.y + ui.spacing().item_spacing.y;
+        let available_height = ui.available_height() - footer_rows as f32 * footer_row_height;
         let width_chars = (ui.available_width() / character_size.0).floor() as usize;
-        let height_chars = (ui.available_height() / character_size.1).floor() as usize;
+        let height_chars = (available_height / character_size.1).floor() as usize;
         (width_chars, height_chars)
     }
 
+    /// Window size that fits a terminal of the given size in a [`egui::CentralPanel`], the
+    /// inverse of [`TerminalWidget::calculate_available_size`]
+    pub fn window_size_for(&mut self, ctx: &Context, win_size: (usize, usize)) -> egui::Vec2 {
+        let character_size = self.char_size(ctx);
+        let style = ctx.style();
+        let footer_rows = usize::from(self.find_bar.is_some()) + usize::from(self.show_status_bar);
+        let footer_row_height = style.spacing.interact_size.y + style.spacing.item_spacing.y;
+        // Half a character of slack so that rounding does not lose a row or column
+        let terminal_size = egui::Vec2::new(
+            (win_size.0 as f32 + 0.5) * character_size.0,
+            (win_size.1 as f32 + 0.5) * character_size.1,
+        );
+        terminal_size
+            + egui::Vec2::new(0.0, footer_rows as f32 * footer_row_height)
+            + egui::Frame::central_panel(&style).inner_margin.sum()
+    }
+
     pub fn show<Io: TermIo>(&mut self, ui: &mut Ui, terminal_emulator: &mut TerminalEmulator<Io>) {
-        let character_size = get_char_size(ui.ctx(), self.font_size);
+        let character_size = self.char_size(ui.ctx());
 
         terminal_emulator.read();
+        if terminal_emulator.has_pending_output() {
+            // Nothing else wakes us up for output that has already arrived
+            ui.ctx().request_repaint();
+        }
+
+        if let Some(text) = terminal_emulator.take_clipboard_request() {
+            ui.ctx().copy_text(text);
+        }
+
+        if terminal_emulator.is_dirty() {
+            self.output_cache = None;
+            if let Some(find_bar) = &mut self.find_bar {
+                find_bar.matches = None;
+            }
+            terminal_emulator.clear_dirty();
+        }
+        let scroll_to_bottom = std::mem::take(&mut self.scroll_to_bottom_requested);
+        let output_cache = self
+            .output_cache
+            .get_or_insert_with(|| TerminalOutputCache::new(terminal_emulator));
 
-        let frame_response = egui::Frame::none().show(ui, |ui| {
-            let (width_chars, height_chars) = terminal_emulator.get_win_size();
-            let width_chars = width_chars as f32;
-            let height_chars = height_chars as f32;
+        if let Some(find_bar) = &mut self.find_bar {
+            find_bar.update_matches(output_cache);
+        }
+        let find_bar_id = ui.id().with("find_bar");
+        // Keys typed into the find bar are not for the terminal
+        let find_bar_focused = ui.memory(|m| m.has_focus(find_bar_id));
+        let mut find_requested = false;
+        let mut input_written = false;
+
+        if terminal_emulator.take_bell() && self.bell_enabled {
+            self.bell_flash_remaining = Some(Instant::now());
+        }
+
+        let mut copied_selection = None;
+        let theme_background = self.theme.background;
+        let frame_response = egui::Frame::none().fill(theme_background).show(ui, |ui| {
+            let win_size = terminal_emulator.get_win_size();
+            let width_chars = win_size.0 as f32;
+            let height_chars = win_size.1 as f32;
 
             ui.set_width((width_chars + 0.5) * character_size.0);
             ui.set_height((height_chars + 0.5) * character_size.1);
 
+            let copy_requested = ui.input(|input_state| {
+                input_state
+                    .events
+                    .iter()
+                    .any(|event| matches!(event, Event::Copy))
+                    && copies_selection(&input_state.modifiers)
+            });
+            if let (true, Some((start, end))) = (copy_requested, &self.selection) {
+                copied_selection = Some(terminal_emulator.selected_text(start, end));
+            }
+
+            if !find_bar_focused {
+                ui.input(|input_state| {
+                    write_input_to_terminal(
+                        input_state,
+                        terminal_emulator,
+                        &mut self.font_size,
+                        &self.key_bindings,
+                        &mut find_requested,
+                        &mut input_written,
+                    );
+                });
+            }
             ui.input(|input_state| {
-                write_input_to_terminal(input_state, terminal_emulator);
+                zoom_font(input_state, &mut self.font_size, self.font_zoom_step);
             });
 
-            let output_response = render_terminal_output(ui, terminal_emulator, self.font_size);
+            let output_response = render_terminal_output(
+                ui,
+                output_cache,
+                RenderOptions {
+                    font_size: self.font_size,
+                    show_invisible: self.show_invisible,
+                    search: self
+                        .find_bar
+                        .as_ref()
+                        .map(FindBar::highlight)
+                        .unwrap_or_default(),
+                    theme: &self.theme,
+                    stick_to_bottom: self.stick_to_bottom,
+                    scroll_to_bottom,
+                },
+                self.hovered_url.as_ref(),
+            );
+
+            let hovered_url = output_response
+                .hovered_url
+                .as_ref()
+                .map(|(range, _)| range.clone());
+            if hovered_url.is_some() {
+                ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+            }
+            if hovered_url != self.hovered_url {
+                // Underline is applied on the next frame
+                self.hovered_url = hovered_url;
+                ui.ctx().request_repaint();
+            }
+
+            if let Some(url) = output_response.clicked_url {
+                ui.ctx()
+                    .output_mut(|o| o.open_url = Some(egui::OpenUrl::new_tab(url)));
+            }
+
             self.debug_renderer
                 .render(ui, output_response.canvas_area, Color32::BLUE);
 
+            if let Some(rect) = scroll_indicator_rect(
+                output_response.viewport,
+                output_response.content_height,
+                output_response.scroll_offset,
+            ) {
+                let color = ui.visuals().widgets.inactive.fg_stroke.color;
+                ui.painter().rect_filled(rect, 0.0, color);
+            }
+
+            // The canvas label is only as tall as the rows that have text
+            let grid_rect = Rect::from_min_size(
+                output_response.canvas_area.min,
+                egui::vec2(
+                    width_chars * character_size.0,
+                    height_chars * character_size.1,
+                ),
+            );
+            ui.input(|input_state| {
+                update_selection(
+                    &mut self.selection,
+                    &mut self.selecting,
+                    input_state,
+                    grid_rect,
+                    &character_size,
+                    win_size,
+                );
+            });
+            if let Some(selection) = &self.selection {
+                paint_selection(grid_rect, &character_size, selection, win_size.0, ui);
+            }
+
             self.debug_renderer
                 .render(ui, output_response.scrollback_area, Color32::YELLOW);
 
-            paint_cursor(
-                output_response.canvas_area,
-                &character_size,
-                &terminal_emulator.cursor_pos(),
-                ui,
-            );
+            if terminal_emulator.cursor_visible() {
+                paint_cursor(
+                    output_response.canvas_area,
+                    &character_size,
+                    &terminal_emulator.cursor_pos(),
+                    terminal_emulator.cursor_width(),
+                    terminal_emulator.cursor_style(),
+                    ui,
+                );
+            }
         });
 
         self.debug_renderer
             .render(ui, frame_response.response.rect, Color32::RED);
+
+        if let Some(text) = copied_selection {
+            ui.ctx().copy_text(text);
+        }
+
+        // Typing is for the prompt, show it even if scrolled up
+        if input_written {
+            self.scroll_to_bottom();
+        }
+
+        if find_requested {
+            self.find_bar.get_or_insert_with(FindBar::new);
+            ui.memory_mut(|m| m.request_focus(find_bar_id));
+        }
+        if let Some(find_bar) = &mut self.find_bar {
+            find_bar.scroll_to_current = false;
+            if !find_bar.show(ui, find_bar_id) {
+                self.find_bar = None;
+            }
+        }
+
+        if self.show_status_bar {
+            let cursor_pos = terminal_emulator.cursor_pos();
+            let (width, height) = terminal_emulator.get_win_size();
+            ui.horizontal(|ui| {
+                ui.label(
+                    egui::RichText::new(format!(
+                        "Col {}, Row {}",
+                        cursor_pos.x + 1,
+                        cursor_pos.y + 1
+                    ))
+                    .small(),
+                );
+                ui.label(egui::RichText::new(format!("{width}\u{d7}{height}")).small());
+            });
+        }
+
+        if let Some(bell_time) = self.bell_flash_remaining {
+            let elapsed = bell_time.elapsed();
+            if elapsed < BELL_FLASH_DURATION {
+                ui.painter().rect_filled(
+                    frame_response.response.rect,
+                    0.0,
+                    Color32::from_white_alpha(40),
+                );
+                ui.ctx()
+                    .request_repaint_after(BELL_FLASH_DURATION - elapsed);
+            } else {
+                self.bell_flash_remaining = None;
+            }
+        }
+
+        if self.debug_renderer.enable {
+            show_stats_window(ui.ctx(), terminal_emulator);
+            self.show_debug_mode_panel(ui.ctx(), terminal_emulator);
+        }
     }
 
-    pub fn show_options(&mut self, ui: &mut Ui) {
+    fn show_debug_mode_panel<Io: TermIo>(
+        &self,
+        ctx: &Context,
+        terminal_emulator: &TerminalEmulator<Io>,
+    ) {
+        let modes = terminal_emulator.current_modes();
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+
+        egui::Window::new("Terminal modes")
+            .pivot(egui::Align2::RIGHT_TOP)
+            .default_pos(ctx.screen_rect().right_top())
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label(format!("DECCKM: {}", on_off(modes.decckm)));
+                ui.label(format!("Alt screen: {}", on_off(modes.alt_screen)));
+                ui.label(format!("Origin mode: {}", on_off(modes.origin_mode)));
+                ui.label(format!(
+                    "Bracketed paste: {}",
+                    on_off(modes.bracketed_paste)
+                ));
+                ui.label(format!("Cursor visible: {}", on_off(modes.cursor_visible)));
+                ui.label(format!("DECAWM: {}", on_off(modes.decawm)));
+                ui.label(format!("Insert mode: {}", on_off(modes.insert_mode)));
+            });
+    }
+
+    pub fn show_options<Io: TermIo>(
+        &mut self,
+        ui: &mut Ui,
+        terminal_emulator: &mut TerminalEmulator<Io>,
+    ) {
         ui.horizontal(|ui| {
             ui.label("Font size:");
-            ui.add(DragValue::new(&mut self.font_size).clamp_range(1.0..=100.0));
+            ui.add(DragValue::new(&mut self.font_size).clamp_range(MIN_FONT_SIZE..=MAX_FONT_SIZE));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Zoom step:");
+            ui.add(
+                DragValue::new(&mut self.font_zoom_step)
+                    .clamp_range(0.5..=10.0)
+                    .speed(0.1),
+            );
         });
+        ui.checkbox(&mut self.show_invisible, "Show invisible characters");
+        ui.checkbox(&mut self.bell_enabled, "Flash on bell");
+        ui.checkbox(&mut self.stick_to_bottom, "Scroll to bottom on output");
+        ui.checkbox(&mut self.show_status_bar, "Show status bar");
+        egui::ComboBox::from_label("Theme")
+            .selected_text(self.theme.name)
+            .show_ui(ui, |ui| {
+                for theme in theme::THEMES {
+                    ui.selectable_value(&mut self.theme, theme.clone(), theme.name);
+                }
+            });
         ui.checkbox(&mut self.debug_renderer.enable, "Debug render");
+
+        ui.separator();
+        let copy_screen = ui.button("Copy screen").clicked();
+        let copy_scrollback = ui.button("Copy scrollback").clicked();
+        let copy_all = ui.button("Copy all").clicked();
+        let copied_lines = if copy_screen {
+            Some(terminal_emulator.dump_screen())
+        } else if copy_scrollback {
+            Some(terminal_emulator.dump_scrollback())
+        } else if copy_all {
+            let mut lines = terminal_emulator.dump_scrollback();
+            lines.extend(terminal_emulator.dump_screen());
+            Some(lines)
+        } else {
+            None
+        };
+        if let Some(lines) = copied_lines {
+            ui.ctx().copy_text(lines.join("\n"));
+            ui.close_menu();
+        }
+
+        if ui.button("Paste").clicked() {
+            // egui only hands us the system clipboard through Event::Paste on a key press, so
+            // read it directly through the same backend egui-winit uses
+            match arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text()) {
+                Ok(text) => {
+                    let input = TerminalInput::PasteBuffer(text.into_bytes());
+                    if let Err(e) = terminal_emulator.write(input) {
+                        error!("failed to paste: {}", backtraced_err(&*e));
+                    }
+                }
+                Err(e) => error!("failed to read clipboard: {e}"),
+            }
+            ui.close_menu();
+        }
+
+        if self.debug_renderer.enable && ui.button("Print debug state to stderr").clicked() {
+            if let Err(e) = terminal_emulator.print_debug_state(&mut std::io::stderr()) {
+                error!("failed to print debug state: {e}");
+            }
+            ui.close_menu();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::terminal_emulator::MockIo;
+
+    /// Text of every label drawn in the frame
+    fn rendered_text(shapes: &[egui::epaint::ClippedShape]) -> Vec<String> {
+        fn collect(shape: &egui::Shape, ret: &mut Vec<String>) {
+            match shape {
+                egui::Shape::Text(text) => ret.push(text.galley.text().to_string()),
+                egui::Shape::Vec(shapes) => {
+                    for shape in shapes {
+                        collect(shape, ret);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let mut ret = Vec::new();
+        for clipped in shapes {
+            collect(&clipped.shape, &mut ret);
+        }
+        ret
+    }
+
+    #[test]
+    fn test_split_spaces() {
+        assert_eq!(
+            split_spaces(b"a  bc d", 1..7),
+            &[(1..3, true), (3..5, false), (5..6, true), (6..7, false)]
+        );
+        assert!(split_spaces(b"abc", 1..1).is_empty());
+    }
+
+    #[test]
+    fn test_find_matches() {
+        assert_eq!(
+            find_matches(b"Error: error\nERRORS", b"error"),
+            &[0..5, 7..12, 13..18]
+        );
+        // Matches do not overlap
+        assert_eq!(find_matches(b"aaaaa", b"aa"), &[0..2, 2..4]);
+        // Only ASCII letters are matched ignoring case
+        assert_eq!(
+            find_matches(
+                "\u{c9}t\u{e9} \u{e9}t\u{e9} \u{e9}T\u{e9}".as_bytes(),
+                "\u{e9}t\u{e9}".as_bytes()
+            ),
+            &[6..11, 12..17]
+        );
+        assert!(find_matches(b"abc", b"").is_empty());
+        assert!(find_matches(b"ab", b"abc").is_empty());
+    }
+
+    #[test]
+    fn test_split_for_matches() {
+        let matches = [2..4, 6..9, 12..13];
+        assert_eq!(
+            split_for_matches(0..10, &matches),
+            &[
+                (0..2, None),
+                (2..4, Some(0)),
+                (4..6, None),
+                (6..9, Some(1)),
+                (9..10, None)
+            ]
+        );
+        // Matches are clipped to the range, indices stay the same
+        assert_eq!(
+            split_for_matches(7..12, &matches),
+            &[(7..9, Some(1)), (9..12, None)]
+        );
+        assert_eq!(split_for_matches(4..6, &matches), &[(4..6, None)]);
+        assert_eq!(split_for_matches(0..3, &[]), &[(0..3, None)]);
+    }
+
+    #[test]
+    fn test_scroll_indicator_rect() {
+        let viewport = Rect::from_min_size(egui::pos2(0.0, 10.0), egui::vec2(100.0, 50.0));
+        assert_eq!(scroll_indicator_rect(viewport, 50.0, 0.0), None);
+
+        // A quarter of the content is visible, scrolled to the bottom
+        let rect = scroll_indicator_rect(viewport, 200.0, 150.0).expect("no indicator");
+        assert_eq!(rect.top(), 47.5);
+        assert_eq!(rect.bottom(), 60.0);
+        assert_eq!(rect.right(), 100.0);
+        assert_eq!(rect.width(), SCROLL_INDICATOR_WIDTH);
+
+        let rect = scroll_indicator_rect(viewport, 200.0, 0.0).expect("no indicator");
+        assert_eq!(rect.top(), 10.0);
+        assert_eq!(rect.bottom(), 22.5);
+    }
+
+    #[test]
+    fn test_zoom_font() {
+        let ctx = egui::Context::default();
+        // Modifiers are dropped in the frame the window gains focus
+        let _ = ctx.run(Default::default(), |_| {});
+        let zoom = |events: Vec<Event>, modifiers: Modifiers, font_size: f32| {
+            let input = egui::RawInput {
+                events,
+                modifiers,
+                ..Default::default()
+            };
+            let mut font_size = font_size;
+            let _ = ctx.run(input, |ctx| {
+                ctx.input(|i| zoom_font(i, &mut font_size, 2.0));
+            });
+            font_size
+        };
+
+        // What egui-winit sends for one step of the wheel. With ctrl held it sends a zoom
+        // instead of a scroll
+        let wheel = |lines: f32, modifiers: Modifiers| {
+            let mut events = vec![Event::MouseWheel {
+                unit: egui::MouseWheelUnit::Line,
+                delta: egui::vec2(0.0, lines),
+                modifiers,
+            }];
+            if is_zoom_wheel(&modifiers) {
+                events.push(Event::Zoom((lines * 50.0 / 200.0).exp()));
+            } else {
+                events.push(Event::Scroll(egui::vec2(0.0, lines * 50.0)));
+            }
+            events
+        };
+        assert_eq!(
+            zoom(wheel(1.0, Modifiers::CTRL), Modifiers::CTRL, 12.0),
+            14.0
+        );
+        assert_eq!(
+            zoom(
+                [wheel(-1.0, Modifiers::CTRL), wheel(-1.0, Modifiers::CTRL)].concat(),
+                Modifiers::CTRL,
+                12.0
+            ),
+            8.0
+        );
+        // Scrolling without ctrl scrolls the output instead
+        assert_eq!(
+            zoom(wheel(1.0, Modifiers::NONE), Modifiers::NONE, 12.0),
+            12.0
+        );
+
+        // Pinch gestures only send a zoom
+        assert_eq!(zoom(vec![Event::Zoom(1.5)], Modifiers::NONE, 12.0), 18.0);
+        assert_eq!(
+            zoom(wheel(1.0, Modifiers::CTRL), Modifiers::CTRL, MAX_FONT_SIZE),
+            MAX_FONT_SIZE
+        );
+        assert_eq!(
+            zoom(vec![Event::Zoom(0.1)], Modifiers::NONE, 12.0),
+            MIN_FONT_SIZE
+        );
+    }
+
+    #[test]
+    fn test_status_bar() {
+        let ctx = egui::Context::default();
+        let mut widget = TerminalWidget::new(&ctx, None);
+        let mut emulator = TerminalEmulator::new_with_io(MockIo::new(), "recordings".into());
+        emulator.write_sequence(b"\x1b[3;5H");
+        let (width, height) = emulator.get_win_size();
+
+        let mut show = |widget: &mut TerminalWidget| {
+            let output = ctx.run(Default::default(), |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| widget.show(ui, &mut emulator));
+            });
+            rendered_text(&output.shapes)
+        };
+
+        let text = show(&mut widget);
+        assert!(text.iter().any(|t| t == "Col 5, Row 3"), "{text:?}");
+        assert!(text.contains(&format!("{width}\u{d7}{height}")), "{text:?}");
+
+        widget.show_status_bar = false;
+        let text = show(&mut widget);
+        assert!(!text.iter().any(|t| t.starts_with("Col ")), "{text:?}");
+    }
+
+    #[test]
+    fn test_window_size_for() {
+        let ctx = egui::Context::default();
+        let mut widget = TerminalWidget::new(&ctx, None);
+        // Fonts are loaded on the first frame
+        let _ = ctx.run(Default::default(), |_| {});
+
+        for win_size in [(80, 24), (132, 50), (20, 5)] {
+            let window_size = widget.window_size_for(&ctx, win_size);
+            let input = egui::RawInput {
+                screen_rect: Some(Rect::from_min_size(egui::Pos2::ZERO, window_size)),
+                ..Default::default()
+            };
+            let mut available_size = (0, 0);
+            let _ = ctx.run(input, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    available_size = widget.calculate_available_size(ui);
+                });
+            });
+            assert_eq!(available_size, win_size);
+        }
+    }
+
+    #[test]
+    fn test_pointer_to_cursor_pos() {
+        let grid_rect = Rect::from_min_size(egui::pos2(10.0, 20.0), egui::vec2(80.0, 40.0));
+        let character_size = (8.0, 10.0);
+        let win_size = (10, 4);
+        let to_cursor_pos =
+            |x, y| pointer_to_cursor_pos(egui::pos2(x, y), grid_rect, &character_size, win_size);
+
+        assert_eq!(to_cursor_pos(10.0, 20.0), CursorPos { x: 0, y: 0 });
+        assert_eq!(to_cursor_pos(17.9, 29.9), CursorPos { x: 0, y: 0 });
+        assert_eq!(to_cursor_pos(18.0, 30.0), CursorPos { x: 1, y: 1 });
+        assert_eq!(to_cursor_pos(50.0, 45.0), CursorPos { x: 5, y: 2 });
+        // Dragging outside of the terminal stays on the nearest cell
+        assert_eq!(to_cursor_pos(0.0, 0.0), CursorPos { x: 0, y: 0 });
+        assert_eq!(to_cursor_pos(500.0, 500.0), CursorPos { x: 9, y: 3 });
+    }
+
+    #[test]
+    fn test_char_size_metrics() {
+        let ctx = egui::Context::default();
+        setup_fonts(&ctx);
+        // Fonts are only available once a frame has started
+        let _ = ctx.run(Default::default(), |_| {});
+        let _ = ctx.run(Default::default(), |ctx| {
+            const NUM_CHARS: usize = 200;
+            for font_size in [
+                6.0, 8.0, 11.0, 12.0, 13.0, 14.0, 16.5, 20.0, 24.0, 33.0, 48.0, 100.0,
+            ] {
+                let font_id = FontId {
+                    size: font_size,
+                    family: FontFamily::Name(REGULAR_FONT_NAME.into()),
+                };
+
+                let (width, _) = get_char_size(ctx, font_size);
+                let (line_width, glyph_width) = ctx.fonts(|fonts| {
+                    let line = "M".repeat(NUM_CHARS);
+                    let rect = fonts
+                        .layout_no_wrap(line, font_id.clone(), Color32::WHITE)
+                        .rect;
+                    (rect.width(), fonts.glyph_width(&font_id, 'M'))
+                });
+
+                // The cursor is placed at char width * column, so any error accumulates towards
+                // the end of a long line
+                let layout_err = (line_width - width * NUM_CHARS as f32).abs();
+                let glyph_err = (line_width - glyph_width * NUM_CHARS as f32).abs();
+                assert!(
+                    layout_err / (NUM_CHARS as f32) < 0.05,
+                    "font size {font_size}: cursor drifted {layout_err} px"
+                );
+                assert!(
+                    layout_err <= glyph_err,
+                    "font size {font_size}: glyph_width is more accurate than the layout"
+                );
+            }
+        });
     }
 }
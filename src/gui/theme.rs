@@ -0,0 +1,146 @@
+use eframe::egui::Color32;
+
+/// Colors terminal output is drawn with
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ColorTheme {
+    /// Name used to select the theme with --theme
+    pub name: &'static str,
+    pub foreground: Color32,
+    pub background: Color32,
+    /// Black, red, green, yellow, blue, magenta, cyan and white, followed by their bright variants
+    pub palette: [Color32; 16],
+}
+
+/// Matches egui's dark visuals, with fully saturated named colors
+pub const DEFAULT: ColorTheme = ColorTheme {
+    name: "default",
+    foreground: Color32::from_gray(140),
+    background: Color32::from_gray(27),
+    palette: [
+        Color32::from_rgb(0x00, 0x00, 0x00),
+        Color32::from_rgb(0xff, 0x00, 0x00),
+        Color32::from_rgb(0x00, 0xff, 0x00),
+        Color32::from_rgb(0xff, 0xff, 0x00),
+        Color32::from_rgb(0x00, 0x00, 0xff),
+        Color32::from_rgb(0xff, 0x00, 0xff),
+        Color32::from_rgb(0x00, 0xff, 0xff),
+        Color32::from_rgb(0xff, 0xff, 0xff),
+        Color32::from_rgb(0x7f, 0x7f, 0x7f),
+        Color32::from_rgb(0xff, 0x00, 0x00),
+        Color32::from_rgb(0x00, 0xff, 0x00),
+        Color32::from_rgb(0xff, 0xff, 0x00),
+        Color32::from_rgb(0x00, 0x00, 0xff),
+        Color32::from_rgb(0xff, 0x00, 0xff),
+        Color32::from_rgb(0x00, 0xff, 0xff),
+        Color32::from_rgb(0xff, 0xff, 0xff),
+    ],
+};
+
+pub const SOLARIZED_DARK: ColorTheme = ColorTheme {
+    name: "solarized-dark",
+    foreground: Color32::from_rgb(0x83, 0x94, 0x96),
+    background: Color32::from_rgb(0x00, 0x2b, 0x36),
+    palette: [
+        Color32::from_rgb(0x07, 0x36, 0x42),
+        Color32::from_rgb(0xdc, 0x32, 0x2f),
+        Color32::from_rgb(0x85, 0x99, 0x00),
+        Color32::from_rgb(0xb5, 0x89, 0x00),
+        Color32::from_rgb(0x26, 0x8b, 0xd2),
+        Color32::from_rgb(0xd3, 0x36, 0x82),
+        Color32::from_rgb(0x2a, 0xa1, 0x98),
+        Color32::from_rgb(0xee, 0xe8, 0xd5),
+        Color32::from_rgb(0x00, 0x2b, 0x36),
+        Color32::from_rgb(0xcb, 0x4b, 0x16),
+        Color32::from_rgb(0x58, 0x6e, 0x75),
+        Color32::from_rgb(0x65, 0x7b, 0x83),
+        Color32::from_rgb(0x83, 0x94, 0x96),
+        Color32::from_rgb(0x6c, 0x71, 0xc4),
+        Color32::from_rgb(0x93, 0xa1, 0xa1),
+        Color32::from_rgb(0xfd, 0xf6, 0xe3),
+    ],
+};
+
+pub const NORD: ColorTheme = ColorTheme {
+    name: "nord",
+    foreground: Color32::from_rgb(0xd8, 0xde, 0xe9),
+    background: Color32::from_rgb(0x2e, 0x34, 0x40),
+    palette: [
+        Color32::from_rgb(0x3b, 0x42, 0x52),
+        Color32::from_rgb(0xbf, 0x61, 0x6a),
+        Color32::from_rgb(0xa3, 0xbe, 0x8c),
+        Color32::from_rgb(0xeb, 0xcb, 0x8b),
+        Color32::from_rgb(0x81, 0xa1, 0xc1),
+        Color32::from_rgb(0xb4, 0x8e, 0xad),
+        Color32::from_rgb(0x88, 0xc0, 0xd0),
+        Color32::from_rgb(0xe5, 0xe9, 0xf0),
+        Color32::from_rgb(0x4c, 0x56, 0x6a),
+        Color32::from_rgb(0xbf, 0x61, 0x6a),
+        Color32::from_rgb(0xa3, 0xbe, 0x8c),
+        Color32::from_rgb(0xeb, 0xcb, 0x8b),
+        Color32::from_rgb(0x81, 0xa1, 0xc1),
+        Color32::from_rgb(0xb4, 0x8e, 0xad),
+        Color32::from_rgb(0x8f, 0xbc, 0xbb),
+        Color32::from_rgb(0xec, 0xef, 0xf4),
+    ],
+};
+
+pub const DRACULA: ColorTheme = ColorTheme {
+    name: "dracula",
+    foreground: Color32::from_rgb(0xf8, 0xf8, 0xf2),
+    background: Color32::from_rgb(0x28, 0x2a, 0x36),
+    palette: [
+        Color32::from_rgb(0x21, 0x22, 0x2c),
+        Color32::from_rgb(0xff, 0x55, 0x55),
+        Color32::from_rgb(0x50, 0xfa, 0x7b),
+        Color32::from_rgb(0xf1, 0xfa, 0x8c),
+        Color32::from_rgb(0xbd, 0x93, 0xf9),
+        Color32::from_rgb(0xff, 0x79, 0xc6),
+        Color32::from_rgb(0x8b, 0xe9, 0xfd),
+        Color32::from_rgb(0xf8, 0xf8, 0xf2),
+        Color32::from_rgb(0x62, 0x72, 0xa4),
+        Color32::from_rgb(0xff, 0x6e, 0x6e),
+        Color32::from_rgb(0x69, 0xff, 0x94),
+        Color32::from_rgb(0xff, 0xff, 0xa5),
+        Color32::from_rgb(0xd6, 0xac, 0xff),
+        Color32::from_rgb(0xff, 0x92, 0xdf),
+        Color32::from_rgb(0xa4, 0xff, 0xff),
+        Color32::from_rgb(0xff, 0xff, 0xff),
+    ],
+};
+
+pub const THEMES: [&ColorTheme; 4] = [&DEFAULT, &SOLARIZED_DARK, &NORD, &DRACULA];
+
+impl ColorTheme {
+    pub fn names() -> Vec<&'static str> {
+        THEMES.iter().map(|theme| theme.name).collect()
+    }
+
+    pub fn by_name(name: &str) -> Option<&'static ColorTheme> {
+        THEMES
+            .into_iter()
+            .find(|theme| theme.name.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Default for ColorTheme {
+    fn default() -> ColorTheme {
+        DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_theme_palettes_differ() {
+        for theme in &THEMES[1..] {
+            assert_ne!(theme.palette, DEFAULT.palette, "{}", theme.name);
+            assert_ne!(theme.background, DEFAULT.background, "{}", theme.name);
+        }
+
+        assert_eq!(ColorTheme::by_name("Nord"), Some(&NORD));
+        assert_eq!(ColorTheme::by_name("solarized-dark"), Some(&SOLARIZED_DARK));
+        assert_eq!(ColorTheme::by_name("solarized"), None);
+    }
+}
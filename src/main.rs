@@ -1,27 +1,88 @@
-use std::path::PathBuf;
-use terminal_emulator::TerminalEmulator;
+use config::Config;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+use terminal_emulator::{Recording, TerminalEmulator};
 
 #[macro_use]
 mod log;
+mod benchmark;
+mod config;
 mod error;
 mod gui;
 mod terminal_emulator;
 
 struct Args {
+    shell: PathBuf,
+    env: Vec<(String, String)>,
+    geometry: Option<(usize, usize)>,
     recording_path: PathBuf,
     replay: Option<PathBuf>,
+    recording_chunk_size: Option<usize>,
+    export_html: Option<PathBuf>,
+    export_asciicast: Option<PathBuf>,
+    max_bytes_per_frame: usize,
+    max_scrollback_lines: usize,
+    osc52_enabled: bool,
+    benchmark: Option<PathBuf>,
+    bench_iters: usize,
+    config_path: Option<PathBuf>,
+    theme: gui::ColorTheme,
+    font_size: Option<f32>,
 }
 
 impl Args {
-    fn parse<It: Iterator<Item = String>>(mut it: It) -> Args {
+    /// Parse command line arguments, falling back to values from the config file
+    fn parse<It: Iterator<Item = String>>(mut it: It, config: Config) -> Args {
         let program_name = it.next();
 
         // Default value
-        let mut recording_path = "recordings".into();
+        let mut shell = config.shell.unwrap_or_else(|| "bash".into());
+        let mut env = Vec::new();
+        let mut geometry = config.geometry;
+        let mut recording_path = config.recording_path.unwrap_or_else(|| "recordings".into());
         let mut replay = None;
+        let mut recording_chunk_size = None;
+        let mut export_html = None;
+        let mut export_asciicast = None;
+        let mut max_bytes_per_frame = terminal_emulator::DEFAULT_MAX_BYTES_PER_FRAME;
+        let mut max_scrollback_lines = config
+            .scrollback_lines
+            .unwrap_or(terminal_emulator::DEFAULT_MAX_SCROLLBACK_LINES);
+        let mut osc52_enabled = true;
+        let mut benchmark = None;
+        let mut bench_iters = 1;
+        let mut config_path = None;
+        let mut theme = config.theme.unwrap_or_default();
 
         while let Some(arg) = it.next() {
             match arg.as_str() {
+                "--shell" => {
+                    shell = match it.next() {
+                        Some(p) => p.into(),
+                        None => {
+                            println!("Missing argument for --shell");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
+                "--env" => match it.next().as_deref().and_then(parse_env_var) {
+                    Some(v) => env.push(v),
+                    None => {
+                        println!("Invalid argument for --env, expected KEY=VALUE");
+                        Self::help(program_name.as_deref());
+                    }
+                },
+                "--geometry" => {
+                    geometry = match it.next().as_deref().and_then(config::parse_geometry) {
+                        Some(v) => Some(v),
+                        None => {
+                            println!("Invalid argument for --geometry, expected WIDTHxHEIGHT");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
                 "--recording-path" => {
                     recording_path = match it.next() {
                         Some(p) => p.into(),
@@ -32,6 +93,88 @@ impl Args {
                     };
                 }
                 "--replay" => replay = it.next().map(PathBuf::from),
+                "--export-html" => {
+                    export_html = match it.next() {
+                        Some(p) => Some(p.into()),
+                        None => {
+                            println!("Missing argument for --export-html");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
+                "--export-asciicast" => {
+                    export_asciicast = match it.next() {
+                        Some(p) => Some(p.into()),
+                        None => {
+                            println!("Missing argument for --export-asciicast");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
+                "--config" => {
+                    config_path = match it.next() {
+                        Some(p) => Some(p.into()),
+                        None => {
+                            println!("Missing argument for --config");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
+                "--theme" => {
+                    theme = match it.next().as_deref().and_then(gui::ColorTheme::by_name) {
+                        Some(v) => v.clone(),
+                        None => {
+                            println!("Invalid argument for --theme");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
+                "--disable-osc52" => osc52_enabled = false,
+                "--benchmark" => {
+                    benchmark = match it.next() {
+                        Some(p) => Some(p.into()),
+                        None => {
+                            println!("Missing argument for --benchmark");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
+                "--bench-iters" => {
+                    bench_iters = match it.next().map(|s| s.parse()) {
+                        Some(Ok(v)) if v > 0 => v,
+                        _ => {
+                            println!("Invalid argument for --bench-iters");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
+                "--max-bytes-per-frame" => {
+                    max_bytes_per_frame = match it.next().map(|s| s.parse()) {
+                        Some(Ok(v)) if v > 0 => v,
+                        _ => {
+                            println!("Invalid argument for --max-bytes-per-frame");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
+                "--scrollback-lines" => {
+                    max_scrollback_lines = match it.next().map(|s| s.parse()) {
+                        Some(Ok(v)) => v,
+                        _ => {
+                            println!("Invalid argument for --scrollback-lines");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
+                "--recording-chunk-size" => {
+                    recording_chunk_size = match it.next().map(|s| s.parse()) {
+                        Some(Ok(v)) if v > 0 => Some(v),
+                        _ => {
+                            println!("Invalid argument for --recording-chunk-size");
+                            Self::help(program_name.as_deref());
+                        }
+                    };
+                }
                 _ => {
                     println!("Invalid argument {arg}");
                     Self::help(program_name.as_deref())
@@ -39,36 +182,141 @@ impl Args {
             }
         }
 
+        if replay.is_none() && (export_html.is_some() || export_asciicast.is_some()) {
+            println!("--export-html and --export-asciicast require --replay");
+            Self::help(program_name.as_deref());
+        }
+
+        if export_html.is_some() && export_asciicast.is_some() {
+            println!("Only one of --export-html and --export-asciicast can be used");
+            Self::help(program_name.as_deref());
+        }
+
         Args {
+            shell,
+            env,
+            geometry,
             recording_path,
             replay,
+            recording_chunk_size,
+            export_html,
+            export_asciicast,
+            max_bytes_per_frame,
+            max_scrollback_lines,
+            osc52_enabled,
+            benchmark,
+            bench_iters,
+            config_path,
+            theme,
+            font_size: config.font_size,
         }
     }
 
     fn help(program_name: Option<&str>) -> ! {
         let program_name = program_name.unwrap_or("termie");
+        let default_scrollback_lines = terminal_emulator::DEFAULT_MAX_SCROLLBACK_LINES;
+        let theme_names = gui::ColorTheme::names().join(", ");
         println!(
             "\
                  Usage:\n\
                  {program_name} [ARGS]\n\
                  \n\
                  Args:\n\
+                 --config: Optional, config file to use. Takes precedence over $TERMIE_CONFIG, which takes precedence over ~/.config/termie/keybindings.json
+                 --shell: Optional, shell to run, either a path or a name to look up in $PATH. Defaults to bash
+                 --env: Optional, KEY=VALUE to set in the shell's environment. May be given multiple times
+                 --geometry: Optional, initial terminal size in characters, e.g. 80x24
                  --recording-path: Optional, where to output recordings to
-                 --replay: Replay a recording
+                 --recording-chunk-size: Optional, split recordings into files of at most this many items, at least 1
+                 --disable-osc52: Optional, do not let applications set the clipboard
+                 --theme: Optional, color theme, one of {theme_names}
+                 --max-bytes-per-frame: Optional, limit on child output processed per frame, at least 1
+                 --scrollback-lines: Optional, limit on how many lines of scrollback are kept. Defaults to {default_scrollback_lines}
+                 --replay: Replay a recording, a directory of recording chunks, or an asciicast v2 file
+                 --export-html: Optional, with --replay, write the final state of the replay to an html file instead of opening a window
+                 --export-asciicast: Optional, with --replay and without --export-html, convert the recording to an asciicast v2 file instead of opening a window
+                 --benchmark: Process a recording as fast as possible without a window and print throughput
+                 --bench-iters: Optional, with --benchmark, how many times to process the recording, at least 1
+                 Defaults for --shell, --recording-path, --theme, --scrollback-lines, --geometry and the font size can be set in ~/.config/termie/config.toml
                  "
         );
         std::process::exit(1);
     }
 }
 
+/// Parse a KEY=VALUE pair for the shell's environment. The value may be empty, the key may not
+fn parse_env_var(s: &str) -> Option<(String, String)> {
+    let (key, value) = s.split_once('=')?;
+    (!key.is_empty()).then(|| (key.to_string(), value.to_string()))
+}
+
+fn export_asciicast(
+    recording_path: &Path,
+    output_path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let recording = Recording::load(recording_path)?;
+
+    let mut f = std::io::BufWriter::new(std::fs::File::create(output_path)?);
+    recording.to_asciicast_v2(&mut f)?;
+    f.flush()?;
+    Ok(())
+}
+
 fn main() {
     log::init();
-    let args = Args::parse(std::env::args());
+    let config = Config::load().unwrap_or_else(|e| {
+        error!("Failed to load config: {}", error::backtraced_err(&e));
+        Config::default()
+    });
+    let args = Args::parse(std::env::args(), config);
+    if let Some(benchmark_path) = args.benchmark {
+        if let Err(e) = benchmark::run(&benchmark_path, args.bench_iters) {
+            error!("Failed to run benchmark: {}", error::backtraced_err(&*e));
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let res = if let Some(replay) = args.replay {
-        gui::run_replay(replay)
+        if let Some(export_path) = args.export_asciicast {
+            if let Err(e) = export_asciicast(&replay, &export_path) {
+                error!("Failed to export asciicast: {}", error::backtraced_err(&*e));
+            }
+            return;
+        }
+        if let Some(export_path) = args.export_html {
+            if let Err(e) = gui::export_replay_html(&replay, &export_path) {
+                error!("Failed to export html: {}", error::backtraced_err(&*e));
+            }
+            return;
+        }
+        gui::run_replay(replay, args.config_path, args.theme, args.font_size)
     } else {
-        match TerminalEmulator::new(args.recording_path) {
-            Ok(v) => gui::run(v),
+        let (width_chars, height_chars) = args.geometry.unwrap_or((
+            terminal_emulator::DEFAULT_TERMINAL_WIDTH,
+            terminal_emulator::DEFAULT_TERMINAL_HEIGHT,
+        ));
+        match TerminalEmulator::new(
+            &args.shell,
+            &args.env,
+            args.recording_path,
+            width_chars,
+            height_chars,
+        ) {
+            Ok(mut v) => {
+                info!("Started shell with pid {}", v.child_pid());
+                v.set_recording_chunk_size(args.recording_chunk_size);
+                v.set_max_bytes_per_frame(args.max_bytes_per_frame);
+                v.set_max_scrollback_lines(args.max_scrollback_lines);
+                v.set_osc52_enabled(args.osc52_enabled);
+                gui::run(
+                    v,
+                    args.config_path,
+                    args.theme,
+                    args.geometry,
+                    args.font_size,
+                )
+            }
             Err(e) => {
                 error!(
                     "Failed to create terminal emulator: {}",
@@ -83,3 +331,67 @@ fn main() {
         error!("Failed to run gui: {}", error::backtraced_err(&*e));
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Args {
+        Args::parse(
+            std::iter::once("termie")
+                .chain(args.iter().copied())
+                .map(String::from),
+            Config::default(),
+        )
+    }
+
+    #[test]
+    fn test_geometry_arg() {
+        assert_eq!(parse_args(&[]).geometry, None);
+        assert_eq!(
+            parse_args(&["--geometry", "80x24"]).geometry,
+            Some((80, 24))
+        );
+    }
+
+    #[test]
+    fn test_env_arg() {
+        assert_eq!(
+            parse_args(&["--env", "FOO=bar", "--env", "EMPTY=", "--env", "A=b=c"]).env,
+            [
+                ("FOO".to_string(), "bar".to_string()),
+                ("EMPTY".to_string(), String::new()),
+                ("A".to_string(), "b=c".to_string()),
+            ]
+        );
+
+        // Rejected with a usage error by Args::parse
+        assert_eq!(parse_env_var("FOO"), None);
+        assert_eq!(parse_env_var("=x"), None);
+    }
+
+    #[test]
+    fn test_args_override_config() {
+        let config = || Config {
+            shell: Some("/bin/zsh".into()),
+            scrollback_lines: Some(100),
+            geometry: Some((80, 24)),
+            ..Default::default()
+        };
+
+        let args = Args::parse(["termie".to_string()].into_iter(), config());
+        assert_eq!(args.shell, PathBuf::from("/bin/zsh"));
+        assert_eq!(args.max_scrollback_lines, 100);
+        assert_eq!(args.recording_path, PathBuf::from("recordings"));
+
+        let args = Args::parse(
+            ["termie", "--shell", "/bin/sh", "--geometry", "100x30"]
+                .into_iter()
+                .map(String::from),
+            config(),
+        );
+        assert_eq!(args.shell, PathBuf::from("/bin/sh"));
+        assert_eq!(args.geometry, Some((100, 30)));
+        assert_eq!(args.max_scrollback_lines, 100);
+    }
+}
@@ -1,8 +1,10 @@
 use super::{
     recording::{NotIntOfType, NotMap},
-    Mode,
+    CursorStyle, Mode,
 };
 use crate::terminal_emulator::recording::SnapshotItem;
+use base64::Engine;
+use std::{borrow::Cow, collections::VecDeque};
 use thiserror::Error;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -10,6 +12,17 @@ pub enum SelectGraphicRendition {
     // NOTE: Non-exhaustive list
     Reset,
     Bold,
+    Dim,
+    /// Neither bold nor dim
+    NormalIntensity,
+    Italic,
+    ItalicOff,
+    Underline,
+    ReverseVideo,
+    ReverseVideoOff,
+    UnderlineOff,
+    Strikethrough,
+    StrikethroughOff,
     ForegroundBlack,
     ForegroundRed,
     ForegroundGreen,
@@ -26,6 +39,32 @@ pub enum SelectGraphicRendition {
     ForegroundBrightMagenta,
     ForegroundBrightCyan,
     ForegroundBrightWhite,
+    ForegroundDefault,
+    BackgroundBlack,
+    BackgroundRed,
+    BackgroundGreen,
+    BackgroundYellow,
+    BackgroundBlue,
+    BackgroundMagenta,
+    BackgroundCyan,
+    BackgroundWhite,
+    BackgroundBrightBlack,
+    BackgroundBrightRed,
+    BackgroundBrightGreen,
+    BackgroundBrightYellow,
+    BackgroundBrightBlue,
+    BackgroundBrightMagenta,
+    BackgroundBrightCyan,
+    BackgroundBrightWhite,
+    BackgroundDefault,
+    /// Entry of the xterm 256 color palette, `38;5;n`
+    ForegroundPalette(u8),
+    /// Entry of the xterm 256 color palette, `48;5;n`
+    BackgroundPalette(u8),
+    /// 24 bit color, `38;2;r;g;b`
+    ForegroundRgb(u8, u8, u8),
+    /// 24 bit color, `48;2;r;g;b`
+    BackgroundRgb(u8, u8, u8),
     Unknown(usize),
 }
 
@@ -34,6 +73,16 @@ impl SelectGraphicRendition {
         match val {
             0 => SelectGraphicRendition::Reset,
             1 => SelectGraphicRendition::Bold,
+            2 => SelectGraphicRendition::Dim,
+            3 => SelectGraphicRendition::Italic,
+            4 => SelectGraphicRendition::Underline,
+            7 => SelectGraphicRendition::ReverseVideo,
+            9 => SelectGraphicRendition::Strikethrough,
+            22 => SelectGraphicRendition::NormalIntensity,
+            23 => SelectGraphicRendition::ItalicOff,
+            24 => SelectGraphicRendition::UnderlineOff,
+            27 => SelectGraphicRendition::ReverseVideoOff,
+            29 => SelectGraphicRendition::StrikethroughOff,
             30 => SelectGraphicRendition::ForegroundBlack,
             31 => SelectGraphicRendition::ForegroundRed,
             32 => SelectGraphicRendition::ForegroundGreen,
@@ -42,6 +91,7 @@ impl SelectGraphicRendition {
             35 => SelectGraphicRendition::ForegroundMagenta,
             36 => SelectGraphicRendition::ForegroundCyan,
             37 => SelectGraphicRendition::ForegroundWhite,
+            39 => SelectGraphicRendition::ForegroundDefault,
             90 => SelectGraphicRendition::ForegroundBrightBlack,
             91 => SelectGraphicRendition::ForegroundBrightRed,
             92 => SelectGraphicRendition::ForegroundBrightGreen,
@@ -50,6 +100,23 @@ impl SelectGraphicRendition {
             95 => SelectGraphicRendition::ForegroundBrightMagenta,
             96 => SelectGraphicRendition::ForegroundBrightCyan,
             97 => SelectGraphicRendition::ForegroundBrightWhite,
+            40 => SelectGraphicRendition::BackgroundBlack,
+            41 => SelectGraphicRendition::BackgroundRed,
+            42 => SelectGraphicRendition::BackgroundGreen,
+            43 => SelectGraphicRendition::BackgroundYellow,
+            44 => SelectGraphicRendition::BackgroundBlue,
+            45 => SelectGraphicRendition::BackgroundMagenta,
+            46 => SelectGraphicRendition::BackgroundCyan,
+            47 => SelectGraphicRendition::BackgroundWhite,
+            49 => SelectGraphicRendition::BackgroundDefault,
+            100 => SelectGraphicRendition::BackgroundBrightBlack,
+            101 => SelectGraphicRendition::BackgroundBrightRed,
+            102 => SelectGraphicRendition::BackgroundBrightGreen,
+            103 => SelectGraphicRendition::BackgroundBrightYellow,
+            104 => SelectGraphicRendition::BackgroundBrightBlue,
+            105 => SelectGraphicRendition::BackgroundBrightMagenta,
+            106 => SelectGraphicRendition::BackgroundBrightCyan,
+            107 => SelectGraphicRendition::BackgroundBrightWhite,
             _ => Self::Unknown(val),
         }
     }
@@ -57,15 +124,46 @@ impl SelectGraphicRendition {
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum TerminalOutput {
-    SetCursorPos { x: Option<usize>, y: Option<usize> },
-    SetCursorPosRel { x: Option<i32>, y: Option<i32> },
+    SetCursorPos {
+        x: Option<usize>,
+        y: Option<usize>,
+    },
+    SetCursorPosRel {
+        x: Option<i32>,
+        y: Option<i32>,
+    },
     ClearForwards,
     ClearAll,
     CarriageReturn,
     ClearLineForwards,
+    ClearLineBackwards,
+    ClearLineAll,
     Newline,
+    // IND, moves down, scrolling at the bottom margin
+    Index,
+    // RI, moves up, scrolling down at the top margin
+    ReverseIndex,
+    // RIS
+    FullReset,
+    // DECSC/SCOSC and DECRC/SCORC
+    SaveCursor,
+    RestoreCursor,
+    // DECSCUSR
+    SetCursorStyle(CursorStyle),
     Backspace,
+    Bell,
+    // HT, moves to the next tab stop
+    Tab,
+    // HTS
+    SetTabStop,
+    // TBC 0 and TBC 3
+    ClearTabStop,
+    ClearAllTabStops,
     InsertLines(usize),
+    DeleteLines(usize),
+    // SU and SD
+    ScrollUp(usize),
+    ScrollDown(usize),
     Delete(usize),
     Sgr(SelectGraphicRendition),
     Data(Vec<u8>),
@@ -73,6 +171,30 @@ pub enum TerminalOutput {
     ResetMode(Mode),
     // ich (8.3.64 of ecma-48)
     InsertSpaces(usize),
+    // ech (8.3.38 of ecma-48)
+    EraseCharacters(usize),
+    // DECCRA, 1 indexed. Bottom and right default to the edge of the screen
+    CopyRectangle {
+        src_top: usize,
+        src_left: usize,
+        src_bottom: Option<usize>,
+        src_right: Option<usize>,
+        dst_top: usize,
+        dst_left: usize,
+    },
+    // DECSTBM, 1 indexed. Bottom defaults to the edge of the screen
+    SetScrollRegion {
+        top: usize,
+        bottom: Option<usize>,
+    },
+    // OSC 52, decoded payload
+    SetClipboard(Vec<u8>),
+    // OSC 52 with a payload of ?
+    QueryClipboard,
+    // OSC 8, None ends the current hyperlink
+    SetHyperlink(Option<String>),
+    // OSC 0, 1 and 2
+    SetTitle(String),
     Invalid,
 }
 
@@ -107,19 +229,93 @@ fn is_csi_intermediate(b: u8) -> bool {
     (0x20..=0x2f).contains(&b)
 }
 
-fn extract_param(idx: usize, params: &[Option<usize>]) -> Option<usize> {
-    params.get(idx).copied().flatten()
+/// Extract the main value of the param at idx, ignoring any sub-params
+fn extract_param(idx: usize, params: &[Vec<Option<usize>>]) -> Option<usize> {
+    params
+        .get(idx)
+        .and_then(|param| param.first())
+        .copied()
+        .flatten()
 }
 
-fn split_params_into_semicolon_delimited_usize(params: &[u8]) -> Result<Vec<Option<usize>>, ()> {
+/// Split params on `;`, and each param into colon delimited sub-params. The first sub-param is
+/// the param value itself, e.g. `38:2:1:2:3;1` -> `[[38, 2, 1, 2, 3], [1]]`
+fn split_params_into_semicolon_delimited_usize(
+    params: &[u8],
+) -> Result<Vec<Vec<Option<usize>>>, ()> {
     let params = params
         .split(|b| *b == b';')
-        .map(parse_param_as::<usize>)
-        .collect::<Result<Vec<Option<usize>>, ()>>();
+        .map(|param| {
+            param
+                .split(|b| *b == b':')
+                .map(parse_param_as::<usize>)
+                .collect::<Result<Vec<Option<usize>>, ()>>()
+        })
+        .collect::<Result<Vec<Vec<Option<usize>>>, ()>>();
 
     params
 }
 
+/// Extended color SGRs (38/48) take their arguments either as colon delimited sub-params, or as
+/// the following semicolon delimited params. Returns the arguments in either case, consuming
+/// them from `remaining` if needed
+fn consume_extended_color_args<'a, It: Iterator<Item = &'a Vec<Option<usize>>>>(
+    param: &[Option<usize>],
+    remaining: &mut It,
+) -> Vec<Option<usize>> {
+    if param.len() > 1 {
+        return param[1..].to_vec();
+    }
+
+    let Some(color_type) = remaining.next().and_then(|p| p.first()).copied() else {
+        return Vec::new();
+    };
+
+    let num_args = match color_type {
+        Some(5) => 1,
+        Some(2) => 3,
+        _ => 0,
+    };
+
+    let mut args = vec![color_type];
+    args.extend(
+        remaining
+            .take(num_args)
+            .map(|p| p.first().copied().flatten()),
+    );
+    args
+}
+
+enum ExtendedColor {
+    Palette(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Interpret the arguments of an extended color SGR, e.g. `38` with `[5, 196]` for palette entry
+/// 196, or `[2, 255, 128, 0]` for orange
+fn extended_color_sgr(value: usize, args: &[Option<usize>]) -> Option<SelectGraphicRendition> {
+    let component = |c: &usize| u8::try_from(*c).ok();
+
+    let color = match args {
+        [Some(5), Some(idx)] => ExtendedColor::Palette(component(idx)?),
+        // The colon delimited form may have a color space id before the components
+        [Some(2), Some(r), Some(g), Some(b)] | [Some(2), _, Some(r), Some(g), Some(b)] => {
+            ExtendedColor::Rgb(component(r)?, component(g)?, component(b)?)
+        }
+        _ => return None,
+    };
+
+    let sgr = match (value, color) {
+        (38, ExtendedColor::Palette(idx)) => SelectGraphicRendition::ForegroundPalette(idx),
+        (48, ExtendedColor::Palette(idx)) => SelectGraphicRendition::BackgroundPalette(idx),
+        (38, ExtendedColor::Rgb(r, g, b)) => SelectGraphicRendition::ForegroundRgb(r, g, b),
+        (48, ExtendedColor::Rgb(r, g, b)) => SelectGraphicRendition::BackgroundRgb(r, g, b),
+        _ => return None,
+    };
+
+    Some(sgr)
+}
+
 fn parse_param_as<T: std::str::FromStr>(param_bytes: &[u8]) -> Result<Option<T>, ()> {
     let param_str =
         std::str::from_utf8(param_bytes).expect("parameter should always be valid utf8");
@@ -130,16 +326,77 @@ fn parse_param_as<T: std::str::FromStr>(param_bytes: &[u8]) -> Result<Option<T>,
     Ok(Some(param))
 }
 
-fn push_data_if_non_empty(data: &mut Vec<u8>, output: &mut Vec<TerminalOutput>) {
-    if !data.is_empty() {
-        output.push(TerminalOutput::Data(std::mem::take(data)));
+/// Interpret the body of an OSC sequence, i.e. everything between `ESC ]` and the terminator
+fn parse_osc(osc: &[u8]) -> TerminalOutput {
+    let mut split = osc.splitn(2, |b| *b == b';');
+    let ps = split.next().unwrap_or_default();
+    let pt = split.next().unwrap_or_default();
+
+    match ps {
+        // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h3-Operating-System-Commands
+        // OSC 1 only sets the icon name. There is no icon to name, but some applications only
+        // send that, so treat it as the title too
+        b"0" | b"1" | b"2" => TerminalOutput::SetTitle(String::from_utf8_lossy(pt).into_owned()),
+        b"52" => {
+            // Selection targets (clipboard, primary, etc.) all map to the system clipboard
+            let Some((_selection, data)) = pt
+                .iter()
+                .position(|b| *b == b';')
+                .map(|pos| (&pt[..pos], &pt[pos + 1..]))
+            else {
+                warn!("OSC 52 missing data");
+                return TerminalOutput::Invalid;
+            };
+
+            if data == b"?" {
+                return TerminalOutput::QueryClipboard;
+            }
+
+            match base64::engine::general_purpose::STANDARD.decode(data) {
+                Ok(decoded) => TerminalOutput::SetClipboard(decoded),
+                Err(e) => {
+                    warn!("OSC 52 data is not valid base64: {e}");
+                    TerminalOutput::Invalid
+                }
+            }
+        }
+        b"8" => {
+            // id=... style params are only used to join links split across lines, ignore them
+            let Some(uri_pos) = pt.iter().position(|b| *b == b';') else {
+                warn!("OSC 8 missing uri");
+                return TerminalOutput::Invalid;
+            };
+
+            let uri = &pt[uri_pos + 1..];
+            if uri.is_empty() {
+                TerminalOutput::SetHyperlink(None)
+            } else {
+                TerminalOutput::SetHyperlink(Some(String::from_utf8_lossy(uri).into_owned()))
+            }
+        }
+        _ => {
+            warn!("Unhandled osc: {:?}", String::from_utf8_lossy(ps));
+            TerminalOutput::Invalid
+        }
     }
 }
 
 fn mode_from_params(params: &[u8]) -> Mode {
     match params {
+        // https://vt100.net/docs/vt510-rm/IRM.html
+        b"4" => Mode::Irm,
         // https://vt100.net/docs/vt510-rm/DECCKM.html
         b"?1" => Mode::Decckm,
+        // https://vt100.net/docs/vt510-rm/DECOM.html
+        b"?6" => Mode::Decom,
+        // https://vt100.net/docs/vt510-rm/DECAWM.html
+        b"?7" => Mode::DecAwm,
+        // https://vt100.net/docs/vt510-rm/DECTCEM.html
+        b"?25" => Mode::DecTcem,
+        // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-The-Alternate-Screen-Buffer
+        b"?1049" => Mode::DecAltScreen,
+        // https://invisible-island.net/xterm/ctlseqs/ctlseqs.html#h2-Bracketed-Paste-Mode
+        b"?2004" => Mode::BracketedPaste,
         _ => Mode::Unknown(params.to_vec()),
     }
 }
@@ -351,24 +608,67 @@ enum AnsiParserInner {
     Empty,
     Escape,
     Csi(CsiParser),
+    Osc(Vec<u8>),
+    /// ESC inside of an OSC, which is either the start of the ST terminator or part of the OSC
+    /// data
+    OscEscape(Vec<u8>),
 }
 
+/// Longest OSC payload accepted. A stream that never terminates its OSC would otherwise grow the
+/// buffer until the parser timeout
+const MAX_OSC_LEN: usize = 64 * 1024;
+
 mod ansi_parser_keys {
     pub const EMPTY: &str = "empty";
     pub const ESCAPE: &str = "escape";
     pub const CSI: &str = "csi";
+    pub const OSC: &str = "osc";
+    pub const OSC_ESCAPE: &str = "osc_escape";
     pub const TYPE: &str = "type";
     pub const VAL: &str = "val";
+    pub const DATA: &str = "data";
+}
+
+/// Number of bytes at the end of data that belong to a utf8 character that has not been fully
+/// received yet
+pub(super) fn incomplete_utf8_len(data: &[u8]) -> usize {
+    // Characters are at most 4 bytes, so an incomplete one starts in the last 3
+    for (i, b) in data.iter().rev().take(3).enumerate() {
+        let char_len = match b.leading_ones() {
+            0 => return 0,
+            1 => continue,
+            n => n as usize,
+        };
+
+        if char_len > i + 1 && char_len <= 4 {
+            return i + 1;
+        }
+        return 0;
+    }
+    0
+}
+
+fn replace_invalid_utf8(data: Vec<u8>) -> Vec<u8> {
+    match String::from_utf8_lossy(&data) {
+        Cow::Borrowed(_) => data,
+        Cow::Owned(s) => s.into_bytes(),
+    }
 }
 
 pub struct AnsiParser {
     inner: AnsiParserInner,
+    /// Text that has not been emitted yet
+    data: Vec<u8>,
+    /// Outputs that have not been emitted yet
+    pending: VecDeque<TerminalOutput>,
 }
 
 impl AnsiParser {
     pub fn new() -> AnsiParser {
         AnsiParser {
             inner: AnsiParserInner::Empty,
+            data: Vec::new(),
+            pending: VecDeque::new(),
         }
     }
 
@@ -392,13 +692,59 @@ impl AnsiParser {
                     CsiParser::from_snapshot(item).map_err(LoadSnapshotErrorKind::Csi)?,
                 )
             }
+            ansi_parser_keys::OSC | ansi_parser_keys::OSC_ESCAPE => {
+                let item = root
+                    .remove(ansi_parser_keys::VAL)
+                    .ok_or(MissingElem("root", ansi_parser_keys::VAL))?;
+                let item = item
+                    .into_vec()
+                    .map_err(|_| WrongType(ansi_parser_keys::VAL, "array"))?;
+                let data = item
+                    .into_iter()
+                    .map(|b| b.into_num::<u8>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| WrongType(ansi_parser_keys::VAL, "u8 array"))?;
+                if typ == ansi_parser_keys::OSC {
+                    AnsiParserInner::Osc(data)
+                } else {
+                    AnsiParserInner::OscEscape(data)
+                }
+            }
             _ => Err(UnknownElem("type", typ))?,
         };
-        Ok(AnsiParser { inner })
+
+        // Optional, recordings from before partial characters were held back do not have it
+        let data = match root.remove(ansi_parser_keys::DATA) {
+            Some(data) => data
+                .into_vec()
+                .map_err(|_| WrongType(ansi_parser_keys::DATA, "array"))?
+                .into_iter()
+                .map(|b| b.into_num::<u8>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| WrongType(ansi_parser_keys::DATA, "u8 array"))?,
+            None => Vec::new(),
+        };
+
+        Ok(AnsiParser {
+            inner,
+            data,
+            pending: VecDeque::new(),
+        })
     }
 
-    pub fn snapshot(&self) -> SnapshotItem {
+    /// Which kind of sequence is being parsed, for debugging
+    pub fn state_name(&self) -> &'static str {
         match &self.inner {
+            AnsiParserInner::Empty => "empty",
+            AnsiParserInner::Escape => "escape",
+            AnsiParserInner::Csi(_) => "csi",
+            AnsiParserInner::Osc(_) => "osc",
+            AnsiParserInner::OscEscape(_) => "osc escape",
+        }
+    }
+
+    pub fn snapshot(&self) -> SnapshotItem {
+        let mut ret = match &self.inner {
             AnsiParserInner::Empty => SnapshotItem::Map(
                 [(
                     ansi_parser_keys::TYPE.to_string(),
@@ -423,282 +769,620 @@ impl AnsiParser {
                 ]
                 .into(),
             ),
+            AnsiParserInner::Osc(data) => SnapshotItem::Map(
+                [
+                    (
+                        ansi_parser_keys::TYPE.to_string(),
+                        ansi_parser_keys::OSC.into(),
+                    ),
+                    (ansi_parser_keys::VAL.to_string(), data.iter().collect()),
+                ]
+                .into(),
+            ),
+            AnsiParserInner::OscEscape(data) => SnapshotItem::Map(
+                [
+                    (
+                        ansi_parser_keys::TYPE.to_string(),
+                        ansi_parser_keys::OSC_ESCAPE.into(),
+                    ),
+                    (ansi_parser_keys::VAL.to_string(), data.iter().collect()),
+                ]
+                .into(),
+            ),
+        };
+
+        // Only the start of a character can be left over between calls to push
+        if let SnapshotItem::Map(map) = &mut ret {
+            if !self.data.is_empty() {
+                map.insert(
+                    ansi_parser_keys::DATA.to_string(),
+                    self.data.iter().collect(),
+                );
+            }
         }
+        ret
+    }
+
+    /// Abandon any partially parsed sequence. The bytes consumed so far are returned as data so
+    /// that they are not silently lost
+    pub fn reset(&mut self) -> Option<TerminalOutput> {
+        let mut buffered = std::mem::take(&mut self.data);
+        match std::mem::replace(&mut self.inner, AnsiParserInner::Empty) {
+            AnsiParserInner::Empty => (),
+            AnsiParserInner::Escape => buffered.push(b'\x1b'),
+            AnsiParserInner::Csi(parser) => {
+                buffered.extend_from_slice(b"\x1b[");
+                buffered.extend_from_slice(&parser.params);
+                buffered.extend_from_slice(&parser.intermediates);
+            }
+            AnsiParserInner::Osc(data) => {
+                buffered.extend_from_slice(b"\x1b]");
+                buffered.extend_from_slice(&data);
+            }
+            AnsiParserInner::OscEscape(data) => {
+                buffered.extend_from_slice(b"\x1b]");
+                buffered.extend_from_slice(&data);
+                buffered.push(b'\x1b');
+            }
+        };
+
+        if buffered.is_empty() {
+            return None;
+        }
+
+        Some(TerminalOutput::Data(replace_invalid_utf8(buffered)))
+    }
+
+    /// Process one byte. Returns a completed output if one was produced, or None if still
+    /// accumulating. Plain text is held until the next non-text output or [`AnsiParser::flush`].
+    /// Some sequences produce several outputs (e.g. SGR with multiple parameters), the rest are
+    /// available from [`AnsiParser::next_output`]
+    pub fn push_byte(&mut self, b: u8) -> Option<TerminalOutput> {
+        if let Some(b) = self.parse_byte(b) {
+            self.data.push(b);
+            return None;
+        }
+
+        if !self.pending.is_empty() && !self.data.is_empty() {
+            return self.flush();
+        }
+
+        self.next_output()
+    }
+
+    /// Outputs queued by [`AnsiParser::push_byte`]
+    pub fn next_output(&mut self) -> Option<TerminalOutput> {
+        self.pending.pop_front()
+    }
+
+    /// Text accumulated by [`AnsiParser::push_byte`]. Invalid utf8 is replaced so that the
+    /// emitted text is always valid
+    pub fn flush(&mut self) -> Option<TerminalOutput> {
+        if self.data.is_empty() {
+            return None;
+        }
+
+        let data = std::mem::take(&mut self.data);
+        Some(TerminalOutput::Data(replace_invalid_utf8(data)))
     }
 
     pub fn push(&mut self, incoming: &[u8]) -> Vec<TerminalOutput> {
         let mut output = Vec::new();
-        let mut data_output = Vec::new();
         for b in incoming {
-            match &mut self.inner {
-                AnsiParserInner::Empty => {
-                    if *b == b'\x1b' {
-                        self.inner = AnsiParserInner::Escape;
-                        continue;
-                    }
+            output.extend(self.push_byte(*b));
+            while let Some(item) = self.next_output() {
+                output.push(item);
+            }
+        }
 
-                    if *b == b'\r' {
-                        push_data_if_non_empty(&mut data_output, &mut output);
-                        output.push(TerminalOutput::CarriageReturn);
-                        continue;
-                    }
+        // A read may end part way through a character, hold on to it until the rest arrives
+        let incomplete_len = incomplete_utf8_len(&self.data);
+        let incomplete = self.data.split_off(self.data.len() - incomplete_len);
+        output.extend(self.flush());
+        self.data = incomplete;
+        output
+    }
 
-                    if *b == b'\n' {
-                        push_data_if_non_empty(&mut data_output, &mut output);
-                        output.push(TerminalOutput::Newline);
-                        continue;
-                    }
+    /// Advance the state machine by one byte. Plain data is handed back to the caller, any other
+    /// output is queued in self.pending
+    fn parse_byte(&mut self, b: u8) -> Option<u8> {
+        match &mut self.inner {
+            AnsiParserInner::Empty => {
+                if b == b'\x1b' {
+                    self.inner = AnsiParserInner::Escape;
+                    return None;
+                }
 
-                    if *b == 0x08 {
-                        push_data_if_non_empty(&mut data_output, &mut output);
-                        output.push(TerminalOutput::Backspace);
-                        continue;
-                    }
+                if b == b'\r' {
+                    self.pending.push_back(TerminalOutput::CarriageReturn);
+                    return None;
+                }
 
-                    data_output.push(*b);
+                if b == b'\n' {
+                    self.pending.push_back(TerminalOutput::Newline);
+                    return None;
                 }
-                AnsiParserInner::Escape => {
-                    push_data_if_non_empty(&mut data_output, &mut output);
 
-                    match b {
-                        b'[' => {
-                            self.inner = AnsiParserInner::Csi(CsiParser::new());
-                        }
-                        _ => {
-                            let b_utf8 = std::char::from_u32(*b as u32);
-                            warn!("Unhandled escape sequence {b_utf8:?} {b:x}");
-                            self.inner = AnsiParserInner::Empty;
-                        }
-                    }
+                if b == 0x08 {
+                    self.pending.push_back(TerminalOutput::Backspace);
+                    return None;
                 }
-                AnsiParserInner::Csi(parser) => {
-                    parser.push(*b);
-                    match parser.state {
-                        CsiParserState::Finished(b'A') => {
-                            let Ok(param) = parse_param_as::<i32>(&parser.params) else {
-                                warn!("Invalid cursor move up distance");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
 
-                            output.push(TerminalOutput::SetCursorPosRel {
-                                x: None,
-                                y: Some(-param.unwrap_or(1)),
-                            });
-                            self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'B') => {
-                            let Ok(param) = parse_param_as::<i32>(&parser.params) else {
-                                warn!("Invalid cursor move down distance");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
+                if b == 0x07 {
+                    self.pending.push_back(TerminalOutput::Bell);
+                    return None;
+                }
+
+                if b == b'\t' {
+                    self.pending.push_back(TerminalOutput::Tab);
+                    return None;
+                }
 
-                            output.push(TerminalOutput::SetCursorPosRel {
-                                x: None,
-                                y: Some(param.unwrap_or(1)),
-                            });
+                return Some(b);
+            }
+            AnsiParserInner::Escape => match b {
+                b'[' => {
+                    self.inner = AnsiParserInner::Csi(CsiParser::new());
+                }
+                b']' => {
+                    self.inner = AnsiParserInner::Osc(Vec::new());
+                }
+                b'D' => {
+                    self.pending.push_back(TerminalOutput::Index);
+                    self.inner = AnsiParserInner::Empty;
+                }
+                b'M' => {
+                    self.pending.push_back(TerminalOutput::ReverseIndex);
+                    self.inner = AnsiParserInner::Empty;
+                }
+                b'c' => {
+                    self.pending.push_back(TerminalOutput::FullReset);
+                    self.inner = AnsiParserInner::Empty;
+                }
+                b'H' => {
+                    self.pending.push_back(TerminalOutput::SetTabStop);
+                    self.inner = AnsiParserInner::Empty;
+                }
+                b'7' => {
+                    self.pending.push_back(TerminalOutput::SaveCursor);
+                    self.inner = AnsiParserInner::Empty;
+                }
+                b'8' => {
+                    self.pending.push_back(TerminalOutput::RestoreCursor);
+                    self.inner = AnsiParserInner::Empty;
+                }
+                _ => {
+                    let b_utf8 = std::char::from_u32(b as u32);
+                    warn!("Unhandled escape sequence {b_utf8:?} {b:x}");
+                    self.inner = AnsiParserInner::Empty;
+                }
+            },
+            AnsiParserInner::Osc(data) => match b {
+                0x07 => {
+                    self.pending.push_back(parse_osc(data));
+                    self.inner = AnsiParserInner::Empty;
+                }
+                b'\x1b' => {
+                    self.inner = AnsiParserInner::OscEscape(std::mem::take(data));
+                }
+                _ if data.len() >= MAX_OSC_LEN => {
+                    warn!("OSC longer than {MAX_OSC_LEN} bytes, aborting");
+                    self.pending.push_back(TerminalOutput::Invalid);
+                    self.inner = AnsiParserInner::Empty;
+                }
+                _ => data.push(b),
+            },
+            AnsiParserInner::OscEscape(data) => {
+                if b == b'\\' {
+                    // ST
+                    self.pending.push_back(parse_osc(data));
+                    self.inner = AnsiParserInner::Empty;
+                } else {
+                    // Not ST, so the ESC is part of the OSC data. A sequence that never
+                    // terminates is given up on by the parser timeout
+                    let mut data = std::mem::take(data);
+                    data.push(b'\x1b');
+                    self.inner = AnsiParserInner::Osc(data);
+                    return self.parse_byte(b);
+                }
+            }
+            AnsiParserInner::Csi(parser) => {
+                parser.push(b);
+                match parser.state {
+                    CsiParserState::Finished(b'A') => {
+                        let Ok(param) = parse_param_as::<i32>(&parser.params) else {
+                            warn!("Invalid cursor move up distance");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'C') => {
-                            let Ok(param) = parse_param_as::<i32>(&parser.params) else {
-                                warn!("Invalid cursor move right distance");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
+                            return None;
+                        };
+
+                        self.pending.push_back(TerminalOutput::SetCursorPosRel {
+                            x: None,
+                            y: Some(-param.unwrap_or(1)),
+                        });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'B') => {
+                        let Ok(param) = parse_param_as::<i32>(&parser.params) else {
+                            warn!("Invalid cursor move down distance");
+                            self.pending.push_back(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
+
+                        self.pending.push_back(TerminalOutput::SetCursorPosRel {
+                            x: None,
+                            y: Some(param.unwrap_or(1)),
+                        });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'C') => {
+                        let Ok(param) = parse_param_as::<i32>(&parser.params) else {
+                            warn!("Invalid cursor move right distance");
+                            self.pending.push_back(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
+
+                        self.pending.push_back(TerminalOutput::SetCursorPosRel {
+                            x: Some(param.unwrap_or(1)),
+                            y: None,
+                        });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'D') => {
+                        let Ok(param) = parse_param_as::<i32>(&parser.params) else {
+                            warn!("Invalid cursor move left distance");
+                            self.pending.push_back(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
+
+                        self.pending.push_back(TerminalOutput::SetCursorPosRel {
+                            x: Some(-param.unwrap_or(1)),
+                            y: None,
+                        });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b @ (b'E' | b'F')) => {
+                        let Ok(param) = parse_param_as::<i32>(&parser.params) else {
+                            warn!("Invalid cursor line move distance");
+                            self.pending.push_back(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
+
+                        // CNL and CPL, vertical move to the first column
+                        let distance = param.unwrap_or(1);
+                        let y = if b == b'E' { distance } else { -distance };
+                        self.pending.push_back(TerminalOutput::SetCursorPosRel {
+                            x: None,
+                            y: Some(y),
+                        });
+                        self.pending.push_back(TerminalOutput::SetCursorPos {
+                            x: Some(1),
+                            y: None,
+                        });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'H') => {
+                        let params = split_params_into_semicolon_delimited_usize(&parser.params);
 
-                            output.push(TerminalOutput::SetCursorPosRel {
-                                x: Some(param.unwrap_or(1)),
-                                y: None,
-                            });
+                        let Ok(params) = params else {
+                            warn!("Invalid cursor set position sequence");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'D') => {
-                            let Ok(param) = parse_param_as::<i32>(&parser.params) else {
-                                warn!("Invalid cursor move left distance");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
+                            return None;
+                        };
+
+                        self.pending.push_back(TerminalOutput::SetCursorPos {
+                            x: Some(extract_param(1, &params).unwrap_or(1)),
+                            y: Some(extract_param(0, &params).unwrap_or(1)),
+                        });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'G') => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid cursor set position sequence");
+                            self.pending.push_back(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
 
-                            output.push(TerminalOutput::SetCursorPosRel {
-                                x: Some(-param.unwrap_or(1)),
-                                y: None,
-                            });
+                        let x_pos = param.unwrap_or(1);
+
+                        self.pending.push_back(TerminalOutput::SetCursorPos {
+                            x: Some(x_pos),
+                            y: None,
+                        });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'd') => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid line position sequence");
+                            self.pending.push_back(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
+
+                        // VPA (8.3.158 of ecma-48)
+                        self.pending.push_back(TerminalOutput::SetCursorPos {
+                            x: None,
+                            y: Some(param.unwrap_or(1)),
+                        });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'J') => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid clear command");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
+
+                        let ret = match param.unwrap_or(0) {
+                            0 => TerminalOutput::ClearForwards,
+                            2 | 3 => TerminalOutput::ClearAll,
+                            _ => TerminalOutput::Invalid,
+                        };
+                        self.pending.push_back(ret);
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'K') => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid erase in line command");
+                            self.pending.push_back(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
+
+                        // ECMA-48 8.3.39
+                        match param.unwrap_or(0) {
+                            0 => self.pending.push_back(TerminalOutput::ClearLineForwards),
+                            1 => self.pending.push_back(TerminalOutput::ClearLineBackwards),
+                            2 => self.pending.push_back(TerminalOutput::ClearLineAll),
+                            v => {
+                                warn!("Unsupported erase in line command ({v})");
+                                self.pending.push_back(TerminalOutput::Invalid);
+                            }
                         }
-                        CsiParserState::Finished(b'H') => {
-                            let params =
-                                split_params_into_semicolon_delimited_usize(&parser.params);
-
-                            let Ok(params) = params else {
-                                warn!("Invalid cursor set position sequence");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
 
-                            output.push(TerminalOutput::SetCursorPos {
-                                x: Some(extract_param(1, &params).unwrap_or(1)),
-                                y: Some(extract_param(0, &params).unwrap_or(1)),
-                            });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'L') => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid il command");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'G') => {
-                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
-                                warn!("Invalid cursor set position sequence");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
+                            return None;
+                        };
 
-                            let x_pos = param.unwrap_or(1);
+                        self.pending
+                            .push_back(TerminalOutput::InsertLines(param.unwrap_or(1)));
 
-                            output.push(TerminalOutput::SetCursorPos {
-                                x: Some(x_pos),
-                                y: None,
-                            });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'M') => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid dl command");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'J') => {
-                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
-                                warn!("Invalid clear command");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
+                            return None;
+                        };
 
-                            let ret = match param.unwrap_or(0) {
-                                0 => TerminalOutput::ClearForwards,
-                                2 | 3 => TerminalOutput::ClearAll,
-                                _ => TerminalOutput::Invalid,
-                            };
-                            output.push(ret);
+                        self.pending
+                            .push_back(TerminalOutput::DeleteLines(param.unwrap_or(1)));
+
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'P') => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid del command");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'K') => {
-                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
-                                warn!("Invalid erase in line command");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
+                            return None;
+                        };
 
-                            // ECMA-48 8.3.39
-                            match param.unwrap_or(0) {
-                                0 => output.push(TerminalOutput::ClearLineForwards),
-                                v => {
-                                    warn!("Unsupported erase in line command ({v})");
-                                    output.push(TerminalOutput::Invalid);
-                                }
-                            }
+                        self.pending
+                            .push_back(TerminalOutput::Delete(param.unwrap_or(1)));
 
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b @ (b'S' | b'T')) => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid scroll distance");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'L') => {
-                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
-                                warn!("Invalid il command");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
+                            return None;
+                        };
+
+                        let num_lines = param.unwrap_or(1);
+                        let output = if b == b'S' {
+                            TerminalOutput::ScrollUp(num_lines)
+                        } else {
+                            TerminalOutput::ScrollDown(num_lines)
+                        };
+                        self.pending.push_back(output);
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'X') => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid erase character request");
+                            self.pending.push_back(TerminalOutput::Invalid);
+                            self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
 
-                            output.push(TerminalOutput::InsertLines(param.unwrap_or(1)));
+                        self.pending
+                            .push_back(TerminalOutput::EraseCharacters(param.unwrap_or(1)));
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'g') => {
+                        let output = match parse_param_as::<usize>(&parser.params) {
+                            Ok(None | Some(0)) => TerminalOutput::ClearTabStop,
+                            Ok(Some(3)) => TerminalOutput::ClearAllTabStops,
+                            _ => {
+                                warn!("Unhandled tab clear request");
+                                TerminalOutput::Invalid
+                            }
+                        };
+                        self.pending.push_back(output);
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'm') => {
+                        let params = split_params_into_semicolon_delimited_usize(&parser.params);
 
+                        let Ok(mut params) = params else {
+                            warn!("Invalid SGR sequence");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'P') => {
-                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
-                                warn!("Invalid del command");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
+                            return None;
+                        };
 
-                            output.push(TerminalOutput::Delete(param.unwrap_or(1)));
+                        if params.is_empty() {
+                            params.push(vec![Some(0)]);
+                        }
 
-                            self.inner = AnsiParserInner::Empty;
+                        if params.len() == 1 && extract_param(0, &params).is_none() {
+                            params[0] = vec![Some(0)];
                         }
-                        CsiParserState::Finished(b'm') => {
-                            let params =
-                                split_params_into_semicolon_delimited_usize(&parser.params);
-
-                            let Ok(mut params) = params else {
-                                warn!("Invalid SGR sequence");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
+
+                        let mut it = params.iter();
+                        while let Some(param) = it.next() {
+                            let Some(value) = param.first().copied().flatten() else {
                                 continue;
                             };
 
-                            if params.is_empty() {
-                                params.push(Some(0));
-                            }
-
-                            if params.len() == 1 && params[0].is_none() {
-                                params[0] = Some(0);
-                            }
-
-                            for param in params {
-                                let Some(param) = param else {
+                            if value == 38 || value == 48 {
+                                // Unsupported extended colors still have their arguments
+                                // consumed, so that they are not interpreted as SGRs themselves
+                                let args = consume_extended_color_args(param, &mut it);
+                                if let Some(sgr) = extended_color_sgr(value, &args) {
+                                    self.pending.push_back(TerminalOutput::Sgr(sgr));
                                     continue;
-                                };
-                                output.push(TerminalOutput::Sgr(
-                                    SelectGraphicRendition::from_usize(param),
-                                ));
+                                }
+                                warn!("Unsupported extended color sgr {value} {args:?}");
                             }
 
-                            self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'h') => {
-                            output.push(TerminalOutput::SetMode(mode_from_params(&parser.params)));
-                            self.inner = AnsiParserInner::Empty;
+                            self.pending.push_back(TerminalOutput::Sgr(
+                                SelectGraphicRendition::from_usize(value),
+                            ));
                         }
-                        CsiParserState::Finished(b'l') => {
-                            output
-                                .push(TerminalOutput::ResetMode(mode_from_params(&parser.params)));
+
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'h') => {
+                        self.pending
+                            .push_back(TerminalOutput::SetMode(mode_from_params(&parser.params)));
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'l') => {
+                        self.pending
+                            .push_back(TerminalOutput::ResetMode(mode_from_params(&parser.params)));
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'@') => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid ich command");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(b'@') => {
-                            let Ok(param) = parse_param_as::<usize>(&parser.params) else {
-                                warn!("Invalid ich command");
-                                output.push(TerminalOutput::Invalid);
-                                self.inner = AnsiParserInner::Empty;
-                                continue;
-                            };
+                            return None;
+                        };
 
-                            // ecma-48 8.3.64
-                            output.push(TerminalOutput::InsertSpaces(param.unwrap_or(1)));
+                        // ecma-48 8.3.64
+                        self.pending
+                            .push_back(TerminalOutput::InsertSpaces(param.unwrap_or(1)));
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'v') if parser.intermediates == b"$" => {
+                        let params = split_params_into_semicolon_delimited_usize(&parser.params);
+
+                        let Ok(params) = params else {
+                            warn!("Invalid DECCRA sequence");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        CsiParserState::Finished(esc) => {
-                            warn!(
-                                "Unhandled csi code: {:?} {esc:x} {}/{}",
-                                std::char::from_u32(esc as u32),
-                                esc >> 4,
-                                esc & 0xf,
-                            );
-                            output.push(TerminalOutput::Invalid);
+                            return None;
+                        };
+
+                        // https://vt100.net/docs/vt510-rm/DECCRA.html
+                        // Page parameters (4 and 7) are ignored, we only have one page
+                        self.pending.push_back(TerminalOutput::CopyRectangle {
+                            src_top: extract_param(0, &params).unwrap_or(1),
+                            src_left: extract_param(1, &params).unwrap_or(1),
+                            src_bottom: extract_param(2, &params),
+                            src_right: extract_param(3, &params),
+                            dst_top: extract_param(5, &params).unwrap_or(1),
+                            dst_left: extract_param(6, &params).unwrap_or(1),
+                        });
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'q') if parser.intermediates == b" " => {
+                        let Ok(param) = parse_param_as::<usize>(&parser.params) else {
+                            warn!("Invalid DECSCUSR sequence");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
+                            return None;
+                        };
+
+                        match CursorStyle::from_param(param.unwrap_or(0)) {
+                            Some(style) => self
+                                .pending
+                                .push_back(TerminalOutput::SetCursorStyle(style)),
+                            None => {
+                                warn!("Unknown cursor style {param:?}");
+                                self.pending.push_back(TerminalOutput::Invalid);
+                            }
                         }
-                        CsiParserState::Invalid => {
-                            warn!("Invalid CSI sequence");
-                            output.push(TerminalOutput::Invalid);
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    // Only the parameterless forms, e.g. CSI ? u belongs to other extensions
+                    CsiParserState::Finished(b's') if parser.params.is_empty() => {
+                        self.pending.push_back(TerminalOutput::SaveCursor);
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'u') if parser.params.is_empty() => {
+                        self.pending.push_back(TerminalOutput::RestoreCursor);
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Finished(b'r') => {
+                        let params = split_params_into_semicolon_delimited_usize(&parser.params);
+
+                        let Ok(params) = params else {
+                            warn!("Invalid DECSTBM sequence");
+                            self.pending.push_back(TerminalOutput::Invalid);
                             self.inner = AnsiParserInner::Empty;
-                        }
-                        _ => {}
+                            return None;
+                        };
+
+                        // https://vt100.net/docs/vt510-rm/DECSTBM.html
+                        self.pending.push_back(TerminalOutput::SetScrollRegion {
+                            top: extract_param(0, &params).unwrap_or(1),
+                            bottom: extract_param(1, &params),
+                        });
+                        self.inner = AnsiParserInner::Empty;
                     }
+                    CsiParserState::Finished(esc) => {
+                        warn!(
+                            "Unhandled csi code: {:?} {esc:x} {}/{}",
+                            std::char::from_u32(esc as u32),
+                            esc >> 4,
+                            esc & 0xf,
+                        );
+                        self.pending.push_back(TerminalOutput::Invalid);
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    CsiParserState::Invalid => {
+                        warn!("Invalid CSI sequence");
+                        self.pending.push_back(TerminalOutput::Invalid);
+                        self.inner = AnsiParserInner::Empty;
+                    }
+                    _ => {}
                 }
             }
         }
 
-        if !data_output.is_empty() {
-            output.push(TerminalOutput::Data(data_output));
-        }
-
-        output
+        None
     }
 }
 
@@ -706,6 +1390,433 @@ impl AnsiParser {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_push_byte() {
+        let mut output_buffer = AnsiParser::new();
+        assert_eq!(output_buffer.push_byte(b'a'), None);
+        assert_eq!(output_buffer.push_byte(b'b'), None);
+        assert_eq!(output_buffer.push_byte(b'\x1b'), None);
+        assert_eq!(output_buffer.push_byte(b'['), None);
+        assert_eq!(output_buffer.push_byte(b'1'), None);
+        assert_eq!(output_buffer.push_byte(b';'), None);
+        assert_eq!(output_buffer.push_byte(b'3'), None);
+        assert_eq!(output_buffer.push_byte(b'1'), None);
+        assert_eq!(
+            output_buffer.push_byte(b'm'),
+            Some(TerminalOutput::Data(b"ab".to_vec()))
+        );
+        assert_eq!(
+            output_buffer.next_output(),
+            Some(TerminalOutput::Sgr(SelectGraphicRendition::Bold))
+        );
+        assert_eq!(
+            output_buffer.next_output(),
+            Some(TerminalOutput::Sgr(SelectGraphicRendition::ForegroundRed))
+        );
+        assert_eq!(output_buffer.next_output(), None);
+
+        assert_eq!(output_buffer.push_byte(b'c'), None);
+        assert_eq!(
+            output_buffer.flush(),
+            Some(TerminalOutput::Data(b"c".to_vec()))
+        );
+        assert_eq!(output_buffer.flush(), None);
+
+        assert_eq!(
+            output_buffer.push_byte(b'\n'),
+            Some(TerminalOutput::Newline)
+        );
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut output_buffer = AnsiParser::new();
+        assert_eq!(output_buffer.reset(), None);
+
+        assert!(output_buffer.push(b"\x1b[1;3").is_empty());
+        assert_eq!(
+            output_buffer.reset(),
+            Some(TerminalOutput::Data(b"\x1b[1;3".to_vec()))
+        );
+        assert_eq!(output_buffer.reset(), None);
+
+        // Parsing continues as normal afterwards
+        let parsed = output_buffer.push(b"4m");
+        assert_eq!(parsed, &[TerminalOutput::Data(b"4m".to_vec())]);
+
+        assert!(output_buffer.push(b"\x1b]52;c").is_empty());
+        assert_eq!(
+            output_buffer.reset(),
+            Some(TerminalOutput::Data(b"\x1b]52;c".to_vec()))
+        );
+    }
+
+    #[test]
+    fn test_osc8() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b]8;id=1;https://example.com\x07link\x1b]8;;\x07");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::SetHyperlink(Some("https://example.com".to_string())),
+                TerminalOutput::Data(b"link".to_vec()),
+                TerminalOutput::SetHyperlink(None),
+            ]
+        );
+
+        let parsed = output_buffer.push(b"\x1b]8;https://example.com\x07");
+        assert_eq!(parsed, &[TerminalOutput::Invalid]);
+    }
+
+    #[test]
+    fn test_osc_title() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b]0;vim\x07\x1b]2;user@host: ~\x1b\\\x1b]1;icon\x07");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::SetTitle("vim".to_string()),
+                TerminalOutput::SetTitle("user@host: ~".to_string()),
+                TerminalOutput::SetTitle("icon".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_osc_st_terminator() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::SetHyperlink(Some("https://example.com".to_string())),
+                TerminalOutput::Data(b"link".to_vec()),
+            ]
+        );
+
+        // ST split across reads
+        let parsed = output_buffer.push(b"\\");
+        assert_eq!(parsed, &[TerminalOutput::SetHyperlink(None)]);
+
+        // ESC that does not form ST is kept in the OSC
+        let parsed = output_buffer.push(b"\x1b]8;;a\x1bb\x07");
+        assert_eq!(
+            parsed,
+            &[TerminalOutput::SetHyperlink(Some("a\x1bb".to_string()))]
+        );
+
+        // The byte after it is parsed as part of the OSC too, so it can start ST
+        let parsed = output_buffer.push(b"\x1b]8;;a\x1b\x1b\\");
+        assert_eq!(
+            parsed,
+            &[TerminalOutput::SetHyperlink(Some("a\x1b".to_string()))]
+        );
+    }
+
+    #[test]
+    fn test_osc_len_limit() {
+        let mut output_buffer = AnsiParser::new();
+        let mut data = b"\x1b]0;".to_vec();
+        data.resize(MAX_OSC_LEN + 2, b'a');
+        assert_eq!(output_buffer.push(&data), &[]);
+
+        let parsed = output_buffer.push(b"a\x07");
+        assert_eq!(parsed, &[TerminalOutput::Invalid, TerminalOutput::Bell]);
+        assert_eq!(output_buffer.inner, AnsiParserInner::Empty);
+    }
+
+    #[test]
+    fn test_osc52() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"a\x1b]52;c;aGVsbG8=\x07b");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::Data(b"a".to_vec()),
+                TerminalOutput::SetClipboard(b"hello".to_vec()),
+                TerminalOutput::Data(b"b".to_vec()),
+            ]
+        );
+
+        // Split across pushes
+        assert!(output_buffer.push(b"\x1b]52;;aGVs").is_empty());
+        let parsed = output_buffer.push(b"bG8=\x07");
+        assert_eq!(parsed, &[TerminalOutput::SetClipboard(b"hello".to_vec())]);
+
+        let parsed = output_buffer.push(b"\x1b]52;c;?\x07");
+        assert_eq!(parsed, &[TerminalOutput::QueryClipboard]);
+
+        let parsed = output_buffer.push(b"\x1b]52;c;!!!\x07");
+        assert_eq!(parsed, &[TerminalOutput::Invalid]);
+    }
+
+    #[test]
+    fn test_deccra() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b[2;3;4;5;1;6;7;1$v");
+        assert_eq!(
+            parsed,
+            &[TerminalOutput::CopyRectangle {
+                src_top: 2,
+                src_left: 3,
+                src_bottom: Some(4),
+                src_right: Some(5),
+                dst_top: 6,
+                dst_left: 7,
+            }]
+        );
+
+        let parsed = output_buffer.push(b"\x1b[$v");
+        assert_eq!(
+            parsed,
+            &[TerminalOutput::CopyRectangle {
+                src_top: 1,
+                src_left: 1,
+                src_bottom: None,
+                src_right: None,
+                dst_top: 1,
+                dst_left: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_decstbm() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b[2;5r\x1b[r");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::SetScrollRegion {
+                    top: 2,
+                    bottom: Some(5),
+                },
+                TerminalOutput::SetScrollRegion {
+                    top: 1,
+                    bottom: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_index_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"a\x1bDb\x1bM");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::Data(b"a".to_vec()),
+                TerminalOutput::Index,
+                TerminalOutput::Data(b"b".to_vec()),
+                TerminalOutput::ReverseIndex,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_save_restore_cursor_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b7\x1b8\x1b[s\x1b[u\x1b[?u");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::SaveCursor,
+                TerminalOutput::RestoreCursor,
+                TerminalOutput::SaveCursor,
+                TerminalOutput::RestoreCursor,
+                TerminalOutput::Invalid,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tab_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"a\tb\x1bH\x1b[g\x1b[0g\x1b[3g\x1b[2g");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::Data(b"a".into()),
+                TerminalOutput::Tab,
+                TerminalOutput::Data(b"b".into()),
+                TerminalOutput::SetTabStop,
+                TerminalOutput::ClearTabStop,
+                TerminalOutput::ClearTabStop,
+                TerminalOutput::ClearAllTabStops,
+                TerminalOutput::Invalid,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_full_reset_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1bc\x1b[");
+        assert_eq!(parsed, &[TerminalOutput::FullReset]);
+        assert_eq!(output_buffer.state_name(), "csi");
+    }
+
+    #[test]
+    fn test_decscusr() {
+        let mut output_buffer = AnsiParser::new();
+        let parsed = output_buffer.push(b"\x1b[ q\x1b[2 q\x1b[3 q\x1b[4 q\x1b[5 q\x1b[6 q");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::SetCursorStyle(CursorStyle::BlinkingBlock),
+                TerminalOutput::SetCursorStyle(CursorStyle::SteadyBlock),
+                TerminalOutput::SetCursorStyle(CursorStyle::BlinkingUnderline),
+                TerminalOutput::SetCursorStyle(CursorStyle::SteadyUnderline),
+                TerminalOutput::SetCursorStyle(CursorStyle::BlinkingBar),
+                TerminalOutput::SetCursorStyle(CursorStyle::SteadyBar),
+            ]
+        );
+
+        let parsed = output_buffer.push(b"\x1b[7 q");
+        assert_eq!(parsed, &[TerminalOutput::Invalid]);
+    }
+
+    #[test]
+    fn test_split_sub_params() {
+        assert_eq!(
+            split_params_into_semicolon_delimited_usize(b"38:2:1:2:3;;1"),
+            Ok(vec![
+                vec![Some(38), Some(2), Some(1), Some(2), Some(3)],
+                vec![None],
+                vec![Some(1)],
+            ])
+        );
+        assert_eq!(
+            split_params_into_semicolon_delimited_usize(b"4:3"),
+            Ok(vec![vec![Some(4), Some(3)]])
+        );
+        assert_eq!(split_params_into_semicolon_delimited_usize(b"1:a"), Err(()));
+    }
+
+    #[test]
+    fn test_sgr_extended_color_args() {
+        let mut output_buffer = AnsiParser::new();
+
+        // Arguments to 38/48 should not be treated as SGRs, regardless of delimiter
+        for (seq, color) in [
+            (
+                b"\x1b[38:2:255:0:1;1m".as_slice(),
+                SelectGraphicRendition::ForegroundRgb(255, 0, 1),
+            ),
+            (
+                b"\x1b[38:2::255:0:1;1m",
+                SelectGraphicRendition::ForegroundRgb(255, 0, 1),
+            ),
+            (
+                b"\x1b[38;2;255;0;1;1m",
+                SelectGraphicRendition::ForegroundRgb(255, 0, 1),
+            ),
+            (b"\x1b[38;2;256;0;1;1m", SelectGraphicRendition::Unknown(38)),
+            (
+                b"\x1b[38:5:1;1m",
+                SelectGraphicRendition::ForegroundPalette(1),
+            ),
+            (
+                b"\x1b[38;5;1;1m",
+                SelectGraphicRendition::ForegroundPalette(1),
+            ),
+            (b"\x1b[38;5;256;1m", SelectGraphicRendition::Unknown(38)),
+        ] {
+            let parsed = output_buffer.push(seq);
+            assert_eq!(
+                parsed,
+                &[
+                    TerminalOutput::Sgr(color),
+                    TerminalOutput::Sgr(SelectGraphicRendition::Bold),
+                ]
+            );
+        }
+
+        let parsed = output_buffer.push(b"\x1b[48;5;232m");
+        assert_eq!(
+            parsed,
+            &[TerminalOutput::Sgr(
+                SelectGraphicRendition::BackgroundPalette(232)
+            )]
+        );
+    }
+
+    #[test]
+    fn test_line_position_absolute() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[5d\x1b[d");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetCursorPos {
+                    x: None,
+                    y: Some(5)
+                },
+                TerminalOutput::SetCursorPos {
+                    x: None,
+                    y: Some(1)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_erase_in_line_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[K\x1b[1K\x1b[2K\x1b[3K");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::ClearLineForwards,
+                TerminalOutput::ClearLineBackwards,
+                TerminalOutput::ClearLineAll,
+                TerminalOutput::Invalid,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_delete_lines_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[3M\x1b[M");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::DeleteLines(3),
+                TerminalOutput::DeleteLines(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scroll_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[3S\x1b[S\x1b[2T\x1b[T");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::ScrollUp(3),
+                TerminalOutput::ScrollUp(1),
+                TerminalOutput::ScrollDown(2),
+                TerminalOutput::ScrollDown(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_erase_characters_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[4X\x1b[X");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::EraseCharacters(4),
+                TerminalOutput::EraseCharacters(1),
+            ]
+        );
+    }
+
     #[test]
     fn test_set_cursor_position() {
         let mut output_buffer = AnsiParser::new();
@@ -918,6 +2029,21 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_text_attribute_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        for (seq, sgr) in [
+            (b"\x1b[9m".as_slice(), SelectGraphicRendition::Strikethrough),
+            (b"\x1b[29m", SelectGraphicRendition::StrikethroughOff),
+            (b"\x1b[2m", SelectGraphicRendition::Dim),
+            (b"\x1b[7m", SelectGraphicRendition::ReverseVideo),
+            (b"\x1b[22m", SelectGraphicRendition::NormalIntensity),
+            (b"\x1b[27m", SelectGraphicRendition::ReverseVideoOff),
+        ] {
+            assert_eq!(output_buffer.push(seq), &[TerminalOutput::Sgr(sgr)]);
+        }
+    }
+
     #[test]
     fn test_mode_parsing() {
         let mut output_buffer = AnsiParser::new();
@@ -942,6 +2068,48 @@ mod test {
         let output = output_buffer.push(b"\x1b[?1h");
         assert_eq!(output.len(), 1);
         assert_eq!(output[0], TerminalOutput::SetMode(Mode::Decckm));
+
+        let output = output_buffer.push(b"\x1b[?1049h\x1b[?1049l");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetMode(Mode::DecAltScreen),
+                TerminalOutput::ResetMode(Mode::DecAltScreen),
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b[?6h");
+        assert_eq!(output, &[TerminalOutput::SetMode(Mode::Decom)]);
+
+        let output = output_buffer.push(b"\x1b[?2004h");
+        assert_eq!(output, &[TerminalOutput::SetMode(Mode::BracketedPaste)]);
+
+        let output = output_buffer.push(b"\x1b[4h\x1b[4l");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetMode(Mode::Irm),
+                TerminalOutput::ResetMode(Mode::Irm),
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b[?7l\x1b[?7h");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::ResetMode(Mode::DecAwm),
+                TerminalOutput::SetMode(Mode::DecAwm),
+            ]
+        );
+
+        let output = output_buffer.push(b"\x1b[?25l\x1b[?25h");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::ResetMode(Mode::DecTcem),
+                TerminalOutput::SetMode(Mode::DecTcem),
+            ]
+        );
     }
 
     #[test]
@@ -1012,6 +2180,33 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_line_move_parsing() {
+        let mut output_buffer = AnsiParser::new();
+        let output = output_buffer.push(b"\x1b[3E\x1b[F");
+        assert_eq!(
+            output,
+            &[
+                TerminalOutput::SetCursorPosRel {
+                    x: None,
+                    y: Some(3)
+                },
+                TerminalOutput::SetCursorPos {
+                    x: Some(1),
+                    y: None
+                },
+                TerminalOutput::SetCursorPosRel {
+                    x: None,
+                    y: Some(-1)
+                },
+                TerminalOutput::SetCursorPos {
+                    x: Some(1),
+                    y: None
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_rel_move_right_parsing() {
         let mut output_buffer = AnsiParser::new();
@@ -1113,11 +2308,57 @@ mod test {
                 params: vec![2, 3, 4],
                 intermediates: vec![5, 6, 7],
             }),
+            AnsiParserInner::Osc(b"52;c;aGVs".to_vec()),
+            AnsiParserInner::OscEscape(b"8;;https://example.com".to_vec()),
         ] {
-            let parser = AnsiParser { inner };
+            let parser = AnsiParser {
+                inner,
+                data: Vec::new(),
+                pending: VecDeque::new(),
+            };
             let loaded =
                 AnsiParser::from_snapshot(parser.snapshot()).expect("failed to load snapshot");
             assert_eq!(loaded.inner, parser.inner);
         }
+
+        // The start of a character that has not been completed yet
+        let mut parser = AnsiParser::new();
+        assert_eq!(parser.push(b"a\xc3"), &[TerminalOutput::Data(b"a".into())]);
+        let mut loaded =
+            AnsiParser::from_snapshot(parser.snapshot()).expect("failed to load snapshot");
+        assert_eq!(
+            loaded.push(b"\xa9"),
+            &[TerminalOutput::Data("\u{e9}".into())]
+        );
+    }
+
+    #[test]
+    fn test_split_utf8() {
+        let mut output_buffer = AnsiParser::new();
+        let data = "a\u{e9}\u{4e2d}\u{1f600}".as_bytes();
+        let mut parsed = Vec::new();
+        for b in data {
+            parsed.extend(output_buffer.push(&[*b]));
+        }
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::Data(b"a".into()),
+                TerminalOutput::Data("\u{e9}".into()),
+                TerminalOutput::Data("\u{4e2d}".into()),
+                TerminalOutput::Data("\u{1f600}".into()),
+            ]
+        );
+
+        // Bytes that can never be part of a character are replaced
+        let parsed = output_buffer.push(b"a\xffb\xc3\nc");
+        assert_eq!(
+            parsed,
+            &[
+                TerminalOutput::Data("a\u{fffd}b\u{fffd}".into()),
+                TerminalOutput::Newline,
+                TerminalOutput::Data(b"c".into()),
+            ]
+        );
     }
 }
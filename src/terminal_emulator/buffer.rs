@@ -1,14 +1,87 @@
 use std::{num::TryFromIntError, ops::Range};
 use thiserror::Error;
+use unicode_width::UnicodeWidthChar;
 
 use super::{
     recording::{NotIntOfType, SnapshotItem},
     CursorPos, TerminalData,
 };
 
+/// Continuation bytes of a utf8 encoded character do not take up a column of their own
+fn is_char_start(b: u8) -> bool {
+    b & 0b1100_0000 != 0b1000_0000
+}
+
+/// Split data into the bytes of each character
+pub fn split_chars(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    data.chunk_by(|_, b| !is_char_start(*b))
+}
+
+/// Number of columns taken up by the character at the start of data. Wide (e.g. CJK) characters
+/// take two, anything else takes one
+pub fn char_width(data: &[u8]) -> usize {
+    if data[0].is_ascii() {
+        return 1;
+    }
+
+    let char_len = (data[0].leading_ones() as usize)
+        .clamp(1, 4)
+        .min(data.len());
+    std::str::from_utf8(&data[..char_len])
+        .ok()
+        .and_then(|s| s.chars().next())
+        .and_then(UnicodeWidthChar::width)
+        .unwrap_or(1)
+        .max(1)
+}
+
+pub fn num_columns(data: &[u8]) -> usize {
+    split_chars(data).map(char_width).sum()
+}
+
+/// Find the character covering `column` in data. Returns its byte offset, and how many columns
+/// into the character `column` is, which is only non zero in the second half of a wide
+/// character. One past the last column is the length of data, anything further is None
+fn find_column(data: &[u8], column: usize) -> Option<(usize, usize)> {
+    let mut offset = 0;
+    let mut start_column = 0;
+    for c in split_chars(data) {
+        let width = char_width(c);
+        if column < start_column + width {
+            return Some((offset, column - start_column));
+        }
+        offset += c.len();
+        start_column += width;
+    }
+
+    (column == start_column).then_some((data.len(), 0))
+}
+
+/// Byte offset of the character covering column `column` in data, or None if data does not have
+/// that many columns. One past the last column is the length of data
+pub fn column_to_byte_offset(data: &[u8], column: usize) -> Option<usize> {
+    find_column(data, column).map(|(offset, _)| offset)
+}
+
+/// Number of bytes making up the first `columns` columns of data, and how many columns they
+/// cover. A wide character that is only partly included is included whole, so more columns than
+/// requested may be covered. Fewer are covered if data is not wide enough
+fn columns_to_byte_len(data: &[u8], columns: usize) -> (usize, usize) {
+    let mut len = 0;
+    let mut covered = 0;
+    for c in split_chars(data) {
+        if covered >= columns {
+            break;
+        }
+        len += c.len();
+        covered += char_width(c);
+    }
+    (len, covered)
+}
+
 /// Calculate the indexes of the start and end of each line in the buffer given an input width.
-/// Ranges do not include newlines. If a newline appears past the width, it does not result in an
-/// extra line
+/// Width is measured in characters, not bytes. Ranges do not include newlines. If a newline
+/// appears past the width, it does not result in an extra line
 ///
 /// Example
 /// ```
@@ -19,21 +92,28 @@ fn calc_line_ranges(buf: &[u8], width: usize) -> Vec<Range<usize>> {
     let mut ret = vec![];
 
     let mut current_start = 0;
+    let mut columns_since_start = 0;
 
     for (i, c) in buf.iter().enumerate() {
         if *c == b'\n' {
             ret.push(current_start..i);
             current_start = i + 1;
+            columns_since_start = 0;
+            continue;
+        }
+
+        if !is_char_start(*c) {
             continue;
         }
 
-        let bytes_since_start = i - current_start;
-        assert!(bytes_since_start <= width);
-        if bytes_since_start == width {
+        // Wide characters that do not fit at the end of a line are moved to the next one
+        let char_width = char_width(&buf[i..]);
+        if columns_since_start > 0 && columns_since_start + char_width > width {
             ret.push(current_start..i);
             current_start = i;
-            continue;
+            columns_since_start = 0;
         }
+        columns_since_start += char_width;
     }
 
     if buf.len() > current_start {
@@ -42,6 +122,42 @@ fn calc_line_ranges(buf: &[u8], width: usize) -> Vec<Range<usize>> {
     ret
 }
 
+fn is_url_byte(b: u8) -> bool {
+    b.is_ascii_graphic() && !matches!(b, b'"' | b'\'' | b'<' | b'>' | b'`')
+}
+
+/// Find http(s) urls in buf. Returns (start, end, url) for each url found
+fn find_urls(buf: &[u8]) -> Vec<(usize, usize, String)> {
+    const SCHEMES: [&[u8]; 2] = [b"http://", b"https://"];
+
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < buf.len() {
+        let Some(scheme) = SCHEMES.iter().find(|scheme| buf[i..].starts_with(scheme)) else {
+            i += 1;
+            continue;
+        };
+
+        let mut end = i + buf[i..]
+            .iter()
+            .position(|b| !is_url_byte(*b))
+            .unwrap_or(buf.len() - i);
+
+        // Trailing punctuation is more likely to be part of the surrounding text
+        while end > i && matches!(buf[end - 1], b'.' | b',' | b';' | b':' | b'!' | b'?' | b')') {
+            end -= 1;
+        }
+
+        if end > i + scheme.len() {
+            let url = String::from_utf8_lossy(&buf[i..end]).to_string();
+            ret.push((i, end, url));
+        }
+
+        i = end.max(i + scheme.len());
+    }
+    ret
+}
+
 #[derive(Debug, Error, Eq, PartialEq)]
 #[error("invalid buffer position {buf_pos} for buffer of len {buf_len}")]
 struct InvalidBufPos {
@@ -71,7 +187,7 @@ fn buf_to_cursor_pos(
         return Ok(CursorPos { x: 0, y: 0 });
     };
 
-    let new_cursor_x = buf_pos - new_cursor_line.start;
+    let new_cursor_x = num_columns(&buf[new_cursor_line.start..buf_pos]);
     Ok(CursorPos {
         x: new_cursor_x,
         y: new_cursor_y,
@@ -106,16 +222,20 @@ fn line_ranges_to_visible_line_ranges(
 struct PadBufferForWriteResponse {
     /// Where to copy data into
     write_idx: usize,
+    /// How many columns the cursor is past write_idx. Non zero if the cursor is on the second half
+    /// of a wide character
+    columns_into_char: usize,
     /// Indexes where we added data
     inserted_padding: Range<usize>,
 }
 
+/// Pad the buffer so that `write_columns` characters can be written at the cursor
 fn pad_buffer_for_write(
     buf: &mut Vec<u8>,
     width: usize,
     height: usize,
     cursor_pos: &CursorPos,
-    write_len: usize,
+    write_columns: usize,
 ) -> PadBufferForWriteResponse {
     let mut visible_line_ranges = {
         // Calculate in block scope to avoid accidental usage of scrollback line ranges later
@@ -145,14 +265,14 @@ fn pad_buffer_for_write(
 
     let line_range = &visible_line_ranges[cursor_pos.y];
 
-    let desired_start = line_range.start + cursor_pos.x;
-    let desired_end = desired_start + write_len;
+    let desired_end = cursor_pos.x + write_columns;
 
     // NOTE: We only want to pad if we hit an early newline. If we wrapped because we hit the edge
     // of the screen we can just keep writing and the wrapping will stay as is. This is an
     // important distinction because in the no-newline case we want to make sure we overwrite
     // whatever was in the buffer before
     let actual_end = unwrapped_line_end_pos(buf, line_range.start);
+    let line_columns = num_columns(&buf[line_range.start..actual_end]);
 
     // If we did not set the padding start position, it means that we are padding not at the end of
     // the buffer, but at the end of a line
@@ -160,11 +280,7 @@ fn pad_buffer_for_write(
         padding_start_pos = Some(actual_end);
     }
 
-    let number_of_spaces = if desired_end > actual_end {
-        desired_end - actual_end
-    } else {
-        0
-    };
+    let number_of_spaces = desired_end.saturating_sub(line_columns);
 
     num_inserted_characters += number_of_spaces;
 
@@ -175,26 +291,47 @@ fn pad_buffer_for_write(
     let start_buf_pos =
         padding_start_pos.expect("start buf pos should be guaranteed initialized by this point");
 
+    let line_data = &buf[line_range.start..actual_end + number_of_spaces];
+    let (offset, columns_into_char) =
+        find_column(line_data, cursor_pos.x).expect("line should be padded to the cursor");
+
     PadBufferForWriteResponse {
-        write_idx: desired_start,
+        write_idx: line_range.start + offset,
+        columns_into_char,
         inserted_padding: start_buf_pos..start_buf_pos + num_inserted_characters,
     }
 }
 
 fn cursor_to_buf_pos_from_visible_line_ranges(
+    buf: &[u8],
     cursor_pos: &CursorPos,
     visible_line_ranges: &[Range<usize>],
 ) -> Option<(usize, Range<usize>)> {
     visible_line_ranges.get(cursor_pos.y).and_then(|range| {
-        let candidate_pos = range.start + cursor_pos.x;
-        if candidate_pos > range.end {
-            None
-        } else {
-            Some((candidate_pos, range.clone()))
-        }
+        let offset = column_to_byte_offset(&buf[range.clone()], cursor_pos.x)?;
+        Some((range.start + offset, range.clone()))
     })
 }
 
+/// Lay out data as if it were written starting at column `start_column` of a line `width` columns
+/// wide. A wide character that would be split over the end of a line is preceded by a space so
+/// that it starts on the next line. Returns the laid out data and the number of columns it takes
+fn layout_for_write(data: &[u8], start_column: usize, width: usize) -> (Vec<u8>, usize) {
+    let mut ret = Vec::with_capacity(data.len());
+    let mut column = start_column;
+    for c in split_chars(data) {
+        let char_width = char_width(c);
+        let line_column = column % width.max(1);
+        if line_column > 0 && line_column + char_width > width {
+            ret.push(b' ');
+            column += 1;
+        }
+        ret.extend_from_slice(c);
+        column += char_width;
+    }
+    (ret, column - start_column)
+}
+
 fn cursor_to_buf_pos(
     buf: &[u8],
     cursor_pos: &CursorPos,
@@ -204,7 +341,7 @@ fn cursor_to_buf_pos(
     let line_ranges = calc_line_ranges(buf, width);
     let visible_line_ranges = line_ranges_to_visible_line_ranges(&line_ranges, height);
 
-    cursor_to_buf_pos_from_visible_line_ranges(cursor_pos, visible_line_ranges)
+    cursor_to_buf_pos_from_visible_line_ranges(buf, cursor_pos, visible_line_ranges)
 }
 
 pub struct TerminalBufferInsertResponse {
@@ -214,9 +351,21 @@ pub struct TerminalBufferInsertResponse {
     /// Includes padding that was previously not there, e.g. newlines needed to get to the
     /// requested row for writing
     pub insertion_range: Range<usize>,
+    /// Range of data that written_range took the place of, after insertion_range was added. It
+    /// starts at the same position as written_range, but characters do not all take the same
+    /// number of bytes so the lengths may differ
+    pub replaced_range: Range<usize>,
     pub new_cursor_pos: CursorPos,
 }
 
+/// Data in replaced_range was swapped for the data in written_range. Both start at the same
+/// position but may differ in length
+#[derive(Debug, Eq, PartialEq)]
+pub struct TerminalBufferReplaceResponse {
+    pub replaced_range: Range<usize>,
+    pub written_range: Range<usize>,
+}
+
 #[derive(Debug)]
 pub struct TerminalBufferInsertLineResponse {
     /// Range of deleted data **before insertion**
@@ -235,6 +384,7 @@ mod terminal_buffer_keys {
     pub const BUF: &str = "buf";
     pub const WIDTH: &str = "width";
     pub const HEIGHT: &str = "height";
+    pub const TAB_STOPS: &str = "tab_stops";
 }
 
 #[derive(Debug, Error)]
@@ -243,6 +393,8 @@ enum CreateSnapshotErrorKind {
     Width(#[source] TryFromIntError),
     #[error("failed to convert height to i64")]
     Height(#[source] TryFromIntError),
+    #[error("failed to convert tab stop to i64")]
+    TabStop(#[source] TryFromIntError),
 }
 
 #[derive(Debug, Error)]
@@ -267,25 +419,113 @@ enum LoadSnapshotErrorKind {
     HeightMissing,
     #[error("failed to get height as usize")]
     HeightNotUsize(#[source] NotIntOfType),
+    #[error("tab stops is not a vec")]
+    TabStopsNotVec,
+    #[error("failed to get tab stop as usize")]
+    TabStopNotUsize(#[source] NotIntOfType),
 }
 
 #[derive(Debug, Error)]
 #[error(transparent)]
 pub struct LoadSnapshotError(#[from] LoadSnapshotErrorKind);
 
+/// How many lines of scrollback are kept unless configured otherwise. Wrapped lines count once
+/// per row
+pub const DEFAULT_MAX_SCROLLBACK_LINES: usize = 10_000;
+
+/// Byte offset of the start of line `n` of buf, where wrapped lines count once per row
+fn nth_line_start(buf: &[u8], width: usize, n: usize) -> usize {
+    let mut start = 0;
+    let mut remaining = n;
+    while remaining > 0 && start < buf.len() {
+        let end = buf[start..]
+            .iter()
+            .position(|c| *c == b'\n')
+            .map_or(buf.len(), |pos| start + pos + 1);
+        let rows = calc_line_ranges(&buf[start..end], width);
+        if let Some(row) = rows.get(remaining) {
+            return start + row.start;
+        }
+        remaining -= rows.len();
+        start = end;
+    }
+    start
+}
+
+/// Lines at the start of the buffer that have already been counted, so that enforcing the
+/// scrollback limit only has to look at new data. buf[..end] holds `lines` lines and ends with a
+/// newline
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+struct CountedLines {
+    end: usize,
+    lines: usize,
+}
+
+/// Urls found in buf[..end], which is empty or ends with a newline. Urls never span lines, so
+/// only lines that changed since the last scan have to be scanned again
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+struct DetectedUrls {
+    end: usize,
+    urls: Vec<(usize, usize, String)>,
+}
+
+const DEFAULT_TAB_WIDTH: usize = 8;
+
+/// Default tab stops for the columns in start..width
+fn default_tab_stops(start: usize, width: usize) -> impl Iterator<Item = usize> {
+    let first = start.div_ceil(DEFAULT_TAB_WIDTH).max(1) * DEFAULT_TAB_WIDTH;
+    (first..width).step_by(DEFAULT_TAB_WIDTH)
+}
+
 #[derive(Eq, PartialEq, Debug)]
 pub struct TerminalBuffer {
     buf: Vec<u8>,
     width: usize,
     height: usize,
+    max_scrollback_lines: usize,
+    counted_lines: CountedLines,
+    detected_urls: DetectedUrls,
+    // Sorted
+    tab_stops: Vec<usize>,
 }
 
 impl TerminalBuffer {
     pub fn new(width: usize, height: usize) -> TerminalBuffer {
+        TerminalBuffer::new_with_limit(width, height, DEFAULT_MAX_SCROLLBACK_LINES)
+    }
+
+    pub fn new_with_limit(
+        width: usize,
+        height: usize,
+        max_scrollback_lines: usize,
+    ) -> TerminalBuffer {
         TerminalBuffer {
             buf: vec![],
             width,
             height,
+            max_scrollback_lines,
+            counted_lines: CountedLines::default(),
+            detected_urls: DetectedUrls::default(),
+            tab_stops: default_tab_stops(0, width).collect(),
+        }
+    }
+
+    /// Must be called whenever buf is modified at `pos` or later. Lines that were counted or
+    /// scanned for urls are looked at again if the change touches them
+    fn mark_changed(&mut self, pos: usize) {
+        if pos < self.counted_lines.end {
+            self.counted_lines = CountedLines::default();
+        }
+
+        let detected = &mut self.detected_urls;
+        if pos < detected.end {
+            let line_start = self.buf[..pos]
+                .iter()
+                .rposition(|b| *b == b'\n')
+                .map_or(0, |idx| idx + 1);
+            detected.end = line_start;
+            let num_kept = detected.urls.partition_point(|url| url.0 < line_start);
+            detected.urls.truncate(num_kept);
         }
     }
 
@@ -308,13 +548,38 @@ impl TerminalBuffer {
             .ok_or(HeightMissing)?;
         let height = height.into_num().map_err(HeightNotUsize)?;
 
-        Ok(TerminalBuffer { buf, width, height })
+        // Optional, recordings from before tab stops were supported do not have it
+        let tab_stops = match root.remove(terminal_buffer_keys::TAB_STOPS) {
+            Some(tab_stops) => {
+                let tab_stops = tab_stops.into_vec().map_err(|_| TabStopsNotVec)?;
+                let tab_stops: Result<Vec<usize>, _> =
+                    tab_stops.into_iter().map(|x| x.into_num()).collect();
+                tab_stops.map_err(TabStopNotUsize)?
+            }
+            None => default_tab_stops(0, width).collect(),
+        };
+
+        Ok(TerminalBuffer {
+            buf,
+            width,
+            height,
+            max_scrollback_lines: DEFAULT_MAX_SCROLLBACK_LINES,
+            counted_lines: CountedLines::default(),
+            detected_urls: DetectedUrls::default(),
+            tab_stops,
+        })
     }
 
     pub fn snapshot(&self) -> Result<SnapshotItem, CreateSnapshotError> {
         use CreateSnapshotErrorKind::*;
         let width_i64: i64 = self.width.try_into().map_err(Width)?;
         let height_i64: i64 = self.height.try_into().map_err(Height)?;
+        let tab_stops = self
+            .tab_stops
+            .iter()
+            .map(|x| i64::try_from(*x).map(SnapshotItem::from))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(TabStop)?;
         let ret = SnapshotItem::Map(
             [
                 (
@@ -323,6 +588,10 @@ impl TerminalBuffer {
                 ),
                 (terminal_buffer_keys::WIDTH.to_string(), width_i64.into()),
                 (terminal_buffer_keys::HEIGHT.to_string(), height_i64.into()),
+                (
+                    terminal_buffer_keys::TAB_STOPS.to_string(),
+                    SnapshotItem::Array(tab_stops),
+                ),
             ]
             .into(),
         );
@@ -334,55 +603,81 @@ impl TerminalBuffer {
         cursor_pos: &CursorPos,
         data: &[u8],
     ) -> TerminalBufferInsertResponse {
+        let (laid_out, write_columns) = layout_for_write(data, cursor_pos.x, self.width);
         let PadBufferForWriteResponse {
             write_idx,
+            columns_into_char,
             inserted_padding,
         } = pad_buffer_for_write(
             &mut self.buf,
             self.width,
             self.height,
             cursor_pos,
-            data.len(),
+            write_columns,
         );
-        let write_range = write_idx..write_idx + data.len();
-        self.buf[write_range.clone()].copy_from_slice(data);
-        let new_cursor_pos = buf_to_cursor_pos(&self.buf, self.width, self.height, write_range.end)
+        // Padding goes at the end of the cursor's line or the buffer, before where data is written
+        self.mark_changed(inserted_padding.start.min(write_idx));
+
+        // Wide characters that are partially overwritten are blanked. Replace the first half if
+        // the cursor is on the second half, and the second half if we end on the first half
+        let replaced_columns = columns_into_char + write_columns;
+        let (replaced_len, covered_columns) =
+            columns_to_byte_len(&self.buf[write_idx..], replaced_columns);
+        let mut to_write = vec![b' '; columns_into_char];
+        to_write.extend_from_slice(&laid_out);
+        let data_end = write_idx + to_write.len();
+        to_write.extend(std::iter::repeat_n(
+            b' ',
+            covered_columns - replaced_columns,
+        ));
+
+        let replaced_range = write_idx..write_idx + replaced_len;
+        let write_range = write_idx..write_idx + to_write.len();
+        self.buf.splice(replaced_range.clone(), to_write);
+        let new_cursor_pos = buf_to_cursor_pos(&self.buf, self.width, self.height, data_end)
             .expect("write range should be valid in buf");
         TerminalBufferInsertResponse {
             written_range: write_range,
             insertion_range: inserted_padding,
+            replaced_range,
             new_cursor_pos,
         }
     }
 
-    /// Inserts data, but will not wrap. If line end is hit, data stops
+    /// Insert blank cells at the cursor. The rest of the row moves right, cells pushed past the
+    /// right margin are dropped
     pub fn insert_spaces(
         &mut self,
         cursor_pos: &CursorPos,
         mut num_spaces: usize,
     ) -> TerminalBufferInsertResponse {
-        num_spaces = self.width.min(num_spaces);
+        let cursor_to_margin = self.width.saturating_sub(cursor_pos.x);
+        num_spaces = cursor_to_margin.min(num_spaces);
 
         let buf_pos = cursor_to_buf_pos(&self.buf, cursor_pos, self.width, self.height);
         match buf_pos {
             Some((buf_pos, line_range)) => {
-                // Insert spaces until either we hit num_spaces, or the line width is too long
-                let line_len = line_range.end - line_range.start;
-                let num_inserted = (num_spaces).min(self.width - line_len);
-
-                // Overwrite existing with spaces until we hit num_spaces or we hit the line end
-                let num_overwritten = (num_spaces - num_inserted).min(line_range.end - buf_pos);
-
-                // NOTE: We do the overwrite first so we don't have to worry about adjusting
-                // indices for the newly inserted data
-                self.buf[buf_pos..buf_pos + num_overwritten].fill(b' ');
+                // Keep whatever still fits between the inserted spaces and the right margin. A
+                // wide character that would be split by the margin is replaced by spaces
+                let cursor_to_line_end = &self.buf[buf_pos..line_range.end];
+                let (kept_len, padding) =
+                    find_column(cursor_to_line_end, cursor_to_margin - num_spaces)
+                        .unwrap_or((cursor_to_line_end.len(), 0));
+                let replaced_len = num_spaces + cursor_to_line_end.len();
+                let written_len = num_spaces + kept_len + padding;
+
+                self.mark_changed(buf_pos);
+                self.buf.splice(
+                    buf_pos + kept_len..line_range.end,
+                    std::iter::repeat_n(b' ', padding),
+                );
                 self.buf
-                    .splice(buf_pos..buf_pos, std::iter::repeat(b' ').take(num_inserted));
+                    .splice(buf_pos..buf_pos, std::iter::repeat_n(b' ', num_spaces));
 
-                let used_spaces = num_inserted + num_overwritten;
                 TerminalBufferInsertResponse {
-                    written_range: buf_pos..buf_pos + used_spaces,
-                    insertion_range: buf_pos..buf_pos + num_inserted,
+                    written_range: buf_pos..buf_pos + written_len,
+                    insertion_range: buf_pos..buf_pos + num_spaces,
+                    replaced_range: buf_pos..buf_pos + replaced_len,
                     new_cursor_pos: cursor_pos.clone(),
                 }
             }
@@ -390,6 +685,7 @@ impl TerminalBuffer {
                 let PadBufferForWriteResponse {
                     write_idx,
                     inserted_padding,
+                    ..
                 } = pad_buffer_for_write(
                     &mut self.buf,
                     self.width,
@@ -397,65 +693,187 @@ impl TerminalBuffer {
                     cursor_pos,
                     num_spaces,
                 );
+                self.mark_changed(inserted_padding.start.min(write_idx));
                 TerminalBufferInsertResponse {
                     written_range: write_idx..write_idx + num_spaces,
                     insertion_range: inserted_padding,
+                    replaced_range: write_idx..write_idx + num_spaces,
                     new_cursor_pos: cursor_pos.clone(),
                 }
             }
         }
     }
 
+    /// Insert `num_lines` blank lines at the cursor row. Lines below it up to `bottom` move down
+    /// and lines pushed past `bottom` are discarded. Returns one response per inserted line
     pub fn insert_lines(
         &mut self,
         cursor_pos: &CursorPos,
-        mut num_lines: usize,
+        num_lines: usize,
+        bottom: usize,
+    ) -> Vec<TerminalBufferInsertLineResponse> {
+        if cursor_pos.y > bottom {
+            return Vec::new();
+        }
+
+        let num_lines = num_lines.min(bottom - cursor_pos.y + 1);
+        (0..num_lines)
+            .map(|_| self.scroll_region_down(cursor_pos.y, bottom))
+            .collect()
+    }
+
+    /// Scroll visible lines `top..=bottom` up by one, leaving the lines outside of the region in
+    /// place. The top line of the region is discarded instead of moving into scrollback
+    pub fn scroll_region_up(
+        &mut self,
+        top: usize,
+        bottom: usize,
     ) -> TerminalBufferInsertLineResponse {
         let line_ranges = calc_line_ranges(&self.buf, self.width);
-        let visible_line_ranges = line_ranges_to_visible_line_ranges(&line_ranges, self.height);
+        let visible_line_ranges =
+            line_ranges_to_visible_line_ranges(&line_ranges, self.height).to_vec();
 
-        // NOTE: Cursor x position is not used. If the cursor position was too far to the right,
-        // there may be no buffer position associated with it. Use Y only
-        let Some(line_range) = visible_line_ranges.get(cursor_pos.y) else {
+        let Some(top_line) = visible_line_ranges.get(top) else {
             return TerminalBufferInsertLineResponse {
                 deleted_range: 0..0,
                 inserted_range: 0..0,
             };
         };
 
-        let available_space = self.height - visible_line_ranges.len();
-        // If height is 10, and y is 5, we can only insert 5 lines. If we inserted more it would
-        // adjust the visible line range, and that would be a problem
-        num_lines = num_lines.min(self.height - cursor_pos.y);
-
-        let deletion_range = if num_lines > available_space {
-            let num_lines_removed = num_lines - available_space;
-            let removal_start_idx =
-                visible_line_ranges[visible_line_ranges.len() - num_lines_removed].start;
-            let deletion_range = removal_start_idx..self.buf.len();
-            self.buf.truncate(removal_start_idx);
-            deletion_range
-        } else {
-            0..0
+        let deletion_end = visible_line_ranges
+            .get(top + 1)
+            .map_or(self.buf.len(), |r| r.start);
+        let deletion_range = top_line.start..deletion_end;
+
+        // If nothing exists below the region there is nothing to keep in place, otherwise an
+        // empty line has to take the place of the removed one so that the following lines do not
+        // move up
+        let insertion_pos = (visible_line_ranges.len() > bottom).then(|| {
+            visible_line_ranges
+                .get(bottom + 1)
+                .map_or(self.buf.len(), |r| r.start)
+                - deletion_range.len()
+        });
+
+        self.mark_changed(deletion_range.start);
+        self.buf.drain(deletion_range.clone());
+
+        let Some(insertion_pos) = insertion_pos else {
+            return TerminalBufferInsertLineResponse {
+                deleted_range: deletion_range,
+                inserted_range: 0..0,
+            };
         };
 
-        let insertion_pos = line_range.start;
+        // Same edge case as scroll_region_down, a newline after a wrapped line does not add a line
+        let mut num_newlines = 1;
+        if insertion_pos > 0 && self.buf[insertion_pos - 1] != b'\n' {
+            num_newlines += 1;
+        }
+
+        self.mark_changed(insertion_pos);
+        self.buf.splice(
+            insertion_pos..insertion_pos,
+            std::iter::repeat_n(b'\n', num_newlines),
+        );
+
+        TerminalBufferInsertLineResponse {
+            deleted_range: deletion_range,
+            inserted_range: insertion_pos..insertion_pos + num_newlines,
+        }
+    }
+
+    /// Delete `num_lines` lines starting at the cursor row. Lines below it up to `bottom` move up
+    /// and blank lines are left at `bottom`. Returns one response per deleted line
+    pub fn delete_lines(
+        &mut self,
+        cursor_pos: &CursorPos,
+        num_lines: usize,
+        bottom: usize,
+    ) -> Vec<TerminalBufferInsertLineResponse> {
+        if cursor_pos.y > bottom {
+            return Vec::new();
+        }
+
+        let num_lines = num_lines.min(bottom - cursor_pos.y + 1);
+        (0..num_lines)
+            .map(|_| self.scroll_region_up(cursor_pos.y, bottom))
+            .collect()
+    }
+
+    /// Scroll the whole screen up, moving the top `num_lines` lines into scrollback. Returns the
+    /// range of appended data
+    pub fn scroll_up(&mut self, num_lines: usize) -> Range<usize> {
+        let num_lines = num_lines.min(self.height);
+        let num_visible_lines = {
+            let line_ranges = calc_line_ranges(&self.buf, self.width);
+            line_ranges_to_visible_line_ranges(&line_ranges, self.height).len()
+        };
+
+        let start = self.buf.len();
+        self.mark_changed(start);
+        // Terminating the last line does not add a line, every newline after it does. The screen
+        // is filled to its full height before adding the new lines so that the existing lines
+        // end up num_lines higher
+        if self.buf.last().is_some_and(|c| *c != b'\n') {
+            self.buf.push(b'\n');
+        }
+        let num_newlines = self.height + num_lines - num_visible_lines;
+        self.buf.extend(std::iter::repeat_n(b'\n', num_newlines));
+
+        start..self.buf.len()
+    }
+
+    /// Scroll visible lines `top..=bottom` down by one, inserting an empty line at `top` and
+    /// discarding the bottom line of the region
+    pub fn scroll_region_down(
+        &mut self,
+        top: usize,
+        bottom: usize,
+    ) -> TerminalBufferInsertLineResponse {
+        let line_ranges = calc_line_ranges(&self.buf, self.width);
+        let visible_line_ranges =
+            line_ranges_to_visible_line_ranges(&line_ranges, self.height).to_vec();
+
+        // An empty region stays empty
+        let Some(top_line) = visible_line_ranges.get(top) else {
+            return TerminalBufferInsertLineResponse {
+                deleted_range: 0..0,
+                inserted_range: 0..0,
+            };
+        };
+
+        let deletion_range = match visible_line_ranges.get(bottom) {
+            Some(bottom_line) => {
+                let deletion_end = visible_line_ranges
+                    .get(bottom + 1)
+                    .map_or(self.buf.len(), |r| r.start);
+                bottom_line.start..deletion_end
+            }
+            None => 0..0,
+        };
+        self.mark_changed(deletion_range.start);
+        self.buf.drain(deletion_range.clone());
+
+        let insertion_pos = top_line.start;
 
         // Edge case, if the previous line ended in a line wrap, inserting a new line will not
         // result in an extra line being shown on screen. E.g. with a width of 5, 01234 and 01234\n
         // both look like a line of length 5. In this case we need to add another newline
+        let mut num_newlines = 1;
         if insertion_pos > 0 && self.buf[insertion_pos - 1] != b'\n' {
-            num_lines += 1;
+            num_newlines += 1;
         }
 
+        self.mark_changed(insertion_pos);
         self.buf.splice(
             insertion_pos..insertion_pos,
-            std::iter::repeat(b'\n').take(num_lines),
+            std::iter::repeat_n(b'\n', num_newlines),
         );
 
         TerminalBufferInsertLineResponse {
             deleted_range: deletion_range,
-            inserted_range: insertion_pos..insertion_pos + num_lines,
+            inserted_range: insertion_pos..insertion_pos + num_newlines,
         }
     }
 
@@ -463,14 +881,16 @@ impl TerminalBuffer {
         let line_ranges = calc_line_ranges(&self.buf, self.width);
         let visible_line_ranges = line_ranges_to_visible_line_ranges(&line_ranges, self.height);
 
-        let Some((buf_pos, _)) =
-            cursor_to_buf_pos_from_visible_line_ranges(cursor_pos, visible_line_ranges)
-        else {
-            return None;
-        };
+        let line_range = visible_line_ranges.get(cursor_pos.y)?;
+        let (offset, columns_into_char) = find_column(&self.buf[line_range.clone()], cursor_pos.x)?;
+        let buf_pos = line_range.start + offset;
 
         let previous_last_char = self.buf[buf_pos];
+        self.mark_changed(buf_pos);
         self.buf.truncate(buf_pos);
+        // Blank the first half of a wide character under the cursor
+        self.buf
+            .extend(std::iter::repeat_n(b' ', columns_into_char));
 
         // If we truncate at the start of a line, and the previous line did not end with a newline,
         // the first inserted newline will not have an effect on the number of visible lines. This
@@ -492,17 +912,22 @@ impl TerminalBuffer {
             }
         }
 
-        let new_cursor_pos =
-            buf_to_cursor_pos(&self.buf, self.width, self.height, buf_pos).map(|mut pos| {
-                // NOTE: buf to cursor pos may put the cursor one past the end of the line. In this
-                // case it's ok because there are two valid cursor positions and we only care about one
-                // of them
-                if pos.x == self.width {
-                    pos.x = 0;
-                    pos.y += 1;
-                }
-                pos
-            });
+        let new_cursor_pos = buf_to_cursor_pos(
+            &self.buf,
+            self.width,
+            self.height,
+            buf_pos + columns_into_char,
+        )
+        .map(|mut pos| {
+            // NOTE: buf to cursor pos may put the cursor one past the end of the line. In this
+            // case it's ok because there are two valid cursor positions and we only care about one
+            // of them
+            if pos.x == self.width {
+                pos.x = 0;
+                pos.y += 1;
+            }
+            pos
+        });
 
         assert_eq!(new_cursor_pos, Ok(cursor_pos.clone()));
         Some(buf_pos)
@@ -514,11 +939,71 @@ impl TerminalBuffer {
             cursor_to_buf_pos(&self.buf, cursor_pos, self.width, self.height)?;
 
         let del_range = buf_pos..line_range.end;
+        self.mark_changed(buf_pos);
         self.buf.drain(del_range.clone());
         Some(del_range)
     }
 
+    /// Replace up to `num_chars` characters from the cursor with spaces, without moving the rest
+    /// of the line
+    pub fn erase_characters(
+        &mut self,
+        cursor_pos: &CursorPos,
+        num_chars: usize,
+    ) -> Option<TerminalBufferReplaceResponse> {
+        let line_range = self.visible_line_range(cursor_pos.y)?;
+        let (offset, columns_into_char) = find_column(&self.buf[line_range.clone()], cursor_pos.x)?;
+        let buf_pos = line_range.start + offset;
+
+        // Anything past the end of the line is already blank. Wide characters that are partially
+        // erased are erased whole
+        let cursor_to_line_end = &self.buf[buf_pos..line_range.end];
+        let num_erased = (columns_into_char + num_chars).min(num_columns(cursor_to_line_end));
+        if num_erased == 0 {
+            return None;
+        }
+
+        let (erased_len, erased_columns) = columns_to_byte_len(cursor_to_line_end, num_erased);
+        let replaced_range = buf_pos..buf_pos + erased_len;
+        self.mark_changed(buf_pos);
+        self.buf.splice(
+            replaced_range.clone(),
+            std::iter::repeat_n(b' ', erased_columns),
+        );
+
+        Some(TerminalBufferReplaceResponse {
+            replaced_range,
+            written_range: buf_pos..buf_pos + erased_columns,
+        })
+    }
+
+    /// Blank the line up to and including the cursor
+    pub fn clear_line_backwards(
+        &mut self,
+        cursor_pos: &CursorPos,
+    ) -> Option<TerminalBufferReplaceResponse> {
+        self.erase_characters(
+            &CursorPos {
+                x: 0,
+                y: cursor_pos.y,
+            },
+            cursor_pos.x + 1,
+        )
+    }
+
+    /// Blank the whole line the cursor is on
+    pub fn clear_line(&mut self, cursor_pos: &CursorPos) -> Option<TerminalBufferReplaceResponse> {
+        self.erase_characters(
+            &CursorPos {
+                x: 0,
+                y: cursor_pos.y,
+            },
+            self.width,
+        )
+    }
+
     pub fn clear_all(&mut self) {
+        self.mark_changed(0);
         self.buf.clear();
     }
 
@@ -527,24 +1012,95 @@ impl TerminalBuffer {
         cursor_pos: &CursorPos,
         num_chars: usize,
     ) -> Option<Range<usize>> {
-        let Some((buf_pos, line_range)) =
-            cursor_to_buf_pos(&self.buf, cursor_pos, self.width, self.height)
-        else {
-            return None;
-        };
-
-        let mut delete_range = buf_pos..buf_pos + num_chars;
-
-        if delete_range.end > line_range.end && self.buf.get(line_range.end) != Some(&b'\n') {
+        let line_range = self.visible_line_range(cursor_pos.y)?;
+        let (offset, columns_into_char) = find_column(&self.buf[line_range.clone()], cursor_pos.x)?;
+        let buf_pos = line_range.start + offset;
+
+        // Wide characters that are partially deleted are deleted whole
+        let num_columns = columns_into_char + num_chars;
+        let (delete_len, deleted_columns) =
+            columns_to_byte_len(&self.buf[buf_pos..line_range.end], num_columns);
+        let hit_line_end = deleted_columns < num_columns;
+
+        self.mark_changed(buf_pos);
+        if hit_line_end && self.buf.get(line_range.end) != Some(&b'\n') {
             self.buf.insert(line_range.end, b'\n');
         }
 
-        delete_range.end = line_range.end.min(delete_range.end);
+        let delete_range = buf_pos..buf_pos + delete_len;
 
         self.buf.drain(delete_range.clone());
         Some(delete_range)
     }
 
+    fn visible_line_range(&self, y: usize) -> Option<Range<usize>> {
+        let line_ranges = calc_line_ranges(&self.buf, self.width);
+        line_ranges_to_visible_line_ranges(&line_ranges, self.height)
+            .get(y)
+            .cloned()
+    }
+
+    /// Number of columns taken by the character under the cursor. Two if the cursor is on the
+    /// first half of a wide character, one otherwise
+    pub fn char_width_at(&self, cursor_pos: &CursorPos) -> usize {
+        let Some(line_range) = self.visible_line_range(cursor_pos.y) else {
+            return 1;
+        };
+        let line = &self.buf[line_range];
+        match find_column(line, cursor_pos.x) {
+            Some((offset, 0)) if offset < line.len() => char_width(&line[offset..]),
+            _ => 1,
+        }
+    }
+
+    /// Visible text from start to end inclusive, in reading order. Rows are separated by newlines
+    /// unless they wrapped, trailing spaces of a row are dropped
+    pub fn selected_text(&self, start: &CursorPos, end: &CursorPos) -> Vec<u8> {
+        let (start, end) = if (start.y, start.x) <= (end.y, end.x) {
+            (start, end)
+        } else {
+            (end, start)
+        };
+
+        let line_ranges = calc_line_ranges(&self.buf, self.width);
+        let visible_line_ranges = line_ranges_to_visible_line_ranges(&line_ranges, self.height);
+
+        let mut ret = Vec::new();
+        let mut prev_wrapped = false;
+        for y in start.y..=end.y {
+            let Some(line_range) = visible_line_ranges.get(y) else {
+                break;
+            };
+            if y != start.y && !prev_wrapped {
+                ret.push(b'\n');
+            }
+
+            let line = &self.buf[line_range.clone()];
+
+            let line_start = if y == start.y {
+                column_to_byte_offset(line, start.x).unwrap_or(line.len())
+            } else {
+                0
+            };
+            let line_end = if y == end.y {
+                columns_to_byte_len(line, end.x + 1).0
+            } else {
+                line.len()
+            };
+            let mut selected = &line[line_start.min(line_end)..line_end];
+
+            let wraps = y != end.y && self.buf.get(line_range.end) != Some(&b'\n');
+            if !wraps {
+                while let Some((b' ', rest)) = selected.split_last() {
+                    selected = rest;
+                }
+            }
+            ret.extend_from_slice(selected);
+            prev_wrapped = wraps;
+        }
+        ret
+    }
+
     pub fn data(&self) -> TerminalData<&[u8]> {
         let line_ranges = calc_line_ranges(&self.buf, self.width);
         let visible_line_ranges = line_ranges_to_visible_line_ranges(&line_ranges, self.height);
@@ -561,10 +1117,220 @@ impl TerminalBuffer {
         }
     }
 
+    /// Copy the visible characters in the inclusive rectangle src_top..=src_bottom,
+    /// src_left..=src_right so that its top left corner lands on dst_top, dst_left. The rectangle
+    /// is clipped to the screen. Returns one insert response per written row
+    pub fn copy_region(
+        &mut self,
+        src_top: usize,
+        src_left: usize,
+        src_bottom: usize,
+        src_right: usize,
+        dst_top: usize,
+        dst_left: usize,
+    ) -> Vec<TerminalBufferInsertResponse> {
+        let src_bottom = src_bottom.min(self.height.saturating_sub(1));
+        let src_right = src_right.min(self.width.saturating_sub(1));
+        if src_top > src_bottom || src_left > src_right {
+            return Vec::new();
+        }
+
+        let num_rows = (src_bottom - src_top + 1).min(self.height.saturating_sub(dst_top));
+        let num_cols = (src_right - src_left + 1).min(self.width.saturating_sub(dst_left));
+
+        // Copy out first, source and destination may overlap
+        let region: Vec<Vec<u8>> = {
+            let line_ranges = calc_line_ranges(&self.buf, self.width);
+            let visible_line_ranges = line_ranges_to_visible_line_ranges(&line_ranges, self.height);
+
+            (src_top..src_top + num_rows)
+                .map(|y| {
+                    // One entry per column. The second column of a wide character is None
+                    let mut line_cells: Vec<Option<&[u8]>> = Vec::new();
+                    if let Some(range) = visible_line_ranges.get(y) {
+                        for c in split_chars(&self.buf[range.clone()]) {
+                            line_cells.push(Some(c));
+                            for _ in 1..char_width(c) {
+                                line_cells.push(None);
+                            }
+                        }
+                    }
+
+                    let mut row = Vec::new();
+                    for x in src_left..src_left + num_cols {
+                        let cell = match line_cells.get(x) {
+                            Some(Some(c)) => c,
+                            Some(None) if x > src_left => continue,
+                            // Wide characters cut by the edges of the region are blanked
+                            _ => &b" "[..],
+                        };
+                        if char_width(cell) > 1 && x + 1 >= src_left + num_cols {
+                            row.push(b' ');
+                        } else {
+                            row.extend_from_slice(cell);
+                        }
+                    }
+                    row
+                })
+                .collect()
+        };
+
+        region
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let pos = CursorPos {
+                    x: dst_left,
+                    y: dst_top + i,
+                };
+                self.insert_data(&pos, row)
+            })
+            .collect()
+    }
+
+    /// Find urls in both scrollback and visible data. Returns (start_byte, end_byte, url) where
+    /// positions are offsets into the whole buffer. Lines scanned by a previous call are only
+    /// scanned again if they changed
+    pub fn detect_urls(&mut self) -> Vec<(usize, usize, String)> {
+        let detected = &mut self.detected_urls;
+        let scan_start = detected.end;
+        let new_urls = find_urls(&self.buf[scan_start..])
+            .into_iter()
+            .map(|(start, end, url)| (start + scan_start, end + scan_start, url));
+
+        // The last line may still be written to, keep it out of the cache
+        let new_end = self.buf[scan_start..]
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(scan_start, |idx| scan_start + idx + 1);
+
+        let mut ret = detected.urls.clone();
+        for url in new_urls {
+            if url.0 < new_end {
+                detected.urls.push(url.clone());
+            }
+            ret.push(url);
+        }
+        detected.end = new_end;
+        ret
+    }
+
+    pub fn set_max_scrollback_lines(&mut self, max_lines: usize) {
+        self.max_scrollback_lines = max_lines;
+    }
+
+    /// Drop the oldest scrollback lines if there are more than the limit, where wrapped lines
+    /// count once per row. Only lines added since the last call are counted. Returns the number
+    /// of bytes removed from the front of the buffer
+    pub fn enforce_scrollback_limit(&mut self) -> usize {
+        let mut counted = std::mem::take(&mut self.counted_lines);
+        let mut new_lines = calc_line_ranges(&self.buf[counted.end..], self.width);
+        if new_lines.len() < self.height && counted.lines > 0 {
+            // The screen reaches back into the counted lines
+            counted = CountedLines::default();
+            new_lines = calc_line_ranges(&self.buf, self.width);
+        }
+
+        let num_new_scrollback_lines = new_lines.len().saturating_sub(self.height);
+        let num_scrollback_lines = counted.lines + num_new_scrollback_lines;
+        let num_removed_lines = num_scrollback_lines.saturating_sub(self.max_scrollback_lines);
+        let removal_end = nth_line_start(&self.buf, self.width, num_removed_lines);
+
+        // Count up to the last newline before the screen, later lines may still change
+        let (counted_end, counted_lines) = (0..=num_new_scrollback_lines)
+            .rev()
+            .map(|i| (counted.end + new_lines.get(i).map_or(0, |r| r.start), i))
+            .find(|(pos, _)| *pos == counted.end || self.buf[pos - 1] == b'\n')
+            .map(|(pos, i)| (pos, counted.lines + i))
+            .unwrap_or((counted.end, counted.lines));
+
+        self.remove_detected_urls_before(removal_end);
+        self.buf.drain(0..removal_end);
+        if counted_lines >= num_removed_lines {
+            self.counted_lines = CountedLines {
+                end: counted_end - removal_end,
+                lines: counted_lines - num_removed_lines,
+            };
+        }
+
+        removal_end
+    }
+
+    /// Shift detected urls for the removal of buf[..removal_end]
+    fn remove_detected_urls_before(&mut self, removal_end: usize) {
+        let detected = &mut self.detected_urls;
+        let straddles_removal =
+            |url: &(usize, usize, String)| url.0 < removal_end && url.1 > removal_end;
+        if detected.end < removal_end || detected.urls.iter().any(straddles_removal) {
+            self.detected_urls = DetectedUrls::default();
+            return;
+        }
+
+        detected.urls.retain(|url| url.0 >= removal_end);
+        for url in &mut detected.urls {
+            url.0 -= removal_end;
+            url.1 -= removal_end;
+        }
+        detected.end -= removal_end;
+    }
+
+    /// Remove the oldest scrollback so that at most max_bytes remain. Only whole lines are
+    /// removed, so slightly less than max_bytes may be left. Returns the number of bytes removed
+    /// from the front of the buffer
+    pub fn truncate_scrollback(&mut self, max_bytes: usize) -> usize {
+        let line_ranges = calc_line_ranges(&self.buf, self.width);
+        let visible_line_ranges = line_ranges_to_visible_line_ranges(&line_ranges, self.height);
+        let Some(first_visible_line) = visible_line_ranges.first() else {
+            return 0;
+        };
+
+        let scrollback_len = first_visible_line.start;
+        if scrollback_len <= max_bytes {
+            return 0;
+        }
+
+        // Only remove whole lines so that wrapping of the remaining lines does not change
+        let min_removed = scrollback_len - max_bytes;
+        let removal_end = line_ranges
+            .iter()
+            .map(|r| r.start)
+            .find(|start| *start >= min_removed)
+            .unwrap_or(scrollback_len)
+            .min(scrollback_len);
+
+        self.mark_changed(0);
+        self.buf.drain(0..removal_end);
+        removal_end
+    }
+
     pub fn get_win_size(&self) -> (usize, usize) {
         (self.width, self.height)
     }
 
+    /// Column of the next tab stop after x, or the last column if there are none left
+    pub fn next_tab_stop(&self, x: usize) -> usize {
+        self.tab_stops
+            .iter()
+            .find(|stop| **stop > x)
+            .copied()
+            .unwrap_or(self.width.saturating_sub(1))
+            .max(x)
+    }
+
+    pub fn set_tab_stop(&mut self, x: usize) {
+        if let Err(idx) = self.tab_stops.binary_search(&x) {
+            self.tab_stops.insert(idx, x);
+        }
+    }
+
+    pub fn clear_tab_stop(&mut self, x: usize) {
+        self.tab_stops.retain(|stop| *stop != x);
+    }
+
+    pub fn clear_all_tab_stops(&mut self) {
+        self.tab_stops.clear();
+    }
+
     pub fn set_win_size(
         &mut self,
         width: usize,
@@ -580,6 +1346,9 @@ impl TerminalBuffer {
             };
         }
 
+        // Lines wrap differently at the new width
+        self.mark_changed(0);
+
         // Ensure that the cursor position has a valid buffer position. That way when we resize we
         // can just look up where the cursor is supposed to be and map it back to it's new cursor
         // position
@@ -590,6 +1359,9 @@ impl TerminalBuffer {
         let new_cursor_pos = buf_to_cursor_pos(&self.buf, width, height, buf_pos)
             .expect("buf pos should exist in buffer");
 
+        self.tab_stops.retain(|x| *x < width);
+        self.tab_stops.extend(default_tab_stops(self.width, width));
+
         self.width = width;
         self.height = height;
 
@@ -605,10 +1377,152 @@ impl TerminalBuffer {
 mod test {
     use super::*;
 
+    /// Replace response for ascii data, where replaced and written data have the same length
+    fn ascii_replace(range: Range<usize>) -> TerminalBufferReplaceResponse {
+        TerminalBufferReplaceResponse {
+            replaced_range: range.clone(),
+            written_range: range,
+        }
+    }
+
     #[test]
     fn test_calc_line_ranges() {
         let line_starts = calc_line_ranges(b"asdf\n0123456789\n012345678901", 10);
         assert_eq!(line_starts, &[0..4, 5..15, 16..26, 26..28]);
+
+        // Multi byte characters take one column
+        let line_starts = calc_line_ranges("\u{e9}\u{e9}\u{e9}a\nb".as_bytes(), 2);
+        assert_eq!(line_starts, &[0..4, 4..7, 8..9]);
+    }
+
+    #[test]
+    fn test_utf8() {
+        let mut canvas = TerminalBuffer::new(5, 5);
+        let response = canvas.insert_data(&CursorPos { x: 0, y: 0 }, "h\u{e9}llo".as_bytes());
+        assert_eq!(response.written_range, 0..6);
+        assert_eq!(response.insertion_range, 0..6);
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 5, y: 0 });
+
+        let response =
+            canvas.insert_data(&CursorPos { x: 0, y: 1 }, "\u{4e2d}\u{1f600}".as_bytes());
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 4, y: 1 });
+        assert_eq!(
+            canvas.data().visible,
+            "h\u{e9}llo\n\u{4e2d}\u{1f600}\n".as_bytes()
+        );
+
+        // Overwriting swaps whole characters, even if they have a different length
+        let response = canvas.insert_data(&CursorPos { x: 1, y: 0 }, b"e");
+        assert_eq!(response.replaced_range, 1..3);
+        assert_eq!(response.written_range, 1..2);
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 0 });
+        let response = canvas.insert_data(&CursorPos { x: 2, y: 0 }, "\u{e0}".as_bytes());
+        assert_eq!(response.replaced_range, 2..3);
+        assert_eq!(response.written_range, 2..4);
+        assert_eq!(
+            canvas.data().visible,
+            "he\u{e0}lo\n\u{4e2d}\u{1f600}\n".as_bytes()
+        );
+
+        // Padding is counted in columns
+        let response = canvas.insert_data(&CursorPos { x: 4, y: 1 }, b"x");
+        assert_eq!(response.insertion_range, 14..15);
+        assert_eq!(
+            canvas.data().visible,
+            "he\u{e0}lo\n\u{4e2d}\u{1f600}x\n".as_bytes()
+        );
+
+        assert_eq!(
+            canvas.erase_characters(&CursorPos { x: 0, y: 1 }, 1),
+            Some(TerminalBufferReplaceResponse {
+                replaced_range: 7..10,
+                written_range: 7..9,
+            })
+        );
+        assert_eq!(
+            canvas.delete_forwards(&CursorPos { x: 2, y: 1 }, 1),
+            Some(9..13)
+        );
+        assert_eq!(canvas.data().visible, "he\u{e0}lo\n  x\n".as_bytes());
+    }
+
+    #[test]
+    fn test_utf8_copy_region() {
+        let mut canvas = TerminalBuffer::new(5, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, "\u{e9}\u{e8}\u{ea}".as_bytes());
+        canvas.copy_region(0, 1, 0, 3, 1, 0);
+        assert_eq!(
+            canvas.data().visible,
+            "\u{e9}\u{e8}\u{ea}\n\u{e8}\u{ea} \n".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_wide_chars() {
+        let mut canvas = TerminalBuffer::new(5, 5);
+        let response = canvas.insert_data(&CursorPos { x: 0, y: 0 }, "\u{4e2d}\u{6587}".as_bytes());
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 4, y: 0 });
+        assert_eq!(canvas.char_width_at(&CursorPos { x: 0, y: 0 }), 2);
+        assert_eq!(canvas.char_width_at(&CursorPos { x: 1, y: 0 }), 1);
+        assert_eq!(canvas.char_width_at(&CursorPos { x: 4, y: 0 }), 1);
+
+        // Does not fit in the last column, moves to the next line
+        let response = canvas.insert_data(&response.new_cursor_pos, "\u{5b57}".as_bytes());
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 1 });
+        assert_eq!(
+            canvas.data().visible,
+            "\u{4e2d}\u{6587} \u{5b57}\n".as_bytes()
+        );
+
+        // Overwriting either half of a wide character blanks the other half
+        let response = canvas.insert_data(&CursorPos { x: 1, y: 0 }, b"a");
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 0 });
+        assert_eq!(response.replaced_range, 0..3);
+        assert_eq!(response.written_range, 0..2);
+        let response = canvas.insert_data(&CursorPos { x: 2, y: 0 }, b"b");
+        assert_eq!(response.new_cursor_pos, CursorPos { x: 3, y: 0 });
+        assert_eq!(response.replaced_range, 2..5);
+        assert_eq!(response.written_range, 2..4);
+        assert_eq!(canvas.data().visible, " ab  \u{5b57}\n".as_bytes());
+    }
+
+    #[test]
+    fn test_wide_chars_erase() {
+        let mut canvas = TerminalBuffer::new(10, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, "\u{4e2d}\u{6587}ab".as_bytes());
+
+        let response = canvas.erase_characters(&CursorPos { x: 1, y: 0 }, 1);
+        assert_eq!(
+            response,
+            Some(TerminalBufferReplaceResponse {
+                replaced_range: 0..3,
+                written_range: 0..2,
+            })
+        );
+        assert_eq!(canvas.data().visible, "  \u{6587}ab\n".as_bytes());
+
+        let response = canvas.delete_forwards(&CursorPos { x: 3, y: 0 }, 1);
+        assert_eq!(response, Some(2..5));
+        assert_eq!(canvas.data().visible, b"  ab\n");
+
+        let mut canvas = TerminalBuffer::new(10, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, "\u{4e2d}\u{6587}ab".as_bytes());
+        canvas.clear_forwards(&CursorPos { x: 3, y: 0 });
+        assert_eq!(canvas.data().visible, "\u{4e2d} \n".as_bytes());
+    }
+
+    #[test]
+    fn test_wide_chars_copy_region() {
+        let mut canvas = TerminalBuffer::new(10, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, "\u{4e2d}\u{6587}a".as_bytes());
+
+        // Wide characters cut by the region edges are blanked
+        canvas.copy_region(0, 1, 0, 3, 1, 0);
+        canvas.copy_region(0, 0, 0, 2, 2, 0);
+        assert_eq!(
+            canvas.data().visible,
+            "\u{4e2d}\u{6587}a\n \u{6587}\n\u{4e2d} \n".as_bytes()
+        );
     }
 
     #[test]
@@ -780,6 +1694,75 @@ mod test {
         assert_eq!(canvas.data().visible, b"a\n1234567\n12345\n");
     }
 
+    #[test]
+    fn test_tab_stops() {
+        let mut buffer = TerminalBuffer::new(20, 5);
+        assert_eq!(buffer.next_tab_stop(0), 8);
+        assert_eq!(buffer.next_tab_stop(7), 8);
+        assert_eq!(buffer.next_tab_stop(8), 16);
+        assert_eq!(buffer.next_tab_stop(16), 19);
+        assert_eq!(buffer.next_tab_stop(19), 19);
+
+        buffer.set_tab_stop(3);
+        buffer.clear_tab_stop(8);
+        assert_eq!(buffer.next_tab_stop(0), 3);
+        assert_eq!(buffer.next_tab_stop(3), 16);
+
+        buffer.set_win_size(10, 5, &CursorPos { x: 0, y: 0 });
+        assert_eq!(buffer.tab_stops, [3]);
+        buffer.set_win_size(30, 5, &CursorPos { x: 0, y: 0 });
+        assert_eq!(buffer.tab_stops, [3, 16, 24]);
+
+        buffer.clear_all_tab_stops();
+        assert_eq!(buffer.next_tab_stop(0), 29);
+    }
+
+    #[test]
+    fn test_erase_characters() {
+        let mut canvas = TerminalBuffer::new(10, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"asdf\n123456789012345");
+
+        let erased_range = canvas.erase_characters(&CursorPos { x: 1, y: 0 }, 2);
+        assert_eq!(erased_range, Some(ascii_replace(1..3)));
+        assert_eq!(canvas.data().visible, b"a  f\n123456789012345\n");
+
+        // Clamped on newline
+        let erased_range = canvas.erase_characters(&CursorPos { x: 2, y: 0 }, 10);
+        assert_eq!(erased_range, Some(ascii_replace(2..4)));
+        assert_eq!(canvas.data().visible, b"a   \n123456789012345\n");
+
+        // Clamped on wrap
+        let erased_range = canvas.erase_characters(&CursorPos { x: 8, y: 1 }, 10);
+        assert_eq!(erased_range, Some(ascii_replace(13..15)));
+        assert_eq!(canvas.data().visible, b"a   \n12345678  12345\n");
+
+        // Past the end of the line
+        assert_eq!(canvas.erase_characters(&CursorPos { x: 4, y: 0 }, 10), None);
+        assert_eq!(canvas.erase_characters(&CursorPos { x: 0, y: 4 }, 10), None);
+    }
+
+    #[test]
+    fn test_clear_line_backwards_and_all() {
+        let mut canvas = TerminalBuffer::new(10, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"asdf\n123456789012345");
+
+        let response = canvas.clear_line_backwards(&CursorPos { x: 1, y: 0 });
+        assert_eq!(response, Some(ascii_replace(0..2)));
+        assert_eq!(canvas.data().visible, b"  df\n123456789012345\n");
+
+        // Wrapped lines stay in place
+        let response = canvas.clear_line(&CursorPos { x: 3, y: 1 });
+        assert_eq!(response, Some(ascii_replace(5..15)));
+        assert_eq!(canvas.data().visible, b"  df\n          12345\n");
+
+        // Cursor past the end of the line
+        let response = canvas.clear_line_backwards(&CursorPos { x: 20, y: 2 });
+        assert_eq!(response, Some(ascii_replace(15..20)));
+        assert_eq!(canvas.data().visible, b"  df\n               \n");
+
+        assert_eq!(canvas.clear_line(&CursorPos { x: 0, y: 4 }), None);
+    }
+
     #[test]
     fn test_canvas_insert_spaces() {
         let mut canvas = TerminalBuffer::new(10, 5);
@@ -787,28 +1770,35 @@ mod test {
 
         // Happy path
         let response = canvas.insert_spaces(&CursorPos { x: 2, y: 0 }, 2);
-        assert_eq!(response.written_range, 2..4);
+        assert_eq!(response.written_range, 2..6);
         assert_eq!(response.insertion_range, 2..4);
+        assert_eq!(response.replaced_range, 2..6);
         assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 0 });
         assert_eq!(canvas.data().visible, b"as  df\n123456789012345\n");
 
         // Truncation at newline
         let response = canvas.insert_spaces(&CursorPos { x: 2, y: 0 }, 1000);
         assert_eq!(response.written_range, 2..10);
-        assert_eq!(response.insertion_range, 2..6);
+        assert_eq!(response.insertion_range, 2..10);
+        assert_eq!(response.replaced_range, 2..14);
         assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 0 });
         assert_eq!(canvas.data().visible, b"as        \n123456789012345\n");
 
         // Truncation at line wrap
         let response = canvas.insert_spaces(&CursorPos { x: 4, y: 1 }, 1000);
         assert_eq!(response.written_range, 15..21);
-        assert_eq!(
-            response.insertion_range.start - response.insertion_range.end,
-            0
-        );
+        assert_eq!(response.insertion_range, 15..21);
+        assert_eq!(response.replaced_range, 15..27);
         assert_eq!(response.new_cursor_pos, CursorPos { x: 4, y: 1 });
         assert_eq!(canvas.data().visible, b"as        \n1234      12345\n");
 
+        // A full row shifts right, the next row is not touched
+        let response = canvas.insert_spaces(&CursorPos { x: 0, y: 1 }, 2);
+        assert_eq!(response.written_range, 11..21);
+        assert_eq!(response.insertion_range, 11..13);
+        assert_eq!(response.replaced_range, 11..23);
+        assert_eq!(canvas.data().visible, b"as        \n  1234    12345\n");
+
         // Insertion at non-existant buffer pos
         let response = canvas.insert_spaces(&CursorPos { x: 2, y: 4 }, 3);
         assert_eq!(response.written_range, 30..33);
@@ -816,8 +1806,16 @@ mod test {
         assert_eq!(response.new_cursor_pos, CursorPos { x: 2, y: 4 });
         assert_eq!(
             canvas.data().visible,
-            b"as        \n1234      12345\n\n     \n"
+            b"as        \n  1234    12345\n\n     \n"
         );
+
+        // A wide character split by the right margin is dropped whole
+        let mut canvas = TerminalBuffer::new(5, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, "ab\u{4e2d}c".as_bytes());
+        canvas.insert_spaces(&CursorPos { x: 0, y: 0 }, 1);
+        assert_eq!(canvas.data().visible, " ab\u{4e2d}\n".as_bytes());
+        canvas.insert_spaces(&CursorPos { x: 0, y: 0 }, 1);
+        assert_eq!(canvas.data().visible, "  ab \n".as_bytes());
     }
 
     #[test]
@@ -875,28 +1873,290 @@ mod test {
         let mut canvas = TerminalBuffer::new(5, 5);
 
         // Test empty canvas
-        let response = canvas.insert_lines(&CursorPos { x: 0, y: 0 }, 3);
+        let responses = canvas.insert_lines(&CursorPos { x: 0, y: 0 }, 3, 4);
         // Clear doesn't have to do anything as there's nothing in the canvas to push aside
-        assert_eq!(response.deleted_range.start - response.deleted_range.end, 0);
-        assert_eq!(
-            response.inserted_range.start - response.inserted_range.end,
-            0
-        );
+        for response in responses {
+            assert_eq!(response.deleted_range.start - response.deleted_range.end, 0);
+            assert_eq!(
+                response.inserted_range.start - response.inserted_range.end,
+                0
+            );
+        }
         assert_eq!(canvas.data().visible, b"");
 
         // Test edge wrapped
         canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"0123456789asdf\nxyzw");
         assert_eq!(canvas.data().visible, b"0123456789asdf\nxyzw\n");
-        let response = canvas.insert_lines(&CursorPos { x: 3, y: 2 }, 1);
+        let response = &canvas.insert_lines(&CursorPos { x: 3, y: 2 }, 1, 4)[0];
         assert_eq!(canvas.data().visible, b"0123456789\n\nasdf\nxyzw\n");
         assert_eq!(response.deleted_range.start - response.deleted_range.end, 0);
         assert_eq!(response.inserted_range, 10..12);
 
         // Test newline wrapped + lines pushed off the edge
-        let response = canvas.insert_lines(&CursorPos { x: 3, y: 2 }, 1);
+        let response = &canvas.insert_lines(&CursorPos { x: 3, y: 2 }, 1, 4)[0];
         assert_eq!(canvas.data().visible, b"0123456789\n\n\nasdf\n");
         assert_eq!(response.deleted_range, 17..22);
         assert_eq!(response.inserted_range, 11..12);
+
+        // Lines pushed past the bottom of the region are discarded, lines below it stay put
+        let mut canvas = TerminalBuffer::new(5, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"a\nb\nc\nd\ne");
+        let responses = canvas.insert_lines(&CursorPos { x: 0, y: 1 }, 10, 2);
+        assert_eq!(responses.len(), 2);
+        assert_eq!(canvas.data().visible, b"a\n\n\nd\ne\n");
+
+        // Cursor below the region
+        assert!(canvas
+            .insert_lines(&CursorPos { x: 0, y: 3 }, 1, 2)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_scroll_region_up() {
+        let mut canvas = TerminalBuffer::new(5, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"a\nbbbbbcc\nd\ne");
+        assert_eq!(canvas.data().visible, b"a\nbbbbbcc\nd\ne\n");
+
+        // Wrapped line at the top of the region, line below the region stays put
+        let response = canvas.scroll_region_up(1, 3);
+        assert_eq!(canvas.data().visible, b"a\ncc\nd\n\ne\n");
+        assert_eq!(response.deleted_range, 2..7);
+        assert_eq!(response.inserted_range, 7..8);
+
+        // Region extends to the last line of the buffer
+        let response = canvas.scroll_region_up(3, 4);
+        assert_eq!(canvas.data().visible, b"a\ncc\nd\ne\n\n");
+        assert_eq!(response.deleted_range, 7..8);
+        assert_eq!(response.inserted_range, 9..10);
+
+        // Region below all content
+        let response = canvas.scroll_region_up(5, 6);
+        assert_eq!(response.deleted_range, 0..0);
+    }
+
+    #[test]
+    fn test_delete_lines() {
+        let mut canvas = TerminalBuffer::new(5, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"a\nb\nc\nd\ne");
+
+        let responses = canvas.delete_lines(&CursorPos { x: 3, y: 1 }, 2, 4);
+        assert_eq!(responses.len(), 2);
+        assert_eq!(canvas.data().visible, b"a\nd\ne\n\n\n");
+
+        // Clamped to the bottom of the region
+        canvas.delete_lines(&CursorPos { x: 0, y: 1 }, 10, 2);
+        assert_eq!(canvas.data().visible, b"a\n\n\n\n\n");
+
+        // Cursor below the region
+        assert!(canvas
+            .delete_lines(&CursorPos { x: 0, y: 3 }, 1, 2)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_scroll_up() {
+        let mut canvas = TerminalBuffer::new(5, 4);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"a\nbbbbbcc");
+
+        let inserted_range = canvas.scroll_up(2);
+        assert_eq!(inserted_range, 10..13);
+        assert_eq!(canvas.data().scrollback, b"a\nbbbbb");
+        assert_eq!(canvas.data().visible, b"cc\n\n\n\n");
+
+        // More lines than fit on screen only leave blank lines behind
+        canvas.scroll_up(10);
+        assert_eq!(canvas.data().visible, b"\n\n\n\n");
+    }
+
+    #[test]
+    fn test_scroll_region_down() {
+        let mut canvas = TerminalBuffer::new(5, 5);
+        canvas.insert_data(&CursorPos { x: 0, y: 0 }, b"a\nbbbbbcc\nd\ne");
+
+        // Line below the region stays put
+        let response = canvas.scroll_region_down(1, 3);
+        assert_eq!(canvas.data().visible, b"a\n\nbbbbbcc\ne\n");
+        assert_eq!(response.deleted_range, 10..12);
+        assert_eq!(response.inserted_range, 2..3);
+
+        // Wrapped line above the insertion point
+        let response = canvas.scroll_region_down(3, 4);
+        assert_eq!(canvas.data().visible, b"a\n\nbbbbb\n\ncc\n");
+        assert_eq!(response.deleted_range, 11..13);
+        assert_eq!(response.inserted_range, 8..10);
+    }
+
+    #[test]
+    fn test_detect_urls() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.buf =
+            b"see https://example.com/a?b=c.\nhttp://x.org (http://y.org)\nhttp://".to_vec();
+        assert_eq!(
+            buffer.detect_urls(),
+            &[
+                (4, 29, "https://example.com/a?b=c".to_string()),
+                (31, 43, "http://x.org".to_string()),
+                (45, 57, "http://y.org".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detect_urls_incremental() {
+        let mut buffer = TerminalBuffer::new_with_limit(20, 2, 1);
+        buffer.insert_data(&CursorPos { x: 0, y: 0 }, b"http://a.org\nhttp://b");
+        assert_eq!(buffer.detect_urls(), find_urls(&buffer.buf));
+        assert_eq!(buffer.detected_urls.end, buffer.buf.len());
+
+        // Writing to a line rescans it, but not the lines before it
+        buffer.insert_data(&CursorPos { x: 8, y: 1 }, b".org");
+        assert_eq!(buffer.detected_urls.end, 13);
+        assert_eq!(buffer.detected_urls.urls.len(), 1);
+        assert_eq!(buffer.detect_urls(), find_urls(&buffer.buf));
+
+        // Changes to cached lines drop them from the cache
+        buffer.insert_data(&CursorPos { x: 7, y: 0 }, b"c");
+        assert_eq!(buffer.detected_urls.end, 0);
+        assert_eq!(buffer.detect_urls(), find_urls(&buffer.buf));
+
+        // Scrolled out lines shift the cached urls
+        buffer.insert_data(
+            &CursorPos { x: 0, y: 1 },
+            b"http://b.org\nx\nhttps://d.org/e",
+        );
+        buffer.detect_urls();
+        assert_eq!(buffer.enforce_scrollback_limit(), 13);
+        assert_eq!(buffer.detect_urls(), find_urls(&buffer.buf));
+        assert_eq!(
+            buffer.detect_urls(),
+            &[
+                (0, 12, "http://b.org".to_string()),
+                (15, 30, "https://d.org/e".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_copy_region() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.buf = b"abcd\nefgh\nijkl\n".to_vec();
+        buffer.copy_region(0, 0, 1, 1, 2, 5);
+        assert_eq!(buffer.data().visible, b"abcd\nefgh\nijkl ab\n     ef\n");
+
+        // Source past the end of lines is copied as spaces
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.buf = b"ab\ncdef\n".to_vec();
+        buffer.copy_region(0, 0, 1, 3, 0, 5);
+        assert_eq!(buffer.data().visible, b"ab   ab  \ncdef cdef\n");
+    }
+
+    #[test]
+    fn test_selected_text() {
+        let mut buffer = TerminalBuffer::new(5, 4);
+        buffer.buf = b"ab   \ncdefg12\n\xe6\x97\xa5x\n".to_vec();
+        let pos = |x, y| CursorPos { x, y };
+
+        assert_eq!(buffer.selected_text(&pos(1, 0), &pos(3, 0)), b"b");
+        // Wrapped rows are joined, either end can come first
+        assert_eq!(buffer.selected_text(&pos(3, 2), &pos(1, 0)), b"b\ncdefg12");
+        // Starting on the second half of a wide character includes all of it
+        assert_eq!(
+            buffer.selected_text(&pos(1, 3), &pos(4, 3)),
+            "\u{65e5}x".as_bytes()
+        );
+        // Rows past the end of the buffer are ignored
+        assert_eq!(
+            buffer.selected_text(&pos(0, 3), &pos(0, 10)),
+            "\u{65e5}x".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_copy_region_overlapping() {
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.buf = b"abcdef\n".to_vec();
+        buffer.copy_region(0, 0, 0, 3, 0, 2);
+        assert_eq!(buffer.data().visible, b"ababcd\n");
+
+        let mut buffer = TerminalBuffer::new(10, 5);
+        buffer.buf = b"ab\ncd\nef\n".to_vec();
+        buffer.copy_region(0, 0, 1, 1, 1, 0);
+        assert_eq!(buffer.data().visible, b"ab\nab\ncd\n");
+    }
+
+    #[test]
+    fn test_truncate_scrollback() {
+        let mut buffer = TerminalBuffer::new(5, 2);
+        buffer.buf = b"aa\nbb\ncc\ndd\nee\n".to_vec();
+        assert_eq!(buffer.truncate_scrollback(4), 6);
+        assert_eq!(buffer.data().scrollback, b"cc\n");
+        assert_eq!(buffer.data().visible, b"dd\nee\n");
+        assert_eq!(buffer.truncate_scrollback(4), 0);
+
+        assert_eq!(buffer.truncate_scrollback(0), 3);
+        assert_eq!(buffer.data().scrollback, b"");
+        assert_eq!(buffer.data().visible, b"dd\nee\n");
+
+        // Wrapped lines are removed at the wrap point
+        let mut buffer = TerminalBuffer::new(5, 1);
+        buffer.buf = b"0123456789ab\ncd\n".to_vec();
+        assert_eq!(buffer.truncate_scrollback(5), 10);
+        assert_eq!(buffer.data().scrollback, b"ab\n");
+        assert_eq!(buffer.data().visible, b"cd\n");
+    }
+
+    #[test]
+    fn test_scrollback_limit() {
+        let mut buffer = TerminalBuffer::new_with_limit(5, 2, 3);
+        buffer.buf = (0..100)
+            .map(|i| format!("{i}\n"))
+            .collect::<String>()
+            .into();
+        buffer.enforce_scrollback_limit();
+        assert_eq!(buffer.data().scrollback, b"95\n96\n97\n");
+        assert_eq!(buffer.data().visible, b"98\n99\n");
+
+        // Wrapped lines count once per row
+        let mut buffer = TerminalBuffer::new_with_limit(5, 1, 1);
+        buffer.buf = b"0123456789ab\ncd\n".to_vec();
+        assert_eq!(buffer.enforce_scrollback_limit(), 10);
+        assert_eq!(buffer.data().scrollback, b"ab\n");
+        assert_eq!(buffer.data().visible, b"cd\n");
+
+        buffer.set_max_scrollback_lines(0);
+        assert_eq!(buffer.enforce_scrollback_limit(), 3);
+        assert_eq!(buffer.data().scrollback, b"");
+    }
+
+    #[test]
+    fn test_scrollback_limit_incremental() {
+        let mut buffer = TerminalBuffer::new_with_limit(5, 2, 3);
+        for i in 0..20 {
+            // Every third line wraps onto a second row
+            let line = match i % 3 {
+                0 => format!("{i:08}\n"),
+                _ => format!("{i}\n"),
+            };
+            buffer.buf.extend_from_slice(line.as_bytes());
+            buffer.enforce_scrollback_limit();
+
+            let num_lines = calc_line_ranges(&buffer.buf, buffer.width).len();
+            assert!(num_lines <= 5);
+            let counted = &buffer.counted_lines;
+            assert!(counted.end == 0 || buffer.buf[counted.end - 1] == b'\n');
+            assert_eq!(
+                calc_line_ranges(&buffer.buf[..counted.end], buffer.width).len(),
+                counted.lines
+            );
+        }
+        assert!(buffer.counted_lines.lines > 0);
+        assert_eq!(buffer.data().scrollback, b"16\n17\n00000");
+        assert_eq!(buffer.data().visible, b"018\n19\n");
+
+        buffer.clear_all();
+        buffer.buf.extend_from_slice(b"a\nb\n");
+        assert_eq!(buffer.enforce_scrollback_limit(), 0);
+        assert_eq!(buffer.data().visible, b"a\nb\n");
     }
 
     #[test]
@@ -905,6 +2165,10 @@ mod test {
             buf: vec![1, 5, 9, 11],
             width: 342,
             height: 9999,
+            max_scrollback_lines: DEFAULT_MAX_SCROLLBACK_LINES,
+            counted_lines: CountedLines::default(),
+            detected_urls: DetectedUrls::default(),
+            tab_stops: vec![3, 8, 100],
         };
 
         let snapshot = buf.snapshot().expect("failed to snapshot");
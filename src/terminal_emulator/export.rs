@@ -0,0 +1,244 @@
+use super::{FormatTag, TerminalColor, TerminalData};
+
+use std::fmt::Write;
+
+/// Link targets come from the child process, other schemes (e.g. javascript:) are exported as
+/// plain text
+const ALLOWED_LINK_SCHEMES: [&str; 4] = ["http", "https", "mailto", "file"];
+
+const DEFAULT_FOREGROUND: &str = "#ffffff";
+const DEFAULT_BACKGROUND: &str = "#1b1b1b";
+
+fn terminal_color_to_css(color: &TerminalColor) -> Option<String> {
+    let ret = match color {
+        TerminalColor::Default => return None,
+        TerminalColor::Black => "#000000",
+        TerminalColor::Red => "#ff0000",
+        TerminalColor::Green => "#00ff00",
+        TerminalColor::Yellow => "#ffff00",
+        TerminalColor::Blue => "#0000ff",
+        TerminalColor::Magenta => "#ff00ff",
+        TerminalColor::Cyan => "#00ffff",
+        TerminalColor::White => "#ffffff",
+        TerminalColor::Palette(idx) => {
+            let (r, g, b) = TerminalColor::palette_rgb(*idx);
+            return Some(format!("#{r:02x}{g:02x}{b:02x}"));
+        }
+        TerminalColor::Rgb(r, g, b) => return Some(format!("#{r:02x}{g:02x}{b:02x}")),
+    };
+
+    Some(ret.to_string())
+}
+
+fn push_escaped(output: &mut String, data: &[u8]) {
+    for c in String::from_utf8_lossy(data).chars() {
+        match c {
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '&' => output.push_str("&amp;"),
+            '"' => output.push_str("&quot;"),
+            _ => output.push(c),
+        }
+    }
+}
+
+fn is_allowed_link(uri: &str) -> bool {
+    uri.split_once(':').is_some_and(|(scheme, _)| {
+        ALLOWED_LINK_SCHEMES
+            .iter()
+            .any(|allowed| scheme.eq_ignore_ascii_case(allowed))
+    })
+}
+
+fn push_section(output: &mut String, data: &[u8], tags: &[FormatTag]) {
+    for tag in tags {
+        let start = tag.start.min(data.len());
+        let end = tag.end.min(data.len());
+        if start >= end {
+            continue;
+        }
+
+        let mut color = terminal_color_to_css(&tag.color);
+        let mut background_color = terminal_color_to_css(&tag.background_color);
+        if tag.reverse {
+            (color, background_color) = (
+                background_color.or_else(|| Some(DEFAULT_BACKGROUND.to_string())),
+                color.or_else(|| Some(DEFAULT_FOREGROUND.to_string())),
+            );
+        }
+
+        let mut style = String::new();
+        if let Some(color) = color {
+            write!(style, "color:{color};").expect("writing to string should not fail");
+        }
+        if let Some(color) = background_color {
+            write!(style, "background-color:{color};").expect("writing to string should not fail");
+        }
+        if tag.bold {
+            style.push_str("font-weight:bold;");
+        }
+        if tag.dim {
+            style.push_str("opacity:0.6;");
+        }
+        if tag.italic {
+            style.push_str("font-style:italic;");
+        }
+        match (tag.underline, tag.strikethrough) {
+            (true, true) => style.push_str("text-decoration:underline line-through;"),
+            (true, false) => style.push_str("text-decoration:underline;"),
+            (false, true) => style.push_str("text-decoration:line-through;"),
+            (false, false) => (),
+        }
+
+        let hyperlink = tag.hyperlink.as_deref().filter(|uri| is_allowed_link(uri));
+        if let Some(hyperlink) = hyperlink {
+            output.push_str("<a href=\"");
+            push_escaped(output, hyperlink.as_bytes());
+            output.push_str("\">");
+        }
+
+        if style.is_empty() {
+            push_escaped(output, &data[start..end]);
+        } else {
+            write!(output, "<span style=\"{style}\">").expect("writing to string should not fail");
+            push_escaped(output, &data[start..end]);
+            output.push_str("</span>");
+        }
+
+        if hyperlink.is_some() {
+            output.push_str("</a>");
+        }
+    }
+}
+
+/// Render terminal contents as a self contained html document. Scrollback and visible sections
+/// are separated by a horizontal rule
+pub fn export_to_html(
+    data: &TerminalData<&[u8]>,
+    format_data: &TerminalData<Vec<FormatTag>>,
+) -> String {
+    let mut output = format!(
+        "<!DOCTYPE html>\n\
+         <html>\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <title>termie</title>\n\
+         <style>\n\
+         body {{ background-color: {DEFAULT_BACKGROUND}; color: {DEFAULT_FOREGROUND}; }}\n\
+         pre {{ font-family: monospace; }}\n\
+         </style>\n\
+         </head>\n\
+         <body>\n\
+         <pre>",
+    );
+    push_section(&mut output, data.scrollback, &format_data.scrollback);
+    output.push_str("</pre>\n<hr>\n<pre>");
+    push_section(&mut output, data.visible, &format_data.visible);
+    output.push_str("</pre>\n</body>\n</html>\n");
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_export_to_html() {
+        let data = TerminalData {
+            scrollback: b"a<b\n".as_slice(),
+            visible: b"red bold\n".as_slice(),
+        };
+
+        let format_data = TerminalData {
+            scrollback: vec![FormatTag {
+                start: 0,
+                end: 4,
+                color: TerminalColor::Default,
+                background_color: TerminalColor::Default,
+                bold: false,
+                italic: false,
+                underline: false,
+                strikethrough: false,
+                dim: false,
+                reverse: false,
+                hyperlink: None,
+            }],
+            visible: vec![
+                FormatTag {
+                    start: 0,
+                    end: 4,
+                    color: TerminalColor::Red,
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+                FormatTag {
+                    start: 4,
+                    end: usize::MAX,
+                    color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
+                    bold: true,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+            ],
+        };
+
+        let html = export_to_html(&data, &format_data);
+        assert!(html.contains("<pre>a&lt;b\n</pre>\n<hr>\n<pre>"));
+        assert!(html.contains(
+            "<span style=\"color:#ff0000;\">red </span>\
+             <span style=\"font-weight:bold;\">bold\n</span></pre>"
+        ));
+    }
+
+    #[test]
+    fn test_export_links() {
+        let data = TerminalData {
+            scrollback: b"".as_slice(),
+            visible: b"safeevil".as_slice(),
+        };
+
+        let link_tag = |start: usize, end: usize, uri: &str| FormatTag {
+            start,
+            end,
+            color: TerminalColor::Default,
+            background_color: TerminalColor::Default,
+            bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            dim: false,
+            reverse: false,
+            hyperlink: Some(uri.to_string()),
+        };
+
+        let format_data = TerminalData {
+            scrollback: vec![],
+            visible: vec![
+                link_tag(0, 4, "https://example.com/?a=1&b=2"),
+                link_tag(4, 8, "JavaScript:alert(1)"),
+            ],
+        };
+
+        let html = export_to_html(&data, &format_data);
+        assert!(
+            html.contains("<pre><a href=\"https://example.com/?a=1&amp;b=2\">safe</a>evil</pre>")
+        );
+        assert!(!html.contains("alert"));
+
+        assert!(is_allowed_link("mailto:someone@example.com"));
+        assert!(is_allowed_link("file:///tmp/a"));
+        assert!(!is_allowed_link("data:text/html,hi"));
+        assert!(!is_allowed_link("no scheme"));
+    }
+}
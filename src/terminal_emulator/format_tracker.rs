@@ -1,4 +1,4 @@
-use std::{num::TryFromIntError, ops::Range};
+use std::{collections::BTreeMap, num::TryFromIntError, ops::Range};
 
 use super::{recording::NotIntOfType, CursorState, TerminalColor};
 use crate::terminal_emulator::recording::SnapshotItem;
@@ -58,7 +58,14 @@ fn adjust_existing_format_range(
                 start: range.end,
                 end: existing_elem.end,
                 color: existing_elem.color,
+                background_color: existing_elem.background_color,
                 bold: existing_elem.bold,
+                italic: existing_elem.italic,
+                underline: existing_elem.underline,
+                strikethrough: existing_elem.strikethrough,
+                dim: existing_elem.dim,
+                reverse: existing_elem.reverse,
+                hyperlink: existing_elem.hyperlink.clone(),
             });
         }
 
@@ -83,34 +90,60 @@ fn adjust_existing_format_range(
     ret
 }
 
-fn delete_items_from_vec<T>(mut to_delete: Vec<usize>, vec: &mut Vec<T>) {
-    to_delete.sort();
-    for idx in to_delete.iter().rev() {
-        vec.remove(*idx);
+fn adjust_existing_format_ranges(existing: &mut BTreeMap<usize, FormatTag>, range: &Range<usize>) {
+    // Tags do not overlap, so their ends are in the same order as their starts. The overlapping
+    // tags are the last few that start before the end of the range
+    let effected_starts = existing
+        .range(..range.end)
+        .rev()
+        .take_while(|(_start, item)| ranges_overlap(item.start..item.end, range.clone()))
+        .map(|(start, _item)| *start)
+        .collect::<Vec<_>>();
+
+    for start in effected_starts {
+        let mut info = existing
+            .remove(&start)
+            .expect("start was taken from the map");
+        let adjustment = adjust_existing_format_range(&mut info, range);
+        if let Some(item) = adjustment.to_insert {
+            existing.insert(item.start, item);
+        }
+        if !adjustment.should_delete {
+            existing.insert(info.start, info);
+        }
     }
 }
 
-fn adjust_existing_format_ranges(existing: &mut Vec<FormatTag>, range: &Range<usize>) {
-    let mut effected_infos = existing
-        .iter_mut()
-        .enumerate()
-        .filter(|(_i, item)| ranges_overlap(item.start..item.end, range.clone()))
-        .collect::<Vec<_>>();
+/// Whether a and b format text the same way, regardless of where they are
+fn same_format(a: &FormatTag, b: &FormatTag) -> bool {
+    a.color == b.color
+        && a.background_color == b.background_color
+        && a.bold == b.bold
+        && a.italic == b.italic
+        && a.underline == b.underline
+        && a.strikethrough == b.strikethrough
+        && a.dim == b.dim
+        && a.reverse == b.reverse
+        && a.hyperlink == b.hyperlink
+}
 
-    let mut to_delete = Vec::new();
-    let mut to_push = Vec::new();
-    for info in &mut effected_infos {
-        let adjustment = adjust_existing_format_range(info.1, range);
-        if adjustment.should_delete {
-            to_delete.push(info.0);
+/// Insert tag into the gap left for it, merging it with neighbours that format text the same way
+fn insert_merged(tags: &mut BTreeMap<usize, FormatTag>, mut tag: FormatTag) {
+    if let Some(next) = tags.get(&tag.end) {
+        if same_format(next, &tag) {
+            let next = tags.remove(&tag.end).expect("next was just found");
+            tag.end = next.end;
         }
-        if let Some(item) = adjustment.to_insert {
-            to_push.push(item);
+    }
+
+    if let Some((_start, prev)) = tags.range_mut(..tag.start).next_back() {
+        if prev.end == tag.start && same_format(prev, &tag) {
+            prev.end = tag.end;
+            return;
         }
     }
 
-    delete_items_from_vec(to_delete, existing);
-    existing.extend(to_push);
+    tags.insert(tag.start, tag);
 }
 
 struct ColorRangeAdjustment {
@@ -138,12 +171,28 @@ enum LoadFormatTagSnapshotError {
     BoldMissing,
     #[error("bold element not bool")]
     BoldNotBool,
+    #[error("italic element not bool")]
+    ItalicNotBool,
+    #[error("underline element not bool")]
+    UnderlineNotBool,
+    #[error("strikethrough element not bool")]
+    StrikethroughNotBool,
+    #[error("dim element not bool")]
+    DimNotBool,
+    #[error("reverse element not bool")]
+    ReverseNotBool,
     #[error("color element is missing")]
     ColorMissing,
     #[error("color not a string")]
     ColorNotString,
     #[error("failed to parse color from string")]
     ParseColor(()),
+    #[error("background color not a string")]
+    BackgroundColorNotString,
+    #[error("failed to parse background color from string")]
+    ParseBackgroundColor(()),
+    #[error("hyperlink not a string")]
+    HyperlinkNotString,
 }
 
 #[derive(Debug, Error)]
@@ -162,7 +211,14 @@ mod format_tag_keys {
     pub const START: &str = "start";
     pub const END: &str = "end";
     pub const COLOR: &str = "color";
+    pub const BACKGROUND_COLOR: &str = "background_color";
     pub const BOLD: &str = "bold";
+    pub const ITALIC: &str = "italic";
+    pub const UNDERLINE: &str = "underline";
+    pub const STRIKETHROUGH: &str = "strikethrough";
+    pub const DIM: &str = "dim";
+    pub const REVERSE: &str = "reverse";
+    pub const HYPERLINK: &str = "hyperlink";
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -170,7 +226,15 @@ pub struct FormatTag {
     pub start: usize,
     pub end: usize,
     pub color: TerminalColor,
+    pub background_color: TerminalColor,
     pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub dim: bool,
+    pub reverse: bool,
+    /// OSC 8 uri
+    pub hyperlink: Option<String>,
 }
 
 impl FormatTag {
@@ -192,15 +256,75 @@ impl FormatTag {
         let bold = root.remove(format_tag_keys::BOLD).ok_or(BoldMissing)?;
         let bold = bold.into_bool().map_err(|_| BoldNotBool)?;
 
+        // Optional, recordings from before italic was supported do not have it
+        let italic = root
+            .remove(format_tag_keys::ITALIC)
+            .map(|v| v.into_bool().map_err(|_| ItalicNotBool))
+            .transpose()?
+            .unwrap_or(false);
+
+        // Optional, recordings from before underline was supported do not have it
+        let underline = root
+            .remove(format_tag_keys::UNDERLINE)
+            .map(|v| v.into_bool().map_err(|_| UnderlineNotBool))
+            .transpose()?
+            .unwrap_or(false);
+
+        // Optional, recordings from before strikethrough was supported do not have it
+        let strikethrough = root
+            .remove(format_tag_keys::STRIKETHROUGH)
+            .map(|v| v.into_bool().map_err(|_| StrikethroughNotBool))
+            .transpose()?
+            .unwrap_or(false);
+
+        // Optional, recordings from before dim was supported do not have it
+        let dim = root
+            .remove(format_tag_keys::DIM)
+            .map(|v| v.into_bool().map_err(|_| DimNotBool))
+            .transpose()?
+            .unwrap_or(false);
+
+        // Optional, recordings from before reverse video was supported do not have it
+        let reverse = root
+            .remove(format_tag_keys::REVERSE)
+            .map(|v| v.into_bool().map_err(|_| ReverseNotBool))
+            .transpose()?
+            .unwrap_or(false);
+
         let color = root.remove(format_tag_keys::COLOR).ok_or(ColorMissing)?;
         let color = color.into_string().map_err(|_| ColorNotString)?;
         let color = color.parse().map_err(ParseColor)?;
 
+        // Optional, recordings from before background colors were supported do not have it
+        let background_color = root
+            .remove(format_tag_keys::BACKGROUND_COLOR)
+            .map(|v| {
+                v.into_string()
+                    .map_err(|_| BackgroundColorNotString)?
+                    .parse()
+                    .map_err(ParseBackgroundColor)
+            })
+            .transpose()?
+            .unwrap_or(TerminalColor::Default);
+
+        // Optional, only present on linked text
+        let hyperlink = root
+            .remove(format_tag_keys::HYPERLINK)
+            .map(|v| v.into_string().map_err(|_| HyperlinkNotString))
+            .transpose()?;
+
         Ok(FormatTag {
             start,
             end,
             bold,
+            italic,
+            underline,
+            strikethrough,
+            dim,
+            reverse,
             color,
+            background_color,
+            hyperlink,
         })
     }
 
@@ -212,16 +336,37 @@ impl FormatTag {
         } else {
             self.end.try_into().map_err(EndNotI64)?
         };
-        let arr = [
+        let mut arr = vec![
             (format_tag_keys::START.to_string(), start_i64.into()),
             (format_tag_keys::END.to_string(), end_i64.into()),
             (
                 format_tag_keys::COLOR.to_string(),
                 self.color.to_string().into(),
             ),
+            (
+                format_tag_keys::BACKGROUND_COLOR.to_string(),
+                self.background_color.to_string().into(),
+            ),
             (format_tag_keys::BOLD.to_string(), self.bold.into()),
+            (format_tag_keys::ITALIC.to_string(), self.italic.into()),
+            (
+                format_tag_keys::UNDERLINE.to_string(),
+                self.underline.into(),
+            ),
+            (
+                format_tag_keys::STRIKETHROUGH.to_string(),
+                self.strikethrough.into(),
+            ),
+            (format_tag_keys::DIM.to_string(), self.dim.into()),
+            (format_tag_keys::REVERSE.to_string(), self.reverse.into()),
         ];
-        Ok(SnapshotItem::Map(arr.into()))
+        if let Some(hyperlink) = &self.hyperlink {
+            arr.push((
+                format_tag_keys::HYPERLINK.to_string(),
+                hyperlink.clone().into(),
+            ));
+        }
+        Ok(SnapshotItem::Map(arr.into_iter().collect()))
     }
 }
 
@@ -238,18 +383,29 @@ enum LoadFormatTrackerSnapshotErrorKind {
 pub struct LoadFormatTrackerSnapshotError(#[from] LoadFormatTrackerSnapshotErrorKind);
 
 pub struct FormatTracker {
-    color_info: Vec<FormatTag>,
+    /// Keyed by start
+    color_info: BTreeMap<usize, FormatTag>,
 }
 
 impl FormatTracker {
     pub fn new() -> FormatTracker {
         FormatTracker {
-            color_info: vec![FormatTag {
-                start: 0,
-                end: usize::MAX,
-                color: TerminalColor::Default,
-                bold: false,
-            }],
+            color_info: BTreeMap::from([(
+                0,
+                FormatTag {
+                    start: 0,
+                    end: usize::MAX,
+                    color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+            )]),
         }
     }
 
@@ -259,8 +415,10 @@ impl FormatTracker {
         use LoadFormatTrackerSnapshotErrorKind::*;
         let arr = snapshot.into_vec().map_err(|_| NotArray)?;
 
-        let color_info: Result<Vec<FormatTag>, LoadFormatTagSnapshotError> =
-            arr.into_iter().map(FormatTag::from_snapshot).collect();
+        let color_info: Result<BTreeMap<usize, FormatTag>, LoadFormatTagSnapshotError> = arr
+            .into_iter()
+            .map(|item| FormatTag::from_snapshot(item).map(|tag| (tag.start, tag)))
+            .collect();
         let color_info = color_info.map_err(LoadTag)?;
         Ok(FormatTracker { color_info })
     }
@@ -268,64 +426,113 @@ impl FormatTracker {
     pub fn snapshot(&self) -> Result<SnapshotItem, SnapshotFormatTagError> {
         Ok(SnapshotItem::Array(
             self.color_info
-                .iter()
+                .values()
                 .map(FormatTag::snapshot)
                 .collect::<Result<Vec<_>, _>>()?,
         ))
     }
 
-    pub fn push_range(&mut self, cursor: &CursorState, range: Range<usize>) {
+    pub fn push_range(
+        &mut self,
+        cursor: &CursorState,
+        hyperlink: Option<&str>,
+        range: Range<usize>,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+
         adjust_existing_format_ranges(&mut self.color_info, &range);
 
-        self.color_info.push(FormatTag {
+        let tag = FormatTag {
             start: range.start,
             end: range.end,
             color: cursor.color,
+            background_color: cursor.background_color,
             bold: cursor.bold,
-        });
-
-        // FIXME: Insertion sort
-        // FIXME: Merge adjacent
-        self.color_info.sort_by(|a, b| a.start.cmp(&b.start));
+            italic: cursor.italic,
+            underline: cursor.underline,
+            strikethrough: cursor.strikethrough,
+            dim: cursor.dim,
+            reverse: cursor.reverse,
+            hyperlink: hyperlink.map(str::to_string),
+        };
+        insert_merged(&mut self.color_info, tag);
     }
 
     /// Move all tags > range.start to range.start + range.len
     /// No gaps in coloring data, so one range must expand instead of just be adjusted
     pub fn push_range_adjustment(&mut self, range: Range<usize>) {
         let range_len = range.end - range.start;
-        for info in &mut self.color_info {
+        let moved = self.color_info.split_off(&(range.start + 1));
+
+        for info in self.color_info.values_mut().rev() {
             if info.end <= range.start {
-                continue;
+                break;
             }
 
-            if info.start > range.start {
-                info.start += range_len;
-                if info.end != usize::MAX {
-                    info.end += range_len;
-                }
-            } else if info.end != usize::MAX {
+            if info.end != usize::MAX {
+                info.end += range_len;
+            }
+        }
+
+        self.color_info.extend(moved.into_values().map(|mut info| {
+            info.start += range_len;
+            if info.end != usize::MAX {
                 info.end += range_len;
             }
+            (info.start, info)
+        }));
+    }
+
+    /// Adjust for the data in `replaced` being swapped for `written_len` bytes starting at the
+    /// same position
+    pub fn push_replace_adjustment(&mut self, replaced: Range<usize>, written_len: usize) {
+        let written_end = replaced.start + written_len;
+        match written_end.cmp(&replaced.end) {
+            std::cmp::Ordering::Greater => self.push_range_adjustment(replaced.end..written_end),
+            std::cmp::Ordering::Less => self.delete_range(written_end..replaced.end),
+            std::cmp::Ordering::Equal => (),
         }
     }
 
     pub fn tags(&self) -> Vec<FormatTag> {
-        self.color_info.clone()
+        self.color_info.values().cloned().collect()
+    }
+
+    pub fn tag_count(&self) -> usize {
+        self.color_info.len()
+    }
+
+    /// Shift tags to account for evicted_bytes being removed from the front of the buffer
+    pub fn on_evict_scrollback(&mut self, evicted_bytes: usize) {
+        if evicted_bytes == 0 {
+            return;
+        }
+
+        self.delete_range(0..evicted_bytes);
     }
 
     pub fn delete_range(&mut self, range: Range<usize>) {
-        let mut to_delete = Vec::new();
         let del_size = range.end - range.start;
 
-        for (i, info) in &mut self.color_info.iter_mut().enumerate() {
+        // Tags ending before the deleted range are untouched
+        let split_pos = match self.color_info.range(..=range.start).next_back() {
+            Some((start, info)) if info.end > range.start => *start,
+            _ => range.start,
+        };
+        let affected = self.color_info.split_off(&split_pos);
+
+        for mut info in affected.into_values() {
             let info_range = info.start..info.end;
             if info.end <= range.start {
+                self.color_info.insert(info.start, info);
                 continue;
             }
 
             if ranges_overlap(range.clone(), info_range.clone()) {
                 if range_fully_conatins(&range, &info_range) {
-                    to_delete.push(i);
+                    continue;
                 } else if range_starts_overlapping(&range, &info_range) {
                     if info.end != usize::MAX {
                         info.end = range.start;
@@ -349,10 +556,8 @@ impl FormatTracker {
                     info.end -= del_size;
                 }
             }
-        }
 
-        for i in to_delete.into_iter().rev() {
-            self.color_info.remove(i);
+            self.color_info.insert(info.start, info);
         }
     }
 }
@@ -368,11 +573,17 @@ mod test {
         let mut cursor_state = CursorState {
             pos: CursorPos { x: 0, y: 0 },
             color: TerminalColor::Default,
+            background_color: TerminalColor::Default,
             bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            dim: false,
+            reverse: false,
         };
 
         cursor_state.color = TerminalColor::Yellow;
-        format_tracker.push_range(&cursor_state, 3..10);
+        format_tracker.push_range(&cursor_state, None, 3..10);
         let tags = format_tracker.tags();
         assert_eq!(
             tags,
@@ -381,25 +592,46 @@ mod test {
                     start: 0,
                     end: 3,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 3,
                     end: 10,
                     color: TerminalColor::Yellow,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 10,
                     end: usize::MAX,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
 
         cursor_state.color = TerminalColor::Blue;
-        format_tracker.push_range(&cursor_state, 5..7);
+        format_tracker.push_range(&cursor_state, None, 5..7);
         let tags = format_tracker.tags();
         assert_eq!(
             tags,
@@ -408,37 +640,72 @@ mod test {
                     start: 0,
                     end: 3,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 3,
                     end: 5,
                     color: TerminalColor::Yellow,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 5,
                     end: 7,
                     color: TerminalColor::Blue,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 7,
                     end: 10,
                     color: TerminalColor::Yellow,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 10,
                     end: usize::MAX,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
 
         cursor_state.color = TerminalColor::Green;
-        format_tracker.push_range(&cursor_state, 7..9);
+        format_tracker.push_range(&cursor_state, None, 7..9);
         let tags = format_tracker.tags();
         assert_eq!(
             tags,
@@ -447,44 +714,86 @@ mod test {
                     start: 0,
                     end: 3,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 3,
                     end: 5,
                     color: TerminalColor::Yellow,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 5,
                     end: 7,
                     color: TerminalColor::Blue,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 7,
                     end: 9,
                     color: TerminalColor::Green,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 9,
                     end: 10,
                     color: TerminalColor::Yellow,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 10,
                     end: usize::MAX,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
 
         cursor_state.color = TerminalColor::Red;
         cursor_state.bold = true;
-        format_tracker.push_range(&cursor_state, 6..11);
+        format_tracker.push_range(&cursor_state, None, 6..11);
         let tags = format_tracker.tags();
         assert_eq!(
             tags,
@@ -493,34 +802,185 @@ mod test {
                     start: 0,
                     end: 3,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 3,
                     end: 5,
                     color: TerminalColor::Yellow,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 5,
                     end: 6,
                     color: TerminalColor::Blue,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 6,
                     end: 11,
                     color: TerminalColor::Red,
-                    bold: true
+                    background_color: TerminalColor::Default,
+                    bold: true,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 11,
                     end: usize::MAX,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_adjacent_tags() {
+        let mut format_tracker = FormatTracker::new();
+        let mut cursor_state = CursorState {
+            pos: CursorPos { x: 0, y: 0 },
+            color: TerminalColor::Green,
+            background_color: TerminalColor::Default,
+            bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            dim: false,
+            reverse: false,
+        };
+
+        for i in 0..10 {
+            format_tracker.push_range(&cursor_state, None, i..i + 1);
+        }
+        assert_eq!(format_tracker.tag_count(), 2);
+
+        // Same format but a different link must stay separate
+        format_tracker.push_range(&cursor_state, Some("https://example.com"), 10..12);
+        cursor_state.color = TerminalColor::Default;
+        format_tracker.push_range(&cursor_state, None, 12..14);
+
+        assert_eq!(
+            format_tracker.tags(),
+            &[
+                FormatTag {
+                    start: 0,
+                    end: 10,
+                    color: TerminalColor::Green,
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+                FormatTag {
+                    start: 10,
+                    end: 12,
+                    color: TerminalColor::Green,
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: Some("https://example.com".to_string()),
+                },
+                FormatTag {
+                    start: 12,
+                    end: usize::MAX,
+                    color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
+
+        // Runs in the middle of default formatted text leave the default on either side
+        let mut format_tracker = FormatTracker::new();
+        cursor_state.color = TerminalColor::Blue;
+        for i in 0..100 {
+            format_tracker.push_range(&cursor_state, None, 10 + i..11 + i);
+        }
+        let tags = format_tracker.tags();
+        assert_eq!(tags.len(), 3);
+        assert_eq!(
+            tags.iter()
+                .map(|tag| (tag.start, tag.end, tag.color))
+                .collect::<Vec<_>>(),
+            &[
+                (0, 10, TerminalColor::Default),
+                (10, 110, TerminalColor::Blue),
+                (110, usize::MAX, TerminalColor::Default),
+            ]
+        );
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with cargo test --release -- --ignored --nocapture bench_push_range"]
+    fn bench_push_range() {
+        const NUM_PUSHES: usize = 10_000;
+
+        let mut format_tracker = FormatTracker::new();
+        let mut cursor_state = CursorState::new();
+        let start = std::time::Instant::now();
+        for i in 0..NUM_PUSHES {
+            // Alternate colors so that nothing merges
+            cursor_state.color = if i % 2 == 0 {
+                TerminalColor::Red
+            } else {
+                TerminalColor::Blue
+            };
+            format_tracker.push_range(&cursor_state, None, i * 10..i * 10 + 10);
+        }
+        let elapsed = start.elapsed();
+
+        assert_eq!(format_tracker.tag_count(), NUM_PUSHES + 1);
+        println!(
+            "{NUM_PUSHES} push_range calls in {elapsed:?}, {:.0} calls/s",
+            NUM_PUSHES as f64 / elapsed.as_secs_f64()
+        );
     }
 
     #[test]
@@ -539,11 +999,17 @@ mod test {
         let mut cursor = CursorState {
             pos: CursorPos { x: 0, y: 0 },
             color: TerminalColor::Blue,
+            background_color: TerminalColor::Default,
             bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            dim: false,
+            reverse: false,
         };
-        format_tracker.push_range(&cursor, 0..10);
+        format_tracker.push_range(&cursor, None, 0..10);
         cursor.color = TerminalColor::Red;
-        format_tracker.push_range(&cursor, 10..20);
+        format_tracker.push_range(&cursor, None, 10..20);
 
         format_tracker.delete_range(0..2);
         assert_eq!(
@@ -553,19 +1019,40 @@ mod test {
                     start: 0,
                     end: 8,
                     color: TerminalColor::Blue,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 8,
                     end: 18,
                     color: TerminalColor::Red,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 18,
                     end: usize::MAX,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 }
             ]
         );
@@ -578,19 +1065,40 @@ mod test {
                     start: 0,
                     end: 6,
                     color: TerminalColor::Blue,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 6,
                     end: 16,
                     color: TerminalColor::Red,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 16,
                     end: usize::MAX,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 }
             ]
         );
@@ -603,19 +1111,40 @@ mod test {
                     start: 0,
                     end: 4,
                     color: TerminalColor::Blue,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 4,
                     end: 14,
                     color: TerminalColor::Red,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 14,
                     end: usize::MAX,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 }
             ]
         );
@@ -628,19 +1157,40 @@ mod test {
                     start: 0,
                     end: 2,
                     color: TerminalColor::Blue,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 2,
                     end: 9,
                     color: TerminalColor::Red,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 9,
                     end: usize::MAX,
                     color: TerminalColor::Default,
-                    bold: false
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 }
             ]
         );
@@ -652,11 +1202,17 @@ mod test {
         let mut cursor = CursorState {
             pos: CursorPos { x: 0, y: 0 },
             color: TerminalColor::Blue,
+            background_color: TerminalColor::Default,
             bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            dim: false,
+            reverse: false,
         };
-        format_tracker.push_range(&cursor, 0..5);
+        format_tracker.push_range(&cursor, None, 0..5);
         cursor.color = TerminalColor::Red;
-        format_tracker.push_range(&cursor, 5..10);
+        format_tracker.push_range(&cursor, None, 5..10);
 
         assert_eq!(
             format_tracker.tags(),
@@ -665,19 +1221,40 @@ mod test {
                     start: 0,
                     end: 5,
                     color: TerminalColor::Blue,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 5,
                     end: 10,
                     color: TerminalColor::Red,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 10,
                     end: usize::MAX,
                     color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
@@ -691,19 +1268,40 @@ mod test {
                     start: 0,
                     end: 8,
                     color: TerminalColor::Blue,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 8,
                     end: 13,
                     color: TerminalColor::Red,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 13,
                     end: usize::MAX,
                     color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
@@ -717,19 +1315,40 @@ mod test {
                     start: 0,
                     end: 8,
                     color: TerminalColor::Blue,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 8,
                     end: 13,
                     color: TerminalColor::Red,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 13,
                     end: usize::MAX,
                     color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
@@ -744,19 +1363,40 @@ mod test {
                     start: 0,
                     end: 8,
                     color: TerminalColor::Blue,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 8,
                     end: 15,
                     color: TerminalColor::Red,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 15,
                     end: usize::MAX,
                     color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
@@ -769,7 +1409,14 @@ mod test {
             // Edge case test, usize max needs to be set to -1
             end: usize::MAX,
             color: TerminalColor::Blue,
+            background_color: TerminalColor::Default,
             bold: true,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            dim: true,
+            reverse: false,
+            hyperlink: None,
         };
 
         let loaded = FormatTag::from_snapshot(tag.snapshot().expect("failed to snapshot"))
@@ -781,30 +1428,97 @@ mod test {
             // Edge case test, usize max needs to be set to -1
             end: 105,
             color: TerminalColor::Red,
+            background_color: TerminalColor::Default,
             bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: true,
+            dim: false,
+            reverse: true,
+            hyperlink: Some("https://example.com".to_string()),
         };
         let loaded = FormatTag::from_snapshot(tag.snapshot().expect("failed to snapshot"))
             .expect("failed to load snapshot");
         assert_eq!(loaded, tag);
     }
 
+    #[test]
+    fn test_underline_split() {
+        let mut format_tracker = FormatTracker::new();
+        let mut cursor = CursorState {
+            pos: CursorPos { x: 0, y: 0 },
+            bold: false,
+            italic: false,
+            underline: true,
+            strikethrough: false,
+            dim: false,
+            reverse: false,
+            color: TerminalColor::Default,
+            background_color: TerminalColor::Default,
+        };
+        format_tracker.push_range(&cursor, None, 0..10);
+
+        cursor.underline = false;
+        format_tracker.push_range(&cursor, None, 3..5);
+
+        let underlined = format_tracker
+            .tags()
+            .into_iter()
+            .map(|tag| (tag.start..tag.end, tag.underline))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            underlined,
+            &[
+                (0..3, true),
+                (3..5, false),
+                (5..10, true),
+                (10..usize::MAX, false)
+            ]
+        );
+
+        let loaded =
+            FormatTracker::from_snapshot(format_tracker.snapshot().expect("failed to snapshot"))
+                .expect("failed to load snapshot");
+        assert_eq!(loaded.color_info, format_tracker.color_info);
+    }
+
     #[test]
     fn test_format_tracker_snapshot() {
         let tracker = FormatTracker {
-            color_info: vec![
-                FormatTag {
-                    start: 0,
-                    end: 5,
-                    color: TerminalColor::Black,
-                    bold: false,
-                },
-                FormatTag {
-                    start: 5,
-                    end: usize::MAX,
-                    color: TerminalColor::Red,
-                    bold: true,
-                },
-            ],
+            color_info: BTreeMap::from([
+                (
+                    0,
+                    FormatTag {
+                        start: 0,
+                        end: 5,
+                        color: TerminalColor::Black,
+                        background_color: TerminalColor::Default,
+                        bold: false,
+                        italic: false,
+                        underline: false,
+                        strikethrough: false,
+                        dim: false,
+                        reverse: false,
+                        hyperlink: None,
+                    },
+                ),
+                (
+                    5,
+                    FormatTag {
+                        start: 5,
+                        end: usize::MAX,
+                        color: TerminalColor::Red,
+                        background_color: TerminalColor::Default,
+                        bold: true,
+                        italic: false,
+                        underline: false,
+                        strikethrough: false,
+                        dim: false,
+                        reverse: false,
+                        hyperlink: None,
+                    },
+                ),
+            ]),
         };
 
         let loaded = FormatTracker::from_snapshot(tracker.snapshot().expect("failed to snapshot"))
@@ -0,0 +1,45 @@
+use super::{ReadResponse, TermIo, TermIoErr};
+
+/// Io implementation that does not talk to a child process. Anything written by the terminal
+/// emulator is stored in `written` for inspection, reads are served from `to_read`
+pub struct MockIo {
+    pub written: Vec<u8>,
+    pub to_read: Vec<u8>,
+    /// Once `to_read` is drained, report end of file instead of waiting for more data
+    pub closed: bool,
+}
+
+impl MockIo {
+    pub fn new() -> MockIo {
+        MockIo {
+            written: Vec::new(),
+            to_read: Vec::new(),
+            closed: false,
+        }
+    }
+}
+
+impl TermIo for MockIo {
+    fn read(&mut self, buf: &mut [u8]) -> Result<ReadResponse, TermIoErr> {
+        if self.to_read.is_empty() {
+            if self.closed {
+                return Ok(ReadResponse::Eof);
+            }
+            return Ok(ReadResponse::Empty);
+        }
+
+        let read_size = buf.len().min(self.to_read.len());
+        buf[..read_size].copy_from_slice(&self.to_read[..read_size]);
+        self.to_read.drain(..read_size);
+        Ok(ReadResponse::Success(read_size))
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, TermIoErr> {
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn set_win_size(&mut self, _width: usize, _height: usize) -> Result<(), TermIoErr> {
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
+mod mock;
 mod pty;
+pub use mock::MockIo;
 pub use pty::{CreatePtyIoError, PtyIo};
 
 pub type TermIoErr = Box<dyn std::error::Error>;
@@ -6,6 +8,8 @@ pub type TermIoErr = Box<dyn std::error::Error>;
 pub enum ReadResponse {
     Success(usize),
     Empty,
+    /// The other end is gone, no more data will arrive
+    Eof,
 }
 
 pub trait TermIo {
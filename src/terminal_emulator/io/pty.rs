@@ -1,12 +1,20 @@
-use nix::{errno::Errno, ioctl_write_ptr_bad, unistd::ForkResult};
+use nix::{
+    errno::Errno,
+    ioctl_write_ptr_bad,
+    sys::wait::{WaitPidFlag, WaitStatus},
+    unistd::{AccessFlags, ForkResult, Pid},
+};
 
 use tempfile::TempDir;
 use thiserror::Error;
 
 use std::{
-    ffi::CStr,
-    os::fd::{AsRawFd, OwnedFd},
-    path::Path,
+    ffi::{CString, NulError},
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
 };
 
 use super::{ReadResponse, TermIo, TermIoErr};
@@ -53,6 +61,10 @@ fn extract_terminfo() -> Result<TempDir, ExtractTerminfoError> {
 
 #[derive(Error, Debug)]
 enum SpawnShellErrorKind {
+    #[error("shell {0} not found")]
+    NotFound(PathBuf),
+    #[error("shell path contains a nul byte")]
+    InvalidPath(#[source] NulError),
     #[error("failed to fork")]
     Fork(#[source] Errno),
     #[error("failed to exec")]
@@ -63,35 +75,64 @@ enum SpawnShellErrorKind {
 #[error(transparent)]
 struct SpawnShellError(#[from] SpawnShellErrorKind);
 
-/// Spawn a shell in a child process and return the file descriptor used for I/O
-fn spawn_shell(terminfo_dir: &Path) -> Result<OwnedFd, SpawnShellError> {
+fn is_executable(path: &Path) -> bool {
+    path.is_file() && nix::unistd::access(path, AccessFlags::X_OK).is_ok()
+}
+
+/// Resolve the shell the same way execvp would, so that a missing shell can be reported before
+/// forking
+fn find_shell(shell: &Path) -> Option<PathBuf> {
+    if shell.as_os_str().as_bytes().contains(&b'/') {
+        return is_executable(shell).then(|| shell.to_path_buf());
+    }
+
+    let search_path = std::env::var_os("PATH")?;
+    std::env::split_paths(&search_path)
+        .map(|dir| dir.join(shell))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// Spawn a shell in a child process and return the file descriptor used for I/O along with the
+/// pid of the child. Variables in env are set for the child on top of the defaults
+fn spawn_shell(
+    shell: &Path,
+    env: &[(String, String)],
+    terminfo_dir: &Path,
+) -> Result<(OwnedFd, Pid), SpawnShellError> {
+    let shell_path =
+        find_shell(shell).ok_or_else(|| SpawnShellErrorKind::NotFound(shell.into()))?;
+    let shell_path = CString::new(shell_path.as_os_str().as_bytes())
+        .map_err(SpawnShellErrorKind::InvalidPath)?;
+
+    let mut args =
+        vec![CString::new(shell.as_os_str().as_bytes()).map_err(SpawnShellErrorKind::InvalidPath)?];
+    // Other shells do not understand these, and may refuse to start when given them
+    if shell.file_name() == Some("bash".as_ref()) {
+        args.extend(
+            ["--noprofile", "--norc"]
+                .map(|arg| CString::new(arg).expect("Should never contain a nul byte")),
+        );
+    }
+
     unsafe {
         let res = nix::pty::forkpty(None, None).map_err(SpawnShellErrorKind::Fork)?;
-        match res.fork_result {
-            ForkResult::Parent { .. } => (),
+        let child_pid = match res.fork_result {
+            ForkResult::Parent { child } => child,
             ForkResult::Child => {
-                let shell_name = CStr::from_bytes_with_nul(b"bash\0")
-                    .expect("Should always have null terminator");
-                let args: &[&[u8]] = &[b"bash\0", b"--noprofile\0", b"--norc\0"];
-
-                let args: Vec<&'static CStr> = args
-                    .iter()
-                    .map(|v| {
-                        CStr::from_bytes_with_nul(v).expect("Should always have null terminator")
-                    })
-                    .collect::<Vec<_>>();
-
                 // Temporary workaround to avoid rendering issues
                 std::env::remove_var("PROMPT_COMMAND");
                 std::env::set_var("TERMINFO", terminfo_dir);
                 std::env::set_var("TERM", "termie");
                 std::env::set_var("PS1", "$ ");
-                nix::unistd::execvp(shell_name, &args).map_err(SpawnShellErrorKind::Exec)?;
+                for (key, value) in env {
+                    std::env::set_var(key, value);
+                }
+                nix::unistd::execv(&shell_path, &args).map_err(SpawnShellErrorKind::Exec)?;
                 // Should never run
                 std::process::exit(1);
             }
-        }
-        Ok(res.master)
+        };
+        Ok((res.master, child_pid))
     }
 }
 
@@ -134,33 +175,82 @@ enum PtyIoErrKind {
     Read(#[source] Errno),
     #[error("failed to write to file descriptor")]
     Write(#[source] Errno),
+    #[error("failed to wait for child process")]
+    Wait(#[source] Errno),
 }
 
 #[derive(Debug, Error)]
 #[error(transparent)]
 pub struct PtyIoErr(#[from] PtyIoErrKind);
 
+pub type ExitCallback = Box<dyn Fn(i32) + Send>;
+
 pub struct PtyIo {
     fd: OwnedFd,
+    child_pid: Pid,
+    child_exited: bool,
+    exit_callback: Option<ExitCallback>,
     _terminfo_dir: TempDir,
 }
 
 impl PtyIo {
-    pub fn new() -> Result<PtyIo, CreatePtyIoError> {
+    pub fn new(shell: &Path, env: &[(String, String)]) -> Result<PtyIo, CreatePtyIoError> {
         let terminfo_dir = extract_terminfo().map_err(CreatePtyIoErrorKind::ExtractTerminfo)?;
-        let fd = spawn_shell(terminfo_dir.path()).map_err(CreatePtyIoErrorKind::SpawnShell)?;
+        let (fd, child_pid) = spawn_shell(shell, env, terminfo_dir.path())
+            .map_err(CreatePtyIoErrorKind::SpawnShell)?;
         set_nonblock(&fd).map_err(CreatePtyIoErrorKind::SetNonblock)?;
         Ok(PtyIo {
             fd,
+            child_pid,
+            child_exited: false,
+            exit_callback: None,
             _terminfo_dir: terminfo_dir,
         })
     }
+
+    pub fn child_pid(&self) -> Pid {
+        self.child_pid
+    }
+
+    pub fn set_exit_callback(&mut self, cb: ExitCallback) {
+        self.exit_callback = Some(cb);
+    }
+
+    /// Reap the child after the pty has been closed. Returns the exit code once the child is gone
+    fn reap_child(&mut self) -> Result<Option<i32>, Errno> {
+        let status = nix::sys::wait::waitpid(self.child_pid, Some(WaitPidFlag::WNOHANG))?;
+        let code = match status {
+            WaitStatus::Exited(_, code) => code,
+            // Match shell convention for processes killed by a signal
+            WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+            _ => return Ok(None),
+        };
+        Ok(Some(code))
+    }
 }
 
 impl TermIo for PtyIo {
     fn read(&mut self, buf: &mut [u8]) -> Result<ReadResponse, TermIoErr> {
+        if self.child_exited {
+            return Ok(ReadResponse::Eof);
+        }
+
         let res = nix::unistd::read(self.fd.as_raw_fd(), buf);
         match res {
+            // The slave side of the pty is closed, the child is gone or on its way out. Linux
+            // reports this as EIO, other platforms as end of file
+            Ok(0) | Err(Errno::EIO) => {
+                let Some(code) = self.reap_child().map_err(PtyIoErrKind::Wait)? else {
+                    return Ok(ReadResponse::Empty);
+                };
+
+                info!("Child process exited with code {code}");
+                self.child_exited = true;
+                if let Some(cb) = &self.exit_callback {
+                    cb(code);
+                }
+                Ok(ReadResponse::Eof)
+            }
             Ok(v) => Ok(ReadResponse::Success(v)),
             Err(Errno::EAGAIN) => Ok(ReadResponse::Empty),
             Err(e) => Err(Box::new(PtyIoErrKind::Read(e))),
@@ -194,3 +284,51 @@ impl TermIo for PtyIo {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_spawn_shell() {
+        assert_eq!(
+            find_shell(Path::new("/bin/sh")),
+            Some(PathBuf::from("/bin/sh"))
+        );
+        assert!(find_shell(Path::new("sh")).is_some());
+
+        let io = PtyIo::new(Path::new("/bin/sh"), &[]).expect("failed to spawn /bin/sh");
+        assert!(io.child_pid().as_raw() > 0);
+
+        let res = PtyIo::new(Path::new("/does/not/exist/sh"), &[]);
+        let err = res.err().expect("spawning a missing shell should fail");
+        let source = std::error::Error::source(&err).expect("error should have a source");
+        assert_eq!(source.to_string(), "shell /does/not/exist/sh not found");
+    }
+
+    #[test]
+    fn test_child_exit() {
+        let exit_code = std::sync::Arc::new(std::sync::Mutex::new(None));
+        let mut io = PtyIo::new(Path::new("/bin/true"), &[]).expect("failed to spawn /bin/true");
+        {
+            let exit_code = std::sync::Arc::clone(&exit_code);
+            io.set_exit_callback(Box::new(move |code| {
+                *exit_code.lock().expect("exit code lock poisoned") = Some(code);
+            }));
+        }
+
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let mut buf = [0; 4096];
+        loop {
+            assert!(std::time::Instant::now() < deadline, "child never exited");
+            match io.read(&mut buf).expect("failed to read") {
+                ReadResponse::Eof => break,
+                ReadResponse::Success(_) => (),
+                ReadResponse::Empty => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        }
+
+        assert_eq!(*exit_code.lock().expect("exit code lock poisoned"), Some(0));
+        assert!(matches!(io.read(&mut buf), Ok(ReadResponse::Eof)));
+    }
+}
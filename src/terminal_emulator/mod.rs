@@ -1,35 +1,58 @@
-use std::{fmt, num::TryFromIntError, path::PathBuf};
+use std::{
+    borrow::Cow,
+    fmt,
+    num::TryFromIntError,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
 
 use ansi::{AnsiParser, SelectGraphicRendition, TerminalOutput};
 use buffer::TerminalBuffer;
 use format_tracker::FormatTracker;
 use recording::{NotIntOfType, Recorder};
 
+pub use buffer::DEFAULT_MAX_SCROLLBACK_LINES;
 pub use format_tracker::FormatTag;
-pub use io::{PtyIo, TermIo};
-pub use recording::{LoadRecordingError, Recording, RecordingHandle, SnapshotItem};
-pub use replay::{ControlAction, ReplayControl, ReplayIo};
+pub use io::{MockIo, PtyIo, TermIo};
+pub use recording::{LoadRecordingError, Recording, RecordingHandle, RecordingItem, SnapshotItem};
+pub use replay::{ReplayControl, ReplayIo, Thumbnail};
 
 use crate::{error::backtraced_err, terminal_emulator::io::ReadResponse};
 use thiserror::Error;
 
 use self::{
     io::CreatePtyIoError,
-    recording::{RecordingItem, StartRecordingResponse},
+    recording::{RecordingInitializer, StartRecordingResponse},
 };
 
 mod ansi;
 mod buffer;
+mod export;
 mod format_tracker;
 mod io;
 mod recording;
 mod replay;
+// Recordings store full snapshots, diffs are not used by them yet
+#[allow(unused)]
+mod snapshot_diff;
 
 #[derive(Eq, PartialEq)]
 enum Mode {
     // Cursor keys mode
     // https://vt100.net/docs/vt100-ug/chapter3.html
     Decckm,
+    // Origin mode, cursor positions are relative to the scroll region
+    Decom,
+    // Autowrap mode, text written past the right margin continues on the next line
+    DecAwm,
+    // Insert mode, written text moves the rest of the line right instead of overwriting it
+    Irm,
+    // Text cursor enable
+    DecTcem,
+    // Switch to a separate screen without scrollback, used by full screen applications
+    DecAltScreen,
+    // Wrap pasted text in ESC [200~ and ESC [201~
+    BracketedPaste,
     Unknown(Vec<u8>),
 }
 
@@ -37,6 +60,12 @@ impl fmt::Debug for Mode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Mode::Decckm => f.write_str("Decckm"),
+            Mode::Decom => f.write_str("Decom"),
+            Mode::DecAwm => f.write_str("DecAwm"),
+            Mode::Irm => f.write_str("Irm"),
+            Mode::DecTcem => f.write_str("DecTcem"),
+            Mode::DecAltScreen => f.write_str("DecAltScreen"),
+            Mode::BracketedPaste => f.write_str("BracketedPaste"),
             Mode::Unknown(params) => {
                 let params_s = std::str::from_utf8(params)
                     .expect("parameter parsing should not allow non-utf8 characters here");
@@ -52,10 +81,13 @@ fn char_to_ctrl_code(c: u8) -> u8 {
     c & 0b0001_1111
 }
 
+const BRACKETED_PASTE_START: &[u8] = b"\x1b[200~";
+const BRACKETED_PASTE_END: &[u8] = b"\x1b[201~";
+
 #[derive(Eq, PartialEq, Debug)]
 enum TerminalInputPayload {
     Single(u8),
-    Many(&'static [u8]),
+    Many(Cow<'static, [u8]>),
 }
 
 #[derive(Clone)]
@@ -64,8 +96,15 @@ pub enum TerminalInput {
     Ascii(u8),
     // Normal keypress with ctrl
     Ctrl(u8),
+    // Normal keypress with alt, sent as ESC followed by the key
+    AltAscii(u8),
     Enter,
     Backspace,
+    Tab,
+    // Backtab
+    ShiftTab,
+    // The bare escape key, not an escape sequence
+    Escape,
     ArrowRight,
     ArrowLeft,
     ArrowUp,
@@ -76,50 +115,91 @@ pub enum TerminalInput {
     Insert,
     PageUp,
     PageDown,
+    // F1 to F12
+    F(u8),
+    // Text from the clipboard
+    PasteBuffer(Vec<u8>),
 }
 
 impl TerminalInput {
-    fn to_payload(&self, decckm_mode: bool) -> TerminalInputPayload {
+    fn to_payload(&self, modes: &TerminalModes) -> TerminalInputPayload {
         match self {
             TerminalInput::Ascii(c) => TerminalInputPayload::Single(*c),
             TerminalInput::Ctrl(c) => TerminalInputPayload::Single(char_to_ctrl_code(*c)),
+            TerminalInput::AltAscii(c) => TerminalInputPayload::Many(vec![0x1b, *c].into()),
             TerminalInput::Enter => TerminalInputPayload::Single(b'\n'),
             // Hard to tie back, but check default VERASE in terminfo definition
             TerminalInput::Backspace => TerminalInputPayload::Single(0x7f),
+            TerminalInput::Tab => TerminalInputPayload::Single(b'\t'),
+            TerminalInput::ShiftTab => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[Z")),
+            TerminalInput::Escape => TerminalInputPayload::Single(0x1b),
             // https://vt100.net/docs/vt100-ug/chapter3.html
             // Table 3-6
-            TerminalInput::ArrowRight => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOC"),
-                false => TerminalInputPayload::Many(b"\x1b[C"),
+            TerminalInput::ArrowRight => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOC")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[C")),
             },
-            TerminalInput::ArrowLeft => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOD"),
-                false => TerminalInputPayload::Many(b"\x1b[D"),
+            TerminalInput::ArrowLeft => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOD")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[D")),
             },
-            TerminalInput::ArrowUp => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOA"),
-                false => TerminalInputPayload::Many(b"\x1b[A"),
+            TerminalInput::ArrowUp => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOA")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[A")),
             },
-            TerminalInput::ArrowDown => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOB"),
-                false => TerminalInputPayload::Many(b"\x1b[B"),
+            TerminalInput::ArrowDown => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOB")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[B")),
             },
-            TerminalInput::Home => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOH"),
-                false => TerminalInputPayload::Many(b"\x1b[H"),
+            TerminalInput::Home => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOH")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[H")),
             },
-            TerminalInput::End => match decckm_mode {
-                true => TerminalInputPayload::Many(b"\x1bOF"),
-                false => TerminalInputPayload::Many(b"\x1b[F"),
+            TerminalInput::End => match modes.decckm {
+                true => TerminalInputPayload::Many(Cow::Borrowed(b"\x1bOF")),
+                false => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[F")),
             },
             // Why \e[3~? It seems like we are emulating the vt510. Other terminals do it, so we
             // can too
             // https://web.archive.org/web/20160304024035/http://www.vt100.net/docs/vt510-rm/chapter8
             // https://en.wikipedia.org/wiki/Delete_character
-            TerminalInput::Delete => TerminalInputPayload::Many(b"\x1b[3~"),
-            TerminalInput::Insert => TerminalInputPayload::Many(b"\x1b[2~"),
-            TerminalInput::PageUp => TerminalInputPayload::Many(b"\x1b[5~"),
-            TerminalInput::PageDown => TerminalInputPayload::Many(b"\x1b[6~"),
+            TerminalInput::Delete => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[3~")),
+            TerminalInput::Insert => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[2~")),
+            TerminalInput::PageUp => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[5~")),
+            TerminalInput::PageDown => TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[6~")),
+            // vt220 style, codes skip 16 and 22. Matches kf1-kf12 in res/termie.ti
+            TerminalInput::F(n) => {
+                let code = match n {
+                    1..=5 => n + 10,
+                    6..=10 => n + 11,
+                    11 | 12 => n + 12,
+                    _ => {
+                        warn!("No sequence for F{n}");
+                        return TerminalInputPayload::Many(Cow::Borrowed(b""));
+                    }
+                };
+                TerminalInputPayload::Many(format!("\x1b[{code}~").into_bytes().into())
+            }
+            TerminalInput::PasteBuffer(data) => {
+                if !modes.bracketed_paste {
+                    return TerminalInputPayload::Many(data.clone().into());
+                }
+
+                // A pasted end marker would let the clipboard contents escape the paste and run
+                // as typed input
+                let mut payload = BRACKETED_PASTE_START.to_vec();
+                let mut remaining = data.as_slice();
+                while let Some(pos) = remaining
+                    .windows(BRACKETED_PASTE_END.len())
+                    .position(|w| w == BRACKETED_PASTE_END)
+                {
+                    payload.extend_from_slice(&remaining[..pos]);
+                    remaining = &remaining[pos + BRACKETED_PASTE_END.len()..];
+                }
+                payload.extend_from_slice(remaining);
+                payload.extend_from_slice(BRACKETED_PASTE_END);
+                TerminalInputPayload::Many(payload.into())
+            }
         }
     }
 }
@@ -182,6 +262,45 @@ enum LoadCursorPosError {
     YNotUsize(#[source] NotIntOfType),
 }
 
+/// Set with DECSCUSR
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CursorStyle {
+    #[default]
+    BlinkingBlock,
+    SteadyBlock,
+    BlinkingUnderline,
+    SteadyUnderline,
+    BlinkingBar,
+    SteadyBar,
+}
+
+impl CursorStyle {
+    /// https://vt100.net/docs/vt510-rm/DECSCUSR.html, with the bar styles from xterm
+    fn from_param(param: usize) -> Option<CursorStyle> {
+        let style = match param {
+            0 | 1 => CursorStyle::BlinkingBlock,
+            2 => CursorStyle::SteadyBlock,
+            3 => CursorStyle::BlinkingUnderline,
+            4 => CursorStyle::SteadyUnderline,
+            5 => CursorStyle::BlinkingBar,
+            6 => CursorStyle::SteadyBar,
+            _ => return None,
+        };
+        Some(style)
+    }
+
+    fn to_param(self) -> usize {
+        match self {
+            CursorStyle::BlinkingBlock => 1,
+            CursorStyle::SteadyBlock => 2,
+            CursorStyle::BlinkingUnderline => 3,
+            CursorStyle::SteadyUnderline => 4,
+            CursorStyle::BlinkingBar => 5,
+            CursorStyle::SteadyBar => 6,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CursorPos {
     pub x: usize,
@@ -221,7 +340,13 @@ impl CursorPos {
 mod cursor_state_keys {
     pub const POS: &str = "pos";
     pub const BOLD: &str = "bold";
+    pub const ITALIC: &str = "italic";
+    pub const UNDERLINE: &str = "underline";
+    pub const STRIKETHROUGH: &str = "strikethrough";
+    pub const DIM: &str = "dim";
+    pub const REVERSE: &str = "reverse";
     pub const COLOR: &str = "color";
+    pub const BACKGROUND_COLOR: &str = "background_color";
 }
 
 #[derive(Debug, Error)]
@@ -232,12 +357,26 @@ enum LoadCursorStateErrorPriv {
     BoldNotPresent,
     #[error("bold field is not a bool")]
     BoldNotBool,
+    #[error("italic field is not a bool")]
+    ItalicNotBool,
+    #[error("underline field is not a bool")]
+    UnderlineNotBool,
+    #[error("strikethrough field is not a bool")]
+    StrikethroughNotBool,
+    #[error("dim field is not a bool")]
+    DimNotBool,
+    #[error("reverse field is not a bool")]
+    ReverseNotBool,
     #[error("color field is not present")]
     ColorNotPresent,
     #[error("color field is not a bool")]
     ColorNotString,
     #[error("color failed to parse")]
     ColorInvalid(()),
+    #[error("background_color field is not a string")]
+    BackgroundColorNotString,
+    #[error("background_color failed to parse")]
+    BackgroundColorInvalid(()),
     #[error("pos field not present")]
     PosNotPresent,
     #[error("failed to parse position")]
@@ -252,10 +391,30 @@ pub struct LoadCursorStateError(#[from] LoadCursorStateErrorPriv);
 struct CursorState {
     pos: CursorPos,
     bold: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+    dim: bool,
+    reverse: bool,
     color: TerminalColor,
+    background_color: TerminalColor,
 }
 
 impl CursorState {
+    fn new() -> CursorState {
+        CursorState {
+            pos: CursorPos { x: 0, y: 0 },
+            bold: false,
+            italic: false,
+            underline: false,
+            strikethrough: false,
+            dim: false,
+            reverse: false,
+            color: TerminalColor::Default,
+            background_color: TerminalColor::Default,
+        }
+    }
+
     fn from_snapshot(snapshot: SnapshotItem) -> Result<CursorState, LoadCursorStateError> {
         use LoadCursorStateErrorPriv::*;
         let mut map = snapshot.into_map().map_err(|_| RootNotMap)?;
@@ -265,6 +424,41 @@ impl CursorState {
             Err(BoldNotBool)?
         };
 
+        // Optional, recordings from before italic was supported do not have it
+        let italic = match map.remove(cursor_state_keys::ITALIC) {
+            Some(SnapshotItem::Bool(italic)) => italic,
+            Some(_) => Err(ItalicNotBool)?,
+            None => false,
+        };
+
+        // Optional, recordings from before underline was supported do not have it
+        let underline = match map.remove(cursor_state_keys::UNDERLINE) {
+            Some(SnapshotItem::Bool(underline)) => underline,
+            Some(_) => Err(UnderlineNotBool)?,
+            None => false,
+        };
+
+        // Optional, recordings from before strikethrough was supported do not have it
+        let strikethrough = match map.remove(cursor_state_keys::STRIKETHROUGH) {
+            Some(SnapshotItem::Bool(strikethrough)) => strikethrough,
+            Some(_) => Err(StrikethroughNotBool)?,
+            None => false,
+        };
+
+        // Optional, recordings from before dim was supported do not have it
+        let dim = match map.remove(cursor_state_keys::DIM) {
+            Some(SnapshotItem::Bool(dim)) => dim,
+            Some(_) => Err(DimNotBool)?,
+            None => false,
+        };
+
+        // Optional, recordings from before reverse video was supported do not have it
+        let reverse = match map.remove(cursor_state_keys::REVERSE) {
+            Some(SnapshotItem::Bool(reverse)) => reverse,
+            Some(_) => Err(ReverseNotBool)?,
+            None => false,
+        };
+
         let color = map
             .remove(cursor_state_keys::COLOR)
             .ok_or(ColorNotPresent)?;
@@ -273,10 +467,27 @@ impl CursorState {
         };
         let color = color.parse().map_err(ColorInvalid)?;
 
+        // Optional, recordings from before background colors were supported do not have it
+        let background_color = match map.remove(cursor_state_keys::BACKGROUND_COLOR) {
+            Some(SnapshotItem::String(color)) => color.parse().map_err(BackgroundColorInvalid)?,
+            Some(_) => Err(BackgroundColorNotString)?,
+            None => TerminalColor::Default,
+        };
+
         let pos = map.remove(cursor_state_keys::POS).ok_or(PosNotPresent)?;
         let pos = CursorPos::from_snapshot(pos).map_err(FailParsePos)?;
 
-        Ok(CursorState { bold, color, pos })
+        Ok(CursorState {
+            bold,
+            italic,
+            underline,
+            strikethrough,
+            dim,
+            reverse,
+            color,
+            background_color,
+            pos,
+        })
     }
 
     fn snapshot(&self) -> Result<SnapshotItem, SnapshotCursorPosError> {
@@ -284,10 +495,25 @@ impl CursorState {
             [
                 (cursor_state_keys::POS.to_string(), self.pos.snapshot()?),
                 (cursor_state_keys::BOLD.to_string(), self.bold.into()),
+                (cursor_state_keys::ITALIC.to_string(), self.italic.into()),
+                (
+                    cursor_state_keys::UNDERLINE.to_string(),
+                    self.underline.into(),
+                ),
+                (
+                    cursor_state_keys::STRIKETHROUGH.to_string(),
+                    self.strikethrough.into(),
+                ),
+                (cursor_state_keys::DIM.to_string(), self.dim.into()),
+                (cursor_state_keys::REVERSE.to_string(), self.reverse.into()),
                 (
                     cursor_state_keys::COLOR.to_string(),
                     self.color.to_string().into(),
                 ),
+                (
+                    cursor_state_keys::BACKGROUND_COLOR.to_string(),
+                    self.background_color.to_string().into(),
+                ),
             ]
             .into(),
         );
@@ -306,11 +532,16 @@ pub enum TerminalColor {
     Magenta,
     Cyan,
     White,
+    /// Entry of the xterm 256 color palette
+    Palette(u8),
+    Rgb(u8, u8, u8),
 }
 
 impl fmt::Display for TerminalColor {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match self {
+            TerminalColor::Palette(idx) => return write!(f, "palette({idx})"),
+            TerminalColor::Rgb(r, g, b) => return write!(f, "rgb({r},{g},{b})"),
             TerminalColor::Default => "default",
             TerminalColor::Black => "black",
             TerminalColor::Red => "red",
@@ -330,6 +561,23 @@ impl std::str::FromStr for TerminalColor {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(idx) = s.strip_prefix("palette(").and_then(|s| s.strip_suffix(')')) {
+            return Ok(TerminalColor::Palette(idx.parse().map_err(|_| ())?));
+        }
+
+        if let Some(rgb) = s.strip_prefix("rgb(").and_then(|s| s.strip_suffix(')')) {
+            let mut components = rgb.split(',').map(|c| c.parse::<u8>().map_err(|_| ()));
+            let (Some(r), Some(g), Some(b), None) = (
+                components.next(),
+                components.next(),
+                components.next(),
+                components.next(),
+            ) else {
+                return Err(());
+            };
+            return Ok(TerminalColor::Rgb(r?, g?, b?));
+        }
+
         let ret = match s {
             "default" => TerminalColor::Default,
             "black" => TerminalColor::Black,
@@ -346,7 +594,50 @@ impl std::str::FromStr for TerminalColor {
     }
 }
 
+/// The first 16 palette entries, matching the named colors. Bright colors are not distinguished
+/// except for bright black, which would otherwise be invisible
+const BASE_PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x00, 0x00, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x00, 0x00, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
 impl TerminalColor {
+    /// RGB value of an xterm 256 color palette entry. 16-231 are a 6x6x6 color cube, 232-255 a
+    /// grayscale ramp
+    pub fn palette_rgb(idx: u8) -> (u8, u8, u8) {
+        const CUBE_LEVELS: [u8; 6] = [0x00, 0x5f, 0x87, 0xaf, 0xd7, 0xff];
+
+        match idx {
+            0..=15 => BASE_PALETTE[idx as usize],
+            16..=231 => {
+                let idx = (idx - 16) as usize;
+                (
+                    CUBE_LEVELS[idx / 36],
+                    CUBE_LEVELS[(idx / 6) % 6],
+                    CUBE_LEVELS[idx % 6],
+                )
+            }
+            232..=255 => {
+                let level = 8 + (idx - 232) * 10;
+                (level, level, level)
+            }
+        }
+    }
+
     fn from_sgr(sgr: SelectGraphicRendition) -> Option<TerminalColor> {
         let ret = match sgr {
             SelectGraphicRendition::ForegroundBlack => TerminalColor::Black,
@@ -357,6 +648,45 @@ impl TerminalColor {
             SelectGraphicRendition::ForegroundMagenta => TerminalColor::Magenta,
             SelectGraphicRendition::ForegroundCyan => TerminalColor::Cyan,
             SelectGraphicRendition::ForegroundWhite => TerminalColor::White,
+            // Bright colors are entries 8-15 of the palette
+            SelectGraphicRendition::ForegroundBrightBlack => TerminalColor::Palette(8),
+            SelectGraphicRendition::ForegroundBrightRed => TerminalColor::Palette(9),
+            SelectGraphicRendition::ForegroundBrightGreen => TerminalColor::Palette(10),
+            SelectGraphicRendition::ForegroundBrightYellow => TerminalColor::Palette(11),
+            SelectGraphicRendition::ForegroundBrightBlue => TerminalColor::Palette(12),
+            SelectGraphicRendition::ForegroundBrightMagenta => TerminalColor::Palette(13),
+            SelectGraphicRendition::ForegroundBrightCyan => TerminalColor::Palette(14),
+            SelectGraphicRendition::ForegroundBrightWhite => TerminalColor::Palette(15),
+            SelectGraphicRendition::ForegroundDefault => TerminalColor::Default,
+            SelectGraphicRendition::ForegroundPalette(idx) => TerminalColor::Palette(idx),
+            SelectGraphicRendition::ForegroundRgb(r, g, b) => TerminalColor::Rgb(r, g, b),
+            _ => return None,
+        };
+
+        Some(ret)
+    }
+
+    fn from_background_sgr(sgr: SelectGraphicRendition) -> Option<TerminalColor> {
+        let ret = match sgr {
+            SelectGraphicRendition::BackgroundBlack => TerminalColor::Black,
+            SelectGraphicRendition::BackgroundRed => TerminalColor::Red,
+            SelectGraphicRendition::BackgroundGreen => TerminalColor::Green,
+            SelectGraphicRendition::BackgroundYellow => TerminalColor::Yellow,
+            SelectGraphicRendition::BackgroundBlue => TerminalColor::Blue,
+            SelectGraphicRendition::BackgroundMagenta => TerminalColor::Magenta,
+            SelectGraphicRendition::BackgroundCyan => TerminalColor::Cyan,
+            SelectGraphicRendition::BackgroundWhite => TerminalColor::White,
+            SelectGraphicRendition::BackgroundBrightBlack => TerminalColor::Palette(8),
+            SelectGraphicRendition::BackgroundBrightRed => TerminalColor::Palette(9),
+            SelectGraphicRendition::BackgroundBrightGreen => TerminalColor::Palette(10),
+            SelectGraphicRendition::BackgroundBrightYellow => TerminalColor::Palette(11),
+            SelectGraphicRendition::BackgroundBrightBlue => TerminalColor::Palette(12),
+            SelectGraphicRendition::BackgroundBrightMagenta => TerminalColor::Palette(13),
+            SelectGraphicRendition::BackgroundBrightCyan => TerminalColor::Palette(14),
+            SelectGraphicRendition::BackgroundBrightWhite => TerminalColor::Palette(15),
+            SelectGraphicRendition::BackgroundDefault => TerminalColor::Default,
+            SelectGraphicRendition::BackgroundPalette(idx) => TerminalColor::Palette(idx),
+            SelectGraphicRendition::BackgroundRgb(r, g, b) => TerminalColor::Rgb(r, g, b),
             _ => return None,
         };
 
@@ -409,58 +739,260 @@ enum LoadSnapshotErrorPriv {
     CursorStateNotPresent,
     #[error("failed to load cursor state")]
     LoadCursorState(#[from] LoadCursorStateError),
+    #[error("window_title field not string")]
+    WindowTitleNotString,
+    #[error("current_hyperlink field not string")]
+    CurrentHyperlinkNotString,
+    #[error("primary_screen field not map")]
+    PrimaryScreenNotMap,
+    #[error("scroll_region field is not a pair of line numbers")]
+    ScrollRegionInvalid,
+    #[error("origin_mode field not bool")]
+    OriginModeNotBool,
+    #[error("bracketed_paste_mode field not bool")]
+    BracketedPasteNotBool,
+    #[error("decawm_mode field not bool")]
+    DecawmNotBool,
+    #[error("insert_mode field not bool")]
+    InsertModeNotBool,
+    #[error("cursor_visible field not bool")]
+    CursorVisibleNotBool,
+    #[error("cursor_style field is not a valid DECSCUSR parameter")]
+    CursorStyleInvalid,
+    #[error("failed to load saved cursor")]
+    LoadSavedCursor(#[source] LoadCursorStateError),
 }
 
 #[derive(Debug, Error)]
 #[error(transparent)]
 pub struct LoadSnapshotError(#[from] LoadSnapshotErrorPriv);
 
+/// Lines that scroll, set with DECSTBM. 0 indexed and inclusive
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct ScrollRegion {
+    top: usize,
+    bottom: usize,
+}
+
+impl ScrollRegion {
+    fn from_snapshot(snapshot: SnapshotItem) -> Result<ScrollRegion, LoadSnapshotErrorPriv> {
+        use LoadSnapshotErrorPriv::*;
+
+        let lines = snapshot.into_vec().map_err(|_| ScrollRegionInvalid)?;
+        let [top, bottom] =
+            <[SnapshotItem; 2]>::try_from(lines).map_err(|_| ScrollRegionInvalid)?;
+        Ok(ScrollRegion {
+            top: top.into_num().map_err(|_| ScrollRegionInvalid)?,
+            bottom: bottom.into_num().map_err(|_| ScrollRegionInvalid)?,
+        })
+    }
+
+    fn snapshot(&self) -> SnapshotItem {
+        SnapshotItem::Array(vec![(self.top as i64).into(), (self.bottom as i64).into()])
+    }
+}
+
+/// Primary screen state kept aside while an application is using the alternate screen
+struct SavedScreen {
+    terminal_buffer: TerminalBuffer,
+    format_tracker: FormatTracker,
+    cursor_state: CursorState,
+}
+
+impl SavedScreen {
+    fn from_snapshot(snapshot: SnapshotItem) -> Result<SavedScreen, LoadSnapshotErrorPriv> {
+        use LoadSnapshotErrorPriv::*;
+
+        let mut root = snapshot.into_map().map_err(|_| PrimaryScreenNotMap)?;
+        let terminal_buffer =
+            TerminalBuffer::from_snapshot(root.remove("terminal_buffer").ok_or(BufferNotPresent)?)?;
+        let format_tracker = FormatTracker::from_snapshot(
+            root.remove("format_tracker")
+                .ok_or(FormatTrackerNotPresent)?,
+        )?;
+        let cursor_state =
+            CursorState::from_snapshot(root.remove("cursor_state").ok_or(CursorStateNotPresent)?)?;
+
+        Ok(SavedScreen {
+            terminal_buffer,
+            format_tracker,
+            cursor_state,
+        })
+    }
+
+    fn snapshot(&self) -> Result<SnapshotItem, StartRecordingErrorPriv> {
+        let res = SnapshotItem::Map(
+            [
+                (
+                    "terminal_buffer".to_string(),
+                    self.terminal_buffer.snapshot()?,
+                ),
+                (
+                    "format_tracker".to_string(),
+                    self.format_tracker.snapshot()?,
+                ),
+                ("cursor_state".to_string(), self.cursor_state.snapshot()?),
+            ]
+            .into(),
+        );
+        Ok(res)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EmulatorStats {
+    pub bytes_processed: u64,
+    pub frames_rendered: u64,
+    pub scrollback_bytes: usize,
+    pub format_tag_count: usize,
+    pub cursor_pos: CursorPos,
+}
+
+/// Snapshot of the modes that change how the terminal interprets input and output
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct TerminalModes {
+    pub decckm: bool,
+    pub alt_screen: bool,
+    pub origin_mode: bool,
+    pub bracketed_paste: bool,
+    pub cursor_visible: bool,
+    pub decawm: bool,
+    pub insert_mode: bool,
+}
+
 pub struct TerminalEmulator<Io: TermIo> {
     parser: AnsiParser,
     terminal_buffer: TerminalBuffer,
     format_tracker: FormatTracker,
     cursor_state: CursorState,
     decckm_mode: bool,
+    bracketed_paste_mode: bool,
+    /// Set while the alternate screen is active
+    primary_screen: Option<SavedScreen>,
+    /// None if the whole screen scrolls
+    scroll_region: Option<ScrollRegion>,
+    origin_mode: bool,
+    /// When unset, text written past the right margin overwrites the last column
+    decawm_mode: bool,
+    /// Set with IRM, written text is inserted rather than overwriting
+    insert_mode: bool,
+    cursor_visible: bool,
+    cursor_style: CursorStyle,
+    /// Set with DECSC, restored with DECRC
+    saved_cursor: Option<CursorState>,
     recorder: Recorder,
+    max_bytes_per_read_call: usize,
+    /// The last read stopped at max_bytes_per_read_call, there may be more output waiting
+    output_pending: bool,
+    /// Applied to every buffer we create
+    max_scrollback_lines: usize,
+    bytes_processed: u64,
+    frames_rendered: u64,
+    osc52_enabled: bool,
+    clipboard_request: Option<String>,
+    bell_rung: bool,
+    /// Set once the child has exited and all of its output has been read
+    done: bool,
+    /// Set whenever the buffer or format data may have changed, see [`TerminalEmulator::is_dirty`]
+    dirty: bool,
+    /// Uri applied to written text, set with OSC 8
+    current_hyperlink: Option<String>,
+    /// Set with OSC 0, 1 and 2, empty if the child never set one
+    window_title: String,
+    resize_history: Vec<(usize, usize, SystemTime)>,
+    last_read_time: Instant,
+    /// How long a partial escape sequence may wait for more bytes, None waits forever
+    parser_timeout: Option<Duration>,
     io: Io,
 }
 
-pub const TERMINAL_WIDTH: usize = 50;
-pub const TERMINAL_HEIGHT: usize = 16;
+/// Size used until the gui tells us how much space there is
+pub const DEFAULT_TERMINAL_WIDTH: usize = 50;
+pub const DEFAULT_TERMINAL_HEIGHT: usize = 16;
+const READ_BUF_SIZE: usize = 64 * 1024;
+const MAX_RESIZE_HISTORY: usize = 100;
+const PARSER_TIMEOUT: Duration = Duration::from_millis(100);
+/// Default limit on how much child output is processed per frame, so that a flood of output
+/// does not starve the gui
+pub const DEFAULT_MAX_BYTES_PER_FRAME: usize = 1024 * 1024;
 
 impl TerminalEmulator<PtyIo> {
-    pub fn new(recording_path: PathBuf) -> Result<TerminalEmulator<PtyIo>, CreatePtyIoError> {
-        let mut io = PtyIo::new()?;
+    pub fn new(
+        shell: &Path,
+        env: &[(String, String)],
+        recording_path: PathBuf,
+        width_chars: usize,
+        height_chars: usize,
+    ) -> Result<TerminalEmulator<PtyIo>, CreatePtyIoError> {
+        let mut io = PtyIo::new(shell, env)?;
 
-        if let Err(e) = io.set_win_size(TERMINAL_WIDTH, TERMINAL_HEIGHT) {
+        if let Err(e) = io.set_win_size(width_chars, height_chars) {
             error!("Failed to set initial window size: {}", backtraced_err(&*e));
         }
 
-        let ret = TerminalEmulator {
-            parser: AnsiParser::new(),
-            terminal_buffer: TerminalBuffer::new(TERMINAL_WIDTH, TERMINAL_HEIGHT),
-            format_tracker: FormatTracker::new(),
-            decckm_mode: false,
-            cursor_state: CursorState {
-                pos: CursorPos { x: 0, y: 0 },
-                bold: false,
-                color: TerminalColor::Default,
-            },
-            recorder: Recorder::new(recording_path),
+        Ok(TerminalEmulator::new_with_io_and_size(
             io,
-        };
-        Ok(ret)
+            recording_path,
+            width_chars,
+            height_chars,
+        ))
+    }
+
+    /// Pid of the shell, e.g. for sending it signals or inspecting it in /proc
+    pub fn child_pid(&self) -> nix::unistd::Pid {
+        self.io.child_pid()
+    }
+
+    /// Register a callback to run with the exit code of the child process once it exits
+    pub fn set_exit_callback<F: Fn(i32) + Send + 'static>(&mut self, cb: F) {
+        self.io.set_exit_callback(Box::new(cb));
     }
 }
 
 impl TerminalEmulator<ReplayIo> {
+    /// Apply a resize from the recording. Output recorded before the resize is still queued in
+    /// the io handle, so it is processed first to land at the size it was recorded with
+    pub fn inject_resize(
+        &mut self,
+        width_chars: usize,
+        height_chars: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.read_all();
+        self.set_win_size(width_chars, height_chars)
+    }
+
+    /// Apply a parser reset from the recording, after the output that was recorded before it
+    pub fn inject_parser_reset(&mut self) {
+        self.read_all();
+        self.reset_parser();
+    }
+
+    /// Process all queued output, even if it is more than a single read would handle
+    fn read_all(&mut self) {
+        self.read();
+        while self.output_pending {
+            self.read();
+        }
+    }
+}
+
+impl<Io: TermIo> TerminalEmulator<Io> {
     pub fn from_snapshot(
         snapshot: SnapshotItem,
-        io_handle: ReplayIo,
-    ) -> Result<TerminalEmulator<ReplayIo>, LoadSnapshotError> {
+        io_handle: Io,
+    ) -> Result<TerminalEmulator<Io>, LoadSnapshotError> {
         use LoadSnapshotErrorPriv::*;
 
         let mut root = snapshot.into_map().map_err(|_| RootNotMap)?;
+        // Recordings imported from other formats have no initial state, they start from a fresh
+        // terminal
+        if root.is_empty() {
+            let mut emulator = TerminalEmulator::new_with_io(io_handle, "recordings".into());
+            emulator.osc52_enabled = false;
+            emulator.parser_timeout = None;
+            return Ok(emulator);
+        }
+
         let parser = AnsiParser::from_snapshot(root.remove("parser").ok_or(ParserNotPresent)?)
             .map_err(LoadParser)?;
         let terminal_buffer =
@@ -478,20 +1010,155 @@ impl TerminalEmulator<ReplayIo> {
         let cursor_state =
             CursorState::from_snapshot(root.remove("cursor_state").ok_or(CursorStateNotPresent)?)
                 .map_err(LoadCursorState)?;
+        // Recordings made before titles were tracked do not have one
+        let window_title = match root.remove("window_title") {
+            Some(title) => title.into_string().map_err(|_| WindowTitleNotString)?,
+            None => String::new(),
+        };
+        // Only present if the recording started in the middle of an OSC 8 link
+        let current_hyperlink = root
+            .remove("current_hyperlink")
+            .map(|uri| uri.into_string().map_err(|_| CurrentHyperlinkNotString))
+            .transpose()?;
+        // Only present if the recording started on the alternate screen
+        let primary_screen = root
+            .remove("primary_screen")
+            .map(SavedScreen::from_snapshot)
+            .transpose()?;
+        // Optional, recordings from before scroll regions were supported do not have it
+        let scroll_region = root
+            .remove("scroll_region")
+            .map(ScrollRegion::from_snapshot)
+            .transpose()?;
+        // Optional, recordings from before origin mode was supported do not have it
+        let origin_mode = match root.remove("origin_mode") {
+            Some(mode) => mode.into_bool().map_err(|_| OriginModeNotBool)?,
+            None => false,
+        };
+        // Missing from recordings made before bracketed paste was supported
+        let bracketed_paste_mode = match root.remove("bracketed_paste_mode") {
+            Some(mode) => mode.into_bool().map_err(|_| BracketedPasteNotBool)?,
+            None => false,
+        };
+        // Optional, recordings from before autowrap mode was supported do not have it
+        let decawm_mode = match root.remove("decawm_mode") {
+            Some(mode) => mode.into_bool().map_err(|_| DecawmNotBool)?,
+            None => true,
+        };
+        // Optional, recordings from before insert mode was supported do not have it
+        let insert_mode = match root.remove("insert_mode") {
+            Some(mode) => mode.into_bool().map_err(|_| InsertModeNotBool)?,
+            None => false,
+        };
+        // Optional, recordings from before cursor visibility was supported do not have it
+        let cursor_visible = match root.remove("cursor_visible") {
+            Some(visible) => visible.into_bool().map_err(|_| CursorVisibleNotBool)?,
+            None => true,
+        };
+        // Optional, recordings from before cursor styles were supported do not have it
+        let cursor_style = match root.remove("cursor_style") {
+            Some(style) => style
+                .into_num()
+                .ok()
+                .and_then(CursorStyle::from_param)
+                .ok_or(CursorStyleInvalid)?,
+            None => CursorStyle::default(),
+        };
+        // Optional, recordings from before cursor saving was supported do not have it
+        let saved_cursor = root
+            .remove("saved_cursor")
+            .map(CursorState::from_snapshot)
+            .transpose()
+            .map_err(LoadSavedCursor)?;
 
         Ok(TerminalEmulator {
             parser,
             terminal_buffer,
             format_tracker,
             decckm_mode,
+            bracketed_paste_mode,
+            primary_screen,
+            scroll_region,
+            origin_mode,
+            decawm_mode,
+            insert_mode,
+            cursor_visible,
+            cursor_style,
+            saved_cursor,
             cursor_state,
             recorder: Recorder::new("recordings".into()),
+            max_bytes_per_read_call: DEFAULT_MAX_BYTES_PER_FRAME,
+            output_pending: false,
+            max_scrollback_lines: DEFAULT_MAX_SCROLLBACK_LINES,
+            bytes_processed: 0,
+            frames_rendered: 0,
+            // Snapshots come from recordings, replaying them should not overwrite the user's
+            // clipboard
+            osc52_enabled: false,
+            clipboard_request: None,
+            bell_rung: false,
+            done: false,
+            dirty: true,
+            current_hyperlink,
+            window_title,
+            resize_history: Vec::new(),
+            last_read_time: Instant::now(),
+            // Recordings are stepped through at the user's pace, waiting is not a stall
+            parser_timeout: None,
             io: io_handle,
         })
     }
-}
 
-impl<Io: TermIo> TerminalEmulator<Io> {
+    pub(crate) fn new_with_io(io: Io, recording_path: PathBuf) -> TerminalEmulator<Io> {
+        TerminalEmulator::new_with_io_and_size(
+            io,
+            recording_path,
+            DEFAULT_TERMINAL_WIDTH,
+            DEFAULT_TERMINAL_HEIGHT,
+        )
+    }
+
+    fn new_with_io_and_size(
+        io: Io,
+        recording_path: PathBuf,
+        width_chars: usize,
+        height_chars: usize,
+    ) -> TerminalEmulator<Io> {
+        TerminalEmulator {
+            parser: AnsiParser::new(),
+            terminal_buffer: TerminalBuffer::new(width_chars, height_chars),
+            format_tracker: FormatTracker::new(),
+            decckm_mode: false,
+            bracketed_paste_mode: false,
+            primary_screen: None,
+            scroll_region: None,
+            origin_mode: false,
+            decawm_mode: true,
+            insert_mode: false,
+            cursor_visible: true,
+            cursor_style: CursorStyle::default(),
+            saved_cursor: None,
+            cursor_state: CursorState::new(),
+            recorder: Recorder::new(recording_path),
+            max_bytes_per_read_call: DEFAULT_MAX_BYTES_PER_FRAME,
+            output_pending: false,
+            max_scrollback_lines: DEFAULT_MAX_SCROLLBACK_LINES,
+            bytes_processed: 0,
+            frames_rendered: 0,
+            osc52_enabled: true,
+            clipboard_request: None,
+            bell_rung: false,
+            done: false,
+            dirty: true,
+            current_hyperlink: None,
+            window_title: String::new(),
+            resize_history: Vec::new(),
+            last_read_time: Instant::now(),
+            parser_timeout: Some(PARSER_TIMEOUT),
+            io,
+        }
+    }
+
     pub fn get_win_size(&self) -> (usize, usize) {
         self.terminal_buffer.get_win_size()
     }
@@ -507,51 +1174,312 @@ impl<Io: TermIo> TerminalEmulator<Io> {
         self.cursor_state.pos = response.new_cursor_pos;
 
         if response.changed {
+            self.dirty = true;
+            // The region may not fit anymore, applications set it again after a resize
+            self.scroll_region = None;
             self.io.set_win_size(width_chars, height_chars)?;
             self.recorder.set_win_size(width_chars, height_chars);
+            self.continue_recording_chunk();
+
+            if self.resize_history.len() >= MAX_RESIZE_HISTORY {
+                self.resize_history.remove(0);
+            }
+            self.resize_history
+                .push((width_chars, height_chars, SystemTime::now()));
         }
 
         Ok(())
     }
 
+    /// Swap in an empty screen, keeping the primary one aside until [`Self::exit_alt_screen`]
+    fn enter_alt_screen(&mut self) {
+        if self.primary_screen.is_some() {
+            return;
+        }
+
+        let (width, height) = self.terminal_buffer.get_win_size();
+        self.primary_screen = Some(SavedScreen {
+            terminal_buffer: std::mem::replace(
+                &mut self.terminal_buffer,
+                TerminalBuffer::new_with_limit(width, height, self.max_scrollback_lines),
+            ),
+            format_tracker: std::mem::replace(&mut self.format_tracker, FormatTracker::new()),
+            cursor_state: self.cursor_state.clone(),
+        });
+        self.dirty = true;
+    }
+
+    fn exit_alt_screen(&mut self) {
+        let Some(primary_screen) = self.primary_screen.take() else {
+            return;
+        };
+
+        let (width, height) = self.terminal_buffer.get_win_size();
+        self.terminal_buffer = primary_screen.terminal_buffer;
+        self.format_tracker = primary_screen.format_tracker;
+        self.cursor_state = primary_screen.cursor_state;
+
+        // The window may have been resized while the alternate screen was up
+        let response = self
+            .terminal_buffer
+            .set_win_size(width, height, &self.cursor_state.pos);
+        self.cursor_state.pos = response.new_cursor_pos;
+        self.dirty = true;
+    }
+
+    /// RIS, back to the state of a freshly started terminal. The parser is left alone, it is
+    /// already back in its ground state after the sequence and may hold the start of the next one
+    fn full_reset(&mut self) {
+        let (width, height) = self.terminal_buffer.get_win_size();
+        self.terminal_buffer =
+            TerminalBuffer::new_with_limit(width, height, self.max_scrollback_lines);
+        self.format_tracker = FormatTracker::new();
+        self.cursor_state = CursorState::new();
+        self.decckm_mode = false;
+        self.bracketed_paste_mode = false;
+        self.primary_screen = None;
+        self.scroll_region = None;
+        self.origin_mode = false;
+        self.decawm_mode = true;
+        self.insert_mode = false;
+        self.cursor_visible = true;
+        self.cursor_style = CursorStyle::default();
+        self.saved_cursor = None;
+        self.current_hyperlink = None;
+    }
+
+    fn set_scroll_region(&mut self, top: usize, bottom: Option<usize>) {
+        let (_, height) = self.terminal_buffer.get_win_size();
+        let top = top.saturating_sub(1);
+        let bottom = bottom.unwrap_or(height).min(height).saturating_sub(1);
+        if top >= bottom {
+            warn!("Ignoring invalid scroll region {top}..={bottom}");
+            return;
+        }
+
+        self.scroll_region = if top == 0 && bottom == height - 1 {
+            None
+        } else {
+            Some(ScrollRegion { top, bottom })
+        };
+        self.home_cursor();
+    }
+
+    /// Move the cursor to the top left, which is the top of the scroll region in origin mode
+    fn home_cursor(&mut self) {
+        let top = match &self.scroll_region {
+            Some(region) if self.origin_mode => region.top,
+            _ => 0,
+        };
+        self.cursor_state.pos = CursorPos { x: 0, y: top };
+    }
+
+    fn scroll_region_up(&mut self, region: &ScrollRegion) {
+        let response = self
+            .terminal_buffer
+            .scroll_region_up(region.top, region.bottom);
+        self.format_tracker.delete_range(response.deleted_range);
+        self.format_tracker
+            .push_range_adjustment(response.inserted_range);
+    }
+
+    /// The scroll region, or the whole screen if none is set
+    fn active_scroll_region(&self) -> ScrollRegion {
+        let (_, height) = self.terminal_buffer.get_win_size();
+        self.scroll_region.clone().unwrap_or(ScrollRegion {
+            top: 0,
+            bottom: height - 1,
+        })
+    }
+
+    fn scroll_region_down(&mut self, region: &ScrollRegion) {
+        let response = self
+            .terminal_buffer
+            .scroll_region_down(region.top, region.bottom);
+        self.format_tracker.delete_range(response.deleted_range);
+        self.format_tracker
+            .push_range_adjustment(response.inserted_range);
+    }
+
+    fn insert_data(&mut self, mut data: &[u8]) {
+        if !self.decawm_mode {
+            self.insert_data_no_wrap(data);
+            return;
+        }
+
+        while !data.is_empty() {
+            let chunk_len = match self.scroll_region.clone() {
+                Some(region) if (region.top..=region.bottom).contains(&self.cursor_state.pos.y) => {
+                    // Wrap by hand one line at a time. Letting the buffer wrap would push the
+                    // lines below the region down, and wrapping at the bottom of the region has
+                    // to scroll the region instead of the whole screen
+                    let (width, _) = self.terminal_buffer.get_win_size();
+                    if self.cursor_state.pos.x >= width {
+                        if self.cursor_state.pos.y == region.bottom {
+                            self.scroll_region_up(&region);
+                        } else {
+                            self.cursor_state.pos.y += 1;
+                        }
+                        self.cursor_state.pos.x = 0;
+                    }
+                    match buffer::column_to_byte_offset(data, width - self.cursor_state.pos.x) {
+                        // A wide character does not fit at the end of the line, move it to the
+                        // next one. If it does not fit on a whole line let the buffer deal with it
+                        Some(0) if self.cursor_state.pos.x > 0 => {
+                            self.cursor_state.pos.x = width;
+                            continue;
+                        }
+                        Some(0) | None => data.len(),
+                        Some(len) => len,
+                    }
+                }
+                _ => data.len(),
+            };
+
+            let (chunk, rest) = data.split_at(chunk_len);
+            data = rest;
+            self.insert_chunk(chunk);
+        }
+    }
+
+    /// Insert data with autowrap disabled. Whatever does not fit on the line overwrites the last
+    /// column, so only the last character past the right margin is kept. The cursor stays on the
+    /// last column
+    fn insert_data_no_wrap(&mut self, data: &[u8]) {
+        let (width, _) = self.terminal_buffer.get_win_size();
+        self.cursor_state.pos.x = self.cursor_state.pos.x.min(width.saturating_sub(1));
+
+        let fits_len = buffer::column_to_byte_offset(data, width - self.cursor_state.pos.x)
+            .unwrap_or(data.len());
+        let (chunk, rest) = data.split_at(fits_len);
+        if !chunk.is_empty() {
+            self.insert_chunk(chunk);
+        }
+
+        if let Some(last) = buffer::split_chars(rest).last() {
+            self.cursor_state.pos.x = width.saturating_sub(buffer::char_width(last));
+            self.insert_chunk(last);
+        }
+
+        self.cursor_state.pos.x = self.cursor_state.pos.x.min(width.saturating_sub(1));
+    }
+
+    fn insert_spaces(&mut self, num_spaces: usize) {
+        let response = self
+            .terminal_buffer
+            .insert_spaces(&self.cursor_state.pos, num_spaces);
+        self.format_tracker
+            .push_range_adjustment(response.insertion_range);
+        self.format_tracker
+            .push_replace_adjustment(response.replaced_range, response.written_range.len());
+    }
+
+    fn insert_chunk(&mut self, chunk: &[u8]) {
+        // In insert mode the rest of the line moves right to make room
+        if self.insert_mode {
+            self.insert_spaces(buffer::num_columns(chunk));
+        }
+
+        let response = self
+            .terminal_buffer
+            .insert_data(&self.cursor_state.pos, chunk);
+        self.format_tracker
+            .push_range_adjustment(response.insertion_range);
+        self.format_tracker
+            .push_replace_adjustment(response.replaced_range, response.written_range.len());
+        self.format_tracker.push_range(
+            &self.cursor_state,
+            self.current_hyperlink.as_deref(),
+            response.written_range,
+        );
+        self.cursor_state.pos = response.new_cursor_pos;
+    }
+
+    /// The most recent window sizes, oldest first
+    pub fn resize_history(&self) -> &[(usize, usize, SystemTime)] {
+        &self.resize_history
+    }
+
     pub fn write(&mut self, to_write: TerminalInput) -> Result<(), Box<dyn std::error::Error>> {
-        match to_write.to_payload(self.decckm_mode) {
+        match to_write.to_payload(&self.current_modes()) {
             TerminalInputPayload::Single(c) => {
                 let mut written = 0;
                 while written == 0 {
                     written = self.io.write(&[c])?;
                 }
             }
-            TerminalInputPayload::Many(mut to_write) => {
-                while !to_write.is_empty() {
-                    let written = self.io.write(to_write)?;
-                    to_write = &to_write[written..];
-                }
-            }
+            TerminalInputPayload::Many(to_write) => self.write_all(&to_write)?,
         };
         Ok(())
     }
 
+    fn write_all(&mut self, mut to_write: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        while !to_write.is_empty() {
+            let written = self.io.write(to_write)?;
+            to_write = &to_write[written..];
+        }
+        Ok(())
+    }
+
+    /// Allow applications to set the clipboard with OSC 52
+    pub fn set_osc52_enabled(&mut self, enabled: bool) {
+        self.osc52_enabled = enabled;
+    }
+
+    /// Text the child asked to put on the clipboard since the last call
+    pub fn take_clipboard_request(&mut self) -> Option<String> {
+        self.clipboard_request.take()
+    }
+
+    /// Applications hide the cursor with DECTCEM, e.g. while redrawing
+    pub fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    pub fn cursor_style(&self) -> CursorStyle {
+        self.cursor_style
+    }
+
+    pub fn window_title(&self) -> &str {
+        &self.window_title
+    }
+
+    /// Whether the child rang the bell since the last call
+    pub fn take_bell(&mut self) -> bool {
+        std::mem::take(&mut self.bell_rung)
+    }
+
     fn handle_incoming_data(&mut self, incoming: &[u8]) {
         let parsed = self.parser.push(incoming);
+        self.handle_terminal_output(parsed);
+    }
+
+    fn handle_terminal_output(&mut self, parsed: Vec<TerminalOutput>) {
+        if !parsed.is_empty() {
+            self.dirty = true;
+        }
+
         for segment in parsed {
             match segment {
-                TerminalOutput::Data(data) => {
-                    let response = self
-                        .terminal_buffer
-                        .insert_data(&self.cursor_state.pos, &data);
-                    self.format_tracker
-                        .push_range_adjustment(response.insertion_range);
-                    self.format_tracker
-                        .push_range(&self.cursor_state, response.written_range);
-                    self.cursor_state.pos = response.new_cursor_pos;
-                }
+                TerminalOutput::Data(data) => self.insert_data(&data),
                 TerminalOutput::SetCursorPos { x, y } => {
+                    // A position of 0 is treated as 1
                     if let Some(x) = x {
-                        self.cursor_state.pos.x = x - 1;
+                        self.cursor_state.pos.x = x.max(1) - 1;
                     }
                     if let Some(y) = y {
-                        self.cursor_state.pos.y = y - 1;
+                        // In origin mode the cursor can not leave the scroll region
+                        let region = match &self.scroll_region {
+                            Some(region) if self.origin_mode => region.clone(),
+                            _ => {
+                                let (_, height) = self.terminal_buffer.get_win_size();
+                                ScrollRegion {
+                                    top: 0,
+                                    bottom: height - 1,
+                                }
+                            }
+                        };
+                        self.cursor_state.pos.y = (region.top + y.max(1) - 1).min(region.bottom);
                     }
                 }
                 TerminalOutput::SetCursorPosRel { x, y } => {
@@ -580,13 +1508,16 @@ impl<Io: TermIo> TerminalEmulator<Io> {
                     if let Some(buf_pos) =
                         self.terminal_buffer.clear_forwards(&self.cursor_state.pos)
                     {
-                        self.format_tracker
-                            .push_range(&self.cursor_state, buf_pos..usize::MAX);
+                        self.format_tracker.push_range(
+                            &self.cursor_state,
+                            None,
+                            buf_pos..usize::MAX,
+                        );
                     }
                 }
                 TerminalOutput::ClearAll => {
                     self.format_tracker
-                        .push_range(&self.cursor_state, 0..usize::MAX);
+                        .push_range(&self.cursor_state, None, 0..usize::MAX);
                     self.terminal_buffer.clear_all();
                 }
                 TerminalOutput::ClearLineForwards => {
@@ -597,24 +1528,126 @@ impl<Io: TermIo> TerminalEmulator<Io> {
                         self.format_tracker.delete_range(range);
                     }
                 }
+                TerminalOutput::ClearLineBackwards => {
+                    if let Some(response) = self
+                        .terminal_buffer
+                        .clear_line_backwards(&self.cursor_state.pos)
+                    {
+                        self.format_tracker.push_replace_adjustment(
+                            response.replaced_range,
+                            response.written_range.len(),
+                        );
+                        self.format_tracker.push_range(
+                            &self.cursor_state,
+                            None,
+                            response.written_range,
+                        );
+                    }
+                }
+                TerminalOutput::ClearLineAll => {
+                    if let Some(response) = self.terminal_buffer.clear_line(&self.cursor_state.pos)
+                    {
+                        self.format_tracker.push_replace_adjustment(
+                            response.replaced_range,
+                            response.written_range.len(),
+                        );
+                        self.format_tracker.push_range(
+                            &self.cursor_state,
+                            None,
+                            response.written_range,
+                        );
+                    }
+                }
                 TerminalOutput::CarriageReturn => {
                     self.cursor_state.pos.x = 0;
                 }
-                TerminalOutput::Newline => {
-                    self.cursor_state.pos.y += 1;
+                TerminalOutput::Newline | TerminalOutput::Index => {
+                    match self.scroll_region.clone() {
+                        Some(region) if self.cursor_state.pos.y == region.bottom => {
+                            self.scroll_region_up(&region);
+                        }
+                        _ => self.cursor_state.pos.y += 1,
+                    }
                 }
-                TerminalOutput::Backspace => {
-                    if self.cursor_state.pos.x >= 1 {
-                        self.cursor_state.pos.x -= 1;
+                TerminalOutput::ReverseIndex => {
+                    let region = self.active_scroll_region();
+                    if self.cursor_state.pos.y == region.top {
+                        self.scroll_region_down(&region);
+                    } else {
+                        self.cursor_state.pos.y = self.cursor_state.pos.y.saturating_sub(1);
                     }
                 }
+                TerminalOutput::ScrollUp(num_lines) => match self.scroll_region.clone() {
+                    Some(region) => {
+                        for _ in 0..num_lines.min(region.bottom - region.top + 1) {
+                            self.scroll_region_up(&region);
+                        }
+                    }
+                    None => {
+                        let inserted_range = self.terminal_buffer.scroll_up(num_lines);
+                        self.format_tracker.push_range_adjustment(inserted_range);
+                    }
+                },
+                TerminalOutput::ScrollDown(num_lines) => {
+                    let region = self.active_scroll_region();
+                    for _ in 0..num_lines.min(region.bottom - region.top + 1) {
+                        self.scroll_region_down(&region);
+                    }
+                }
+                TerminalOutput::Backspace => {
+                    if self.cursor_state.pos.x >= 1 {
+                        self.cursor_state.pos.x -= 1;
+                    }
+                }
+                TerminalOutput::Bell => {
+                    self.bell_rung = true;
+                }
+                TerminalOutput::Tab => {
+                    self.cursor_state.pos.x =
+                        self.terminal_buffer.next_tab_stop(self.cursor_state.pos.x);
+                }
+                TerminalOutput::SetTabStop => {
+                    self.terminal_buffer.set_tab_stop(self.cursor_state.pos.x);
+                }
+                TerminalOutput::ClearTabStop => {
+                    self.terminal_buffer.clear_tab_stop(self.cursor_state.pos.x);
+                }
+                TerminalOutput::ClearAllTabStops => {
+                    self.terminal_buffer.clear_all_tab_stops();
+                }
                 TerminalOutput::InsertLines(num_lines) => {
-                    let response = self
-                        .terminal_buffer
-                        .insert_lines(&self.cursor_state.pos, num_lines);
-                    self.format_tracker.delete_range(response.deleted_range);
-                    self.format_tracker
-                        .push_range_adjustment(response.inserted_range);
+                    let region = self.active_scroll_region();
+                    if self.cursor_state.pos.y < region.top {
+                        continue;
+                    }
+
+                    let responses = self.terminal_buffer.insert_lines(
+                        &self.cursor_state.pos,
+                        num_lines,
+                        region.bottom,
+                    );
+                    for response in responses {
+                        self.format_tracker.delete_range(response.deleted_range);
+                        self.format_tracker
+                            .push_range_adjustment(response.inserted_range);
+                    }
+                }
+                TerminalOutput::DeleteLines(num_lines) => {
+                    let region = self.active_scroll_region();
+                    if self.cursor_state.pos.y < region.top {
+                        continue;
+                    }
+
+                    let responses = self.terminal_buffer.delete_lines(
+                        &self.cursor_state.pos,
+                        num_lines,
+                        region.bottom,
+                    );
+                    for response in responses {
+                        self.format_tracker.delete_range(response.deleted_range);
+                        self.format_tracker
+                            .push_range_adjustment(response.inserted_range);
+                    }
                 }
                 TerminalOutput::Delete(num_chars) => {
                     let deleted_buf_range = self
@@ -628,11 +1661,40 @@ impl<Io: TermIo> TerminalEmulator<Io> {
                     // Should this be one big match ???????
                     if let Some(color) = TerminalColor::from_sgr(sgr) {
                         self.cursor_state.color = color;
+                    } else if let Some(color) = TerminalColor::from_background_sgr(sgr) {
+                        self.cursor_state.background_color = color;
                     } else if sgr == SelectGraphicRendition::Reset {
                         self.cursor_state.color = TerminalColor::Default;
+                        self.cursor_state.background_color = TerminalColor::Default;
                         self.cursor_state.bold = false;
+                        self.cursor_state.italic = false;
+                        self.cursor_state.underline = false;
+                        self.cursor_state.strikethrough = false;
+                        self.cursor_state.dim = false;
+                        self.cursor_state.reverse = false;
                     } else if sgr == SelectGraphicRendition::Bold {
                         self.cursor_state.bold = true;
+                    } else if sgr == SelectGraphicRendition::Dim {
+                        self.cursor_state.dim = true;
+                    } else if sgr == SelectGraphicRendition::NormalIntensity {
+                        self.cursor_state.bold = false;
+                        self.cursor_state.dim = false;
+                    } else if sgr == SelectGraphicRendition::ReverseVideo {
+                        self.cursor_state.reverse = true;
+                    } else if sgr == SelectGraphicRendition::ReverseVideoOff {
+                        self.cursor_state.reverse = false;
+                    } else if sgr == SelectGraphicRendition::Italic {
+                        self.cursor_state.italic = true;
+                    } else if sgr == SelectGraphicRendition::ItalicOff {
+                        self.cursor_state.italic = false;
+                    } else if sgr == SelectGraphicRendition::Underline {
+                        self.cursor_state.underline = true;
+                    } else if sgr == SelectGraphicRendition::UnderlineOff {
+                        self.cursor_state.underline = false;
+                    } else if sgr == SelectGraphicRendition::Strikethrough {
+                        self.cursor_state.strikethrough = true;
+                    } else if sgr == SelectGraphicRendition::StrikethroughOff {
+                        self.cursor_state.strikethrough = false;
                     } else {
                         warn!("Unhandled sgr: {:?}", sgr);
                     }
@@ -641,21 +1703,119 @@ impl<Io: TermIo> TerminalEmulator<Io> {
                     Mode::Decckm => {
                         self.decckm_mode = true;
                     }
+                    Mode::BracketedPaste => {
+                        self.bracketed_paste_mode = true;
+                    }
+                    Mode::DecAltScreen => self.enter_alt_screen(),
+                    Mode::Decom => {
+                        self.origin_mode = true;
+                        self.home_cursor();
+                    }
+                    Mode::DecAwm => self.decawm_mode = true,
+                    Mode::Irm => self.insert_mode = true,
+                    Mode::DecTcem => self.cursor_visible = true,
                     _ => {
                         warn!("unhandled set mode: {mode:?}");
                     }
                 },
-                TerminalOutput::InsertSpaces(num_spaces) => {
-                    let response = self
+                TerminalOutput::InsertSpaces(num_spaces) => self.insert_spaces(num_spaces),
+                TerminalOutput::EraseCharacters(num_chars) => {
+                    if let Some(response) = self
                         .terminal_buffer
-                        .insert_spaces(&self.cursor_state.pos, num_spaces);
-                    self.format_tracker
-                        .push_range_adjustment(response.insertion_range);
+                        .erase_characters(&self.cursor_state.pos, num_chars)
+                    {
+                        self.format_tracker.push_replace_adjustment(
+                            response.replaced_range,
+                            response.written_range.len(),
+                        );
+                        self.format_tracker.push_range(
+                            &self.cursor_state,
+                            None,
+                            response.written_range,
+                        );
+                    }
+                }
+                TerminalOutput::CopyRectangle {
+                    src_top,
+                    src_left,
+                    src_bottom,
+                    src_right,
+                    dst_top,
+                    dst_left,
+                } => {
+                    let (width, height) = self.terminal_buffer.get_win_size();
+                    let responses = self.terminal_buffer.copy_region(
+                        src_top.saturating_sub(1),
+                        src_left.saturating_sub(1),
+                        src_bottom.unwrap_or(height).saturating_sub(1),
+                        src_right.unwrap_or(width).saturating_sub(1),
+                        dst_top.saturating_sub(1),
+                        dst_left.saturating_sub(1),
+                    );
+                    for response in responses {
+                        self.format_tracker
+                            .push_range_adjustment(response.insertion_range);
+                        self.format_tracker.push_replace_adjustment(
+                            response.replaced_range,
+                            response.written_range.len(),
+                        );
+                    }
+                }
+                TerminalOutput::FullReset => self.full_reset(),
+                TerminalOutput::SaveCursor => {
+                    self.saved_cursor = Some(self.cursor_state.clone());
+                }
+                TerminalOutput::RestoreCursor => {
+                    // Like xterm, restoring without a save goes back to the initial state
+                    self.cursor_state = self.saved_cursor.clone().unwrap_or_else(CursorState::new);
+                }
+                TerminalOutput::SetCursorStyle(style) => {
+                    self.cursor_style = style;
+                }
+                TerminalOutput::SetScrollRegion { top, bottom } => {
+                    self.set_scroll_region(top, bottom);
+                }
+                TerminalOutput::SetClipboard(data) => {
+                    if !self.osc52_enabled {
+                        info!("Ignoring clipboard request, OSC 52 is disabled");
+                        continue;
+                    }
+
+                    match String::from_utf8(data) {
+                        Ok(text) => self.clipboard_request = Some(text),
+                        Err(e) => warn!("Clipboard request is not valid utf8: {e}"),
+                    }
+                }
+                TerminalOutput::SetHyperlink(uri) => {
+                    self.current_hyperlink = uri;
+                }
+                TerminalOutput::SetTitle(title) => {
+                    self.window_title = title;
+                }
+                TerminalOutput::QueryClipboard => {
+                    // Reading the clipboard is not supported, answer with an empty selection
+                    if let Err(e) = self.write_all(b"\x1b]52;c;\x07") {
+                        error!(
+                            "Failed to respond to clipboard query: {}",
+                            backtraced_err(&*e)
+                        );
+                    }
                 }
                 TerminalOutput::ResetMode(mode) => match mode {
                     Mode::Decckm => {
                         self.decckm_mode = false;
                     }
+                    Mode::BracketedPaste => {
+                        self.bracketed_paste_mode = false;
+                    }
+                    Mode::DecAltScreen => self.exit_alt_screen(),
+                    Mode::Decom => {
+                        self.origin_mode = false;
+                        self.home_cursor();
+                    }
+                    Mode::DecAwm => self.decawm_mode = false,
+                    Mode::Irm => self.insert_mode = false,
+                    Mode::DecTcem => self.cursor_visible = false,
                     _ => {
                         warn!("unhandled set mode: {mode:?}");
                     }
@@ -663,13 +1823,57 @@ impl<Io: TermIo> TerminalEmulator<Io> {
                 TerminalOutput::Invalid => {}
             }
         }
+
+        let evicted_bytes = self.terminal_buffer.enforce_scrollback_limit();
+        self.format_tracker.on_evict_scrollback(evicted_bytes);
+    }
+
+    /// Limit how many lines of scrollback are kept, see [`DEFAULT_MAX_SCROLLBACK_LINES`]
+    pub fn set_max_scrollback_lines(&mut self, max_lines: usize) {
+        self.max_scrollback_lines = max_lines;
+        self.terminal_buffer.set_max_scrollback_lines(max_lines);
+        if let Some(primary_screen) = &mut self.primary_screen {
+            primary_screen
+                .terminal_buffer
+                .set_max_scrollback_lines(max_lines);
+        }
+
+        let evicted_bytes = self.terminal_buffer.enforce_scrollback_limit();
+        self.format_tracker.on_evict_scrollback(evicted_bytes);
+        self.dirty = true;
+    }
+
+    /// Limit how many bytes a single call to [`TerminalEmulator::read`] will process. A limit of
+    /// 0 would never make progress, so at least one byte is always processed
+    pub fn set_max_bytes_per_frame(&mut self, max_bytes: usize) {
+        self.max_bytes_per_read_call = max_bytes.max(1);
     }
 
+    /// Read and process pending output from the child. Expected to be called once per frame
     pub fn read(&mut self) {
-        let mut buf = vec![0u8; 4096];
+        self.frames_rendered += 1;
+
+        let mut buf = vec![0u8; READ_BUF_SIZE];
+        let mut total_read = 0;
+        self.output_pending = false;
         loop {
-            let read_size = match self.io.read(&mut buf) {
+            let remaining = self.max_bytes_per_read_call - total_read;
+            if remaining == 0 {
+                self.output_pending = true;
+                warn!(
+                    "Hit read limit of {} bytes, deferring remaining output to next frame",
+                    self.max_bytes_per_read_call
+                );
+                break;
+            }
+
+            let read_buf_len = buf.len().min(remaining);
+            let read_size = match self.io.read(&mut buf[..read_buf_len]) {
                 Ok(ReadResponse::Empty) => break,
+                Ok(ReadResponse::Eof) => {
+                    self.done = true;
+                    break;
+                }
                 Ok(ReadResponse::Success(v)) => v,
                 Err(e) => {
                     error!("Failed to read from child process: {e}");
@@ -677,53 +1881,319 @@ impl<Io: TermIo> TerminalEmulator<Io> {
                 }
             };
 
+            total_read += read_size;
+            self.bytes_processed += read_size as u64;
+            self.last_read_time = Instant::now();
             let incoming = &buf[0..read_size];
             debug!("Incoming data: {:?}", std::str::from_utf8(incoming));
-            self.recorder.write(incoming);
-            self.handle_incoming_data(incoming);
+            self.inject_and_record(incoming);
+        }
+
+        self.reset_stalled_parser();
+    }
+
+    /// If the child stopped in the middle of an escape sequence, give up on it so that output
+    /// after it is not swallowed
+    fn reset_stalled_parser(&mut self) {
+        let Some(timeout) = self.parser_timeout else {
+            return;
+        };
+
+        if self.last_read_time.elapsed() < timeout {
+            return;
+        }
+
+        if let Some(output) = self.parser.reset() {
+            warn!("Timed out waiting for end of escape sequence");
+            self.recorder.reset_parser();
+            self.handle_terminal_output(vec![output]);
+            self.continue_recording_chunk();
+        }
+    }
+
+    /// Give up on any partially parsed escape sequence, its bytes are handled as plain data
+    pub fn reset_parser(&mut self) {
+        if let Some(output) = self.parser.reset() {
+            self.handle_terminal_output(vec![output]);
         }
     }
 
+    /// Process data as if it came from the child process, and add it to the active recording
+    pub fn inject_and_record(&mut self, incoming: &[u8]) {
+        self.recorder.write(incoming);
+        self.handle_incoming_data(incoming);
+        // The data is in the finished chunk, so the next chunk starts from the state after it
+        self.continue_recording_chunk();
+    }
+
+    /// Process data as if it came from the child process without recording it. Useful for
+    /// driving the emulator with specific escape sequences
+    pub fn write_sequence(&mut self, seq: &[u8]) {
+        self.handle_incoming_data(seq);
+    }
+
     pub fn data(&self) -> TerminalData<&[u8]> {
         self.terminal_buffer.data()
     }
 
+    /// Visible text, one string per line, without any formatting
+    pub fn dump_screen(&self) -> Vec<String> {
+        String::from_utf8_lossy(self.data().visible)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Like [`TerminalEmulator::dump_screen`], but for the lines that have scrolled off screen
+    pub fn dump_scrollback(&self) -> Vec<String> {
+        String::from_utf8_lossy(self.data().scrollback)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
     pub fn format_data(&self) -> TerminalData<Vec<FormatTag>> {
         let offset = self.terminal_buffer.data().scrollback.len();
         split_format_data_for_scrollback(self.format_tracker.tags(), offset)
     }
 
+    /// Urls in the scrollback and visible area as (start, end, url). Positions are offsets into
+    /// scrollback followed by visible data
+    pub fn detect_urls(&mut self) -> Vec<(usize, usize, String)> {
+        self.terminal_buffer.detect_urls()
+    }
+
+    /// Drop the oldest scrollback, keeping at most max_bytes
+    pub fn trim_scrollback(&mut self, max_bytes: usize) {
+        let evicted_bytes = self.terminal_buffer.truncate_scrollback(max_bytes);
+        self.format_tracker.on_evict_scrollback(evicted_bytes);
+        if evicted_bytes > 0 {
+            self.dirty = true;
+        }
+    }
+
+    /// Whether [`TerminalEmulator::data`] and friends may return something different than they
+    /// did before the last [`TerminalEmulator::clear_dirty`]
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub fn clear_dirty(&mut self) {
+        self.dirty = false;
+    }
+
+    /// Whether the child has exited, no more output will be read after this
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Render the scrollback and visible area as a standalone html document
+    pub fn export_to_html(&self) -> String {
+        export::export_to_html(&self.data(), &self.format_data())
+    }
+
+    /// Cursor position on the visible screen, which is where the gui draws it. Origin mode is
+    /// applied when the cursor moves, so this is never relative to the scroll region
     pub fn cursor_pos(&self) -> CursorPos {
         self.cursor_state.pos.clone()
     }
 
+    /// Whether the last [`TerminalEmulator::read`] left output unread because of the read limit.
+    /// Callers should read again soon instead of waiting for more output to arrive
+    pub fn has_pending_output(&self) -> bool {
+        self.output_pending
+    }
+
+    /// Number of columns the cursor should cover, two when on a wide character
+    pub fn cursor_width(&self) -> usize {
+        self.terminal_buffer.char_width_at(&self.cursor_state.pos)
+    }
+
+    /// Text of the visible area from start to end inclusive, as selected with the mouse
+    pub fn selected_text(&self, start: &CursorPos, end: &CursorPos) -> String {
+        String::from_utf8_lossy(&self.terminal_buffer.selected_text(start, end)).into_owned()
+    }
+
+    pub fn current_modes(&self) -> TerminalModes {
+        TerminalModes {
+            decckm: self.decckm_mode,
+            alt_screen: self.primary_screen.is_some(),
+            origin_mode: self.origin_mode,
+            bracketed_paste: self.bracketed_paste_mode,
+            cursor_visible: self.cursor_visible,
+            decawm: self.decawm_mode,
+            insert_mode: self.insert_mode,
+        }
+    }
+
+    /// Write a human readable dump of the emulator's internal state, for attaching to bug
+    /// reports
+    pub fn print_debug_state<W: std::io::Write>(&self, out: &mut W) -> std::io::Result<()> {
+        const NUM_TAGS: usize = 10;
+
+        let (width, height) = self.get_win_size();
+        let tags = self.format_tracker.tags();
+
+        writeln!(
+            out,
+            "Cursor: ({}, {})",
+            self.cursor_state.pos.x, self.cursor_state.pos.y
+        )?;
+        writeln!(
+            out,
+            "Cursor color: {:?}, background: {:?}",
+            self.cursor_state.color, self.cursor_state.background_color,
+        )?;
+        writeln!(
+            out,
+            "Cursor attributes: bold: {}, italic: {}, underline: {}, strikethrough: {}, dim: {}, reverse: {}",
+            self.cursor_state.bold,
+            self.cursor_state.italic,
+            self.cursor_state.underline,
+            self.cursor_state.strikethrough,
+            self.cursor_state.dim,
+            self.cursor_state.reverse
+        )?;
+        writeln!(out, "Modes: {:?}", self.current_modes())?;
+        writeln!(out, "Alternate screen: {}", self.primary_screen.is_some())?;
+        writeln!(
+            out,
+            "Cursor visible: {}, style: {:?}",
+            self.cursor_visible, self.cursor_style
+        )?;
+        writeln!(
+            out,
+            "Scroll region: {:?}, origin mode: {}",
+            self.scroll_region, self.origin_mode
+        )?;
+        writeln!(out, "Size: {width}x{height}")?;
+        writeln!(
+            out,
+            "Scrollback bytes: {}",
+            self.terminal_buffer.data().scrollback.len()
+        )?;
+        writeln!(out, "Parser state: {}", self.parser.state_name())?;
+        writeln!(out, "Format tags: {}", tags.len())?;
+        for tag in tags.iter().take(NUM_TAGS) {
+            writeln!(out, "  {tag:?}")?;
+        }
+
+        if tags.len() > 2 * NUM_TAGS {
+            writeln!(out, "  ...")?;
+        }
+
+        let last_start = tags.len().saturating_sub(NUM_TAGS).max(NUM_TAGS);
+        for tag in tags.iter().skip(last_start) {
+            writeln!(out, "  {tag:?}")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> EmulatorStats {
+        EmulatorStats {
+            bytes_processed: self.bytes_processed,
+            frames_rendered: self.frames_rendered,
+            scrollback_bytes: self.terminal_buffer.data().scrollback.len(),
+            format_tag_count: self.format_tracker.tag_count(),
+            cursor_pos: self.cursor_pos(),
+        }
+    }
+
+    /// Reset the throughput counters in [`EmulatorStats`], useful for measuring per interval
+    pub fn reset_stats(&mut self) {
+        self.bytes_processed = 0;
+        self.frames_rendered = 0;
+    }
+
     pub fn start_recording(&mut self) -> Result<RecordingHandle, StartRecordingError> {
         use StartRecordingErrorPriv::*;
 
         let recording_handle = self.recorder.start_recording().map_err(Start)?;
         match recording_handle {
             StartRecordingResponse::New(initializer) => {
-                initializer.snapshot_item("parser".to_string(), self.parser.snapshot());
-                initializer.snapshot_item(
-                    "terminal_buffer".to_string(),
-                    self.terminal_buffer.snapshot().map_err(SnapshotBuffer)?,
-                );
-                initializer.snapshot_item(
-                    "format_tracker".to_string(),
-                    self.format_tracker
-                        .snapshot()
-                        .map_err(SnapshotFormatTracker)?,
-                );
-                initializer.snapshot_item("decckm_mode".to_string(), self.decckm_mode.into());
-                initializer.snapshot_item(
-                    "cursor_state".to_string(),
-                    self.cursor_state.snapshot().map_err(SnapshotCursor)?,
-                );
+                self.snapshot_into(&initializer)?;
                 Ok(initializer.into_handle())
             }
             StartRecordingResponse::Existing(handle) => Ok(handle),
         }
     }
+
+    /// Write the active recording to disk without stopping it
+    pub fn flush_recording(&self) -> Result<(), std::io::Error> {
+        self.recorder.flush()
+    }
+
+    /// Split recordings into chunks of at most `max_items` items, or disable chunking with None
+    pub fn set_recording_chunk_size(&mut self, max_items: Option<usize>) {
+        self.recorder.set_max_items_per_file(max_items);
+    }
+
+    fn continue_recording_chunk(&mut self) {
+        let Some(initializer) = self.recorder.next_chunk() else {
+            return;
+        };
+
+        if let Err(e) = self.snapshot_into(&initializer) {
+            error!(
+                "Failed to snapshot state for recording chunk: {}",
+                backtraced_err(&e)
+            );
+        }
+    }
+
+    fn snapshot_into(&self, initializer: &RecordingInitializer) -> Result<(), StartRecordingError> {
+        use StartRecordingErrorPriv::*;
+
+        initializer.snapshot_item("parser".to_string(), self.parser.snapshot());
+        initializer.snapshot_item(
+            "terminal_buffer".to_string(),
+            self.terminal_buffer.snapshot().map_err(SnapshotBuffer)?,
+        );
+        initializer.snapshot_item(
+            "format_tracker".to_string(),
+            self.format_tracker
+                .snapshot()
+                .map_err(SnapshotFormatTracker)?,
+        );
+        initializer.snapshot_item("decckm_mode".to_string(), self.decckm_mode.into());
+        initializer.snapshot_item(
+            "cursor_state".to_string(),
+            self.cursor_state.snapshot().map_err(SnapshotCursor)?,
+        );
+        initializer.snapshot_item(
+            "window_title".to_string(),
+            self.window_title.as_str().into(),
+        );
+        if let Some(uri) = &self.current_hyperlink {
+            initializer.snapshot_item("current_hyperlink".to_string(), uri.as_str().into());
+        }
+        if let Some(primary_screen) = &self.primary_screen {
+            initializer.snapshot_item("primary_screen".to_string(), primary_screen.snapshot()?);
+        }
+        if let Some(scroll_region) = &self.scroll_region {
+            initializer.snapshot_item("scroll_region".to_string(), scroll_region.snapshot());
+        }
+        initializer.snapshot_item("origin_mode".to_string(), self.origin_mode.into());
+        initializer.snapshot_item(
+            "bracketed_paste_mode".to_string(),
+            self.bracketed_paste_mode.into(),
+        );
+        initializer.snapshot_item("decawm_mode".to_string(), self.decawm_mode.into());
+        initializer.snapshot_item("insert_mode".to_string(), self.insert_mode.into());
+        initializer.snapshot_item("cursor_visible".to_string(), self.cursor_visible.into());
+        if let Some(saved_cursor) = &self.saved_cursor {
+            initializer.snapshot_item(
+                "saved_cursor".to_string(),
+                saved_cursor.snapshot().map_err(SnapshotCursor)?,
+            );
+        }
+        initializer.snapshot_item(
+            "cursor_style".to_string(),
+            (self.cursor_style.to_param() as i64).into(),
+        );
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -737,25 +2207,53 @@ mod test {
                 start: 0,
                 end: 5,
                 color: TerminalColor::Blue,
+                background_color: TerminalColor::Default,
                 bold: true,
+                italic: false,
+                underline: false,
+                strikethrough: false,
+                dim: false,
+                reverse: false,
+                hyperlink: None,
             },
             FormatTag {
                 start: 5,
                 end: 7,
                 color: TerminalColor::Red,
+                background_color: TerminalColor::Default,
                 bold: false,
+                italic: false,
+                underline: false,
+                strikethrough: false,
+                dim: false,
+                reverse: false,
+                hyperlink: None,
             },
             FormatTag {
                 start: 7,
                 end: 10,
                 color: TerminalColor::Blue,
+                background_color: TerminalColor::Default,
                 bold: true,
+                italic: false,
+                underline: false,
+                strikethrough: false,
+                dim: false,
+                reverse: false,
+                hyperlink: None,
             },
             FormatTag {
                 start: 10,
                 end: usize::MAX,
                 color: TerminalColor::Red,
+                background_color: TerminalColor::Default,
                 bold: true,
+                italic: false,
+                underline: false,
+                strikethrough: false,
+                dim: false,
+                reverse: false,
+                hyperlink: None,
             },
         ];
 
@@ -773,7 +2271,14 @@ mod test {
                 start: 0,
                 end: usize::MAX,
                 color: TerminalColor::Red,
+                background_color: TerminalColor::Default,
                 bold: true,
+                italic: false,
+                underline: false,
+                strikethrough: false,
+                dim: false,
+                reverse: false,
+                hyperlink: None,
             },]
         );
 
@@ -786,19 +2291,40 @@ mod test {
                     start: 0,
                     end: 5,
                     color: TerminalColor::Blue,
+                    background_color: TerminalColor::Default,
                     bold: true,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 5,
                     end: 7,
                     color: TerminalColor::Red,
+                    background_color: TerminalColor::Default,
                     bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 7,
                     end: 9,
                     color: TerminalColor::Blue,
+                    background_color: TerminalColor::Default,
                     bold: true,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
@@ -809,13 +2335,27 @@ mod test {
                     start: 0,
                     end: 1,
                     color: TerminalColor::Blue,
+                    background_color: TerminalColor::Default,
                     bold: true,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
                 FormatTag {
                     start: 1,
                     end: usize::MAX,
                     color: TerminalColor::Red,
+                    background_color: TerminalColor::Default,
                     bold: true,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
                 },
             ]
         );
@@ -826,11 +2366,1515 @@ mod test {
         let state = CursorState {
             pos: CursorPos { x: 10, y: 50 },
             bold: false,
+            italic: true,
+            underline: false,
+            strikethrough: true,
+            dim: false,
+            reverse: false,
             color: TerminalColor::Magenta,
+            background_color: TerminalColor::Palette(196),
         };
 
         let snapshot = state.snapshot().expect("failed to create snapshot");
         let loaded = CursorState::from_snapshot(snapshot).expect("failed to load snapshot");
         assert_eq!(loaded, state);
     }
+
+    #[test]
+    fn test_palette_rgb() {
+        assert_eq!(TerminalColor::palette_rgb(1), (0xff, 0x00, 0x00));
+        assert_eq!(TerminalColor::palette_rgb(16), (0x00, 0x00, 0x00));
+        assert_eq!(TerminalColor::palette_rgb(196), (0xff, 0x00, 0x00));
+        assert_eq!(TerminalColor::palette_rgb(110), (0x87, 0xaf, 0xd7));
+        assert_eq!(TerminalColor::palette_rgb(231), (0xff, 0xff, 0xff));
+        assert_eq!(TerminalColor::palette_rgb(232), (0x08, 0x08, 0x08));
+        assert_eq!(TerminalColor::palette_rgb(255), (0xee, 0xee, 0xee));
+
+        assert_eq!("palette(42)".parse(), Ok(TerminalColor::Palette(42)));
+        assert_eq!(TerminalColor::Palette(42).to_string(), "palette(42)");
+        assert_eq!("palette(256)".parse::<TerminalColor>(), Err(()));
+    }
+
+    #[test]
+    fn test_rgb_color() {
+        let color = TerminalColor::Rgb(255, 128, 0);
+        assert_eq!(color.to_string(), "rgb(255,128,0)");
+        assert_eq!("rgb(255,128,0)".parse(), Ok(color));
+        assert_eq!("rgb(255,128)".parse::<TerminalColor>(), Err(()));
+        assert_eq!("rgb(255,128,0,0)".parse::<TerminalColor>(), Err(()));
+        assert_eq!("rgb(256,128,0)".parse::<TerminalColor>(), Err(()));
+
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"\x1b[38;2;255;128;0mhello\x1b[m");
+        let tag = &emulator.format_data().visible[0];
+        assert_eq!(tag.start..tag.end, 0..5);
+        assert_eq!(tag.color, color);
+    }
+
+    fn create_test_emulator() -> TerminalEmulator<MockIo> {
+        TerminalEmulator::new_with_io(MockIo::new(), "recordings".into())
+    }
+
+    /// Record the emulator's state and load it back into a new emulator, the way a replay would
+    fn roundtrip_snapshot(emulator: &TerminalEmulator<MockIo>) -> TerminalEmulator<MockIo> {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut recorder = Recorder::new(temp_dir.path().into());
+        let StartRecordingResponse::New(initializer) = recorder
+            .start_recording()
+            .expect("failed to start recording")
+        else {
+            panic!("recording already started");
+        };
+        emulator
+            .snapshot_into(&initializer)
+            .expect("failed to snapshot");
+        drop(initializer.into_handle());
+
+        let recording =
+            Recording::load(&temp_dir.path().join("0.json")).expect("failed to load recording");
+        TerminalEmulator::from_snapshot(recording.initial_state(), MockIo::new())
+            .expect("failed to load snapshot")
+    }
+
+    #[test]
+    fn test_common_sequences() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(80, 24)
+            .expect("failed to set win size");
+
+        emulator.write_sequence(b"hello\r\n");
+        assert_eq!(emulator.dump_screen()[0], "hello");
+
+        emulator.write_sequence(b"\x1b[1;31mRed Bold\x1b[m plain");
+        assert_eq!(emulator.dump_screen()[1], "Red Bold plain");
+        let red_bold = emulator
+            .format_data()
+            .visible
+            .into_iter()
+            .find(|tag| tag.bold && tag.color == TerminalColor::Red)
+            .expect("no red bold tag");
+        // Offsets are relative to the visible area, "hello\n" comes first
+        assert_eq!(red_bold.start..red_bold.end, 6..14);
+        assert_eq!(red_bold.background_color, TerminalColor::Default);
+
+        emulator.write_sequence(b" \x1b[41mred bg\x1b[m");
+        let red_bg = emulator
+            .format_data()
+            .visible
+            .into_iter()
+            .find(|tag| tag.background_color == TerminalColor::Red)
+            .expect("no red background tag");
+        assert_eq!(red_bg.start..red_bg.end, 21..27);
+        assert_eq!(red_bg.color, TerminalColor::Default);
+
+        emulator.write_sequence(b" \x1b[3mitalic\x1b[m");
+        let italic = emulator
+            .format_data()
+            .visible
+            .into_iter()
+            .find(|tag| tag.italic)
+            .expect("no italic tag");
+        assert_eq!(italic.start..italic.end, 28..34);
+        assert!(!italic.bold);
+
+        // Overwrite the start of the line and clear the rest of it
+        emulator.write_sequence(b"\rBlue\x1b[K");
+        assert_eq!(emulator.dump_screen(), ["hello", "Blue"]);
+
+        emulator.write_sequence(b"\x1b[2J\x1b[Hcleared");
+        assert_eq!(emulator.dump_screen(), ["cleared"]);
+    }
+
+    #[test]
+    fn test_bright_colors() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"\x1b[101mX\x1b[49mY\x1b[97mZ\x1b[39mW");
+        let tags = emulator.format_data().visible;
+        let tag_at = |pos: usize| {
+            tags.iter()
+                .find(|tag| tag.start <= pos && pos < tag.end)
+                .expect("no tag for position")
+        };
+
+        assert_eq!(tag_at(0).background_color, TerminalColor::Palette(9));
+        assert_eq!(tag_at(1).background_color, TerminalColor::Default);
+        assert_eq!(tag_at(2).color, TerminalColor::Palette(15));
+        assert_eq!(tag_at(2).background_color, TerminalColor::Default);
+        assert_eq!(tag_at(3).color, TerminalColor::Default);
+    }
+
+    #[test]
+    fn test_sgr_attributes_off() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"\x1b[31;3mitalic\x1b[23mplain");
+        assert!(!emulator.cursor_state.italic);
+        // Only the attribute is cleared, the color stays
+        assert_eq!(emulator.cursor_state.color, TerminalColor::Red);
+
+        let tags = emulator.format_data().visible;
+        assert!(tags[0].italic);
+        assert_eq!(tags[0].start..tags[0].end, 0..6);
+        assert!(!tags[1].italic);
+
+        emulator.write_sequence(b"\x1b[1;2m\x1b[22m");
+        assert!(!emulator.cursor_state.bold);
+        assert!(!emulator.cursor_state.dim);
+        assert_eq!(emulator.cursor_state.color, TerminalColor::Red);
+
+        emulator.write_sequence(b"\x1b[7m\x1b[27m");
+        assert!(!emulator.cursor_state.reverse);
+        assert_eq!(emulator.cursor_state.color, TerminalColor::Red);
+    }
+
+    #[test]
+    fn test_sgr_reset_clears_dim() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"\x1b[2m");
+        assert!(emulator.cursor_state.dim);
+        emulator.write_sequence(b"dim\x1b[0m");
+        assert!(!emulator.cursor_state.dim);
+
+        let tags = emulator.format_data().visible;
+        assert!(tags[0].dim);
+        assert_eq!(tags[0].start..tags[0].end, 0..3);
+    }
+
+    #[test]
+    fn test_alt_screen() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 2)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"one\r\ntwo\r\n\x1b[31mthree");
+        let scrollback = emulator.dump_scrollback();
+        let screen = emulator.dump_screen();
+        let format_data = emulator.format_data();
+        let cursor_state = emulator.cursor_state.clone();
+
+        emulator.write_sequence(b"\x1b[?1049h");
+        assert!(emulator.current_modes().alt_screen);
+        assert!(emulator.dump_scrollback().is_empty());
+        assert!(emulator.dump_screen().is_empty());
+        emulator.write_sequence(b"\x1b[Hvim\r\n\x1b[32mmore\r\nlines\r\nthan\r\nfit");
+        assert_eq!(emulator.dump_screen(), ["than", "fit"]);
+
+        // A recording started on the alternate screen should still be able to go back
+        let mut replay_emulator = roundtrip_snapshot(&emulator);
+
+        for emulator in [&mut emulator, &mut replay_emulator] {
+            emulator.write_sequence(b"\x1b[?1049l");
+            assert!(!emulator.current_modes().alt_screen);
+            assert_eq!(emulator.dump_scrollback(), scrollback);
+            assert_eq!(emulator.dump_screen(), screen);
+            assert_eq!(emulator.format_data().visible, format_data.visible);
+            assert_eq!(emulator.cursor_state, cursor_state);
+        }
+    }
+
+    #[test]
+    fn test_scroll_region() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 5)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"header\r\none\r\ntwo\r\nthree\r\nfooter");
+
+        // Newlines at the bottom of the region only scroll the region
+        emulator.write_sequence(b"\x1b[2;4r\x1b[4;1H\r\nfour\r\nfive");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 4, y: 3 });
+        assert_eq!(
+            emulator.dump_screen(),
+            ["header", "three", "four", "five", "footer"]
+        );
+
+        // So does wrapping past the end of the region
+        emulator.write_sequence(b"\x1b[2;1H0123456789abcdefghijklmnopqrst");
+        assert_eq!(
+            emulator.dump_screen(),
+            ["header", "0123456789", "abcdefghij", "klmnopqrst", "footer"]
+        );
+        emulator.write_sequence(b"uvwxyz");
+        assert_eq!(
+            emulator.dump_screen(),
+            ["header", "abcdefghij", "klmnopqrst", "uvwxyz", "footer"]
+        );
+        assert!(emulator.dump_scrollback().is_empty());
+
+        // Origin mode positions relative to the region, and survives a recording
+        emulator.write_sequence(b"\x1b[?6h\x1b[2;1H");
+        assert!(emulator.current_modes().origin_mode);
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 0, y: 2 });
+
+        let mut replay_emulator = roundtrip_snapshot(&emulator);
+        assert_eq!(replay_emulator.scroll_region, emulator.scroll_region);
+        replay_emulator.write_sequence(b"\x1b[3;1H");
+        assert_eq!(replay_emulator.cursor_state.pos, CursorPos { x: 0, y: 3 });
+
+        // Resetting the region scrolls the whole screen again
+        emulator.write_sequence(b"\x1b[?6l\x1b[r\x1b[5;1H\r\n");
+        emulator.write_sequence(b"end");
+        assert_eq!(emulator.dump_scrollback(), ["header"]);
+    }
+
+    #[test]
+    fn test_cursor_pos_clamped() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 5)
+            .expect("failed to set win size");
+
+        // Origin mode keeps the cursor inside the region
+        emulator.write_sequence(b"\x1b[2;3r\x1b[?6h\x1b[9;1H");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 0, y: 2 });
+
+        // Otherwise it is kept on the screen
+        emulator.write_sequence(b"\x1b[?6l\x1b[9;1H");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 0, y: 4 });
+    }
+
+    #[test]
+    fn test_insert_lines_region() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 5)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"1\r\n2\r\n3\r\n4\r\n5");
+
+        // Row 3 is pushed out of the region, rows below it do not move
+        emulator.write_sequence(b"\x1b[1;3r\x1b[1;1H\x1b[L");
+        assert_eq!(emulator.dump_screen(), ["", "1", "2", "4", "5"]);
+
+        // Outside of the region nothing happens
+        emulator.write_sequence(b"\x1b[2;3r\x1b[1;1H\x1b[L");
+        assert_eq!(emulator.dump_screen(), ["", "1", "2", "4", "5"]);
+    }
+
+    #[test]
+    fn test_index() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 4)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"one\r\ntwo\r\nthree");
+
+        // Index moves down without returning the cursor
+        emulator.write_sequence(b"\x1b[1;1H\x1bDx");
+        assert_eq!(emulator.dump_screen(), ["one", "xwo", "three"]);
+
+        // Reverse index at the top of the screen pushes everything down
+        emulator.write_sequence(b"\x1b[2;1H\x1bM\x1bMtop");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 3, y: 0 });
+        assert_eq!(emulator.dump_screen(), ["top", "one", "xwo", "three"]);
+        emulator.write_sequence(b"\x1b[1;1H\x1bM");
+        assert_eq!(emulator.dump_screen(), ["", "top", "one", "xwo"]);
+        assert!(emulator.dump_scrollback().is_empty());
+
+        // Both only scroll within the scroll region
+        emulator.write_sequence(b"\x1b[2;3r\x1b[2;1H\x1bM");
+        assert_eq!(emulator.dump_screen(), ["", "", "top", "xwo"]);
+        emulator.write_sequence(b"\x1b[3;1H\x1bD\x1bD");
+        assert_eq!(emulator.dump_screen(), ["", "", "", "xwo"]);
+        assert!(emulator.dump_scrollback().is_empty());
+    }
+
+    #[test]
+    fn test_full_reset() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 2)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"\x1b[?1h\x1b[?2004h\x1b[?25l\x1b[?7l\x1b[4h\x1b[2;2r\x1b[?6h");
+        emulator.write_sequence(b"\x1b]8;;https://example.com\x07");
+        emulator.write_sequence(b"\x1b[1;31mone\r\ntwo\r\nthree\x1b[?1049h");
+
+        emulator.write_sequence(b"\x1bc");
+        assert!(emulator.dump_scrollback().is_empty());
+        assert!(emulator.dump_screen().is_empty());
+        assert_eq!(emulator.format_tracker.tags(), FormatTracker::new().tags());
+        assert_eq!(emulator.cursor_state, CursorState::new());
+        assert_eq!(
+            emulator.current_modes(),
+            TerminalModes {
+                cursor_visible: true,
+                decawm: true,
+                ..Default::default()
+            }
+        );
+        assert!(emulator.primary_screen.is_none());
+        assert!(emulator.scroll_region.is_none());
+        assert!(emulator.current_hyperlink.is_none());
+        assert_eq!(emulator.get_win_size(), (10, 2));
+    }
+
+    #[test]
+    fn test_scroll_up_down() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 4)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"\x1b[32mone\r\ntwo\r\nthree\r\nfour\x1b[m");
+
+        emulator.write_sequence(b"\x1b[2S");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 4, y: 3 });
+        assert_eq!(emulator.dump_scrollback(), ["one", "two"]);
+        assert_eq!(emulator.dump_screen(), ["three", "four", "", ""]);
+
+        emulator.write_sequence(b"\x1b[T");
+        assert_eq!(emulator.dump_screen(), ["", "three", "four", ""]);
+
+        // Text that moved keeps its format
+        let tags = emulator.format_data().visible;
+        let four_pos = emulator
+            .data()
+            .visible
+            .windows(4)
+            .position(|w| w == b"four");
+        assert!(tags.iter().any(|tag| tag.color == TerminalColor::Green
+            && Some(tag.start) <= four_pos
+            && four_pos.map(|p| p + 4) <= Some(tag.end)));
+
+        // Within a region nothing goes to scrollback
+        emulator.write_sequence(b"\x1b[2;3r\x1b[5S");
+        assert_eq!(emulator.dump_screen(), ["", "", "", ""]);
+        assert_eq!(emulator.dump_scrollback(), ["one", "two"]);
+    }
+
+    #[test]
+    fn test_delete_lines() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 4)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"\x1b[31mred\r\n\x1b[32mgreen\r\n\x1b[34mblue\x1b[m");
+
+        emulator.write_sequence(b"\x1b[2;1H\x1b[M");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 0, y: 1 });
+        assert_eq!(emulator.dump_screen(), ["red", "blue"]);
+
+        let tags = emulator.format_data().visible;
+        let color_at = |pos: usize| {
+            tags.iter()
+                .find(|tag| tag.start <= pos && pos < tag.end)
+                .map(|tag| tag.color)
+        };
+        assert_eq!(color_at(0), Some(TerminalColor::Red));
+        assert_eq!(color_at(4), Some(TerminalColor::Blue));
+        assert_eq!(color_at(7), Some(TerminalColor::Blue));
+        assert!(!tags.iter().any(|tag| tag.color == TerminalColor::Green));
+    }
+
+    #[test]
+    fn test_clear_line() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"\x1b[31mhello\r\nworld\x1b[m\x1b[1;3H\x1b[1K");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 2, y: 0 });
+        assert_eq!(emulator.dump_screen(), ["   lo", "world"]);
+
+        let tags = emulator.format_data().visible;
+        assert_eq!(tags[0].color, TerminalColor::Default);
+        assert_eq!(tags[0].start..tags[0].end, 0..3);
+        assert_eq!(tags[1].color, TerminalColor::Red);
+
+        emulator.write_sequence(b"\x1b[2;2H\x1b[2K");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 1, y: 1 });
+        assert_eq!(emulator.dump_screen(), ["   lo", "     "]);
+        let tags = emulator.format_data().visible;
+        assert!(!tags
+            .iter()
+            .any(|tag| tag.color == TerminalColor::Red && tag.end > 6));
+    }
+
+    #[test]
+    fn test_utf8() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 5)
+            .expect("failed to set win size");
+
+        emulator.write_sequence("\x1b[31mh\u{e9}llo\x1b[m w\u{f6}rld".as_bytes());
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 1, y: 1 });
+        assert_eq!(emulator.dump_screen()[0], "h\u{e9}llo w\u{f6}rld");
+
+        let tags = emulator.format_data().visible;
+        assert_eq!(tags[0].color, TerminalColor::Red);
+        assert_eq!(tags[0].start..tags[0].end, 0..6);
+
+        // Overwriting with characters of a different length keeps formatting in place
+        emulator.write_sequence("\x1b[1;2He\x1b[1;8H\u{e9}".as_bytes());
+        assert_eq!(emulator.dump_screen()[0], "hello w\u{e9}rld");
+        let tags = emulator.format_data().visible;
+        assert_eq!(tags[1].color, TerminalColor::Default);
+        assert_eq!(tags[1].start..tags[1].end, 1..2);
+        assert_eq!(tags[2].color, TerminalColor::Red);
+        assert_eq!(tags[2].start..tags[2].end, 2..5);
+        assert_eq!(tags[3].color, TerminalColor::Default);
+        assert_eq!(tags[3].start, 5);
+
+        // Characters split across reads
+        emulator.io.to_read = "\x1b[3H\u{4e2d}".as_bytes()[..5].to_vec();
+        emulator.read();
+        emulator.io.to_read = "\u{4e2d}".as_bytes()[1..].to_vec();
+        emulator.read();
+        assert_eq!(
+            emulator.dump_screen()[..2],
+            ["hello w\u{e9}rld", "\u{4e2d}"]
+        );
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 2, y: 2 });
+    }
+
+    #[test]
+    fn test_wide_chars() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence("\u{4e2d}\u{6587}".as_bytes());
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 4, y: 0 });
+
+        emulator.write_sequence(b"\x1b[1;1H");
+        assert_eq!(emulator.cursor_width(), 2);
+
+        emulator.write_sequence(b"\x1b[1;2Ha");
+        assert_eq!(emulator.dump_screen(), [" a\u{6587}"]);
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 2, y: 0 });
+        assert_eq!(emulator.cursor_width(), 2);
+    }
+
+    #[test]
+    fn test_erase_characters() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"\x1b[31mhello world\x1b[1;3H\x1b[m\x1b[3X");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 2, y: 0 });
+        assert_eq!(emulator.dump_screen(), ["he    world"]);
+
+        let tags = emulator.format_data().visible;
+        assert_eq!(tags[0].color, TerminalColor::Red);
+        assert_eq!(tags[0].end, 2);
+        assert_eq!(tags[1].color, TerminalColor::Default);
+        assert_eq!(tags[1].start..tags[1].end, 2..5);
+        assert_eq!(tags[2].color, TerminalColor::Red);
+    }
+
+    #[test]
+    fn test_tab() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(20, 5)
+            .expect("failed to set win size");
+
+        emulator.write_sequence(b"\ta");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 9, y: 0 });
+        emulator.write_sequence(b"\tb\t\tc");
+        assert_eq!(emulator.dump_screen()[0], "        a       b  c");
+
+        emulator.write_sequence(b"\r\n12345678\t");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 16, y: 1 });
+
+        // Custom stops replace the defaults
+        emulator.write_sequence(b"\r\n\x1b[3g\x1b[1;3H\x1bH\r\t");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 2, y: 0 });
+        emulator.write_sequence(b"\t");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 19, y: 0 });
+    }
+
+    #[test]
+    fn test_max_scrollback_lines() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 2)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"one\r\ntwo\r\nthree\r\nfour");
+        emulator.set_max_scrollback_lines(1);
+        assert_eq!(emulator.dump_scrollback(), ["two"]);
+
+        for i in 0..1000 {
+            emulator.write_sequence(format!("\x1b[31m{i}\r\n").as_bytes());
+        }
+        assert_eq!(emulator.dump_scrollback(), ["997"]);
+        assert_eq!(emulator.dump_screen(), ["998", "999"]);
+        let tags = emulator.format_data().scrollback;
+        assert_eq!(tags[0].start, 0);
+        assert_eq!(tags[0].color, TerminalColor::Red);
+
+        // Survives a reset
+        emulator.write_sequence(b"\x1bc");
+        for i in 0..10 {
+            emulator.write_sequence(format!("{i}\r\n").as_bytes());
+        }
+        assert_eq!(emulator.dump_scrollback(), ["7"]);
+    }
+
+    #[test]
+    fn test_dump_scrollback() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 2)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"one\r\ntwo\r\nthree\r\nfour");
+
+        assert_eq!(emulator.dump_scrollback(), ["one", "two"]);
+        assert_eq!(emulator.dump_screen(), ["three", "four"]);
+    }
+
+    #[test]
+    fn test_parser_timeout() {
+        let mut emulator = create_test_emulator();
+        emulator.io.to_read = b"a\x1b[".to_vec();
+        emulator.read();
+        assert_eq!(emulator.data().visible, b"a\n");
+
+        // Still within the timeout, the sequence may be completed
+        emulator.read();
+        assert_eq!(emulator.data().visible, b"a\n");
+
+        emulator.last_read_time -= PARSER_TIMEOUT;
+        emulator.read();
+        assert_eq!(emulator.data().visible, b"a\x1b[\n");
+
+        emulator.io.to_read = b"1mb".to_vec();
+        emulator.read();
+        assert_eq!(emulator.data().visible, b"a\x1b[1mb\n");
+    }
+
+    #[test]
+    fn test_done() {
+        let mut emulator = create_test_emulator();
+        emulator.io.to_read = b"bye".to_vec();
+        emulator.io.closed = true;
+        emulator.read();
+        assert!(emulator.is_done());
+        assert_eq!(emulator.data().visible, b"bye\n");
+    }
+
+    #[test]
+    fn test_shell_env_recorded() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let env = [("MYVAR".to_string(), "hello".to_string())];
+        let mut emulator =
+            TerminalEmulator::new(Path::new("/bin/sh"), &env, temp_dir.path().into(), 80, 24)
+                .expect("failed to spawn /bin/sh");
+        let handle = emulator
+            .start_recording()
+            .expect("failed to start recording");
+        emulator
+            .write(TerminalInput::PasteBuffer(b"echo $MYVAR\n".to_vec()))
+            .expect("failed to write command");
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !emulator
+            .dump_screen()
+            .iter()
+            .any(|line| line.ends_with("hello"))
+        {
+            assert!(
+                Instant::now() < deadline,
+                "timed out, got {:?}",
+                emulator.dump_screen()
+            );
+            emulator.read();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        drop(handle);
+
+        let recording =
+            Recording::load(&temp_dir.path().join("0.json")).expect("failed to load recording");
+        let output = recording
+            .items()
+            .iter()
+            .filter_map(|item| match item {
+                RecordingItem::Write { data, .. } => Some(data.as_slice()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .concat();
+        assert!(
+            output.windows(7).any(|w| w == b"hello\r\n"),
+            "{:?}",
+            String::from_utf8_lossy(&output)
+        );
+    }
+
+    #[test]
+    fn test_window_title_recording() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"\x1b]0;vim\x07");
+        assert_eq!(emulator.window_title(), "vim");
+
+        let replay_emulator = roundtrip_snapshot(&emulator);
+        assert_eq!(replay_emulator.window_title(), "vim");
+    }
+
+    #[test]
+    fn test_autowrap() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 5)
+            .expect("failed to set win size");
+
+        emulator.write_sequence(b"0123456789ab");
+        assert_eq!(emulator.dump_screen(), ["0123456789ab"]);
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 2, y: 1 });
+
+        emulator.write_sequence(b"\x1b[2J\x1b[H\x1b[?7l0123456789ab");
+        assert!(!emulator.current_modes().decawm);
+        assert_eq!(emulator.dump_screen()[0], "012345678b");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 9, y: 0 });
+
+        emulator.write_sequence(b"c");
+        assert_eq!(emulator.dump_screen()[0], "012345678c");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 9, y: 0 });
+
+        // The cursor is still on the last column, so the next character overwrites it before
+        // wrapping
+        emulator.write_sequence(b"\x1b[?7hd");
+        assert_eq!(emulator.dump_screen()[0], "012345678d");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 10, y: 0 });
+        emulator.write_sequence(b"e");
+        assert_eq!(emulator.dump_screen()[0], "012345678de");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 1, y: 1 });
+    }
+
+    #[test]
+    fn test_insert_mode() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"hello world\x1b[1;7H\x1b[4hbig ");
+        assert!(emulator.current_modes().insert_mode);
+        emulator.write_sequence(b"\x1b[4l");
+        assert!(!emulator.current_modes().insert_mode);
+        assert_eq!(emulator.dump_screen(), ["hello big world"]);
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 10, y: 0 });
+
+        emulator.write_sequence(b"BIG");
+        assert_eq!(emulator.dump_screen(), ["hello big BIGld"]);
+
+        // A full line shifts right, the last character falls off the right margin
+        emulator
+            .set_win_size(10, 5)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"\x1b[2J\x1b[H0123456789\x1b[1;3H\x1b[4hX");
+        assert_eq!(emulator.dump_screen()[0], "01X2345678");
+    }
+
+    #[test]
+    fn test_replay_asciicast() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res/test/hello.cast");
+        let recording = Recording::load(&path).expect("failed to load recording");
+        assert_eq!(
+            Recording::from_asciicast_v2(&path).expect("failed to load asciicast"),
+            recording
+        );
+        let mut replay_control = ReplayControl::new(recording);
+        let io_handle = replay_control.io_handle();
+        let mut emulator =
+            TerminalEmulator::from_snapshot(replay_control.initial_state(), io_handle)
+                .expect("failed to load snapshot");
+
+        replay_control
+            .seek_to(replay_control.len(), &mut emulator)
+            .expect("failed to seek replay");
+        emulator.read();
+
+        assert_eq!(emulator.get_win_size(), (30, 6));
+        assert_eq!(emulator.dump_screen(), ["$ echo hello", "hello", "$ "]);
+        assert_eq!(emulator.format_data().visible[1].color, TerminalColor::Red);
+    }
+
+    #[test]
+    fn test_replay_playback() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res/test/hello.cast");
+        let recording = Recording::load(&path).expect("failed to load recording");
+        let mut replay_control = ReplayControl::new(recording);
+        let io_handle = replay_control.io_handle();
+        let mut emulator =
+            TerminalEmulator::from_snapshot(replay_control.initial_state(), io_handle)
+                .expect("failed to load snapshot");
+
+        // Only the initial resize is due at the start
+        replay_control.play_at_speed(1.0);
+        replay_control
+            .poll_playback(&mut emulator)
+            .expect("failed to poll playback");
+        assert_eq!(emulator.get_win_size(), (20, 5));
+        assert_eq!(replay_control.current_item_index(), 1);
+        let wait = replay_control
+            .time_to_next_item()
+            .expect("playback should be running");
+        assert!(wait <= Duration::from_millis(500));
+        assert!(wait > Duration::from_millis(400));
+
+        // Changing speed keeps the position reached in the recording
+        replay_control.set_playback_speed(2.0);
+        let wait = replay_control
+            .time_to_next_item()
+            .expect("playback should be running");
+        assert!(wait <= Duration::from_millis(250));
+        assert!(wait > Duration::from_millis(200));
+
+        replay_control.play_at_speed(1_000_000.0);
+        std::thread::sleep(Duration::from_millis(1));
+        replay_control
+            .poll_playback(&mut emulator)
+            .expect("failed to poll playback");
+        assert_eq!(replay_control.current_pos(), replay_control.len());
+        assert!(!replay_control.is_playing());
+        assert_eq!(emulator.get_win_size(), (30, 6));
+
+        // The resize read the output recorded before it, but not the output after it
+        assert_eq!(emulator.dump_screen(), ["$ echo hello", "hello", ""]);
+        emulator.read();
+        assert_eq!(emulator.dump_screen(), ["$ echo hello", "hello", "$ "]);
+    }
+
+    #[test]
+    fn test_replay_read_timing() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut emulator = TerminalEmulator::new_with_io(MockIo::new(), temp_dir.path().into());
+        let handle = emulator
+            .start_recording()
+            .expect("failed to start recording");
+
+        emulator.io.to_read = b"first ".to_vec();
+        emulator.read();
+        std::thread::sleep(Duration::from_millis(100));
+        emulator.io.to_read = b"second".to_vec();
+        emulator.read();
+        drop(handle);
+
+        let recording =
+            Recording::load(&temp_dir.path().join("0.json")).expect("failed to load recording");
+        let mut replay_control = ReplayControl::new(recording);
+        let mut replay_emulator = TerminalEmulator::from_snapshot(
+            replay_control.initial_state(),
+            replay_control.io_handle(),
+        )
+        .expect("failed to load snapshot");
+
+        // The second read is played back as long after the first as it arrived
+        replay_control.play_at_speed(1.0);
+        replay_control
+            .poll_playback(&mut replay_emulator)
+            .expect("failed to poll playback");
+        replay_emulator.read();
+        assert_eq!(replay_emulator.dump_screen(), ["first "]);
+        let wait = replay_control
+            .time_to_next_item()
+            .expect("playback should be running");
+        assert!(wait > Duration::from_millis(50));
+
+        replay_control.play_at_speed(1_000_000.0);
+        std::thread::sleep(Duration::from_millis(1));
+        replay_control
+            .poll_playback(&mut replay_emulator)
+            .expect("failed to poll playback");
+        replay_emulator.read();
+        assert_eq!(replay_emulator.dump_screen(), ["first second"]);
+    }
+
+    #[test]
+    fn test_cursor_visible() {
+        let mut emulator = create_test_emulator();
+        assert!(emulator.cursor_visible());
+        emulator.write_sequence(b"\x1b[?25l\x1b[6 q");
+        assert!(!emulator.cursor_visible());
+        assert_eq!(emulator.cursor_style(), CursorStyle::SteadyBar);
+
+        let mut replay_emulator = roundtrip_snapshot(&emulator);
+        assert!(!replay_emulator.cursor_visible());
+        replay_emulator.write_sequence(b"\x1b[?25h");
+        assert!(replay_emulator.cursor_visible());
+        assert_eq!(replay_emulator.cursor_style(), CursorStyle::SteadyBar);
+    }
+
+    #[test]
+    fn test_save_restore_cursor() {
+        let mut emulator = create_test_emulator();
+
+        emulator.write_sequence(b"\x1b[2;3H\x1b[1;31m\x1b7\x1b[m\x1b[5;5Hx\x1b8y");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 3, y: 1 });
+        assert!(emulator.cursor_state.bold);
+        assert_eq!(emulator.cursor_state.color, TerminalColor::Red);
+
+        // A second save replaces the first, saves do not nest
+        emulator.write_sequence(b"\x1b[m\x1b[s\x1b[4;1H\x1b[s\x1b[1;1H\x1b[u");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 0, y: 3 });
+        emulator.write_sequence(b"\x1b[1;1H\x1b[u");
+        assert_eq!(emulator.cursor_state.pos, CursorPos { x: 0, y: 3 });
+        assert!(!emulator.cursor_state.bold);
+
+        let mut replay_emulator = roundtrip_snapshot(&emulator);
+        replay_emulator.write_sequence(b"\x1b[1;1H\x1b8");
+        assert_eq!(replay_emulator.cursor_state.pos, CursorPos { x: 0, y: 3 });
+    }
+
+    #[test]
+    fn test_replay_resize() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut emulator = TerminalEmulator::new_with_io(MockIo::new(), temp_dir.path().into());
+        emulator
+            .set_win_size(DEFAULT_TERMINAL_WIDTH, DEFAULT_TERMINAL_HEIGHT)
+            .expect("failed to set win size");
+
+        let handle = emulator
+            .start_recording()
+            .expect("failed to start recording");
+        emulator.io.to_read = b"0123456789".to_vec();
+        emulator.read();
+        emulator.set_win_size(5, 3).expect("failed to set win size");
+        emulator.io.to_read = b"abcdefgh".to_vec();
+        emulator.read();
+        drop(handle);
+
+        let recording =
+            Recording::load(&temp_dir.path().join("0.json")).expect("failed to load recording");
+        let mut replay_control = ReplayControl::new(recording);
+        let mut replay_emulator = TerminalEmulator::from_snapshot(
+            replay_control.initial_state(),
+            replay_control.io_handle(),
+        )
+        .expect("failed to load snapshot");
+
+        // write, resize, write
+        assert_eq!(replay_control.item_count(), 3);
+        assert_eq!(replay_control.item_start_pos(2), 11);
+        assert_eq!(replay_control.current_item_index(), 0);
+
+        replay_control
+            .seek_to(replay_control.len(), &mut replay_emulator)
+            .expect("failed to seek replay");
+        replay_emulator.read();
+
+        assert_eq!(replay_control.current_item_index(), 3);
+        assert_eq!(replay_emulator.get_win_size(), (5, 3));
+        assert_eq!(
+            replay_emulator.data().scrollback,
+            emulator.data().scrollback
+        );
+        assert_eq!(replay_emulator.data().visible, emulator.data().visible);
+    }
+
+    #[test]
+    fn test_replay_parser_timeout() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut emulator = TerminalEmulator::new_with_io(MockIo::new(), temp_dir.path().into());
+        let handle = emulator
+            .start_recording()
+            .expect("failed to start recording");
+
+        emulator.io.to_read = b"a\x1b[".to_vec();
+        emulator.read();
+        emulator.last_read_time -= PARSER_TIMEOUT;
+        emulator.read();
+        emulator.io.to_read = b"1mb".to_vec();
+        emulator.read();
+        drop(handle);
+
+        let recording =
+            Recording::load(&temp_dir.path().join("0.json")).expect("failed to load recording");
+        let mut replay_control = ReplayControl::new(recording);
+        let mut replay_emulator = TerminalEmulator::from_snapshot(
+            replay_control.initial_state(),
+            replay_control.io_handle(),
+        )
+        .expect("failed to load snapshot");
+        replay_control
+            .seek_to(replay_control.len(), &mut replay_emulator)
+            .expect("failed to seek");
+        replay_emulator.read();
+
+        // The replay gives up on the sequence at the same point the live session did
+        assert_eq!(replay_emulator.data().visible, emulator.data().visible);
+        assert_eq!(replay_emulator.data().visible, b"a\x1b[1mb\n");
+    }
+
+    #[test]
+    fn test_replay_thumbnails() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut emulator = TerminalEmulator::new_with_io(MockIo::new(), temp_dir.path().into());
+        emulator
+            .set_win_size(DEFAULT_TERMINAL_WIDTH, DEFAULT_TERMINAL_HEIGHT)
+            .expect("failed to set win size");
+
+        let handle = emulator
+            .start_recording()
+            .expect("failed to start recording");
+        emulator.io.to_read = b"0123456789".to_vec();
+        emulator.read();
+        emulator.set_win_size(5, 3).expect("failed to set win size");
+        emulator.io.to_read = b"abcdefgh".to_vec();
+        emulator.read();
+        drop(handle);
+
+        let recording =
+            Recording::load(&temp_dir.path().join("0.json")).expect("failed to load recording");
+        let replay_control = ReplayControl::new(recording);
+        let thumbnails = replay_control
+            .generate_thumbnails(3)
+            .expect("failed to generate thumbnails");
+
+        // 19 bytes total, the resize counts as one
+        let positions = thumbnails.iter().map(|t| t.pos).collect::<Vec<_>>();
+        assert_eq!(positions, &[6, 12, 19]);
+        assert_eq!(thumbnails[0].screen, &["012345"]);
+        // Existing lines are not re-wrapped on resize
+        assert_eq!(thumbnails[1].screen, &["0123456789a"]);
+        assert_eq!(thumbnails[2].screen, emulator.dump_screen());
+    }
+
+    #[test]
+    fn test_ctrl_d() {
+        // EOF for the shell. Unlike ctrl+c this reaches us as a plain key event, see
+        // write_input_to_terminal
+        let mut emulator = create_test_emulator();
+        emulator
+            .write(TerminalInput::Ctrl(b'D'))
+            .expect("failed to write input");
+        emulator
+            .write(TerminalInput::Ctrl(b'd'))
+            .expect("failed to write input");
+        assert_eq!(emulator.io.written, b"\x04\x04");
+    }
+
+    #[test]
+    fn test_ctrl_z() {
+        // The gui sends upper case key names, applications may send either
+        assert_eq!(char_to_ctrl_code(b'z'), 0x1a);
+        assert_eq!(char_to_ctrl_code(b'Z'), 0x1a);
+        assert_eq!(
+            TerminalInput::Ctrl(b'z').to_payload(&TerminalModes::default()),
+            TerminalInputPayload::Single(0x1a)
+        );
+        assert_eq!(
+            TerminalInput::Ctrl(b'Z').to_payload(&TerminalModes {
+                decckm: true,
+                ..Default::default()
+            }),
+            TerminalInputPayload::Single(0x1a)
+        );
+
+        let mut emulator = create_test_emulator();
+        emulator
+            .write(TerminalInput::Ctrl(b'Z'))
+            .expect("failed to write input");
+        assert_eq!(emulator.io.written, b"\x1a");
+    }
+
+    #[test]
+    fn test_function_keys() {
+        let modes = TerminalModes::default();
+        let payload = |n| match TerminalInput::F(n).to_payload(&modes) {
+            TerminalInputPayload::Many(data) => data.into_owned(),
+            TerminalInputPayload::Single(c) => vec![c],
+        };
+
+        assert_eq!(payload(1), b"\x1b[11~");
+        assert_eq!(payload(5), b"\x1b[15~");
+        assert_eq!(payload(6), b"\x1b[17~");
+        assert_eq!(payload(10), b"\x1b[21~");
+        assert_eq!(payload(11), b"\x1b[23~");
+        assert_eq!(payload(12), b"\x1b[24~");
+        assert_eq!(payload(13), b"");
+    }
+
+    #[test]
+    fn test_alt_keys() {
+        let modes = TerminalModes::default();
+        let payload = |c| match TerminalInput::AltAscii(c).to_payload(&modes) {
+            TerminalInputPayload::Many(data) => data.into_owned(),
+            TerminalInputPayload::Single(c) => vec![c],
+        };
+
+        assert_eq!(payload(b'b'), b"\x1bb");
+        assert_eq!(payload(b'f'), b"\x1bf");
+        assert_eq!(payload(b'.'), b"\x1b.");
+        assert_eq!(payload(0x7f), b"\x1b\x7f");
+    }
+
+    #[test]
+    fn test_tab_key() {
+        assert_eq!(
+            TerminalInput::Tab.to_payload(&TerminalModes::default()),
+            TerminalInputPayload::Single(b'\t')
+        );
+    }
+
+    #[test]
+    fn test_shift_tab_key() {
+        assert_eq!(
+            TerminalInput::ShiftTab.to_payload(&TerminalModes::default()),
+            TerminalInputPayload::Many(Cow::Borrowed(b"\x1b[Z"))
+        );
+    }
+
+    #[test]
+    fn test_escape_key() {
+        assert_eq!(
+            TerminalInput::Escape.to_payload(&TerminalModes {
+                decckm: true,
+                bracketed_paste: true,
+                ..Default::default()
+            }),
+            TerminalInputPayload::Single(0x1b)
+        );
+    }
+
+    #[test]
+    fn test_read_limit() {
+        let mut emulator = create_test_emulator();
+        emulator.set_max_bytes_per_frame(4);
+        emulator.io.to_read = b"abcdefghij".to_vec();
+
+        emulator.read();
+        assert_eq!(emulator.data().visible, b"abcd\n");
+        assert_eq!(emulator.io.to_read, b"efghij");
+        assert!(emulator.has_pending_output());
+
+        emulator.set_max_bytes_per_frame(DEFAULT_MAX_BYTES_PER_FRAME);
+        emulator.read();
+        assert_eq!(emulator.data().visible, b"abcdefghij\n");
+        assert!(emulator.io.to_read.is_empty());
+        assert!(!emulator.has_pending_output());
+
+        emulator.set_max_bytes_per_frame(0);
+        emulator.io.to_read = b"kl".to_vec();
+        emulator.read();
+        assert_eq!(emulator.io.to_read, b"l");
+    }
+
+    #[test]
+    fn test_osc8_hyperlink() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"a\x1b]8;;https://example.com\x07link\x1b]8;;\x07b");
+
+        let tags = emulator.format_data().visible;
+        let linked = tags
+            .iter()
+            .filter(|tag| tag.hyperlink.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(linked.len(), 1);
+        assert_eq!(linked[0].start, 1);
+        assert_eq!(linked[0].end, 5);
+        assert_eq!(linked[0].hyperlink.as_deref(), Some("https://example.com"));
+    }
+
+    #[test]
+    fn test_osc8_hyperlink_recording() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"\x1b]8;;https://example.com\x07li");
+
+        let mut replay_emulator = roundtrip_snapshot(&emulator);
+
+        // Text written after the recording started is still part of the link
+        replay_emulator.write_sequence(b"nk\x1b]8;;\x07");
+        let tags = replay_emulator.format_data().visible;
+        let linked = tags
+            .iter()
+            .filter(|tag| tag.hyperlink.is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(linked.len(), 1);
+        assert_eq!((linked[0].start, linked[0].end), (0, 4));
+        assert_eq!(linked[0].hyperlink.as_deref(), Some("https://example.com"));
+    }
+
+    #[test]
+    fn test_dirty() {
+        let mut emulator = create_test_emulator();
+        assert!(emulator.is_dirty());
+
+        emulator.clear_dirty();
+        emulator.read();
+        assert!(!emulator.is_dirty());
+
+        emulator.write_sequence(b"hello");
+        assert!(emulator.is_dirty());
+
+        emulator.clear_dirty();
+        let (width, height) = emulator.get_win_size();
+        emulator
+            .set_win_size(width, height)
+            .expect("failed to set win size");
+        assert!(!emulator.is_dirty());
+
+        emulator
+            .set_win_size(width + 1, height)
+            .expect("failed to set win size");
+        assert!(emulator.is_dirty());
+    }
+
+    #[test]
+    fn test_print_debug_state() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"a\x1b[32mb\x1b[1");
+
+        let mut out = Vec::new();
+        emulator
+            .print_debug_state(&mut out)
+            .expect("failed to write debug state");
+        let out = String::from_utf8(out).expect("debug state is not utf8");
+        assert!(out.contains("Cursor: (2, 0)"));
+        assert!(out.contains("Cursor color: Green"));
+        assert!(out.contains("Parser state: csi"));
+        assert!(out.contains("Format tags: 3"));
+
+        let debug_state = |num_tags: usize| {
+            let mut emulator = create_test_emulator();
+            // Alternate colors so that neighbouring tags are not merged
+            for i in 0..num_tags - 1 {
+                emulator.write_sequence(format!("\x1b[{}mx", 31 + i % 2).as_bytes());
+            }
+            let mut out = Vec::new();
+            emulator
+                .print_debug_state(&mut out)
+                .expect("failed to write debug state");
+            let out = String::from_utf8(out).expect("debug state is not utf8");
+            assert!(out.contains(&format!("Format tags: {num_tags}")));
+            out
+        };
+
+        // Nothing is skipped until there are more tags than are printed
+        let out = debug_state(20);
+        assert!(!out.contains("..."));
+        assert_eq!(
+            out.lines().filter(|l| l.starts_with("  FormatTag")).count(),
+            20
+        );
+
+        let out = debug_state(21);
+        assert!(out.contains("..."));
+        assert_eq!(
+            out.lines().filter(|l| l.starts_with("  FormatTag")).count(),
+            20
+        );
+    }
+
+    #[test]
+    fn test_bell() {
+        let mut emulator = create_test_emulator();
+        // BEL terminating an OSC is not a bell
+        emulator.write_sequence(b"\x1b]8;;\x07");
+        assert!(!emulator.take_bell());
+
+        emulator.write_sequence(b"a\x07b");
+        assert!(emulator.take_bell());
+        assert!(!emulator.take_bell());
+        assert_eq!(emulator.cursor_pos().x, 2);
+    }
+
+    #[test]
+    fn test_osc52() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"\x1b]52;c;aGVsbG8=\x07");
+        assert_eq!(emulator.take_clipboard_request().as_deref(), Some("hello"));
+        assert_eq!(emulator.take_clipboard_request(), None);
+
+        emulator.write_sequence(b"\x1b]52;c;?\x07");
+        assert_eq!(emulator.io.written, b"\x1b]52;c;\x07");
+
+        emulator.set_osc52_enabled(false);
+        emulator.write_sequence(b"\x1b]52;c;aGVsbG8=\x07");
+        assert_eq!(emulator.take_clipboard_request(), None);
+    }
+
+    #[test]
+    fn test_resize_history() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(DEFAULT_TERMINAL_WIDTH, DEFAULT_TERMINAL_HEIGHT)
+            .expect("failed to set win size");
+        assert!(emulator.resize_history().is_empty());
+
+        for i in 0..MAX_RESIZE_HISTORY + 5 {
+            emulator
+                .set_win_size(10 + i, 20)
+                .expect("failed to set win size");
+        }
+
+        let history = emulator.resize_history();
+        assert_eq!(history.len(), MAX_RESIZE_HISTORY);
+        assert_eq!((history[0].0, history[0].1), (15, 20));
+        let last = history.last().expect("history should not be empty");
+        assert_eq!((last.0, last.1), (10 + MAX_RESIZE_HISTORY + 4, 20));
+    }
+
+    #[test]
+    fn test_trim_scrollback() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .set_win_size(10, 1)
+            .expect("failed to set win size");
+        emulator.write_sequence(b"aa\r\n\x1b[32mbb\x1b[0m\r\ncc\r\ndd");
+        assert_eq!(emulator.data().scrollback, b"aa\nbb\ncc\n");
+
+        emulator.trim_scrollback(6);
+        assert_eq!(emulator.data().scrollback, b"bb\ncc\n");
+        assert_eq!(emulator.data().visible, b"dd\n");
+
+        let tags = emulator.format_data().scrollback;
+        assert_eq!(
+            tags,
+            &[
+                FormatTag {
+                    start: 0,
+                    end: 2,
+                    color: TerminalColor::Green,
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+                FormatTag {
+                    start: 2,
+                    end: 6,
+                    color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut emulator = create_test_emulator();
+        emulator.io.to_read = b"\x1b[32mhello\r\n".to_vec();
+        emulator.read();
+        emulator.read();
+
+        let stats = emulator.stats();
+        assert_eq!(stats.bytes_processed, 12);
+        assert_eq!(stats.frames_rendered, 2);
+        assert_eq!(stats.scrollback_bytes, 0);
+        assert_eq!(stats.format_tag_count, 2);
+        assert_eq!(stats.cursor_pos, CursorPos { x: 0, y: 1 });
+
+        emulator.reset_stats();
+        let stats = emulator.stats();
+        assert_eq!(stats.bytes_processed, 0);
+        assert_eq!(stats.frames_rendered, 0);
+        assert_eq!(stats.cursor_pos, CursorPos { x: 0, y: 1 });
+    }
+
+    #[test]
+    fn test_write_sequence_text() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"hello\r\nworld");
+        assert_eq!(emulator.data().visible, b"hello\nworld\n");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 5, y: 1 });
+    }
+
+    #[test]
+    fn test_write_sequence_cursor_movement() {
+        let mut emulator = create_test_emulator();
+
+        emulator.write_sequence(b"\x1b[5;10H");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 9, y: 4 });
+
+        emulator.write_sequence(b"\x1b[2A\x1b[3D");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 6, y: 2 });
+
+        emulator.write_sequence(b"\x1b[B\x1b[C");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 7, y: 3 });
+
+        emulator.write_sequence(b"\x1b[20G");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 19, y: 3 });
+
+        // Relative movement should not go past the top left corner
+        emulator.write_sequence(b"\x1b[100A\x1b[100D");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 0, y: 0 });
+    }
+
+    #[test]
+    fn test_line_position_absolute_zero() {
+        let mut emulator = create_test_emulator();
+
+        emulator.write_sequence(b"\x1b[5;3H");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 2, y: 4 });
+
+        emulator.write_sequence(b"\x1b[0d");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 2, y: 0 });
+    }
+
+    #[test]
+    fn test_cursor_column_zero() {
+        let mut emulator = create_test_emulator();
+
+        emulator.write_sequence(b"\x1b[5;3H");
+        emulator.write_sequence(b"\x1b[0G");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 0, y: 4 });
+
+        emulator.write_sequence(b"\x1b[3;4H\x1b[5;0H");
+        assert_eq!(emulator.cursor_pos(), CursorPos { x: 0, y: 4 });
+    }
+
+    #[test]
+    fn test_write_sequence_overwrite_and_clear() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"hello world\r\nsecond line");
+
+        emulator.write_sequence(b"\x1b[1;7Hthere");
+        assert_eq!(emulator.data().visible, b"hello there\nsecond line\n");
+
+        emulator.write_sequence(b"\x1b[1;6H\x1b[K");
+        assert_eq!(emulator.data().visible, b"hello\nsecond line\n");
+
+        emulator.write_sequence(b"\x1b[2;1H\x1b[3P");
+        assert_eq!(emulator.data().visible, b"hello\nond line\n");
+
+        emulator.write_sequence(b"\x1b[2@");
+        assert_eq!(emulator.data().visible, b"hello\n  ond line\n");
+
+        emulator.write_sequence(b"\x1b[2J");
+        assert_eq!(emulator.data().visible, b"");
+    }
+
+    #[test]
+    fn test_write_sequence_sgr() {
+        let mut emulator = create_test_emulator();
+        emulator.write_sequence(b"ab\x1b[1;32mcd\x1b[0mef");
+
+        assert_eq!(emulator.data().visible, b"abcdef\n");
+        assert_eq!(
+            emulator.format_data().visible,
+            &[
+                FormatTag {
+                    start: 0,
+                    end: 2,
+                    color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+                FormatTag {
+                    start: 2,
+                    end: 4,
+                    color: TerminalColor::Green,
+                    background_color: TerminalColor::Default,
+                    bold: true,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+                FormatTag {
+                    start: 4,
+                    end: usize::MAX,
+                    color: TerminalColor::Default,
+                    background_color: TerminalColor::Default,
+                    bold: false,
+                    italic: false,
+                    underline: false,
+                    strikethrough: false,
+                    dim: false,
+                    reverse: false,
+                    hyperlink: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_paste() {
+        let mut emulator = create_test_emulator();
+        emulator
+            .write(TerminalInput::PasteBuffer(b"ls\n".to_vec()))
+            .expect("failed to write input");
+        assert_eq!(emulator.io.written, b"ls\n");
+
+        emulator.io.written.clear();
+        emulator.write_sequence(b"\x1b[?2004h");
+        assert!(emulator.current_modes().bracketed_paste);
+        emulator
+            .write(TerminalInput::PasteBuffer(b"a\x1b[201~b".to_vec()))
+            .expect("failed to write input");
+        assert_eq!(emulator.io.written, b"\x1b[200~ab\x1b[201~");
+
+        // Newlines are pasted as is, inside the markers
+        emulator.io.written.clear();
+        emulator
+            .write(TerminalInput::PasteBuffer(b"echo a\necho b\n".to_vec()))
+            .expect("failed to write input");
+        assert_eq!(emulator.io.written, b"\x1b[200~echo a\necho b\n\x1b[201~");
+
+        emulator.io.written.clear();
+        emulator.write_sequence(b"\x1b[?2004l");
+        assert!(!emulator.current_modes().bracketed_paste);
+        emulator
+            .write(TerminalInput::PasteBuffer(b"ls\n".to_vec()))
+            .expect("failed to write input");
+        assert_eq!(emulator.io.written, b"ls\n");
+    }
+
+    #[test]
+    fn test_write_sequence_decckm() {
+        let mut emulator = create_test_emulator();
+
+        emulator.write_sequence(b"\x1b[?1h");
+        assert!(emulator.current_modes().decckm);
+        emulator
+            .write(TerminalInput::ArrowUp)
+            .expect("failed to write input");
+        emulator.write_sequence(b"\x1b[?1l");
+        assert!(!emulator.current_modes().decckm);
+        emulator
+            .write(TerminalInput::ArrowUp)
+            .expect("failed to write input");
+
+        assert_eq!(emulator.io.written, b"\x1bOA\x1b[A");
+    }
+
+    #[test]
+    fn test_recording_chunk_state() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut emulator = TerminalEmulator::new_with_io(MockIo::new(), temp_dir.path().into());
+        emulator.set_recording_chunk_size(Some(1));
+        let handle = emulator
+            .start_recording()
+            .expect("failed to start recording");
+
+        emulator.inject_and_record(b"first ");
+        emulator.inject_and_record(b"second");
+        drop(handle);
+
+        let chunk_dir = temp_dir.path().join("0");
+        let load_chunk = |name: &str| {
+            let recording = Recording::load(&chunk_dir.join(name)).expect("failed to load chunk");
+            let mut replay_control = ReplayControl::new(recording);
+            let mut replay_emulator = TerminalEmulator::from_snapshot(
+                replay_control.initial_state(),
+                replay_control.io_handle(),
+            )
+            .expect("failed to load snapshot");
+            let visible_at_start = replay_emulator.data().visible.to_vec();
+            while replay_control.current_pos() < replay_control.len() {
+                replay_control.next();
+            }
+            replay_emulator.read();
+            (visible_at_start, replay_emulator.data().visible.to_vec())
+        };
+
+        // Each chunk starts from the state its predecessor ends with
+        assert_eq!(load_chunk("0.json"), (vec![], b"first \n".to_vec()));
+        assert_eq!(
+            load_chunk("1.json"),
+            (b"first \n".to_vec(), b"first second\n".to_vec())
+        );
+        assert_eq!(
+            load_chunk("2.json"),
+            (b"first second\n".to_vec(), b"first second\n".to_vec())
+        );
+    }
 }
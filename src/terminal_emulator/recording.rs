@@ -1,10 +1,13 @@
+use super::ansi::incomplete_utf8_len;
 use crate::error::backtraced_err;
 
 use std::{
     collections::HashMap,
+    io::Write,
     num::TryFromIntError,
     path::{Path, PathBuf},
     sync::{Arc, Mutex, Weak},
+    time::Instant,
 };
 
 use thiserror::Error;
@@ -38,7 +41,7 @@ enum NotIntOfTypeKind {
 #[error(transparent)]
 pub struct NotIntOfType(#[from] NotIntOfTypeKind);
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum SnapshotItem {
     Bool(bool),
     Int(i64),
@@ -142,10 +145,10 @@ impl<T: Into<SnapshotItem>> FromIterator<T> for SnapshotItem {
     }
 }
 
-fn find_recording_path(recording_dir: &Path) -> PathBuf {
+fn find_recording_path(recording_dir: &Path, suffix: &str) -> PathBuf {
     let mut i = 0;
     loop {
-        let candidate_path = recording_dir.join(format!("{}.json", i));
+        let candidate_path = recording_dir.join(format!("{}{}", i, suffix));
         if candidate_path.exists() {
             i += 1;
             continue;
@@ -154,6 +157,23 @@ fn find_recording_path(recording_dir: &Path) -> PathBuf {
     }
 }
 
+fn state_to_snapshot(state: &HashMap<String, JsonValue>) -> SnapshotItem {
+    SnapshotItem::Map(
+        state
+            .iter()
+            .map(|(k, v)| (k.clone(), tinyjson_to_snapshot(v.clone())))
+            .collect(),
+    )
+}
+
+fn parse_recording_root(content: &str) -> Result<HashMap<String, JsonValue>, LoadRecordingError> {
+    let json: tinyjson::JsonValue = content.parse().map_err(LoadRecordingErrorKind::Parse)?;
+    let tinyjson::JsonValue::Object(root) = json else {
+        Err(LoadRecordingErrorKind::RootNotObject)?
+    };
+    Ok(root)
+}
+
 fn tinyjson_to_snapshot(value: tinyjson::JsonValue) -> SnapshotItem {
     match value {
         tinyjson::JsonValue::Null => {
@@ -216,12 +236,123 @@ enum LoadRecordingErrorKind {
     ItemsNotArray,
     #[error("invalid item in items")]
     ItemInvalid(#[source] ParseRecordingItemError),
+    #[error("failed to read recording directory")]
+    ReadDir(#[source] std::io::Error),
+    #[error("recording directory has no chunks")]
+    NoChunks,
+    #[error("failed to load chunk {0}")]
+    LoadChunk(PathBuf, #[source] Box<LoadRecordingError>),
+    #[error("asciicast header is not a v2 header with a terminal size")]
+    AsciicastHeaderInvalid,
+    #[error("asciicast event on line {0} is invalid")]
+    AsciicastEventInvalid(usize),
 }
 
 #[derive(Debug, Error)]
 #[error(transparent)]
 pub struct LoadRecordingError(#[from] LoadRecordingErrorKind);
 
+#[derive(Debug, Error)]
+enum AsciicastExportErrorKind {
+    #[error("initial state has no terminal size")]
+    SizeMissing,
+    #[error("failed to write asciicast")]
+    Write(#[source] std::io::Error),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct AsciicastExportError(#[from] AsciicastExportErrorKind);
+
+/// Recordings from before timing was recorded are spaced out evenly at this rate when exporting
+const ASCIICAST_EVENTS_PER_SECOND: f64 = 10.0;
+
+/// Asciicast files start with a header line holding the format version, our recordings are a
+/// single json object that does not have one
+fn is_asciicast_v2(content: &str) -> bool {
+    let Some(Ok(JsonValue::Object(header))) = content.lines().next().map(str::parse) else {
+        return false;
+    };
+    header.contains_key("version")
+}
+
+fn parse_asciicast_header(line: &str) -> Option<(usize, usize)> {
+    let JsonValue::Object(header) = line.parse().ok()? else {
+        return None;
+    };
+    let version: f64 = *header.get("version")?.get()?;
+    let width: f64 = *header.get("width")?.get()?;
+    let height: f64 = *header.get("height")?.get()?;
+    if version != 2.0 {
+        return None;
+    }
+    Some((width as usize, height as usize))
+}
+
+/// Split an asciicast event into its time in milliseconds, code and data
+fn parse_asciicast_event(line: &str) -> Option<(u64, String, String)> {
+    let JsonValue::Array(event) = line.parse().ok()? else {
+        return None;
+    };
+    let [JsonValue::Number(time), JsonValue::String(code), JsonValue::String(data)] =
+        <[JsonValue; 3]>::try_from(event).ok()?
+    else {
+        return None;
+    };
+    if time < 0.0 {
+        return None;
+    }
+    Some(((time * 1000.0).round() as u64, code, data))
+}
+
+fn parse_asciicast_v2(content: &str) -> Result<Recording, LoadRecordingError> {
+    use LoadRecordingErrorKind::*;
+
+    let mut lines = content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+
+    let (_, header) = lines.next().ok_or(AsciicastHeaderInvalid)?;
+    let (width, height) = parse_asciicast_header(header).ok_or(AsciicastHeaderInvalid)?;
+
+    // There is no initial state to restore, the replay starts from a fresh terminal of the
+    // recorded size
+    let mut items = vec![RecordingItem::SetWinSize {
+        width,
+        height,
+        timestamp_ms: 0,
+    }];
+    for (i, line) in lines {
+        let invalid = || AsciicastEventInvalid(i + 1);
+        let (timestamp_ms, code, data) = parse_asciicast_event(line).ok_or_else(invalid)?;
+        match code.as_str() {
+            "o" => items.push(RecordingItem::Write {
+                data: data.into_bytes(),
+                timestamp_ms,
+            }),
+            "r" => {
+                let (width, height) = data
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .ok_or_else(invalid)?;
+                items.push(RecordingItem::SetWinSize {
+                    width,
+                    height,
+                    timestamp_ms,
+                });
+            }
+            // Input and marker events do not change what is on screen
+            _ => (),
+        }
+    }
+
+    Ok(Recording {
+        initial_state: Default::default(),
+        items,
+    })
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Recording {
     initial_state: HashMap<String, tinyjson::JsonValue>,
@@ -236,13 +367,20 @@ impl Recording {
         }
     }
 
+    /// Load a recording, an asciicast v2 file, or a directory of recording chunks
     pub fn load(path: &Path) -> Result<Recording, LoadRecordingError> {
         use LoadRecordingErrorKind::*;
+        if path.is_dir() {
+            return Recording::load_directory(path);
+        }
+
         let content = std::fs::read_to_string(path).map_err(Read)?;
-        let json: tinyjson::JsonValue = content.parse().map_err(Parse)?;
-        let tinyjson::JsonValue::Object(mut root) = json else {
-            Err(RootNotObject)?
-        };
+        if is_asciicast_v2(&content) {
+            return parse_asciicast_v2(&content);
+        }
+
+        let mut root = parse_recording_root(&content)?;
+        let items = Self::parse_items(&mut root)?;
 
         // FIXME: strings should be constnants
         let initial_state = root.remove("initial_state").ok_or(InitialStateMissing)?;
@@ -250,6 +388,17 @@ impl Recording {
             Err(InitialStateNotObject)?
         };
 
+        Ok(Recording {
+            initial_state,
+            items,
+        })
+    }
+
+    fn parse_items(
+        root: &mut HashMap<String, JsonValue>,
+    ) -> Result<Vec<RecordingItem>, LoadRecordingError> {
+        use LoadRecordingErrorKind::*;
+
         // FIXME: strings should be constnants
         let items = root.remove("items").ok_or(ItemsNotPresent)?;
         let tinyjson::JsonValue::Array(items) = items else {
@@ -260,11 +409,52 @@ impl Recording {
             .into_iter()
             .map(|v| RecordingItem::from_json(v).map_err(ItemInvalid))
             .collect::<Result<_, _>>()?;
+        Ok(items)
+    }
 
-        Ok(Recording {
-            initial_state,
-            items,
-        })
+    /// Load a recording in the asciicast v2 format, see
+    /// https://docs.asciinema.org/manual/asciicast/v2/. [`Recording::load`] detects the format on
+    /// its own, this is for callers that already know it
+    #[allow(unused)]
+    pub fn from_asciicast_v2(path: &Path) -> Result<Recording, LoadRecordingError> {
+        let content = std::fs::read_to_string(path).map_err(LoadRecordingErrorKind::Read)?;
+        parse_asciicast_v2(&content)
+    }
+
+    /// Load a recording split into numbered chunks by [`Recorder`]. The initial state comes
+    /// from the first chunk, items from all chunks are concatenated in order
+    fn load_directory(dir: &Path) -> Result<Recording, LoadRecordingError> {
+        use LoadRecordingErrorKind::*;
+
+        let mut chunks = std::fs::read_dir(dir)
+            .map_err(ReadDir)?
+            .map(|entry| {
+                let path = entry.map_err(ReadDir)?.path();
+                let idx = path
+                    .extension()
+                    .filter(|ext| *ext == "json")
+                    .and_then(|_| path.file_stem()?.to_str()?.parse::<usize>().ok());
+                Ok(idx.map(|idx| (idx, path)))
+            })
+            .filter_map(Result::transpose)
+            .collect::<Result<Vec<_>, LoadRecordingErrorKind>>()?;
+        chunks.sort_by_key(|(idx, _)| *idx);
+
+        let mut chunks = chunks.into_iter().map(|(_, path)| path);
+        let first_path = chunks.next().ok_or(NoChunks)?;
+        let mut recording =
+            Recording::load(&first_path).map_err(|e| LoadChunk(first_path, Box::new(e)))?;
+
+        // Only the items of later chunks are needed
+        for path in chunks {
+            let items = std::fs::read_to_string(&path)
+                .map_err(|e| LoadRecordingError::from(Read(e)))
+                .and_then(|content| Self::parse_items(&mut parse_recording_root(&content)?))
+                .map_err(|e| LoadChunk(path, Box::new(e)))?;
+            recording.items.extend(items);
+        }
+
+        Ok(recording)
     }
 
     fn to_json(&self) -> JsonValue {
@@ -284,40 +474,135 @@ impl Recording {
     }
 
     pub fn initial_state(&self) -> SnapshotItem {
-        let state: HashMap<String, SnapshotItem> = self
-            .initial_state
-            .iter()
-            .map(|(k, v)| (k.clone(), tinyjson_to_snapshot(v.clone())))
-            .collect();
-
-        SnapshotItem::Map(state)
+        state_to_snapshot(&self.initial_state)
     }
 
     pub fn items(&self) -> &[RecordingItem] {
         &self.items
     }
+
+    /// Terminal size at the start of the recording
+    fn initial_win_size(&self) -> Option<(f64, f64)> {
+        let JsonValue::Object(buffer) = self.initial_state.get("terminal_buffer")? else {
+            return None;
+        };
+        let width: f64 = *buffer.get("width")?.get()?;
+        let height: f64 = *buffer.get("height")?.get()?;
+        Some((width, height))
+    }
+
+    /// Write the recording in the asciicast v2 format, see
+    /// https://docs.asciinema.org/manual/asciicast/v2/
+    pub fn to_asciicast_v2(&self, writer: &mut impl Write) -> Result<(), AsciicastExportError> {
+        use AsciicastExportErrorKind::*;
+
+        let (width, height) = self.initial_win_size().ok_or(SizeMissing)?;
+        let header = JsonValue::Object(
+            [
+                ("version".to_string(), JsonValue::Number(2.0)),
+                ("width".to_string(), JsonValue::Number(width)),
+                ("height".to_string(), JsonValue::Number(height)),
+            ]
+            .into(),
+        );
+        header.write_to(writer).map_err(Write)?;
+        writeln!(writer).map_err(Write)?;
+
+        let has_timing = self.items.iter().any(|item| item.timestamp_ms() != 0);
+        // Characters can be split across writes, the incomplete end of one write is held back
+        // until the rest of it arrives
+        let mut pending_output = Vec::new();
+        let mut time = 0.0;
+        for (i, item) in self.items.iter().enumerate() {
+            time = if has_timing {
+                item.timestamp_ms() as f64 / 1000.0
+            } else {
+                i as f64 / ASCIICAST_EVENTS_PER_SECOND
+            };
+            let (code, data) = match item {
+                RecordingItem::Write { data, .. } => {
+                    pending_output.extend_from_slice(data);
+                    let complete_len = pending_output.len() - incomplete_utf8_len(&pending_output);
+                    if complete_len == 0 {
+                        continue;
+                    }
+                    let output: Vec<u8> = pending_output.drain(..complete_len).collect();
+                    ("o", String::from_utf8_lossy(&output).into_owned())
+                }
+                RecordingItem::SetWinSize { width, height, .. } => {
+                    ("r", format!("{width}x{height}"))
+                }
+                // Asciicast players have no equivalent, the unfinished sequence is left as is
+                RecordingItem::ResetParser { .. } => continue,
+            };
+            write_asciicast_event(writer, time, code, data)?;
+        }
+
+        // A character that was never finished
+        if !pending_output.is_empty() {
+            let data = String::from_utf8_lossy(&pending_output).into_owned();
+            write_asciicast_event(writer, time, "o", data)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_asciicast_event(
+    writer: &mut impl Write,
+    time: f64,
+    code: &str,
+    data: String,
+) -> Result<(), AsciicastExportError> {
+    use AsciicastExportErrorKind::*;
+
+    let event = JsonValue::Array(vec![
+        JsonValue::Number(time),
+        JsonValue::String(code.to_string()),
+        JsonValue::String(data),
+    ]);
+    event.write_to(writer).map_err(Write)?;
+    writeln!(writer).map_err(Write)?;
+    Ok(())
 }
 
 struct RecordingHandleInner {
     recording: Recording,
     path: PathBuf,
+    /// Item timestamps are relative to this. Kept across chunks
+    start: Instant,
 }
 
-impl Drop for RecordingHandleInner {
-    fn drop(&mut self) {
-        let res = (|| -> Result<(), Box<dyn std::error::Error>> {
-            let mut f = std::fs::OpenOptions::new()
-                .create(true)
-                .truncate(true)
-                .write(true)
-                .open(&self.path)?;
+impl RecordingHandleInner {
+    fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+}
 
-            self.recording.to_json().format_to(&mut f)?;
-            Ok(())
-        })();
+impl RecordingHandleInner {
+    /// Write the recording so far. The file is replaced atomically so that a crash while saving
+    /// does not lose what was previously flushed
+    fn flush_to_disk(&self) -> Result<(), std::io::Error> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&tmp_path)?;
+
+        self.recording.to_json().format_to(&mut f)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)
+    }
+}
 
-        if let Err(e) = res {
-            error!("Failed to save recording: {}", backtraced_err(&*e));
+impl Drop for RecordingHandleInner {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_to_disk() {
+            error!("Failed to save recording: {}", backtraced_err(&e));
         }
     }
 }
@@ -350,6 +635,8 @@ enum ParseRecordingItemErrorKind {
     DataElemNotNumber,
     #[error("data elem does not fit in u8")]
     DataElemNotU8,
+    #[error("timestamp_ms field is not a u64")]
+    TimestampNotU64,
     #[error("unexpected field: {0}")]
     UnexpectedField(String),
 }
@@ -364,13 +651,35 @@ pub struct RecordingHandle {
     inner: Arc<Mutex<RecordingHandleInner>>,
 }
 
+/// `timestamp_ms` is the time since the recording started at which the item was recorded. Items
+/// from recordings made before timing was recorded have a timestamp of 0
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum RecordingItem {
-    SetWinSize { width: usize, height: usize },
-    Write { data: Vec<u8> },
+    SetWinSize {
+        width: usize,
+        height: usize,
+        timestamp_ms: u64,
+    },
+    Write {
+        data: Vec<u8>,
+        timestamp_ms: u64,
+    },
+    /// The parser gave up on an unfinished escape sequence after waiting too long for the rest of
+    /// it. Replays do not time out, so they reset the parser here instead
+    ResetParser {
+        timestamp_ms: u64,
+    },
 }
 
 impl RecordingItem {
+    pub fn timestamp_ms(&self) -> u64 {
+        match self {
+            RecordingItem::SetWinSize { timestamp_ms, .. }
+            | RecordingItem::Write { timestamp_ms, .. }
+            | RecordingItem::ResetParser { timestamp_ms } => *timestamp_ms,
+        }
+    }
+
     fn from_json(json: JsonValue) -> Result<RecordingItem, ParseRecordingItemError> {
         use ParseRecordingItemErrorKind::*;
 
@@ -383,6 +692,13 @@ impl RecordingItem {
             Err(TypeNotString)?
         };
 
+        // Optional, recordings from before timing was recorded do not have it
+        let timestamp_ms = match map.remove("timestamp_ms") {
+            Some(JsonValue::Number(timestamp_ms)) if timestamp_ms >= 0.0 => timestamp_ms as u64,
+            Some(_) => Err(TimestampNotU64)?,
+            None => 0,
+        };
+
         match typ.as_str() {
             "set_win_size" => {
                 let width = map.remove("width").ok_or(WidthNotPresent)?;
@@ -400,7 +716,11 @@ impl RecordingItem {
                 let height = height.round() as i64;
                 let height = height.try_into().map_err(HeightNotUsize)?;
 
-                Ok(RecordingItem::SetWinSize { width, height })
+                Ok(RecordingItem::SetWinSize {
+                    width,
+                    height,
+                    timestamp_ms,
+                })
             }
             "write" => {
                 let data = map.remove("data").ok_or(DataNotPresent)?;
@@ -419,23 +739,32 @@ impl RecordingItem {
                     })
                     .collect::<Result<_, _>>()?;
 
-                Ok(RecordingItem::Write { data })
+                Ok(RecordingItem::Write { data, timestamp_ms })
             }
+            "reset_parser" => Ok(RecordingItem::ResetParser { timestamp_ms }),
             _ => Err(UnexpectedField(typ))?,
         }
     }
 
     fn to_json(&self) -> tinyjson::JsonValue {
         match self {
-            RecordingItem::SetWinSize { width, height } => JsonValue::Object(
+            RecordingItem::SetWinSize {
+                width,
+                height,
+                timestamp_ms,
+            } => JsonValue::Object(
                 [
                     ("type".into(), JsonValue::String("set_win_size".into())),
                     ("width".into(), JsonValue::Number(*width as f64)),
                     ("height".into(), JsonValue::Number(*height as f64)),
+                    (
+                        "timestamp_ms".into(),
+                        JsonValue::Number(*timestamp_ms as f64),
+                    ),
                 ]
                 .into(),
             ),
-            RecordingItem::Write { data } => JsonValue::Object(
+            RecordingItem::Write { data, timestamp_ms } => JsonValue::Object(
                 [
                     ("type".into(), JsonValue::String("write".into())),
                     (
@@ -444,6 +773,20 @@ impl RecordingItem {
                             data.iter().map(|v| JsonValue::Number(*v as f64)).collect(),
                         ),
                     ),
+                    (
+                        "timestamp_ms".into(),
+                        JsonValue::Number(*timestamp_ms as f64),
+                    ),
+                ]
+                .into(),
+            ),
+            RecordingItem::ResetParser { timestamp_ms } => JsonValue::Object(
+                [
+                    ("type".into(), JsonValue::String("reset_parser".into())),
+                    (
+                        "timestamp_ms".into(),
+                        JsonValue::Number(*timestamp_ms as f64),
+                    ),
                 ]
                 .into(),
             ),
@@ -475,6 +818,9 @@ pub enum StartRecordingResponse {
 pub struct Recorder {
     recording_dir: PathBuf,
     handle: Weak<Mutex<RecordingHandleInner>>,
+    /// When set, recordings are written as a directory of numbered chunks holding at most this
+    /// many items each
+    max_items_per_file: Option<usize>,
 }
 
 impl Recorder {
@@ -482,29 +828,82 @@ impl Recorder {
         Recorder {
             recording_dir,
             handle: Weak::new(),
+            max_items_per_file: None,
+        }
+    }
+
+    pub fn set_max_items_per_file(&mut self, max_items_per_file: Option<usize>) {
+        self.max_items_per_file = max_items_per_file;
+    }
+
+    /// If the active chunk is full, save it and start the next one. The caller is responsible
+    /// for filling in the initial state of the new chunk
+    pub fn next_chunk(&self) -> Option<RecordingInitializer> {
+        let max_items = self.max_items_per_file?;
+        let handle = self.handle.upgrade()?;
+
+        {
+            let mut inner = handle.lock().expect("poisoned lock");
+            if inner.recording.items.len() < max_items {
+                return None;
+            }
+
+            if let Err(e) = inner.flush_to_disk() {
+                error!("Failed to save recording chunk: {}", backtraced_err(&e));
+            }
+
+            let chunk_dir = inner.path.parent().unwrap_or(Path::new("."));
+            let next_path = find_recording_path(chunk_dir, ".json");
+            info!("Continuing recording in {}", next_path.display());
+
+            inner.path = next_path;
+            inner.recording = Recording::new();
+        }
+
+        Some(RecordingInitializer { inner: handle })
+    }
+
+    /// Write the active recording to disk without ending it
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        match self.handle.upgrade() {
+            Some(inner) => inner.lock().expect("poisoned lock").flush_to_disk(),
+            None => Ok(()),
         }
     }
 
     pub fn set_win_size(&self, width: usize, height: usize) {
         if let Some(inner) = self.handle.upgrade() {
             let mut inner = inner.lock().expect("poisoned lock");
-            inner
-                .recording
-                .items
-                .push(RecordingItem::SetWinSize { width, height });
+            let timestamp_ms = inner.elapsed_ms();
+            inner.recording.items.push(RecordingItem::SetWinSize {
+                width,
+                height,
+                timestamp_ms,
+            });
         }
     }
 
+    /// Record a batch of output from the child. Each batch is its own item so that replay can
+    /// space them out like they arrived
     pub fn write(&self, to_insert: &[u8]) {
         if let Some(inner) = self.handle.upgrade() {
             let mut inner = inner.lock().expect("poisoned lock");
-            if let Some(RecordingItem::Write { data }) = inner.recording.items.last_mut() {
-                data.extend_from_slice(to_insert);
-            } else {
-                inner.recording.items.push(RecordingItem::Write {
-                    data: to_insert.to_vec(),
-                });
-            }
+            let timestamp_ms = inner.elapsed_ms();
+            inner.recording.items.push(RecordingItem::Write {
+                data: to_insert.to_vec(),
+                timestamp_ms,
+            });
+        }
+    }
+
+    pub fn reset_parser(&self) {
+        if let Some(inner) = self.handle.upgrade() {
+            let mut inner = inner.lock().expect("poisoned lock");
+            let timestamp_ms = inner.elapsed_ms();
+            inner
+                .recording
+                .items
+                .push(RecordingItem::ResetParser { timestamp_ms });
         }
     }
 
@@ -517,13 +916,21 @@ impl Recorder {
             }));
         }
 
-        let recording_path = find_recording_path(&self.recording_dir);
+        let recording_path = match self.max_items_per_file {
+            Some(_) => {
+                let chunk_dir = find_recording_path(&self.recording_dir, "");
+                std::fs::create_dir_all(&chunk_dir)?;
+                chunk_dir.join("0.json")
+            }
+            None => find_recording_path(&self.recording_dir, ".json"),
+        };
 
         info!("Recording to {}", recording_path.display());
 
         let handle_inner = Arc::new(Mutex::new(RecordingHandleInner {
             recording: Recording::new(),
             path: recording_path,
+            start: Instant::now(),
         }));
         self.handle = Arc::downgrade(&handle_inner);
 
@@ -537,6 +944,21 @@ impl Recorder {
 mod test {
     use super::*;
 
+    fn clear_timestamps(items: &[RecordingItem]) -> Vec<RecordingItem> {
+        items
+            .iter()
+            .cloned()
+            .map(|mut item| {
+                match &mut item {
+                    RecordingItem::SetWinSize { timestamp_ms, .. }
+                    | RecordingItem::Write { timestamp_ms, .. }
+                    | RecordingItem::ResetParser { timestamp_ms } => *timestamp_ms = 0,
+                }
+                item
+            })
+            .collect()
+    }
+
     #[test]
     fn test_recorder() {
         let _temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
@@ -584,4 +1006,341 @@ mod test {
 
         assert_eq!(loaded, saved);
     }
+
+    #[test]
+    fn test_asciicast_export() {
+        let recording = Recording {
+            initial_state: [(
+                "terminal_buffer".to_string(),
+                JsonValue::Object(
+                    [
+                        ("width".to_string(), JsonValue::Number(80.0)),
+                        ("height".to_string(), JsonValue::Number(24.0)),
+                    ]
+                    .into(),
+                ),
+            )]
+            .into(),
+            items: vec![
+                RecordingItem::Write {
+                    data: b"hello\r\n\x1b[31m\"world\"".to_vec(),
+                    timestamp_ms: 0,
+                },
+                RecordingItem::SetWinSize {
+                    width: 100,
+                    height: 30,
+                    timestamp_ms: 0,
+                },
+                RecordingItem::Write {
+                    data: "\u{e9}".as_bytes().to_vec(),
+                    timestamp_ms: 0,
+                },
+            ],
+        };
+
+        let mut output = Vec::new();
+        recording
+            .to_asciicast_v2(&mut output)
+            .expect("failed to export asciicast");
+        let output = String::from_utf8(output).expect("asciicast is not utf8");
+
+        let mut lines = output
+            .lines()
+            .map(|line| line.parse::<JsonValue>().expect("line is not json"));
+
+        let header = lines.next().expect("header missing");
+        let header: &HashMap<String, JsonValue> = header.get().expect("header not a map");
+        let header_field = |name: &str| -> f64 {
+            *header
+                .get(name)
+                .expect("header field missing")
+                .get()
+                .expect("header field not a number")
+        };
+        assert_eq!(header_field("version"), 2.0);
+        assert_eq!(header_field("width"), 80.0);
+        assert_eq!(header_field("height"), 24.0);
+
+        let events: Vec<(f64, String, String)> = lines
+            .map(|event| {
+                let event: Vec<JsonValue> = event.try_into().expect("event not an array");
+                let [time, code, data] =
+                    <[JsonValue; 3]>::try_from(event).expect("event does not have 3 elements");
+                (
+                    time.try_into().expect("time not a number"),
+                    code.try_into().expect("code not a string"),
+                    data.try_into().expect("data not a string"),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (
+                    0.0,
+                    "o".to_string(),
+                    "hello\r\n\x1b[31m\"world\"".to_string()
+                ),
+                (0.1, "r".to_string(), "100x30".to_string()),
+                (0.2, "o".to_string(), "\u{e9}".to_string()),
+            ]
+        );
+
+        let recording = Recording::new();
+        assert!(recording.to_asciicast_v2(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_asciicast_export_split_char() {
+        let mut recording = Recording::new();
+        recording.items = vec![
+            RecordingItem::SetWinSize {
+                width: 80,
+                height: 24,
+                timestamp_ms: 0,
+            },
+            RecordingItem::Write {
+                data: b"a\xe2\x82".to_vec(),
+                timestamp_ms: 100,
+            },
+            RecordingItem::Write {
+                data: b"\xacb\xe2".to_vec(),
+                timestamp_ms: 200,
+            },
+        ];
+        recording.initial_state = [(
+            "terminal_buffer".to_string(),
+            JsonValue::Object(
+                [
+                    ("width".to_string(), JsonValue::Number(80.0)),
+                    ("height".to_string(), JsonValue::Number(24.0)),
+                ]
+                .into(),
+            ),
+        )]
+        .into();
+
+        let mut output = Vec::new();
+        recording
+            .to_asciicast_v2(&mut output)
+            .expect("failed to export asciicast");
+        let output = String::from_utf8(output).expect("asciicast is not utf8");
+        let events: Vec<String> = output
+            .lines()
+            .skip(2)
+            .map(|line| {
+                let event: Vec<JsonValue> = line
+                    .parse::<JsonValue>()
+                    .expect("line is not json")
+                    .try_into()
+                    .expect("event not an array");
+                event[2].clone().try_into().expect("data not a string")
+            })
+            .collect();
+
+        // The euro sign is written whole with the write that finished it, a character that is
+        // never finished is still written
+        assert_eq!(events, ["a", "\u{20ac}b", "\u{fffd}"]);
+    }
+
+    #[test]
+    fn test_asciicast_import() {
+        let mut recording = Recording::new();
+        recording.initial_state.insert(
+            "terminal_buffer".to_string(),
+            JsonValue::Object(
+                [
+                    ("width".to_string(), JsonValue::Number(80.0)),
+                    ("height".to_string(), JsonValue::Number(24.0)),
+                ]
+                .into(),
+            ),
+        );
+        recording.items = vec![
+            RecordingItem::Write {
+                data: b"hello\r\n\x1b[31m".to_vec(),
+                timestamp_ms: 250,
+            },
+            RecordingItem::SetWinSize {
+                width: 100,
+                height: 30,
+                timestamp_ms: 1500,
+            },
+        ];
+
+        let mut output = Vec::new();
+        recording
+            .to_asciicast_v2(&mut output)
+            .expect("failed to export asciicast");
+        let output = String::from_utf8(output).expect("asciicast is not utf8");
+        assert!(is_asciicast_v2(&output));
+
+        // The initial size becomes the first item
+        let loaded = parse_asciicast_v2(&output).expect("failed to parse asciicast");
+        assert!(loaded.initial_state.is_empty());
+        let mut expected_items = vec![RecordingItem::SetWinSize {
+            width: 80,
+            height: 24,
+            timestamp_ms: 0,
+        }];
+        expected_items.extend(recording.items.iter().cloned());
+        assert_eq!(loaded.items(), expected_items);
+
+        assert!(!is_asciicast_v2(
+            &recording
+                .to_json()
+                .stringify()
+                .expect("failed to stringify")
+        ));
+        assert!(parse_asciicast_v2("{\"version\": 1, \"width\": 80, \"height\": 24}").is_err());
+        assert!(parse_asciicast_v2(
+            "{\"version\": 2, \"width\": 80, \"height\": 24}\n[0.1, \"r\", \"80\"]"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_recorder_timestamps() {
+        let _temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut recorder = Recorder::new(_temp_dir.path().into());
+        let StartRecordingResponse::New(initializer) = recorder
+            .start_recording()
+            .expect("failed to start recording")
+        else {
+            panic!("Did not get initializer");
+        };
+        let handle = initializer.into_handle();
+
+        recorder.write(b"asdf");
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        // Later output is a new item with its own timestamp
+        recorder.write(b"1234");
+        recorder.set_win_size(10, 20);
+        drop(handle);
+
+        let loaded =
+            Recording::load(&_temp_dir.path().join("0.json")).expect("failed to load recording");
+        let timestamps: Vec<u64> = loaded
+            .items()
+            .iter()
+            .map(RecordingItem::timestamp_ms)
+            .collect();
+        assert_eq!(timestamps.len(), 3);
+        assert!(timestamps[0] < 20);
+        assert!(timestamps[1] >= 20);
+        assert!(timestamps[2] >= timestamps[1]);
+
+        // Items from before timing was recorded load with a timestamp of 0
+        let item = RecordingItem::from_json(
+            "{\"type\": \"write\", \"data\": [97]}"
+                .parse()
+                .expect("invalid json"),
+        )
+        .expect("failed to parse item");
+        assert_eq!(item.timestamp_ms(), 0);
+    }
+
+    #[test]
+    fn test_recorder_flush() {
+        let _temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut recorder = Recorder::new(_temp_dir.path().into());
+        let StartRecordingResponse::New(initializer) = recorder
+            .start_recording()
+            .expect("failed to start recording")
+        else {
+            panic!("Did not get initializer");
+        };
+        let handle = initializer.into_handle();
+
+        recorder.write(b"asdf");
+        recorder.flush().expect("failed to flush recording");
+
+        let path = _temp_dir.path().join("0.json");
+        let loaded = Recording::load(&path).expect("failed to load recording");
+        assert_eq!(
+            clear_timestamps(loaded.items()),
+            &[RecordingItem::Write {
+                data: b"asdf".to_vec(),
+                timestamp_ms: 0,
+            }]
+        );
+
+        // Recording continues after a flush
+        recorder.write(b"1234");
+        drop(handle);
+        let loaded = Recording::load(&path).expect("failed to load recording");
+        assert_eq!(
+            clear_timestamps(loaded.items()),
+            &[
+                RecordingItem::Write {
+                    data: b"asdf".to_vec(),
+                    timestamp_ms: 0,
+                },
+                RecordingItem::Write {
+                    data: b"1234".to_vec(),
+                    timestamp_ms: 0,
+                }
+            ]
+        );
+        assert!(!_temp_dir.path().join("0.json.tmp").exists());
+    }
+
+    #[test]
+    fn test_recorder_chunking() {
+        let _temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let mut recorder = Recorder::new(_temp_dir.path().into());
+        recorder.set_max_items_per_file(Some(2));
+
+        let StartRecordingResponse::New(initializer) = recorder
+            .start_recording()
+            .expect("failed to start recording")
+        else {
+            panic!("Did not get initializer");
+        };
+        initializer.snapshot_item("chunk".to_string(), 0u8.into());
+        let handle = initializer.into_handle();
+
+        let mut expected_items = Vec::new();
+        for i in 0..5u8 {
+            recorder.write(&[i]);
+            recorder.set_win_size(i.into(), 10);
+            expected_items.push(RecordingItem::Write {
+                data: vec![i],
+                timestamp_ms: 0,
+            });
+            expected_items.push(RecordingItem::SetWinSize {
+                width: i.into(),
+                height: 10,
+                timestamp_ms: 0,
+            });
+
+            let initializer = recorder.next_chunk().expect("chunk should be full");
+            initializer.snapshot_item("chunk".to_string(), (i + 1).into());
+        }
+        assert!(recorder.next_chunk().is_none());
+        drop(handle);
+
+        let chunk_idx = |recording: &Recording| -> u8 {
+            recording
+                .initial_state()
+                .into_map()
+                .expect("initial state not a map")
+                .remove("chunk")
+                .expect("chunk not present")
+                .into_num()
+                .expect("chunk not a u8")
+        };
+
+        let chunk_dir = _temp_dir.path().join("0");
+        let loaded = Recording::load(&chunk_dir).expect("failed to load recording");
+        assert_eq!(clear_timestamps(loaded.items()), expected_items);
+        assert_eq!(chunk_idx(&loaded), 0);
+
+        // Every chunk has its full initial state, so it loads without the ones before it
+        std::fs::remove_file(chunk_dir.join("4.json")).expect("failed to remove chunk");
+        let last_chunk =
+            Recording::load(&chunk_dir.join("5.json")).expect("failed to load last chunk");
+        assert!(last_chunk.items().is_empty());
+        assert_eq!(chunk_idx(&last_chunk), 5);
+    }
 }
@@ -1,7 +1,12 @@
 use super::io::TermIo;
-use crate::terminal_emulator::{ReadResponse, Recording, RecordingItem, SnapshotItem};
+use crate::terminal_emulator::{
+    MockIo, ReadResponse, Recording, RecordingItem, SnapshotItem, TerminalEmulator,
+};
 
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::{Duration, Instant},
+};
 
 pub struct ReplayIo {
     rx: Receiver<u8>,
@@ -10,10 +15,10 @@ pub struct ReplayIo {
 impl TermIo for ReplayIo {
     fn read(&mut self, buf: &mut [u8]) -> Result<super::io::ReadResponse, super::io::TermIoErr> {
         let mut idx = 0;
-        while let Ok(b) = self.rx.try_recv() {
-            if idx >= buf.len() {
+        while idx < buf.len() {
+            let Ok(b) = self.rx.try_recv() else {
                 break;
-            }
+            };
 
             buf[idx] = b;
             idx += 1;
@@ -36,8 +41,8 @@ impl TermIo for ReplayIo {
 
 fn item_len(item: &RecordingItem) -> usize {
     match item {
-        RecordingItem::Write { data } => data.len(),
-        RecordingItem::SetWinSize { .. } => 1,
+        RecordingItem::Write { data, .. } => data.len(),
+        RecordingItem::SetWinSize { .. } | RecordingItem::ResetParser { .. } => 1,
     }
 }
 
@@ -48,6 +53,7 @@ fn calc_segment_lengths(recording: &Recording) -> Vec<usize> {
 enum RecordingAction {
     Write(u8),
     SetWinSize { width: usize, height: usize },
+    ResetParser,
     None,
 }
 
@@ -75,11 +81,12 @@ impl RecordingTracker {
             }
 
             let ret = match item {
-                RecordingItem::Write { data } => RecordingAction::Write(data[self.item_pos]),
-                RecordingItem::SetWinSize { width, height } => RecordingAction::SetWinSize {
+                RecordingItem::Write { data, .. } => RecordingAction::Write(data[self.item_pos]),
+                RecordingItem::SetWinSize { width, height, .. } => RecordingAction::SetWinSize {
                     width: *width,
                     height: *height,
                 },
+                RecordingItem::ResetParser { .. } => RecordingAction::ResetParser,
             };
 
             self.item_pos += 1;
@@ -89,11 +96,49 @@ impl RecordingTracker {
     }
 }
 
+/// Screen contents at some point of a recording, used to preview seek positions
+pub struct Thumbnail {
+    /// Position in the units of [`ReplayControl::current_pos`]
+    pub pos: usize,
+    pub screen: Vec<String>,
+}
+
 pub enum ControlAction {
     Resize { width: usize, height: usize },
+    ResetParser,
     None,
 }
 
+/// Real time playback, started with [`ReplayControl::play_at_speed`]
+struct Playback {
+    speed: f64,
+    /// Wall clock time playback started at
+    started: Instant,
+    /// Recording time playback started from
+    start_ms: u64,
+}
+
+impl Playback {
+    /// Time in the recording that should have been reached by now
+    fn recording_time_ms(&self) -> u64 {
+        self.start_ms + (self.started.elapsed().as_secs_f64() * 1000.0 * self.speed) as u64
+    }
+}
+
+/// Items of recordings made before timing was recorded are played back this far apart
+const UNTIMED_ITEM_INTERVAL_MS: u64 = 50;
+
+/// Playback time is scaled by the speed, anything but a positive finite speed would never reach
+/// the next item or divide by zero
+fn is_valid_speed(speed: f64) -> bool {
+    speed.is_finite() && speed > 0.0
+}
+
+/// Wall clock time it takes to play `recording_ms` of a recording at `speed`
+fn scaled_delay(recording_ms: u64, speed: f64) -> Duration {
+    Duration::from_secs_f64(recording_ms as f64 / 1000.0 / speed)
+}
+
 pub struct ReplayControl {
     recording: Recording,
     tracker: RecordingTracker,
@@ -101,6 +146,9 @@ pub struct ReplayControl {
     total_len: usize,
     tx: Sender<u8>,
     rx: Option<Receiver<u8>>,
+    playback: Option<Playback>,
+    /// False if every item has a timestamp of 0, see [`UNTIMED_ITEM_INTERVAL_MS`]
+    has_timing: bool,
 }
 
 impl ReplayControl {
@@ -113,6 +161,10 @@ impl ReplayControl {
         let (tx, rx) = mpsc::channel();
         let segment_lengths = calc_segment_lengths(&recording);
         let total_len = segment_lengths.iter().sum();
+        let has_timing = recording
+            .items()
+            .iter()
+            .any(|item| item.timestamp_ms() != 0);
         ReplayControl {
             recording,
             tracker,
@@ -120,9 +172,15 @@ impl ReplayControl {
             total_len,
             tx,
             rx: Some(rx),
+            playback: None,
+            has_timing,
         }
     }
 
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+
     pub fn initial_state(&self) -> SnapshotItem {
         self.recording.initial_state()
     }
@@ -149,6 +207,231 @@ impl ReplayControl {
         self.total_len
     }
 
+    /// Number of items in the recording, as opposed to [`ReplayControl::len`] which counts
+    /// every byte of a write
+    pub fn item_count(&self) -> usize {
+        self.segment_lengths.len()
+    }
+
+    /// Number of items that have been fully replayed
+    pub fn current_item_index(&self) -> usize {
+        // The tracker only moves to the next item when it is asked for more data
+        match self.segment_lengths.get(self.tracker.item_idx) {
+            Some(len) if self.tracker.item_pos >= *len => self.tracker.item_idx + 1,
+            _ => self.tracker.item_idx,
+        }
+    }
+
+    /// Position, in the units of [`ReplayControl::current_pos`], at which item_idx starts
+    pub fn item_start_pos(&self, item_idx: usize) -> usize {
+        self.segment_lengths.iter().take(item_idx).sum()
+    }
+
+    /// Replay the whole recording on a separate emulator, capturing the screen at n evenly
+    /// spaced positions. The last thumbnail is at the end of the recording. This is slow for large
+    /// recordings, callers should run it off of the gui thread
+    pub fn generate_thumbnails(
+        &self,
+        n: usize,
+    ) -> Result<Vec<Thumbnail>, Box<dyn std::error::Error>> {
+        let mut terminal_emulator =
+            TerminalEmulator::from_snapshot(self.initial_state(), MockIo::new())?;
+        let mut targets = (1..=n).map(|i| i * self.total_len / n).peekable();
+        let mut thumbnails = Vec::with_capacity(n);
+        let mut pos = 0;
+
+        let mut take_thumbnails = |pos: usize, terminal_emulator: &TerminalEmulator<MockIo>| {
+            while let Some(target) = targets.next_if(|target| *target <= pos) {
+                thumbnails.push(Thumbnail {
+                    pos: target,
+                    screen: terminal_emulator.dump_screen(),
+                });
+            }
+            targets.peek().copied()
+        };
+
+        for item in self.recording.items() {
+            match item {
+                RecordingItem::Write { data, .. } => {
+                    let mut data = &data[..];
+                    while !data.is_empty() {
+                        let next_target = take_thumbnails(pos, &terminal_emulator);
+                        let chunk_len = next_target.map_or(data.len(), |t| data.len().min(t - pos));
+                        terminal_emulator.write_sequence(&data[..chunk_len]);
+                        data = &data[chunk_len..];
+                        pos += chunk_len;
+                    }
+                }
+                RecordingItem::SetWinSize { width, height, .. } => {
+                    take_thumbnails(pos, &terminal_emulator);
+                    terminal_emulator.set_win_size(*width, *height)?;
+                    pos += 1;
+                }
+                RecordingItem::ResetParser { .. } => {
+                    take_thumbnails(pos, &terminal_emulator);
+                    terminal_emulator.reset_parser();
+                    pos += 1;
+                }
+            }
+        }
+        take_thumbnails(pos, &terminal_emulator);
+
+        Ok(thumbnails)
+    }
+
+    /// Play the recording back in real time, scaled by `speed`, starting from the current
+    /// position. Items are stepped through by [`ReplayControl::poll_playback`] as they come due.
+    /// Speeds that are not positive and finite are ignored
+    pub fn play_at_speed(&mut self, speed: f64) {
+        if !is_valid_speed(speed) {
+            warn!("Ignoring invalid playback speed {speed}");
+            return;
+        }
+
+        let start_ms = self.item_time_ms(self.current_item_index()).unwrap_or(0);
+        self.playback = Some(Playback {
+            speed,
+            started: Instant::now(),
+            start_ms,
+        });
+    }
+
+    /// Change the speed of running playback, continuing from the point it has reached. Invalid
+    /// speeds are ignored like in [`ReplayControl::play_at_speed`]
+    pub fn set_playback_speed(&mut self, speed: f64) {
+        if !is_valid_speed(speed) {
+            warn!("Ignoring invalid playback speed {speed}");
+            return;
+        }
+
+        if let Some(playback) = &mut self.playback {
+            *playback = Playback {
+                speed,
+                started: Instant::now(),
+                start_ms: playback.recording_time_ms(),
+            };
+        }
+    }
+
+    /// When item_idx is due in the recording
+    fn item_time_ms(&self, item_idx: usize) -> Option<u64> {
+        let item = self.recording.items().get(item_idx)?;
+        if self.has_timing {
+            Some(item.timestamp_ms())
+        } else {
+            Some(item_idx as u64 * UNTIMED_ITEM_INTERVAL_MS)
+        }
+    }
+
+    pub fn pause(&mut self) {
+        self.playback = None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    /// Step terminal_emulator through every item that is due at the current playback time.
+    /// Resizes are applied as they are reached, so that output around them is processed at the
+    /// right size. Playback stops at the end of the recording
+    pub fn poll_playback(
+        &mut self,
+        terminal_emulator: &mut TerminalEmulator<ReplayIo>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(playback) = &self.playback else {
+            return Ok(());
+        };
+        let now_ms = playback.recording_time_ms();
+
+        let mut item_idx = self.current_item_index();
+        let mut pos = self.current_pos();
+        let mut item_end = self.item_start_pos(item_idx);
+        while let Some(item_ms) = self.item_time_ms(item_idx) {
+            if item_ms > now_ms {
+                break;
+            }
+
+            item_end += self.segment_lengths[item_idx];
+            item_idx += 1;
+            while pos < item_end {
+                self.step(terminal_emulator)?;
+                pos += 1;
+            }
+        }
+
+        if pos >= self.total_len {
+            self.playback = None;
+        }
+
+        Ok(())
+    }
+
+    /// How long until the next item is due, None if not playing
+    pub fn time_to_next_item(&self) -> Option<Duration> {
+        let playback = self.playback.as_ref()?;
+        let next_ms = self.item_time_ms(self.current_item_index())?;
+        let wait_ms = next_ms.saturating_sub(playback.recording_time_ms());
+        Some(scaled_delay(wait_ms, playback.speed))
+    }
+
+    /// Move terminal_emulator to `pos`, in the units of [`ReplayControl::current_pos`]. The
+    /// replay cannot run backwards, so seeking backwards replaces terminal_emulator with a new
+    /// one from the initial state and replays up to `pos`
+    pub fn seek_to(
+        &mut self,
+        pos: usize,
+        terminal_emulator: &mut TerminalEmulator<ReplayIo>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let pos = pos.min(self.total_len);
+        let mut current_pos = self.current_pos();
+        if pos < current_pos {
+            self.reset();
+            *terminal_emulator =
+                TerminalEmulator::from_snapshot(self.initial_state(), self.io_handle())?;
+            current_pos = 0;
+        }
+
+        for _ in current_pos..pos {
+            self.step(terminal_emulator)?;
+        }
+        Ok(())
+    }
+
+    pub fn step_back(
+        &mut self,
+        terminal_emulator: &mut TerminalEmulator<ReplayIo>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.seek_to(self.current_pos().saturating_sub(1), terminal_emulator)
+    }
+
+    /// Go back to the start of the recording. Data that was queued for the previous io handle is
+    /// dropped with it, a new one has to be taken with [`ReplayControl::io_handle`]
+    fn reset(&mut self) {
+        self.tracker = RecordingTracker {
+            item_pos: 0,
+            item_idx: 0,
+        };
+        let (tx, rx) = mpsc::channel();
+        self.tx = tx;
+        self.rx = Some(rx);
+    }
+
+    /// Advance one position, applying resizes and parser resets to terminal_emulator as they are
+    /// reached
+    fn step(
+        &mut self,
+        terminal_emulator: &mut TerminalEmulator<ReplayIo>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match self.next() {
+            ControlAction::Resize { width, height } => {
+                terminal_emulator.inject_resize(width, height)?;
+            }
+            ControlAction::ResetParser => terminal_emulator.inject_parser_reset(),
+            ControlAction::None => (),
+        }
+        Ok(())
+    }
+
     pub fn next(&mut self) -> ControlAction {
         let action = self.tracker.next(&self.recording);
         match action {
@@ -159,7 +442,100 @@ impl ReplayControl {
             RecordingAction::SetWinSize { width, height } => {
                 ControlAction::Resize { width, height }
             }
+            RecordingAction::ResetParser => ControlAction::ResetParser,
             RecordingAction::None => ControlAction::None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_replay_io_read_full_buffer() {
+        let (tx, rx) = mpsc::channel();
+        let mut io = ReplayIo { rx };
+        for b in b"abc" {
+            tx.send(*b).expect("failed to send byte");
+        }
+
+        let mut buf = [0u8; 2];
+        assert!(matches!(io.read(&mut buf), Ok(ReadResponse::Success(2))));
+        assert_eq!(&buf, b"ab");
+
+        // The byte that did not fit is still there for the next read
+        assert!(matches!(io.read(&mut buf), Ok(ReadResponse::Success(1))));
+        assert_eq!(buf[0], b'c');
+        assert!(matches!(io.read(&mut buf), Ok(ReadResponse::Empty)));
+    }
+
+    #[test]
+    fn test_play_at_invalid_speed() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res/test/hello.cast");
+        let recording = Recording::load(&path).expect("failed to load recording");
+        let mut replay_control = ReplayControl::new(recording);
+
+        for speed in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            replay_control.play_at_speed(speed);
+            assert!(!replay_control.is_playing());
+            assert_eq!(replay_control.time_to_next_item(), None);
+        }
+
+        replay_control.play_at_speed(2.0);
+        assert!(replay_control.is_playing());
+        replay_control.set_playback_speed(0.0);
+        assert!(replay_control.time_to_next_item().is_some());
+    }
+
+    #[test]
+    fn test_speed_scales_time_to_next_item() {
+        let temp_dir = tempfile::TempDir::new().expect("failed to create tmp dir");
+        let path = temp_dir.path().join("gap.cast");
+        std::fs::write(
+            &path,
+            "{\"version\": 2, \"width\": 20, \"height\": 5}\n[0.1, \"o\", \"a\"]\n",
+        )
+        .expect("failed to write recording");
+        let recording = Recording::load(&path).expect("failed to load recording");
+        let mut replay_control = ReplayControl::new(recording);
+
+        // The header's resize is due immediately, the output 100ms later
+        replay_control.play_at_speed(1.0);
+        let mut emulator = TerminalEmulator::from_snapshot(
+            replay_control.initial_state(),
+            replay_control.io_handle(),
+        )
+        .expect("failed to load snapshot");
+        replay_control
+            .poll_playback(&mut emulator)
+            .expect("failed to poll playback");
+        assert_eq!(replay_control.current_item_index(), 1);
+
+        let wait = replay_control
+            .time_to_next_item()
+            .expect("playback should be running");
+        assert!(wait <= Duration::from_millis(100));
+        assert!(wait > Duration::from_millis(50));
+
+        replay_control.set_playback_speed(4.0);
+        let wait = replay_control
+            .time_to_next_item()
+            .expect("playback should be running");
+        assert!(wait <= Duration::from_millis(25));
+        assert!(wait > Duration::from_millis(12));
+
+        replay_control.set_playback_speed(f64::NAN);
+        let wait = replay_control
+            .time_to_next_item()
+            .expect("playback should be running");
+        assert!(wait <= Duration::from_millis(25));
+    }
+
+    #[test]
+    fn test_scaled_delay() {
+        assert_eq!(scaled_delay(100, 1.0), Duration::from_millis(100));
+        assert_eq!(scaled_delay(100, 2.0), Duration::from_millis(50));
+        assert_eq!(scaled_delay(100, 0.5), Duration::from_millis(200));
+    }
+}
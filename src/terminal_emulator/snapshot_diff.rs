@@ -0,0 +1,231 @@
+use super::recording::SnapshotItem;
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+mod snapshot_diff_keys {
+    pub const REPLACE: &str = "replace";
+    pub const CHANGED: &str = "changed";
+    pub const REMOVED: &str = "removed";
+}
+
+#[derive(Debug, Error)]
+enum ApplyDiffErrorKind {
+    #[error("map diff applied to non-map item")]
+    BaseNotMap,
+    #[error("failed to apply diff for key {0}")]
+    Key(String, #[source] Box<ApplyDiffError>),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct ApplyDiffError(#[from] ApplyDiffErrorKind);
+
+#[derive(Debug, Error)]
+enum LoadSnapshotDiffErrorKind {
+    #[error("diff is not a map")]
+    NotMap,
+    #[error("diff has neither replace nor changed field")]
+    UnknownDiff,
+    #[error("changed field is not a map")]
+    ChangedNotMap,
+    #[error("removed field is not an array")]
+    RemovedNotArray,
+    #[error("removed key is not a string")]
+    RemovedKeyNotString,
+    #[error("failed to load diff for key {0}")]
+    Key(String, #[source] Box<LoadSnapshotDiffError>),
+}
+
+#[derive(Debug, Error)]
+#[error(transparent)]
+pub struct LoadSnapshotDiffError(#[from] LoadSnapshotDiffErrorKind);
+
+/// Difference between two snapshots. Only keys that differ are stored, so nested maps that
+/// barely change between snapshots produce small diffs
+#[derive(Clone, Debug, PartialEq)]
+pub enum SnapshotDiff {
+    /// The item was added, or changed in a way that is not expressed as a diff (e.g. a
+    /// non-map value)
+    Replace(SnapshotItem),
+    Map {
+        changed: HashMap<String, SnapshotDiff>,
+        removed: Vec<String>,
+    },
+}
+
+impl SnapshotDiff {
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        match self {
+            SnapshotDiff::Replace(_) => false,
+            SnapshotDiff::Map { changed, removed } => changed.is_empty() && removed.is_empty(),
+        }
+    }
+
+    pub fn snapshot(self) -> SnapshotItem {
+        match self {
+            SnapshotDiff::Replace(item) => {
+                SnapshotItem::Map([(snapshot_diff_keys::REPLACE.to_string(), item)].into())
+            }
+            SnapshotDiff::Map { changed, removed } => {
+                let changed = changed
+                    .into_iter()
+                    .map(|(k, v)| (k, v.snapshot()))
+                    .collect();
+                let removed = removed.into_iter().collect();
+                SnapshotItem::Map(
+                    [
+                        (
+                            snapshot_diff_keys::CHANGED.to_string(),
+                            SnapshotItem::Map(changed),
+                        ),
+                        (snapshot_diff_keys::REMOVED.to_string(), removed),
+                    ]
+                    .into(),
+                )
+            }
+        }
+    }
+
+    pub fn from_snapshot(snapshot: SnapshotItem) -> Result<SnapshotDiff, LoadSnapshotDiffError> {
+        use LoadSnapshotDiffErrorKind::*;
+
+        let mut map = snapshot.into_map().map_err(|_| NotMap)?;
+
+        if let Some(item) = map.remove(snapshot_diff_keys::REPLACE) {
+            return Ok(SnapshotDiff::Replace(item));
+        }
+
+        let changed = map
+            .remove(snapshot_diff_keys::CHANGED)
+            .ok_or(UnknownDiff)?
+            .into_map()
+            .map_err(|_| ChangedNotMap)?
+            .into_iter()
+            .map(|(k, v)| {
+                let diff =
+                    SnapshotDiff::from_snapshot(v).map_err(|e| Key(k.clone(), Box::new(e)))?;
+                Ok((k, diff))
+            })
+            .collect::<Result<_, LoadSnapshotDiffErrorKind>>()?;
+
+        let removed = match map.remove(snapshot_diff_keys::REMOVED) {
+            Some(removed) => removed
+                .into_vec()
+                .map_err(|_| RemovedNotArray)?
+                .into_iter()
+                .map(|k| k.into_string().map_err(|_| RemovedKeyNotString))
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+
+        Ok(SnapshotDiff::Map { changed, removed })
+    }
+}
+
+/// Compute the diff that turns `old` into `new`. Maps are compared key by key, anything else is
+/// replaced wholesale if it changed
+pub fn snapshot_diff(old: &SnapshotItem, new: &SnapshotItem) -> SnapshotDiff {
+    let (SnapshotItem::Map(old), SnapshotItem::Map(new)) = (old, new) else {
+        return SnapshotDiff::Replace(new.clone());
+    };
+
+    let mut changed = HashMap::new();
+    for (k, new_v) in new {
+        match old.get(k) {
+            Some(old_v) if old_v == new_v => (),
+            Some(old_v) => {
+                changed.insert(k.clone(), snapshot_diff(old_v, new_v));
+            }
+            None => {
+                changed.insert(k.clone(), SnapshotDiff::Replace(new_v.clone()));
+            }
+        }
+    }
+
+    let removed = old
+        .keys()
+        .filter(|k| !new.contains_key(*k))
+        .cloned()
+        .collect();
+
+    SnapshotDiff::Map { changed, removed }
+}
+
+pub fn apply_diff(base: SnapshotItem, diff: SnapshotDiff) -> Result<SnapshotItem, ApplyDiffError> {
+    use ApplyDiffErrorKind::*;
+
+    let (mut base, changed, removed) = match diff {
+        SnapshotDiff::Replace(item) => return Ok(item),
+        SnapshotDiff::Map { changed, removed } => {
+            (base.into_map().map_err(|_| BaseNotMap)?, changed, removed)
+        }
+    };
+
+    for k in removed {
+        base.remove(&k);
+    }
+
+    for (k, diff) in changed {
+        let item = match (base.remove(&k), diff) {
+            (_, SnapshotDiff::Replace(item)) => item,
+            (Some(base_item), diff) => {
+                apply_diff(base_item, diff).map_err(|e| Key(k.clone(), Box::new(e)))?
+            }
+            (None, diff) => apply_diff(SnapshotItem::Map(Default::default()), diff)
+                .map_err(|e| Key(k.clone(), Box::new(e)))?,
+        };
+        base.insert(k, item);
+    }
+
+    Ok(SnapshotItem::Map(base))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn map<const N: usize>(items: [(&str, SnapshotItem); N]) -> SnapshotItem {
+        SnapshotItem::Map(items.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    #[test]
+    fn test_snapshot_diff() {
+        let old = map([
+            ("cursor", map([("x", 1i64.into()), ("y", 2i64.into())])),
+            ("buf", [1u8, 2, 3].into_iter().collect()),
+            ("decckm", false.into()),
+        ]);
+        let new = map([
+            ("cursor", map([("x", 5i64.into()), ("y", 2i64.into())])),
+            ("buf", [1u8, 2, 3].into_iter().collect()),
+            ("title", "termie".into()),
+        ]);
+
+        let diff = snapshot_diff(&old, &new);
+        let SnapshotDiff::Map { changed, removed } = &diff else {
+            panic!("top level diff should be a map");
+        };
+        assert_eq!(removed, &["decckm".to_string()]);
+        assert_eq!(changed.len(), 2);
+        assert_eq!(
+            changed["cursor"],
+            SnapshotDiff::Map {
+                changed: [("x".to_string(), SnapshotDiff::Replace(5i64.into()))].into(),
+                removed: Vec::new(),
+            }
+        );
+        assert_eq!(changed["title"], SnapshotDiff::Replace("termie".into()));
+
+        let loaded =
+            SnapshotDiff::from_snapshot(diff.clone().snapshot()).expect("failed to load diff");
+        assert_eq!(loaded, diff);
+
+        let applied = apply_diff(old, diff).expect("failed to apply diff");
+        assert_eq!(applied, new);
+
+        assert!(snapshot_diff(&new, &new).is_empty());
+    }
+}